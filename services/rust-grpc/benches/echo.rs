@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! The throughput listener's echo path over loopback TCP, from one-frame
+//! floods of small blocks up to the largest block copied through
+//! userspace (bigger ones are spliced).
+//!
+//! Run with `cargo bench --bench echo`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use hermit_server::throughput::{self, Socket};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+const PAYLOAD: usize = 256 * 1024;
+
+/// One verified echo test of `payload` in `block`-sized writes.
+async fn echo(listener: &TcpListener, payload: &[u8], block: usize) {
+    let addr = listener.local_addr().unwrap();
+    let (client, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+    let (server, _) = accepted.unwrap();
+    let socket = Socket::of(&server);
+    let (served, echoed) = tokio::join!(
+        throughput::run(server, socket),
+        throughput::verify_echo(client.unwrap(), payload, block, false),
+    );
+    assert_eq!(served.unwrap().bytes, payload.len() as u64);
+    echoed.unwrap();
+}
+
+fn echo_path(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let listener = rt.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+    let payload: Vec<u8> = (0..PAYLOAD).map(|i| i as u8).collect();
+
+    let mut group = c.benchmark_group("echo");
+    group.throughput(Throughput::Bytes(PAYLOAD as u64));
+    for block in [64, 1024, 16 * 1024, 128 * 1024] {
+        group.bench_with_input(BenchmarkId::from_parameter(block), &block, |b, &block| {
+            b.iter(|| rt.block_on(echo(&listener, &payload, block)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, echo_path);
+criterion_main!(benches);
@@ -1,9 +1,177 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+#[cfg(feature = "tls")]
+use prost::Message;
+#[cfg(feature = "tls")]
+use prost_types::FileDescriptorSet;
+#[cfg(feature = "tls")]
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A proto file, the descriptor set checked in next to it, and how its
+/// code is generated.
+#[cfg(feature = "tls")]
+struct Proto {
+    path: &'static str,
+    vendored: &'static str,
+    builder: fn() -> tonic_build::Builder,
+}
+
+#[cfg(feature = "tls")]
+const PROTOS: &[Proto] = &[
+    // The client is used by test_harness. The descriptor set is also
+    // served by GetSchema.
+    #[cfg(feature = "grpc")]
+    Proto {
+        path: "proto/hermit.proto",
+        vendored: "proto/hermit.binpb",
+        builder: || {
+            tonic_build::configure()
+                .build_server(true)
+                .build_client(true)
+        },
+    },
+    // SPIFFE Workload API, for SVIDs as TLS certificates: we only ever
+    // call it.
+    Proto {
+        path: "proto/workload.proto",
+        vendored: "proto/workload.binpb",
+        builder: || {
+            tonic_build::configure()
+                .build_server(false)
+                .build_client(true)
+        },
+    },
+];
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::configure()
-        .build_server(true)
-        .build_client(false)
-        .compile_protos(&["proto/hermit.proto"], &["proto"])?;
+    #[cfg(feature = "tls")]
+    protos()?;
+    build_info()?;
     Ok(())
 }
+
+#[cfg(feature = "tls")]
+fn protos() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    println!("cargo:rerun-if-env-changed=PROTOC");
+    println!("cargo:rerun-if-env-changed=HERMIT_VENDORED_PROTO");
+
+    let vendored = std::env::var_os("HERMIT_VENDORED_PROTO").is_some() || !protoc_available();
+    if vendored {
+        println!("cargo:warning=generating code from the vendored descriptors in proto/*.binpb, not protoc");
+    }
+    for proto in PROTOS {
+        let stem = Path::new(proto.path).file_stem().unwrap().to_string_lossy();
+        let descriptor = out_dir.join(format!("{}_descriptor.bin", stem));
+        if vendored {
+            println!("cargo:rerun-if-changed={}", proto.vendored);
+            let bytes = std::fs::read(proto.vendored)?;
+            std::fs::write(&descriptor, &bytes)?;
+            (proto.builder)().compile_fds(FileDescriptorSet::decode(bytes.as_slice())?)?;
+        } else {
+            (proto.builder)()
+                .file_descriptor_set_path(&descriptor)
+                .compile_protos(&[proto.path], &["proto"])?;
+            if !same_schema(&std::fs::read(&descriptor)?, proto.vendored) {
+                println!(
+                    "cargo:warning={} is out of date with {}; refresh it with: cp {} {}",
+                    proto.vendored,
+                    proto.path,
+                    descriptor.display(),
+                    proto.vendored
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+fn protoc_available() -> bool {
+    let protoc = std::env::var_os("PROTOC").unwrap_or_else(|| "protoc".into());
+    Command::new(protoc)
+        .arg("--version")
+        .output()
+        .is_ok_and(|out| out.status.success())
+}
+
+/// Whether the vendored descriptor set at `path` describes the same schema
+/// as `fresh`. Source info (comment text and line numbers) is ignored, so
+/// protoc versions that lay it out differently don't count as a change.
+#[cfg(feature = "tls")]
+fn same_schema(fresh: &[u8], path: &str) -> bool {
+    let decode = |bytes: &[u8]| {
+        FileDescriptorSet::decode(bytes).ok().map(|mut set| {
+            for file in &mut set.file {
+                file.source_code_info = None;
+            }
+            set
+        })
+    };
+    let vendored = std::fs::read(path).ok();
+    match (decode(fresh), vendored.as_deref().and_then(decode)) {
+        (Some(fresh), Some(vendored)) => fresh == vendored,
+        _ => false,
+    }
+}
+
+/// Environment for `build_info`. Docker builds have no .git, so the SHA
+/// can be passed in as HERMIT_GIT_SHA; SOURCE_DATE_EPOCH pins the build
+/// time for reproducible builds.
+fn build_info() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-env-changed=HERMIT_GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let git_sha = match std::env::var("HERMIT_GIT_SHA") {
+        Ok(sha) => sha,
+        Err(_) => {
+            if let Some(dir) = git(&["rev-parse", "--git-dir"]) {
+                // Rerun when HEAD moves, whether it names a branch or a commit.
+                println!("cargo:rerun-if-changed={}/HEAD", dir);
+                if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+                    println!("cargo:rerun-if-changed={}/{}", dir, head_ref);
+                }
+            }
+            git(&["rev-parse", "HEAD"]).unwrap_or_else(|| "unknown".to_string())
+        }
+    };
+    let built_at = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch.parse::<u64>()?,
+        Err(_) => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    };
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map(|v| v.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=HERMIT_GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=HERMIT_BUILD_TIMESTAMP={}", built_at);
+    println!("cargo:rustc-env=HERMIT_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=HERMIT_TARGET={}", std::env::var("TARGET")?);
+    println!(
+        "cargo:rustc-env=HERMIT_PROTO_SCHEMA={:016x}",
+        fnv1a(&std::fs::read("proto/hermit.proto")?)
+    );
+    Ok(())
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let out = Command::new("git").args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(out.stdout).ok()?.trim().to_string())
+}
+
+/// Stable across toolchains, unlike `DefaultHasher`.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
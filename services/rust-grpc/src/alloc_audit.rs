@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A code path that should stay allocation-free, counted by `track`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HotPath {
+    /// The Ping RPC handler.
+    Ping,
+    /// One read/write round trip on an echo-mode throughput connection.
+    Echo,
+}
+
+impl HotPath {
+    pub const ALL: [HotPath; 2] = [HotPath::Ping, HotPath::Echo];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HotPath::Ping => "ping",
+            HotPath::Echo => "echo",
+        }
+    }
+}
+
+/// Totals for one `HotPath` since startup.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PathStats {
+    pub requests: u64,
+    pub allocations: u64,
+    pub bytes: u64,
+}
+
+impl PathStats {
+    pub fn allocations_per_request(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            self.allocations as f64 / self.requests as f64
+        }
+    }
+}
+
+struct Totals {
+    requests: AtomicU64,
+    allocations: AtomicU64,
+    bytes: AtomicU64,
+}
+
+static TOTALS: [Totals; 2] = [const {
+    Totals {
+        requests: AtomicU64::new(0),
+        allocations: AtomicU64::new(0),
+        bytes: AtomicU64::new(0),
+    }
+}; 2];
+
+/// Per-path totals, or `None` when allocations aren't being counted.
+pub fn stats() -> Option<Vec<(HotPath, PathStats)>> {
+    if !cfg!(feature = "alloc-audit") {
+        return None;
+    }
+    Some(
+        HotPath::ALL
+            .iter()
+            .map(|&path| {
+                let t = &TOTALS[path as usize];
+                let stats = PathStats {
+                    requests: t.requests.load(Ordering::Relaxed),
+                    allocations: t.allocations.load(Ordering::Relaxed),
+                    bytes: t.bytes.load(Ordering::Relaxed),
+                };
+                (path, stats)
+            })
+            .collect(),
+    )
+}
+
+/// Runs `fut` as one request on `path`, adding the allocations made while
+/// polling it to the path's totals. Only counts with `--features
+/// alloc-audit`, which installs a counting global allocator; otherwise
+/// this just awaits `fut`.
+#[cfg(feature = "alloc-audit")]
+pub async fn track<F: Future>(path: HotPath, fut: F) -> F::Output {
+    let mut tracked = counting::Tracked {
+        fut: std::pin::pin!(fut),
+        allocations: 0,
+        bytes: 0,
+    };
+    let out = (&mut tracked).await;
+    let t = &TOTALS[path as usize];
+    t.requests.fetch_add(1, Ordering::Relaxed);
+    t.allocations
+        .fetch_add(tracked.allocations, Ordering::Relaxed);
+    t.bytes.fetch_add(tracked.bytes, Ordering::Relaxed);
+    out
+}
+
+#[cfg(not(feature = "alloc-audit"))]
+pub async fn track<F: Future>(_path: HotPath, fut: F) -> F::Output {
+    fut.await
+}
+
+#[cfg(feature = "alloc-audit")]
+mod counting {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    thread_local! {
+        // Const-initialized and without a destructor, so reading these
+        // from inside the allocator never allocates.
+        static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+        static BYTES: Cell<u64> = const { Cell::new(0) };
+    }
+
+    fn current() -> (u64, u64) {
+        (
+            ALLOCATIONS.try_with(Cell::get).unwrap_or(0),
+            BYTES.try_with(Cell::get).unwrap_or(0),
+        )
+    }
+
+    /// `System` plus per-thread counts of allocations (including
+    /// reallocations) and requested bytes.
+    struct Counting;
+
+    // SAFETY: every call is forwarded unchanged to System, which upholds
+    // GlobalAlloc's contract; counting touches only thread-locals that
+    // never allocate.
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count(layout.size());
+            // SAFETY: the caller's guarantees, passed on to System.
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            count(layout.size());
+            // SAFETY: the caller's guarantees, passed on to System.
+            unsafe { System.alloc_zeroed(layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count(new_size);
+            // SAFETY: the caller's guarantees, passed on to System.
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            // SAFETY: the caller's guarantees, passed on to System.
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    fn count(size: usize) {
+        let _ = ALLOCATIONS.try_with(|c| c.set(c.get() + 1));
+        let _ = BYTES.try_with(|c| c.set(c.get() + size as u64));
+    }
+
+    #[global_allocator]
+    static GLOBAL: Counting = Counting;
+
+    /// Sums the thread's counters across each poll of `fut`. A task only
+    /// runs on one thread per poll, so work on other tasks in between
+    /// isn't included even if the task moves between workers.
+    pub(super) struct Tracked<F> {
+        pub(super) fut: F,
+        pub(super) allocations: u64,
+        pub(super) bytes: u64,
+    }
+
+    impl<F: Future + Unpin> Future for Tracked<F> {
+        type Output = F::Output;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+            let (allocations, bytes) = current();
+            let poll = Pin::new(&mut self.fut).poll(cx);
+            let (after_allocations, after_bytes) = current();
+            self.allocations += after_allocations - allocations;
+            self.bytes += after_bytes - bytes;
+            poll
+        }
+    }
+}
+
+#[cfg(all(test, feature = "alloc-audit"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn counts_allocations_made_while_polling() {
+        let before = stats().unwrap()[HotPath::Echo as usize].1;
+        track(HotPath::Echo, async {
+            let v: Vec<u8> = Vec::with_capacity(64);
+            tokio::task::yield_now().await;
+            std::hint::black_box(Box::new(v));
+        })
+        .await;
+        let after = stats().unwrap()[HotPath::Echo as usize].1;
+        assert!(after.requests > before.requests);
+        assert!(after.allocations - before.allocations >= 2);
+        assert!(after.bytes - before.bytes >= 64);
+    }
+}
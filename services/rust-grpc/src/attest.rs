@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::hermit::BenchmarkResponse;
+use prost::Message;
+use ring::digest;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use rustls::pki_types::PrivateKeyDer;
+use std::io::BufReader;
+
+/// Signs benchmark results with the host's Ed25519 key so they can be
+/// forwarded between teams and still be verified as unmodified.
+///
+/// The signed message is the SHA-256 digest of the protobuf encoding of
+/// the `BenchmarkResponse` with `signature` empty and `signing_key_id`
+/// already set. Verifiers clear `signature`, re-encode, hash, and check
+/// against the public key from `ServerInfo.signing_public_key`.
+pub struct Signer {
+    key_pair: Ed25519KeyPair,
+    key_id: String,
+}
+
+impl Signer {
+    /// Parse a PKCS#8 Ed25519 key, PEM or DER.
+    pub fn from_pkcs8(data: &[u8]) -> Result<Self, String> {
+        let der = if data.starts_with(b"-----") {
+            match rustls_pemfile::private_key(&mut BufReader::new(data)) {
+                Ok(Some(PrivateKeyDer::Pkcs8(k))) => k.secret_pkcs8_der().to_vec(),
+                Ok(Some(_)) => return Err("signing key must be PKCS#8".to_string()),
+                Ok(None) => return Err("no private key found in PEM".to_string()),
+                Err(e) => return Err(e.to_string()),
+            }
+        } else {
+            data.to_vec()
+        };
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+            .map_err(|e| format!("invalid Ed25519 key: {}", e))?;
+        // Short fingerprint of the public key; stable across restarts.
+        let fp = digest::digest(&digest::SHA256, key_pair.public_key().as_ref());
+        let key_id = hex::encode(&fp.as_ref()[..8]);
+        Ok(Signer { key_pair, key_id })
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.key_pair.public_key().as_ref()
+    }
+
+    pub fn sign(&self, resp: &mut BenchmarkResponse) {
+        resp.signing_key_id = self.key_id.clone();
+        resp.signature.clear();
+        let digest = digest::digest(&digest::SHA256, &resp.encode_to_vec());
+        resp.signature = self.key_pair.sign(digest.as_ref()).as_ref().to_vec();
+    }
+}
@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::{AuthBackend, AuthError, User};
+use ldap3::{dn_escape, ldap_escape, LdapConnAsync, Scope, SearchEntry};
+use tracing::warn;
+
+/// Authenticates by binding to an LDAP directory as the user.
+///
+/// `user_dn_template` contains a `{username}` placeholder, e.g.
+/// `uid={username},ou=people,dc=example,dc=org`. Group membership under
+/// `base_dn` (`member=<user dn>`) is mapped onto roles by group `cn`.
+pub struct LdapBackend {
+    url: String,
+    user_dn_template: String,
+    base_dn: String,
+}
+
+impl LdapBackend {
+    pub fn new(url: String, user_dn_template: String, base_dn: String) -> Self {
+        LdapBackend {
+            url,
+            user_dn_template,
+            base_dn,
+        }
+    }
+
+    fn user_dn(&self, username: &str) -> String {
+        self.user_dn_template
+            .replace("{username}", &dn_escape(username))
+    }
+
+    async fn connect(&self) -> Result<ldap3::Ldap, AuthError> {
+        let (conn, ldap) = LdapConnAsync::new(&self.url)
+            .await
+            .map_err(|e| AuthError::Backend(e.to_string()))?;
+        tokio::spawn(async move {
+            if let Err(e) = conn.drive().await {
+                warn!("ldap connection error: {}", e);
+            }
+        });
+        Ok(ldap)
+    }
+
+    async fn roles(&self, ldap: &mut ldap3::Ldap, dn: &str) -> Result<Vec<String>, AuthError> {
+        let filter = format!("(member={})", ldap_escape(dn));
+        let (entries, _) = ldap
+            .search(&self.base_dn, Scope::Subtree, &filter, vec!["cn"])
+            .await
+            .and_then(|r| r.success())
+            .map_err(|e| AuthError::Backend(e.to_string()))?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|e| SearchEntry::construct(e).attrs.remove("cn"))
+            .flatten()
+            .collect())
+    }
+}
+
+#[tonic::async_trait]
+impl AuthBackend for LdapBackend {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    async fn verify_credentials(&self, username: &str, secret: &str) -> Result<User, AuthError> {
+        // An empty password would be an unauthenticated bind, which most
+        // servers accept -- never treat that as a successful login.
+        if secret.is_empty() {
+            return Err(AuthError::InvalidCredentials);
+        }
+        let dn = self.user_dn(username);
+        let mut ldap = self.connect().await?;
+        ldap.simple_bind(&dn, secret)
+            .await
+            .and_then(|r| r.success())
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        let roles = self.roles(&mut ldap, &dn).await?;
+        let _ = ldap.unbind().await;
+        Ok(User {
+            username: username.to_string(),
+            roles,
+        })
+    }
+
+    async fn fetch_user(&self, username: &str) -> Result<Option<User>, AuthError> {
+        let dn = self.user_dn(username);
+        let mut ldap = self.connect().await?;
+        let found = ldap
+            .search(&dn, Scope::Base, "(objectClass=*)", vec!["1.1"])
+            .await
+            .and_then(|r| r.success())
+            .map(|(entries, _)| !entries.is_empty())
+            .unwrap_or(false);
+        let user = if found {
+            Some(User {
+                username: username.to_string(),
+                roles: self.roles(&mut ldap, &dn).await?,
+            })
+        } else {
+            None
+        };
+        let _ = ldap.unbind().await;
+        Ok(user)
+    }
+
+    async fn validate_token(&self, _token: &str) -> Result<User, AuthError> {
+        Err(AuthError::Unsupported("token validation"))
+    }
+}
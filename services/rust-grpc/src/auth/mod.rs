@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+#[cfg(feature = "ldap")]
+mod ldap;
+mod oidc;
+mod static_file;
+pub mod totp;
+
+#[cfg(feature = "ldap")]
+pub use ldap::LdapBackend;
+pub use oidc::OidcIntrospectionBackend;
+pub use static_file::StaticFileBackend;
+
+use std::fmt;
+use tonic::{Request, Status};
+use tracing::warn;
+
+/// Extracts the shared secret from environment and validates it against
+/// the `x-hermit-secret` metadata header on each gRPC request.
+///
+/// If HERMIT_SECRET is not set (dev mode), all requests are allowed.
+#[allow(clippy::result_large_err)] // tonic interceptors must return Status
+pub fn secret_interceptor(req: Request<()>) -> Result<Request<()>, Status> {
+    let expected = match std::env::var("HERMIT_SECRET") {
+        Ok(s) if !s.is_empty() => s,
+        _ => return Ok(req), // dev mode: no secret required
+    };
+
+    match req.metadata().get("x-hermit-secret") {
+        Some(val) => match val.to_str() {
+            Ok(v) if v == expected => Ok(req),
+            _ => {
+                warn!("invalid x-hermit-secret");
+                Err(Status::unauthenticated("invalid secret"))
+            }
+        },
+        None => {
+            warn!("missing x-hermit-secret header");
+            Err(Status::unauthenticated("missing secret"))
+        }
+    }
+}
+
+/// Identity returned by a successful authentication.
+#[derive(Clone, Debug)]
+pub struct User {
+    pub username: String,
+    pub roles: Vec<String>,
+}
+
+impl User {
+    pub fn is_admin(&self) -> bool {
+        self.roles.iter().any(|r| r == "admin")
+    }
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    /// Username/password or token did not check out.
+    InvalidCredentials,
+    /// The backend cannot perform this kind of check (e.g. tokens on LDAP).
+    Unsupported(&'static str),
+    /// The backend itself failed (network, malformed directory entry, ...).
+    Backend(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => write!(f, "invalid credentials"),
+            AuthError::Unsupported(what) => write!(f, "{} not supported by auth backend", what),
+            AuthError::Backend(e) => write!(f, "auth backend error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Source of truth for Login. Implementations are selected at startup via
+/// `--auth-backend` so hermit can sit on an org's existing identity store.
+#[tonic::async_trait]
+pub trait AuthBackend: Send + Sync {
+    /// Short name used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Check a username + secret pair (password, or a bearer token for
+    /// token-only backends).
+    async fn verify_credentials(&self, username: &str, secret: &str) -> Result<User, AuthError>;
+
+    /// Look up a user without authenticating them.
+    async fn fetch_user(&self, username: &str) -> Result<Option<User>, AuthError>;
+
+    /// Validate an opaque bearer token and return the identity it belongs to.
+    async fn validate_token(&self, token: &str) -> Result<User, AuthError>;
+}
+
+/// Dev-mode backend: every login succeeds. Matches the historical
+/// hardcoded-success behaviour and is the default when no backend is set.
+pub struct AllowAllBackend;
+
+/// Who a bearer token logs in as under `AllowAllBackend`: the token names
+/// no one, and sessions need a username to be listed and revoked by.
+pub const DEV_USERNAME: &str = "dev";
+
+#[tonic::async_trait]
+impl AuthBackend for AllowAllBackend {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    async fn verify_credentials(&self, username: &str, _secret: &str) -> Result<User, AuthError> {
+        Ok(User {
+            username: username.to_string(),
+            roles: Vec::new(),
+        })
+    }
+
+    async fn fetch_user(&self, username: &str) -> Result<Option<User>, AuthError> {
+        Ok(Some(User {
+            username: username.to_string(),
+            roles: Vec::new(),
+        }))
+    }
+
+    async fn validate_token(&self, _token: &str) -> Result<User, AuthError> {
+        Ok(User {
+            username: DEV_USERNAME.to_string(),
+            roles: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn allow_all_tokens_log_in_as_the_dev_user() {
+        let user = AllowAllBackend.validate_token("anything").await.unwrap();
+        assert_eq!(user.username, DEV_USERNAME);
+        assert!(!user.is_admin());
+    }
+}
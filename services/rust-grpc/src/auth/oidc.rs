@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::{AuthBackend, AuthError, User};
+use serde::Deserialize;
+
+/// Validates bearer tokens against an OAuth 2.0 token introspection
+/// endpoint (RFC 7662), as exposed by most OIDC providers.
+pub struct OidcIntrospectionBackend {
+    http: reqwest::Client,
+    introspection_url: String,
+    client_id: String,
+    client_secret: String,
+}
+
+#[derive(Deserialize)]
+struct IntrospectionResponse {
+    active: bool,
+    #[serde(default)]
+    sub: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+impl OidcIntrospectionBackend {
+    pub fn new(introspection_url: String, client_id: String, client_secret: String) -> Self {
+        OidcIntrospectionBackend {
+            http: reqwest::Client::new(),
+            introspection_url,
+            client_id,
+            client_secret,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl AuthBackend for OidcIntrospectionBackend {
+    fn name(&self) -> &'static str {
+        "oidc"
+    }
+
+    /// The "password" is the access token; it must belong to `username`.
+    async fn verify_credentials(&self, username: &str, secret: &str) -> Result<User, AuthError> {
+        let user = self.validate_token(secret).await?;
+        if !username.is_empty() && user.username != username {
+            return Err(AuthError::InvalidCredentials);
+        }
+        Ok(user)
+    }
+
+    async fn fetch_user(&self, _username: &str) -> Result<Option<User>, AuthError> {
+        Err(AuthError::Unsupported("user lookup"))
+    }
+
+    async fn validate_token(&self, token: &str) -> Result<User, AuthError> {
+        let resp = self
+            .http
+            .post(&self.introspection_url)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[("token", token), ("token_type_hint", "access_token")])
+            .send()
+            .await
+            .map_err(|e| AuthError::Backend(e.to_string()))?
+            .error_for_status()
+            .map_err(|e| AuthError::Backend(e.to_string()))?
+            .json::<IntrospectionResponse>()
+            .await
+            .map_err(|e| AuthError::Backend(e.to_string()))?;
+
+        if !resp.active {
+            return Err(AuthError::InvalidCredentials);
+        }
+        let username = resp
+            .username
+            .or(resp.sub)
+            .ok_or_else(|| AuthError::Backend("introspection response has no subject".into()))?;
+        // Scopes double as roles so an "admin" scope maps onto hermit's admin role.
+        let roles = resp
+            .scope
+            .map(|s| s.split_whitespace().map(String::from).collect())
+            .unwrap_or_default();
+        Ok(User { username, roles })
+    }
+}
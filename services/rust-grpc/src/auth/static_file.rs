@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::{AuthBackend, AuthError, User};
+use ring::pbkdf2;
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+
+/// Users loaded once from a flat file, one per line:
+///
+/// ```text
+/// # username:pbkdf2-sha256$<iterations>$<salt hex>$<hash hex>:role,role
+/// alice:pbkdf2-sha256$100000$8f1c...$2b7e...:admin
+/// bob:pbkdf2-sha256$100000$41d0...$9ac3...
+/// ```
+///
+/// Blank lines and lines starting with `#` are ignored.
+pub struct StaticFileBackend {
+    users: HashMap<String, Entry>,
+}
+
+struct Entry {
+    iterations: NonZeroU32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+    roles: Vec<String>,
+}
+
+impl StaticFileBackend {
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::parse(&contents).map_err(|e| format!("{}: {}", path, e).into())
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut users = HashMap::new();
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(3, ':');
+            let (username, hash_spec) = match (parts.next(), parts.next()) {
+                (Some(u), Some(h)) if !u.is_empty() => (u, h),
+                _ => return Err(format!("line {}: expected username:hash", lineno + 1)),
+            };
+            let roles = parts
+                .next()
+                .map(|r| {
+                    r.split(',')
+                        .map(str::trim)
+                        .filter(|r| !r.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default();
+            let entry =
+                parse_hash(hash_spec, roles).map_err(|e| format!("line {}: {}", lineno + 1, e))?;
+            users.insert(username.to_string(), entry);
+        }
+        Ok(StaticFileBackend { users })
+    }
+}
+
+fn parse_hash(spec: &str, roles: Vec<String>) -> Result<Entry, String> {
+    let fields: Vec<&str> = spec.split('$').collect();
+    if fields.len() != 4 || fields[0] != "pbkdf2-sha256" {
+        return Err("hash must be pbkdf2-sha256$<iterations>$<salt>$<hash>".to_string());
+    }
+    let iterations = fields[1]
+        .parse::<u32>()
+        .ok()
+        .and_then(NonZeroU32::new)
+        .ok_or("invalid iteration count")?;
+    let salt = hex::decode(fields[2]).map_err(|e| format!("salt: {}", e))?;
+    let hash = hex::decode(fields[3]).map_err(|e| format!("hash: {}", e))?;
+    Ok(Entry {
+        iterations,
+        salt,
+        hash,
+        roles,
+    })
+}
+
+#[tonic::async_trait]
+impl AuthBackend for StaticFileBackend {
+    fn name(&self) -> &'static str {
+        "static"
+    }
+
+    async fn verify_credentials(&self, username: &str, secret: &str) -> Result<User, AuthError> {
+        let entry = self
+            .users
+            .get(username)
+            .ok_or(AuthError::InvalidCredentials)?;
+        pbkdf2::verify(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            entry.iterations,
+            &entry.salt,
+            secret.as_bytes(),
+            &entry.hash,
+        )
+        .map_err(|_| AuthError::InvalidCredentials)?;
+        Ok(User {
+            username: username.to_string(),
+            roles: entry.roles.clone(),
+        })
+    }
+
+    async fn fetch_user(&self, username: &str) -> Result<Option<User>, AuthError> {
+        Ok(self.users.get(username).map(|e| User {
+            username: username.to_string(),
+            roles: e.roles.clone(),
+        }))
+    }
+
+    async fn validate_token(&self, _token: &str) -> Result<User, AuthError> {
+        Err(AuthError::Unsupported("token validation"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(username: &str, password: &str, roles: &str) -> String {
+        let salt = b"hermit-test-salt";
+        let iterations = NonZeroU32::new(1_000).unwrap();
+        let mut hash = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            salt,
+            password.as_bytes(),
+            &mut hash,
+        );
+        format!(
+            "{}:pbkdf2-sha256${}${}${}:{}",
+            username,
+            iterations,
+            hex::encode(salt),
+            hex::encode(hash),
+            roles
+        )
+    }
+
+    #[test]
+    fn parses_users_and_roles() {
+        let contents = format!(
+            "# comment\n\n{}\n  {}  \n",
+            line("alice", "pw", "admin, ops,"),
+            line("bob", "pw", "")
+        );
+        let backend = StaticFileBackend::parse(&contents).unwrap();
+        assert_eq!(backend.users.len(), 2);
+        assert_eq!(backend.users["alice"].roles, ["admin", "ops"]);
+        assert!(backend.users["bob"].roles.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for (contents, error) in [
+            ("alice", "line 1: expected username:hash"),
+            (":pbkdf2-sha256$1$00$00", "line 1: expected username:hash"),
+            ("alice:bcrypt$1$00$00", "line 1: hash must be"),
+            ("alice:pbkdf2-sha256$1$00", "line 1: hash must be"),
+            (
+                "\nalice:pbkdf2-sha256$0$00$00",
+                "line 2: invalid iteration count",
+            ),
+            ("alice:pbkdf2-sha256$1$zz$00", "line 1: salt:"),
+            ("alice:pbkdf2-sha256$1$00$0", "line 1: hash:"),
+        ] {
+            match StaticFileBackend::parse(contents) {
+                Ok(_) => panic!("{:?} parsed", contents),
+                Err(e) => assert!(e.starts_with(error), "{:?}: {}", contents, e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn verifies_passwords() {
+        let backend = StaticFileBackend::parse(&line("alice", "hunter2", "admin")).unwrap();
+        let user = backend
+            .verify_credentials("alice", "hunter2")
+            .await
+            .unwrap();
+        assert_eq!(user.username, "alice");
+        assert!(user.is_admin());
+        for (username, password) in [("alice", "hunter3"), ("alice", ""), ("bob", "hunter2")] {
+            assert!(matches!(
+                backend.verify_credentials(username, password).await,
+                Err(AuthError::InvalidCredentials)
+            ));
+        }
+        assert!(backend.fetch_user("alice").await.unwrap().is_some());
+        assert!(backend.fetch_user("bob").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,736 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const ISSUER: &str = "hermit";
+const DIGITS: u32 = 6;
+const PERIOD_SECS: u64 = 30;
+/// Accept codes one step either side of now to tolerate clock skew.
+const SKEW_STEPS: u64 = 1;
+const SECRET_BYTES: usize = 20;
+/// Wrong codes a user may send within `LOCKOUT` before every code,
+/// right or wrong, is refused until it ends. A million codes and three
+/// valid at a time would otherwise fall to guessing.
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Where TOTP secrets live. Every gRPC instance, and with Redis every
+/// host, must see the same enrollments: a user missing from one would
+/// log in there with a password alone.
+#[tonic::async_trait]
+pub trait TotpStore: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn secret(&self, username: &str) -> Result<Option<Vec<u8>>, String>;
+
+    /// Replaces any existing secret.
+    async fn set_secret(&self, username: &str, secret: &[u8]) -> Result<(), String>;
+
+    /// Marks time step `step` used by `username`; false if it already was,
+    /// which makes the code a replay.
+    async fn claim_step(&self, username: &str, step: u64) -> Result<bool, String>;
+
+    /// Wrong codes from `username` in the current lockout window.
+    async fn failures(&self, username: &str) -> Result<u32, String>;
+
+    /// Counts a wrong code, starting a lockout window if none is open.
+    async fn record_failure(&self, username: &str) -> Result<(), String>;
+
+    async fn clear_failures(&self, username: &str) -> Result<(), String>;
+}
+
+pub enum Verify {
+    Ok,
+    NotEnrolled,
+    Invalid,
+    /// Too many wrong codes; nothing is checked until the lockout ends.
+    LockedOut,
+}
+
+/// RFC 6238 TOTP second factors, over a `TotpStore`.
+pub struct Totp {
+    store: Arc<dyn TotpStore>,
+    rng: SystemRandom,
+}
+
+impl Totp {
+    pub fn new(store: Arc<dyn TotpStore>) -> Totp {
+        Totp {
+            store,
+            rng: SystemRandom::new(),
+        }
+    }
+
+    pub fn store_name(&self) -> &'static str {
+        self.store.name()
+    }
+
+    pub async fn is_enrolled(&self, username: &str) -> Result<bool, String> {
+        Ok(self.store.secret(username).await?.is_some())
+    }
+
+    /// Generate and store a fresh secret, returning the otpauth:// URI for
+    /// authenticator apps. Replaces any existing enrollment.
+    pub async fn enroll(&self, username: &str) -> Result<String, String> {
+        let mut secret = vec![0u8; SECRET_BYTES];
+        self.rng
+            .fill(&mut secret)
+            .map_err(|_| "failed to generate TOTP secret".to_string())?;
+        self.store.set_secret(username, &secret).await?;
+        Ok(format!(
+            "otpauth://totp/{issuer}:{user}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={PERIOD_SECS}",
+            issuer = ISSUER,
+            user = percent_encode(username),
+            secret = base32_encode(&secret),
+        ))
+    }
+
+    pub async fn verify(&self, username: &str, code: &str) -> Result<Verify, String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.verify_at(username, code, now).await
+    }
+
+    /// `verify` as of `unix_secs`. An empty code is Invalid without
+    /// counting against the user, so clients can ask whether one is needed.
+    async fn verify_at(
+        &self,
+        username: &str,
+        code: &str,
+        unix_secs: u64,
+    ) -> Result<Verify, String> {
+        let secret = match self.store.secret(username).await? {
+            Some(s) => s,
+            None => return Ok(Verify::NotEnrolled),
+        };
+        if self.store.failures(username).await? >= MAX_FAILURES {
+            return Ok(Verify::LockedOut);
+        }
+        if code.is_empty() {
+            return Ok(Verify::Invalid);
+        }
+        // Exactly six ASCII digits: parsing alone would take "+12345".
+        let code = match code.parse::<u32>() {
+            Ok(c) if code.len() == DIGITS as usize && code.bytes().all(|b| b.is_ascii_digit()) => {
+                Some(c)
+            }
+            _ => None,
+        };
+        if let Some(code) = code {
+            let now = unix_secs / PERIOD_SECS;
+            for step in now.saturating_sub(SKEW_STEPS)..=now + SKEW_STEPS {
+                if hotp(&secret, step) == code && self.store.claim_step(username, step).await? {
+                    self.store.clear_failures(username).await?;
+                    return Ok(Verify::Ok);
+                }
+            }
+        }
+        self.store.record_failure(username).await?;
+        Ok(Verify::Invalid)
+    }
+}
+
+/// Process-local; enrollments are lost on restart. For tests and dev.
+#[derive(Default)]
+pub struct MemoryTotpStore {
+    users: RwLock<HashMap<String, Enrollment>>,
+    /// Wrong codes per user and when their lockout window opened.
+    failures: Mutex<HashMap<String, (u32, Instant)>>,
+}
+
+struct Enrollment {
+    secret: Vec<u8>,
+    /// Last accepted time step; codes at or before it are replays.
+    last_step: u64,
+}
+
+impl MemoryTotpStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[tonic::async_trait]
+impl TotpStore for MemoryTotpStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn secret(&self, username: &str) -> Result<Option<Vec<u8>>, String> {
+        let users = self.users.read().map_err(|e| e.to_string())?;
+        Ok(users.get(username).map(|e| e.secret.clone()))
+    }
+
+    async fn set_secret(&self, username: &str, secret: &[u8]) -> Result<(), String> {
+        let mut users = self.users.write().map_err(|e| e.to_string())?;
+        users.insert(
+            username.to_string(),
+            Enrollment {
+                secret: secret.to_vec(),
+                last_step: 0,
+            },
+        );
+        Ok(())
+    }
+
+    async fn claim_step(&self, username: &str, step: u64) -> Result<bool, String> {
+        let mut users = self.users.write().map_err(|e| e.to_string())?;
+        Ok(match users.get_mut(username) {
+            Some(e) if step > e.last_step => {
+                e.last_step = step;
+                true
+            }
+            _ => false,
+        })
+    }
+
+    async fn failures(&self, username: &str) -> Result<u32, String> {
+        let failures = self.failures.lock().map_err(|e| e.to_string())?;
+        Ok(match failures.get(username) {
+            Some(&(count, since)) if since.elapsed() < LOCKOUT => count,
+            _ => 0,
+        })
+    }
+
+    async fn record_failure(&self, username: &str) -> Result<(), String> {
+        let mut failures = self.failures.lock().map_err(|e| e.to_string())?;
+        let entry = failures
+            .entry(username.to_string())
+            .or_insert((0, Instant::now()));
+        if entry.1.elapsed() >= LOCKOUT {
+            *entry = (0, Instant::now());
+        }
+        entry.0 += 1;
+        Ok(())
+    }
+
+    async fn clear_failures(&self, username: &str) -> Result<(), String> {
+        let mut failures = self.failures.lock().map_err(|e| e.to_string())?;
+        failures.remove(username);
+        Ok(())
+    }
+}
+
+/// Secrets in a file, one `username:BASE32SECRET` per line, rewritten
+/// whole on every enrollment. Operators can provision users, admins in
+/// particular, by adding lines before starting the server. Blank lines
+/// and lines starting with `#` are ignored.
+pub struct FileTotpStore {
+    path: PathBuf,
+    memory: MemoryTotpStore,
+    /// Serializes rewrites of the file.
+    write: Mutex<()>,
+}
+
+impl FileTotpStore {
+    /// Loads `path`, which is created on the first enrollment if missing.
+    pub fn open(path: &Path) -> Result<FileTotpStore, String> {
+        let memory = MemoryTotpStore::new();
+        let contents = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        {
+            let mut users = memory.users.write().map_err(|e| e.to_string())?;
+            for (lineno, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let secret = line
+                    .rsplit_once(':')
+                    .filter(|(user, _)| !user.is_empty())
+                    .and_then(|(user, secret)| Some((user, base32_decode(secret)?)))
+                    .filter(|(_, secret)| !secret.is_empty());
+                let (user, secret) = secret.ok_or_else(|| {
+                    format!(
+                        "{}: line {}: expected username:BASE32SECRET",
+                        path.display(),
+                        lineno + 1
+                    )
+                })?;
+                users.insert(
+                    user.to_string(),
+                    Enrollment {
+                        secret,
+                        last_step: 0,
+                    },
+                );
+            }
+        }
+        Ok(FileTotpStore {
+            path: path.to_path_buf(),
+            memory,
+            write: Mutex::new(()),
+        })
+    }
+
+    /// Writes every secret, with `username`'s replaced by `secret`, to a
+    /// temporary file and renames it over the old one, so a crash never
+    /// leaves a torn file behind. Memory is only updated once that worked.
+    fn save(&self, username: &str, secret: &[u8]) -> Result<(), String> {
+        let _write = self.write.lock().map_err(|e| e.to_string())?;
+        let mut lines = std::collections::BTreeMap::new();
+        {
+            let users = self.memory.users.read().map_err(|e| e.to_string())?;
+            for (name, e) in users.iter() {
+                lines.insert(name.as_str(), base32_encode(&e.secret));
+            }
+            lines.insert(username, base32_encode(secret));
+            let mut out = String::from("# hermit TOTP secrets: username:BASE32SECRET\n");
+            for (name, secret) in lines {
+                out.push_str(&format!("{}:{}\n", name, secret));
+            }
+            let err = |e: std::io::Error| format!("{}: {}", self.path.display(), e);
+            let tmp = self.path.with_extension("tmp");
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(&tmp).map_err(err)?;
+            file.write_all(out.as_bytes()).map_err(err)?;
+            file.sync_all().map_err(err)?;
+            std::fs::rename(&tmp, &self.path).map_err(err)?;
+        }
+        let mut users = self.memory.users.write().map_err(|e| e.to_string())?;
+        users.insert(
+            username.to_string(),
+            Enrollment {
+                secret: secret.to_vec(),
+                last_step: 0,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl TotpStore for FileTotpStore {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn secret(&self, username: &str) -> Result<Option<Vec<u8>>, String> {
+        self.memory.secret(username).await
+    }
+
+    async fn set_secret(&self, username: &str, secret: &[u8]) -> Result<(), String> {
+        if username.contains(['\n', '\r']) {
+            return Err("username can't contain line breaks".to_string());
+        }
+        self.save(username, secret)
+    }
+
+    async fn claim_step(&self, username: &str, step: u64) -> Result<bool, String> {
+        self.memory.claim_step(username, step).await
+    }
+
+    async fn failures(&self, username: &str) -> Result<u32, String> {
+        self.memory.failures(username).await
+    }
+
+    async fn record_failure(&self, username: &str) -> Result<(), String> {
+        self.memory.record_failure(username).await
+    }
+
+    async fn clear_failures(&self, username: &str) -> Result<(), String> {
+        self.memory.clear_failures(username).await
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis_store::RedisTotpStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use super::{base32_decode, base32_encode, TotpStore, LOCKOUT, PERIOD_SECS, SKEW_STEPS};
+    use redis::aio::ConnectionManager;
+    use redis::AsyncCommands;
+
+    const SECRET_PREFIX: &str = "hermit:totp:secret:";
+    const STEP_PREFIX: &str = "hermit:totp:step:";
+    const FAILURES_PREFIX: &str = "hermit:totp:failures:";
+
+    /// Secrets as base32 strings under `hermit:totp:secret:<user>`, next
+    /// to the sessions, so every host shares them. A used time step is a
+    /// key set with NX that expires once the step can no longer be
+    /// accepted. Wrong codes are a counter that expires with the lockout
+    /// window it opened.
+    pub struct RedisTotpStore {
+        conn: ConnectionManager,
+    }
+
+    impl RedisTotpStore {
+        pub async fn connect(url: &str) -> Result<Self, String> {
+            let client = redis::Client::open(url).map_err(|e| e.to_string())?;
+            let conn = ConnectionManager::new(client)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(RedisTotpStore { conn })
+        }
+    }
+
+    #[tonic::async_trait]
+    impl TotpStore for RedisTotpStore {
+        fn name(&self) -> &'static str {
+            "redis"
+        }
+
+        async fn secret(&self, username: &str) -> Result<Option<Vec<u8>>, String> {
+            let mut conn = self.conn.clone();
+            let value: Option<String> = conn
+                .get(format!("{}{}", SECRET_PREFIX, username))
+                .await
+                .map_err(|e| e.to_string())?;
+            value
+                .map(|v| {
+                    base32_decode(&v).ok_or_else(|| format!("corrupt TOTP secret for {}", username))
+                })
+                .transpose()
+        }
+
+        async fn set_secret(&self, username: &str, secret: &[u8]) -> Result<(), String> {
+            let mut conn = self.conn.clone();
+            conn.set::<_, _, ()>(
+                format!("{}{}", SECRET_PREFIX, username),
+                base32_encode(secret),
+            )
+            .await
+            .map_err(|e| e.to_string())
+        }
+
+        async fn claim_step(&self, username: &str, step: u64) -> Result<bool, String> {
+            let mut conn = self.conn.clone();
+            let ttl = (2 * SKEW_STEPS + 2) * PERIOD_SECS;
+            let claimed: Option<String> = redis::cmd("SET")
+                .arg(format!("{}{}:{}", STEP_PREFIX, username, step))
+                .arg(1)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl)
+                .query_async(&mut conn)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(claimed.is_some())
+        }
+
+        async fn failures(&self, username: &str) -> Result<u32, String> {
+            let mut conn = self.conn.clone();
+            let count: Option<u32> = conn
+                .get(format!("{}{}", FAILURES_PREFIX, username))
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(count.unwrap_or(0))
+        }
+
+        async fn record_failure(&self, username: &str) -> Result<(), String> {
+            let mut conn = self.conn.clone();
+            let key = format!("{}{}", FAILURES_PREFIX, username);
+            // Only the first failure sets the expiry; INCR keeps it.
+            redis::cmd("SET")
+                .arg(&key)
+                .arg(0)
+                .arg("NX")
+                .arg("EX")
+                .arg(LOCKOUT.as_secs())
+                .query_async::<Option<String>>(&mut conn)
+                .await
+                .map_err(|e| e.to_string())?;
+            conn.incr::<_, _, ()>(&key, 1)
+                .await
+                .map_err(|e| e.to_string())
+        }
+
+        async fn clear_failures(&self, username: &str) -> Result<(), String> {
+            let mut conn = self.conn.clone();
+            conn.del::<_, ()>(format!("{}{}", FAILURES_PREFIX, username))
+                .await
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// RFC 4226 HOTP with dynamic truncation.
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let digest = tag.as_ref();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let bin = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    bin % 10u32.pow(DIGITS)
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// RFC 4648 base32 without padding, as expected by otpauth URIs.
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+/// The inverse of `base32_encode`, also taking lowercase and `=`
+/// padding; `None` for any other character.
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in s.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn totp() -> Totp {
+        Totp::new(Arc::new(MemoryTotpStore::new()))
+    }
+
+    fn secret_of(uri: &str) -> Vec<u8> {
+        let secret = uri
+            .split(['?', '&'])
+            .find_map(|p| p.strip_prefix("secret="));
+        base32_decode(secret.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn hotp_matches_rfc4226() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(
+                hotp(RFC_SECRET, counter as u64),
+                code,
+                "counter {}",
+                counter
+            );
+        }
+    }
+
+    #[test]
+    fn totp_matches_rfc6238() {
+        // The RFC's SHA-1 codes are eight digits; ours are their last six.
+        for (time, code) in [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ] {
+            assert_eq!(
+                hotp(RFC_SECRET, time / PERIOD_SECS),
+                code % 1_000_000,
+                "T={}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        assert_eq!(base32_encode(b"Hello!\xDE\xAD\xBE\xEF"), "JBSWY3DPEHPK3PXP");
+        assert_eq!(
+            base32_decode("jbswy3dpehpk3pxp").unwrap(),
+            b"Hello!\xDE\xAD\xBE\xEF"
+        );
+        assert_eq!(base32_decode("MZXW6===").unwrap(), b"foo");
+        assert!(base32_decode("MZXW1").is_none());
+        for len in 0..=SECRET_BYTES {
+            let data: Vec<u8> = (0..len as u8).map(|b| b.wrapping_mul(37)).collect();
+            assert_eq!(base32_decode(&base32_encode(&data)).unwrap(), data);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_replays() {
+        let totp = totp();
+        totp.store.set_secret("alice", RFC_SECRET).await.unwrap();
+        assert!(matches!(
+            totp.verify_at("alice", "287082", 59).await.unwrap(),
+            Verify::Ok
+        ));
+        assert!(matches!(
+            totp.verify_at("alice", "287082", 59).await.unwrap(),
+            Verify::Invalid
+        ));
+        // Nor can an older step's code follow a newer one.
+        assert!(matches!(
+            totp.verify_at("alice", "359152", 60).await.unwrap(),
+            Verify::Ok
+        ));
+        assert!(matches!(
+            totp.verify_at("alice", "287082", 60).await.unwrap(),
+            Verify::Invalid
+        ));
+    }
+
+    #[tokio::test]
+    async fn takes_only_six_ascii_digits() {
+        // 081804 is the code at T=1111111109; each of these parses to it.
+        for code in ["+81804", " 081804", "081804 ", "0081804", "81804"] {
+            let totp = totp();
+            totp.store.set_secret("alice", RFC_SECRET).await.unwrap();
+            assert!(
+                matches!(
+                    totp.verify_at("alice", code, 1111111109).await.unwrap(),
+                    Verify::Invalid
+                ),
+                "{:?}",
+                code
+            );
+            assert!(matches!(
+                totp.verify_at("alice", "081804", 1111111109).await.unwrap(),
+                Verify::Ok
+            ));
+        }
+        assert!(matches!(
+            totp().verify_at("bob", "081804", 1111111109).await.unwrap(),
+            Verify::NotEnrolled
+        ));
+    }
+
+    #[tokio::test]
+    async fn locks_out_after_repeated_failures() {
+        let totp = totp();
+        totp.store.set_secret("alice", RFC_SECRET).await.unwrap();
+        // Asking without a code doesn't count.
+        for _ in 0..MAX_FAILURES {
+            assert!(matches!(
+                totp.verify_at("alice", "", 59).await.unwrap(),
+                Verify::Invalid
+            ));
+        }
+        for _ in 0..MAX_FAILURES {
+            assert!(matches!(
+                totp.verify_at("alice", "000000", 59).await.unwrap(),
+                Verify::Invalid
+            ));
+        }
+        assert!(matches!(
+            totp.verify_at("alice", "287082", 59).await.unwrap(),
+            Verify::LockedOut
+        ));
+
+        // A good code before the limit resets the count.
+        let totp = self::totp();
+        totp.store.set_secret("alice", RFC_SECRET).await.unwrap();
+        for _ in 0..MAX_FAILURES - 1 {
+            totp.verify_at("alice", "000000", 59).await.unwrap();
+        }
+        assert!(matches!(
+            totp.verify_at("alice", "287082", 59).await.unwrap(),
+            Verify::Ok
+        ));
+        assert_eq!(totp.store.failures("alice").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn enrolls_and_verifies() {
+        let totp = totp();
+        assert!(!totp.is_enrolled("a b").await.unwrap());
+        let uri = totp.enroll("a b").await.unwrap();
+        assert!(
+            uri.starts_with("otpauth://totp/hermit:a%20b?secret="),
+            "{}",
+            uri
+        );
+        assert!(totp.is_enrolled("a b").await.unwrap());
+
+        let code = format!("{:06}", hotp(&secret_of(&uri), 1_000_000 / PERIOD_SECS));
+        assert!(matches!(
+            totp.verify_at("a b", &code, 1_000_000).await.unwrap(),
+            Verify::Ok
+        ));
+
+        // Re-enrolling replaces the secret.
+        let again = totp.enroll("a b").await.unwrap();
+        assert_ne!(secret_of(&again), secret_of(&uri));
+    }
+
+    #[tokio::test]
+    async fn file_store_persists_enrollments() {
+        let path = std::env::temp_dir().join(format!(
+            "hermit-totp-{}-{}.txt",
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let totp = Totp::new(Arc::new(FileTotpStore::open(&path).unwrap()));
+        let alice = secret_of(&totp.enroll("alice").await.unwrap());
+        let bob = secret_of(&totp.enroll("bob").await.unwrap());
+        drop(totp);
+
+        let store = FileTotpStore::open(&path).unwrap();
+        assert_eq!(store.secret("alice").await.unwrap(), Some(alice));
+        assert_eq!(store.secret("bob").await.unwrap(), Some(bob));
+        assert_eq!(store.secret("carol").await.unwrap(), None);
+
+        // Operators provision users by hand, with comments and blank lines.
+        std::fs::write(&path, "# provisioned\n\ncarol:JBSWY3DPEHPK3PXP\n").unwrap();
+        let store = FileTotpStore::open(&path).unwrap();
+        assert_eq!(
+            store.secret("carol").await.unwrap(),
+            Some(b"Hello!\xDE\xAD\xBE\xEF".to_vec())
+        );
+        assert!(store.set_secret("eve\nmallory", b"x").await.is_err());
+
+        std::fs::write(&path, "carol:not base32!\n").unwrap();
+        assert!(FileTotpStore::open(&path).err().unwrap().contains("line 1"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
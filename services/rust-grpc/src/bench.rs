@@ -1,46 +1,801 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use std::time::Instant;
+use crate::clock::ClockSource;
+use hdrhistogram::serialization::interval_log::IntervalLogWriterBuilder;
+use hdrhistogram::serialization::V2DeflateSerializer;
+use hdrhistogram::Histogram;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+/// TSC ticks are timed against CLOCK_MONOTONIC over this window to get the
+/// tick rate, then over a second window to check the rate holds.
+const CALIBRATION_WINDOW: Duration = Duration::from_millis(50);
+
+/// Maximum disagreement with CLOCK_MONOTONIC before the TSC is rejected.
+const MAX_DRIFT_PPM: f64 = 100.0;
+
+/// Fixed-point nanoseconds per tick, scaled by 2^32.
+struct Tsc {
+    epoch: u64,
+    ns_per_tick: u64,
+}
+
+impl Tsc {
+    #[inline(always)]
+    fn ticks_to_ns(&self, ticks: u64) -> i64 {
+        ((ticks as u128 * self.ns_per_tick as u128) >> 32) as i64
+    }
+}
+
+static TSC: OnceLock<Tsc> = OnceLock::new();
 
 /// Returns current monotonic time in nanoseconds.
-/// Uses `Instant` which is backed by clock_gettime(CLOCK_MONOTONIC) on Linux
-/// and mach_absolute_time on macOS -- sub-microsecond resolution.
+/// Uses `Instant` which is backed by clock_gettime(CLOCK_MONOTONIC) on Linux,
+/// mach_absolute_time on macOS and QueryPerformanceCounter on Windows,
+/// or the TSC once `enable_tsc` has succeeded.
 #[inline(always)]
 pub fn now_ns() -> i64 {
+    if let Some(tsc) = TSC.get() {
+        return tsc.ticks_to_ns(rdtsc().wrapping_sub(tsc.epoch));
+    }
     // We use a process-local epoch to keep values small and avoid overflow.
-    static EPOCH: std::sync::OnceLock<Instant> = std::sync::OnceLock::new();
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
     let epoch = EPOCH.get_or_init(Instant::now);
     epoch.elapsed().as_nanos() as i64
 }
 
+/// Switch `now_ns` to reading the TSC directly (a single rdtsc instead of
+/// a vDSO clock_gettime call). Only allowed on x86_64 CPUs that advertise
+/// an invariant TSC, and only if its rate, calibrated against
+/// CLOCK_MONOTONIC, holds over a second window. Call before serving:
+/// readings taken before and after the switch are not comparable. Returns
+/// the measured drift in ppm.
+pub fn enable_tsc() -> Result<f64, String> {
+    if !invariant_tsc() {
+        return Err("CPU does not advertise an invariant TSC".to_string());
+    }
+    let (ticks, mono) = sample_tsc();
+    if ticks == 0 {
+        return Err("TSC did not advance during calibration".to_string());
+    }
+    let tsc = Tsc {
+        epoch: 0,
+        ns_per_tick: ((mono << 32) / ticks as u128) as u64,
+    };
+    let (ticks, mono) = sample_tsc();
+    let predicted = tsc.ticks_to_ns(ticks) as f64;
+    let drift_ppm = ((predicted - mono as f64) / mono as f64 * 1e6).abs();
+    if drift_ppm > MAX_DRIFT_PPM {
+        return Err(format!(
+            "TSC disagrees with CLOCK_MONOTONIC by {:.0} ppm",
+            drift_ppm
+        ));
+    }
+    TSC.set(Tsc {
+        epoch: rdtsc(),
+        ..tsc
+    })
+    .map_err(|_| "TSC clock already enabled".to_string())?;
+    Ok(drift_ppm)
+}
+
+/// TSC ticks and CLOCK_MONOTONIC nanoseconds elapsed over one
+/// calibration window.
+fn sample_tsc() -> (u64, u128) {
+    let (m0, t0) = (Instant::now(), rdtsc());
+    std::thread::sleep(CALIBRATION_WINDOW);
+    let (m1, t1) = (Instant::now(), rdtsc());
+    (t1.wrapping_sub(t0), m1.duration_since(m0).as_nanos())
+}
+
+/// Name of the clock behind `now_ns`, reported alongside results.
+pub fn clock_source() -> &'static str {
+    if TSC.get().is_some() {
+        "tsc"
+    } else {
+        "monotonic"
+    }
+}
+
+/// CPUID 0x8000_0007 EDX bit 8: the TSC ticks at a constant rate across
+/// P-states and C-states and is synchronized across cores.
+#[cfg(target_arch = "x86_64")]
+fn invariant_tsc() -> bool {
+    use std::arch::x86_64::__cpuid;
+    let max_extended = __cpuid(0x8000_0000).eax;
+    max_extended >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn invariant_tsc() -> bool {
+    false
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+fn rdtsc() -> u64 {
+    // SAFETY: rdtsc is part of the x86_64 baseline and has no side effects.
+    unsafe { std::arch::x86_64::_rdtsc() }
+}
+
+/// Never reached: `enable_tsc` fails before the TSC is selected.
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(always)]
+fn rdtsc() -> u64 {
+    0
+}
+
+/// Smallest nonzero step between consecutive `now_ns()` reads. Linux and
+/// macOS tick in nanoseconds; QueryPerformanceCounter's frequency depends
+/// on the host (commonly 10 MHz, so 100ns), and latencies below one tick
+/// read as 0.
+pub fn clock_resolution_ns() -> i64 {
+    let mut best = i64::MAX;
+    for _ in 0..1_000 {
+        let t0 = now_ns();
+        let mut t1 = now_ns();
+        while t1 == t0 {
+            t1 = now_ns();
+        }
+        best = best.min(t1 - t0);
+    }
+    best
+}
+
+/// Cost of one `clock.now_ns()` call: the median gap between back-to-back
+/// reads. Every benchmark sample includes this once, which matters when
+/// the work being timed is itself only tens of nanoseconds.
+pub fn timer_overhead_ns(clock: &dyn ClockSource) -> i64 {
+    const SAMPLES: usize = 1_001;
+    let mut gaps: Vec<i64> = (0..SAMPLES)
+        .map(|_| {
+            let t0 = clock.now_ns();
+            clock.now_ns() - t0
+        })
+        .collect();
+    gaps.sort_unstable();
+    gaps[SAMPLES / 2]
+}
+
+/// Summary of a set of latencies. Stats of separate sets (tasks,
+/// connections, peers) combine with `merge` without their samples: count,
+/// min, max and mean stay exact, while percentiles of merged stats come
+/// from a histogram kept alongside and are within 0.1% of a sample near
+/// the true rank.
+#[derive(Clone, Debug)]
 pub struct Stats {
+    pub count: u64,
     pub min: i64,
     pub max: i64,
     pub mean: i64,
     pub p50: i64,
     pub p99: i64,
+    // Wide enough that no i64 samples can overflow it.
+    sum: i128,
+    /// Negative samples are recorded as 0.
+    histogram: Histogram<u64>,
+}
+
+impl Default for Stats {
+    /// Stats of no samples, all zero; the identity for `merge`.
+    fn default() -> Self {
+        Stats {
+            count: 0,
+            min: 0,
+            max: 0,
+            mean: 0,
+            p50: 0,
+            p99: 0,
+            sum: 0,
+            histogram: Histogram::new(3).expect("3 significant figures is in range"),
+        }
+    }
 }
 
 impl Stats {
     /// Compute stats from a pre-sorted slice of latency values.
     pub fn from_sorted(sorted: &[i64]) -> Self {
+        let mut stats = Stats::default();
         if sorted.is_empty() {
-            return Stats {
-                min: 0,
-                max: 0,
-                mean: 0,
-                p50: 0,
-                p99: 0,
-            };
+            return stats;
+        }
+        for &x in sorted {
+            record(&mut stats.histogram, x);
         }
         let n = sorted.len();
-        let sum: i64 = sorted.iter().sum();
-        Stats {
-            min: sorted[0],
-            max: sorted[n - 1],
-            mean: sum / n as i64,
-            p50: sorted[n / 2],
-            p99: sorted[(n as f64 * 0.99) as usize],
+        stats.count = n as u64;
+        stats.sum = sorted.iter().map(|&x| i128::from(x)).sum();
+        stats.min = sorted[0];
+        stats.max = sorted[n - 1];
+        stats.mean = (stats.sum / n as i128) as i64;
+        stats.p50 = percentile(sorted, 50.0);
+        stats.p99 = percentile(sorted, 99.0);
+        stats
+    }
+
+    /// Adds `other`'s samples to these stats.
+    pub fn merge(&mut self, other: &Stats) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+        self.histogram
+            .add(&other.histogram)
+            .expect("auto-resizing histograms accept any values");
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.mean = (self.sum / i128::from(self.count)) as i64;
+        self.p50 = self.percentile(50.0);
+        self.p99 = self.percentile(99.0);
+    }
+
+    /// Value at percentile `p` (0..=100), from the histogram; see `Stats`
+    /// for its precision. Use the free `percentile` where the sorted
+    /// samples are at hand.
+    pub fn percentile(&self, p: f64) -> i64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let value = self
+            .histogram
+            .value_at_quantile(p.clamp(0.0, 100.0) / 100.0);
+        (value.min(i64::MAX as u64) as i64).clamp(self.min, self.max)
+    }
+}
+
+/// Largest value an auto-resizing histogram can grow to hold.
+const HISTOGRAM_MAX: u64 = i64::MAX as u64 / 2;
+
+/// Records `ns` in a histogram made with `Histogram::new`, growing it as
+/// needed; negative values count as 0. (`saturating_record` would clamp
+/// to the histogram's current range rather than grow it.)
+fn record(histogram: &mut Histogram<u64>, ns: i64) {
+    let value = (ns.max(0) as u64).min(HISTOGRAM_MAX);
+    histogram
+        .record(value)
+        .expect("auto-resizing histograms grow to HISTOGRAM_MAX");
+}
+
+/// Percentiles reported when a request doesn't ask for specific ones.
+pub const DEFAULT_PERCENTILES: &[f64] = &[50.0, 90.0, 99.0, 99.9, 99.99];
+
+/// Value at percentile `p` (0..=100) of a pre-sorted slice, interpolating
+/// linearly between the two closest ranks (the method NumPy and
+/// PERCENTILE.INC use). Unlike indexing at `n * p`, this neither rounds a
+/// small sample's p99 up to its maximum nor jumps between samples as `n`
+/// changes. `p` counts to 9 decimal places.
+pub fn percentile(sorted: &[i64], p: f64) -> i64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    // The rank in fixed point, so one that falls exactly on a sample is
+    // exact; in f64 it can come out just below and interpolate towards
+    // the sample before.
+    const SCALE: u128 = 100 * 1_000_000_000;
+    let p = (p.clamp(0.0, 100.0) * 1e9).round() as u128;
+    let rank = p * (sorted.len() - 1) as u128;
+    let lo = (rank / SCALE) as usize;
+    let hi = (lo + 1).min(sorted.len() - 1);
+    let frac = (rank % SCALE) as f64 / SCALE as f64;
+    // In i128, as the gap between two samples can exceed i64::MAX.
+    let gap = (i128::from(sorted[hi]) - i128::from(sorted[lo])) as f64;
+    (i128::from(sorted[lo]) + (gap * frac).round() as i128) as i64
+}
+
+/// Merges two sorted sample sets into one sorted set, in linear time, so
+/// samples gathered apart (per task, per connection, per peer) can be
+/// combined for `Stats::from_sorted` and `percentile` without sorting
+/// them all again.
+pub fn merge_sorted(a: &[i64], b: &[i64]) -> Vec<i64> {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if b[j] < a[i] {
+            out.push(b[j]);
+            j += 1;
+        } else {
+            out.push(a[i]);
+            i += 1;
+        }
+    }
+    out.extend_from_slice(&a[i..]);
+    out.extend_from_slice(&b[j..]);
+    out
+}
+
+/// Slow samples whose modified z-score, `(x - median) / (MAD / 0.6745)`,
+/// exceeds `threshold` (Iglewicz and Hoaglin suggest 3.5). When more than
+/// half the samples are identical the MAD is 0, so the scaled mean absolute
+/// deviation stands in for it. Returns `(index, score)` in sample order.
+pub fn outliers(samples: &[i64], threshold: f64) -> Vec<(usize, f64)> {
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    let median = median_f64(&sorted.iter().map(|&x| x as f64).collect::<Vec<_>>());
+    let mut deviations: Vec<f64> = samples.iter().map(|&x| (x as f64 - median).abs()).collect();
+    deviations.sort_unstable_by(f64::total_cmp);
+    let mad = median_f64(&deviations);
+    let scale = if mad > 0.0 {
+        mad / 0.6745
+    } else {
+        deviations.iter().sum::<f64>() / deviations.len() as f64 * 1.253314
+    };
+    if scale == 0.0 {
+        return Vec::new();
+    }
+    samples
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &x)| {
+            let score = (x as f64 - median) / scale;
+            (score > threshold).then_some((i, score))
+        })
+        .collect()
+}
+
+fn median_f64(sorted: &[f64]) -> f64 {
+    let n = sorted.len();
+    if n % 2 == 1 {
+        sorted[n / 2]
+    } else {
+        (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+    }
+}
+
+/// Samples from one slice of a benchmark run.
+pub struct Interval {
+    /// Offset from the start of the run.
+    pub start_ns: i64,
+    pub histogram: Histogram<u64>,
+}
+
+/// Group `(start_offset_ns, latency_ns)` samples, in run order, into
+/// consecutive `width`-long intervals by when each sample started, so a
+/// periodic stall shows up where it happened instead of only moving the
+/// aggregate p99. Intervals in which no sample started are skipped, and
+/// samples after the first `limit` intervals are left out, as each
+/// interval costs a histogram.
+pub fn intervals(samples: &[(i64, i64)], width: Duration, limit: usize) -> Vec<Interval> {
+    let width_ns = width.as_nanos().clamp(1, i64::MAX as u128) as i64;
+    let mut out: Vec<Interval> = Vec::new();
+    for &(offset, latency) in samples {
+        let start_ns = offset.max(0) / width_ns * width_ns;
+        if out.last().is_none_or(|i| i.start_ns != start_ns) {
+            if out.len() == limit {
+                break;
+            }
+            out.push(Interval {
+                start_ns,
+                histogram: Histogram::new(3).expect("3 significant figures is in range"),
+            });
+        }
+        let current = out.last_mut().expect("pushed above if missing");
+        record(&mut current.histogram, latency);
+    }
+    out
+}
+
+/// Render intervals as an HdrHistogram interval log with `run_start` as
+/// its BaseTime.
+pub fn interval_log(
+    intervals: &[Interval],
+    width: Duration,
+    run_start: SystemTime,
+) -> Result<String, String> {
+    let mut buf = Vec::new();
+    let mut serializer = V2DeflateSerializer::new();
+    let mut log = IntervalLogWriterBuilder::new()
+        .add_comment("hermit benchmark latencies in nanoseconds")
+        .with_start_time(run_start)
+        .with_base_time(run_start)
+        .begin_log_with(&mut buf, &mut serializer)
+        .map_err(|e| e.to_string())?;
+    for interval in intervals {
+        log.write_histogram(
+            &interval.histogram,
+            Duration::from_nanos(interval.start_ns as u64),
+            width,
+            None,
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    String::from_utf8(buf).map_err(|e| e.to_string())
+}
+
+/// What a benchmark payload is filled with, from most to least
+/// compressible. Entropy changes results once compression or TLS record
+/// packing is involved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PayloadContent {
+    /// 0xAB repeated; the default, and all payloads were before.
+    #[default]
+    Fixed,
+    Zeros,
+    /// Space-separated words from a small vocabulary, like log lines.
+    Text,
+    /// ASCII letters and digits, about 6 bits of entropy per byte.
+    Random,
+    /// Uniformly random bytes.
+    Incompressible,
+}
+
+impl PayloadContent {
+    pub fn name(self) -> &'static str {
+        match self {
+            PayloadContent::Fixed => "fixed",
+            PayloadContent::Zeros => "zeros",
+            PayloadContent::Text => "text",
+            PayloadContent::Random => "random",
+            PayloadContent::Incompressible => "incompressible",
+        }
+    }
+}
+
+impl std::str::FromStr for PayloadContent {
+    type Err = String;
+
+    /// An empty string is `Fixed`, so unset proto fields keep the old
+    /// payload.
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "" | "fixed" => Ok(PayloadContent::Fixed),
+            "zeros" => Ok(PayloadContent::Zeros),
+            "text" => Ok(PayloadContent::Text),
+            "random" => Ok(PayloadContent::Random),
+            "incompressible" => Ok(PayloadContent::Incompressible),
+            _ => Err(format!(
+                "unknown payload content {:?} (fixed, zeros, text, random, incompressible)",
+                s
+            )),
+        }
+    }
+}
+
+/// Seed of the pseudo-random payload kinds when a request doesn't pick one.
+pub const DEFAULT_SEED: u64 = 0x6865_726d_6974;
+
+/// `len` bytes of `content`. The pseudo-random kinds use a fixed seed, so
+/// the same request always gets the same payload and runs stay comparable.
+pub fn payload(content: PayloadContent, len: usize) -> Vec<u8> {
+    let mut out = vec![0; len];
+    fill_payload(content, &mut out);
+    out
+}
+
+/// Overwrites `buf` with the `payload` of its length, e.g. to fill a
+/// hugepage buffer.
+pub fn fill_payload(content: PayloadContent, buf: &mut [u8]) {
+    fill_payload_seeded(content, DEFAULT_SEED, buf)
+}
+
+/// `fill_payload` with the pseudo-random kinds drawn from `seed`.
+pub fn fill_payload_seeded(content: PayloadContent, seed: u64, buf: &mut [u8]) {
+    const WORDS: &[&[u8]] = &[
+        b"the",
+        b"request",
+        b"server",
+        b"latency",
+        b"of",
+        b"and",
+        b"client",
+        b"to",
+        b"error",
+        b"in",
+        b"connection",
+        b"a",
+        b"timeout",
+        b"ok",
+        b"for",
+        b"status",
+    ];
+    const ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+    // splitmix64: fast, and its output defeats general-purpose compressors.
+    let mut state = seed;
+    let mut next = move || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    match content {
+        PayloadContent::Fixed => buf.fill(0xAB),
+        PayloadContent::Zeros => buf.fill(0),
+        PayloadContent::Text => {
+            let mut pos = 0;
+            while pos < buf.len() {
+                let word = WORDS[next() as usize % WORDS.len()];
+                for &b in word.iter().chain(b" ") {
+                    if pos == buf.len() {
+                        break;
+                    }
+                    buf[pos] = b;
+                    pos += 1;
+                }
+            }
+        }
+        PayloadContent::Random => buf
+            .iter_mut()
+            .for_each(|b| *b = ALPHANUMERIC[next() as usize % ALPHANUMERIC.len()]),
+        PayloadContent::Incompressible => {
+            for chunk in buf.chunks_mut(8) {
+                chunk.copy_from_slice(&next().to_le_bytes()[..chunk.len()]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::time::Duration;
+
+    #[test]
+    fn stats_from_sorted() {
+        let sorted: Vec<i64> = (1..=100).collect();
+        let stats = Stats::from_sorted(&sorted);
+        assert_eq!(stats.min, 1);
+        assert_eq!(stats.max, 100);
+        assert_eq!(stats.mean, 50);
+        assert_eq!(stats.p50, 51);
+        assert_eq!(stats.p99, 99);
+    }
+
+    #[test]
+    fn percentile_interpolates_between_ranks() {
+        let sorted = [10, 20, 30, 40];
+        assert_eq!(percentile(&sorted, 0.0), 10);
+        assert_eq!(percentile(&sorted, 50.0), 25);
+        assert_eq!(percentile(&sorted, 90.0), 37);
+        assert_eq!(percentile(&sorted, 100.0), 40);
+        assert_eq!(percentile(&[7], 99.99), 7);
+        assert_eq!(percentile(&[], 50.0), 0);
+    }
+
+    #[test]
+    fn stats_of_nothing_are_zero() {
+        let stats = Stats::from_sorted(&[]);
+        assert_eq!((stats.min, stats.max, stats.mean), (0, 0, 0));
+        assert_eq!((stats.p50, stats.p99), (0, 0));
+    }
+
+    #[test]
+    fn merged_stats_cover_every_sample() {
+        let low: Vec<i64> = (1..=50).collect();
+        let high: Vec<i64> = (51..=100).map(|x| x * 1_000).collect();
+        let mut stats = Stats::default();
+        stats.merge(&Stats::from_sorted(&high));
+        stats.merge(&Stats::default());
+        stats.merge(&Stats::from_sorted(&low));
+        let exact = Stats::from_sorted(&merge_sorted(&low, &high));
+        assert_eq!((stats.count, stats.min, stats.max), (100, 1, 100_000));
+        assert_eq!(stats.mean, exact.mean);
+        assert_eq!(stats.p50, 50);
+        assert!((stats.p99 - 99_000).abs() <= 99, "p99 {}", stats.p99);
+        assert_eq!(stats.percentile(100.0), 100_000);
+    }
+
+    #[test]
+    fn outliers_flag_slow_spikes_only() {
+        let samples = [50, 52, 49, 51, 50, 48, 5_000, 50, 10, 53];
+        let flagged = outliers(&samples, 3.5);
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].0, 6);
+        assert!(flagged[0].1 > 1_000.0);
+    }
+
+    #[test]
+    fn outliers_fall_back_when_mad_is_zero() {
+        let samples = [50, 50, 50, 50, 50, 50, 900];
+        assert_eq!(
+            outliers(&samples, 3.5)
+                .iter()
+                .map(|o| o.0)
+                .collect::<Vec<_>>(),
+            vec![6]
+        );
+        assert!(outliers(&[50; 8], 3.5).is_empty());
+    }
+
+    #[test]
+    fn intervals_group_by_start_time() {
+        // A stall in the second 100ns slice.
+        let samples = [(0, 10), (40, 12), (120, 9_000_000), (350, 11), (390, 13)];
+        let intervals = intervals(&samples, Duration::from_nanos(100), 10);
+        let starts: Vec<i64> = intervals.iter().map(|i| i.start_ns).collect();
+        assert_eq!(starts, vec![0, 100, 300]);
+        let first_two = super::intervals(&samples, Duration::from_nanos(100), 2);
+        assert_eq!(first_two.len(), 2);
+        assert_eq!(first_two[1].histogram.len(), 1);
+        assert_eq!(intervals[0].histogram.len(), 2);
+        let stall = &intervals[1].histogram;
+        assert!(stall.equivalent(stall.max(), 9_000_000));
+        assert_eq!(intervals[2].histogram.len(), 2);
+
+        let log = interval_log(&intervals, Duration::from_nanos(100), SystemTime::now()).unwrap();
+        let lines: Vec<&str> = log.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn timer_overhead_is_gap_between_reads() {
+        let clock = MockClock::with_step(0, Duration::from_nanos(40));
+        assert_eq!(timer_overhead_ns(&clock), 40);
+    }
+
+    #[test]
+    fn payload_contents_differ_in_entropy() {
+        let distinct = |content| {
+            let p = payload(content, 64 * 1024);
+            assert_eq!(p.len(), 64 * 1024);
+            let mut seen = [false; 256];
+            for b in &p {
+                seen[*b as usize] = true;
+            }
+            seen.iter().filter(|&&s| s).count()
+        };
+        assert_eq!(distinct(PayloadContent::Fixed), 1);
+        assert_eq!(distinct(PayloadContent::Zeros), 1);
+        assert!(distinct(PayloadContent::Text) < 26);
+        assert_eq!(distinct(PayloadContent::Random), 62);
+        assert_eq!(distinct(PayloadContent::Incompressible), 256);
+
+        let text = payload(PayloadContent::Text, 100);
+        assert!(text.is_ascii());
+        assert_eq!(text, payload(PayloadContent::Text, 100));
+        let mut seeded = vec![0; 100];
+        fill_payload_seeded(PayloadContent::Text, 7, &mut seeded);
+        assert_ne!(text, seeded);
+        assert_eq!("".parse(), Ok(PayloadContent::Fixed));
+        assert!("noise".parse::<PayloadContent>().is_err());
+    }
+
+    /// Property tests against deliberately naive reference versions: exact
+    /// integer arithmetic, and concatenating then sorting instead of
+    /// merging.
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Sample sets that have broken statistics code before: a lone
+        /// sample, heavy duplication, and values spanning all of i64.
+        fn samples() -> impl Strategy<Value = Vec<i64>> {
+            prop_oneof![
+                any::<i64>().prop_map(|x| vec![x]),
+                prop::collection::vec(0i64..4, 1..200),
+                prop::collection::vec(0i64..10_000_000, 1..500),
+                prop::collection::vec(any::<i64>(), 1..200),
+                prop::collection::vec(prop::sample::select(vec![i64::MIN, 0, i64::MAX]), 1..50),
+            ]
+        }
+
+        fn sorted(mut samples: Vec<i64>) -> Vec<i64> {
+            samples.sort_unstable();
+            samples
+        }
+
+        /// `percentile` at `milli / 1000` percent, in exact rational
+        /// arithmetic, rounding half away from zero.
+        fn reference_percentile(sorted: &[i64], milli: u32) -> i64 {
+            const DEN: i128 = 100_000;
+            let num = i128::from(milli) * (sorted.len() as i128 - 1);
+            let (lo, rem) = ((num / DEN) as usize, num % DEN);
+            let hi = (lo + 1).min(sorted.len() - 1);
+            let gap = i128::from(sorted[hi]) - i128::from(sorted[lo]);
+            (i128::from(sorted[lo]) + (2 * gap * rem + DEN) / (2 * DEN)) as i64
+        }
+
+        fn reference_mean(samples: &[i64]) -> i64 {
+            let sum: i128 = samples.iter().map(|&x| i128::from(x)).sum();
+            (sum / samples.len() as i128) as i64
+        }
+
+        /// Float interpolation is off by a rounding step for small
+        /// ranges, and by a few ulps of the range for huge ones.
+        fn close(got: i64, want: i64, sorted: &[i64]) -> bool {
+            let range = (i128::from(sorted[sorted.len() - 1]) - i128::from(sorted[0])) as u128;
+            (i128::from(got) - i128::from(want)).unsigned_abs() <= 1 + (range >> 40)
+        }
+
+        /// Non-negative, like latencies, which is all the histogram behind
+        /// merged percentiles records faithfully.
+        fn latencies() -> impl Strategy<Value = Vec<i64>> {
+            prop_oneof![
+                (0i64..1 << 40).prop_map(|x| vec![x]),
+                prop::collection::vec(0i64..4, 1..200),
+                prop::collection::vec(0i64..1 << 40, 1..500),
+            ]
+        }
+
+        proptest! {
+            #[test]
+            fn stats_match_reference(samples in samples()) {
+                let sorted = sorted(samples);
+                let stats = Stats::from_sorted(&sorted);
+                prop_assert_eq!(stats.min, sorted[0]);
+                prop_assert_eq!(stats.max, sorted[sorted.len() - 1]);
+                prop_assert_eq!(stats.mean, reference_mean(&sorted));
+                prop_assert!(stats.min <= stats.mean && stats.mean <= stats.max);
+                let p50 = reference_percentile(&sorted, 50_000);
+                prop_assert!(close(stats.p50, p50, &sorted), "p50 {} != {}", stats.p50, p50);
+                let p99 = reference_percentile(&sorted, 99_000);
+                prop_assert!(close(stats.p99, p99, &sorted), "p99 {} != {}", stats.p99, p99);
+            }
+
+            #[test]
+            fn percentiles_match_reference_and_never_decrease(
+                samples in samples(),
+                a in 0u32..=100_000,
+                b in 0u32..=100_000,
+            ) {
+                let sorted = sorted(samples);
+                let (lo, hi) = (a.min(b), a.max(b));
+                let at = |milli: u32| percentile(&sorted, f64::from(milli) / 1000.0);
+                prop_assert!(close(at(lo), reference_percentile(&sorted, lo), &sorted));
+                prop_assert!(close(at(hi), reference_percentile(&sorted, hi), &sorted));
+                prop_assert!(at(lo) <= at(hi));
+                prop_assert_eq!(at(0), sorted[0]);
+                prop_assert_eq!(at(100_000), sorted[sorted.len() - 1]);
+                // Ranks that land exactly on a sample return it.
+                let n = sorted.len() as u32;
+                if n > 1 && 100_000 % (n - 1) == 0 {
+                    let k = (a as usize) % sorted.len();
+                    prop_assert_eq!(at(k as u32 * (100_000 / (n - 1))), sorted[k]);
+                }
+            }
+
+            #[test]
+            fn merging_matches_sorting_everything(a in samples(), b in samples()) {
+                let (a, b) = (sorted(a), sorted(b));
+                let merged = merge_sorted(&a, &b);
+                let everything = sorted(a.iter().chain(&b).copied().collect());
+                prop_assert_eq!(&merged, &everything);
+                prop_assert_eq!(&merge_sorted(&b, &a), &everything);
+                prop_assert_eq!(merge_sorted(&a, &[]), a.clone());
+                let stats = Stats::from_sorted(&merged);
+                prop_assert_eq!(stats.mean, reference_mean(&everything));
+                prop_assert_eq!(stats.p99, percentile(&everything, 99.0));
+            }
+
+            #[test]
+            fn merged_stats_match_stats_of_everything(
+                a in latencies(),
+                b in latencies(),
+                milli in 0u32..=100_000,
+            ) {
+                let (a, b) = (sorted(a), sorted(b));
+                let mut stats = Stats::from_sorted(&a);
+                stats.merge(&Stats::from_sorted(&b));
+                let everything = sorted(a.iter().chain(&b).copied().collect());
+                let n = everything.len();
+                prop_assert_eq!(stats.count, n as u64);
+                prop_assert_eq!(stats.min, everything[0]);
+                prop_assert_eq!(stats.max, everything[n - 1]);
+                prop_assert_eq!(stats.mean, reference_mean(&everything));
+
+                // The histogram answers with the sample at the nearest rank,
+                // give or take its 0.1% precision and a rank of float error.
+                for p in [50.0, 99.0, f64::from(milli) / 1000.0] {
+                    let rank = ((p / 100.0 * n as f64).ceil() as usize).clamp(1, n) - 1;
+                    let lo = everything[rank.saturating_sub(1)];
+                    let hi = everything[(rank + 1).min(n - 1)];
+                    let got = stats.percentile(p);
+                    prop_assert!(
+                        lo - lo / 1000 - 1 <= got && got <= hi + hi / 1000 + 1,
+                        "p{} = {}, want {}..={}", p, got, lo, hi
+                    );
+                }
+                prop_assert_eq!(stats.p50, stats.percentile(50.0));
+            }
         }
     }
 }
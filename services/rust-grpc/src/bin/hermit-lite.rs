@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+// Copyright (c) 2026 Jared Redh. All rights reserved.
+
+use clap::Parser;
+use hermit_server::{build_info, health, kernel, throughput, wakeup};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+/// The raw TCP listener without gRPC, for embedded and constrained
+/// deployments. Builds with `--no-default-features` (plaintext only) or
+/// `--no-default-features --features tls`.
+#[derive(Parser, Debug)]
+#[command(
+    name = "hermit-lite",
+    version,
+    about = "Hermit throughput/echo listener"
+)]
+struct Args {
+    /// Serve throughput and echo tests (the hermit-throughput/1 protocol)
+    /// on this port.
+    #[arg(short, long, default_value_t = 9090)]
+    port: u16,
+
+    /// TCP congestion control for test connections (reno, cubic, bbr;
+    /// Linux only). Tests can still ask for another per connection.
+    #[arg(long)]
+    congestion: Option<throughput::CongestionControl>,
+
+    /// Serve /healthz, /readyz and Prometheus /metrics over HTTP on this
+    /// port.
+    #[arg(long)]
+    health_port: Option<u16>,
+
+    /// After SIGTERM, fail readiness for this long before shutting down.
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    drain_grace: Duration,
+
+    /// How often to measure the runtime's wakeup latency for /metrics.
+    /// 0 disables.
+    #[arg(long, default_value = "100ms", value_parser = humantime::parse_duration)]
+    wakeup_probe_interval: Duration,
+
+    /// Serve over TLS with this PEM certificate (requires --tls-key).
+    #[cfg(feature = "tls")]
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+
+    /// PEM private key for --tls-cert.
+    #[cfg(feature = "tls")]
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "hermit_server=info,hermit_lite=info".into()),
+        )
+        .init();
+    let args = Args::parse();
+    let kernel = kernel::Support::probe();
+    info!(
+        version = build_info::VERSION,
+        features = ?build_info::FEATURES,
+        target = build_info::TARGET,
+        static_binary = build_info::STATIC,
+        io_uring = kernel.io_uring,
+        ktls = kernel.ktls,
+        "hermit-lite starting"
+    );
+
+    let health = Arc::new(health::Health::new());
+    if let Some(port) = args.health_port {
+        let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+        tokio::spawn(health::serve(listener, health.clone()));
+    }
+
+    let listener = std::net::TcpListener::bind(("0.0.0.0", args.port))?;
+    listener.set_nonblocking(true)?;
+    if let Some(cc) = args.congestion {
+        throughput::set_listener_congestion(&listener, cc)?;
+    }
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    #[cfg(feature = "tls")]
+    let acceptor = match (args.tls_cert, args.tls_key) {
+        (Some(cert), Some(key)) => {
+            use hermit_server::tls;
+            let _ = tls::crypto_provider(false)?.install_default();
+            let cfg = tls::resolve_tls_config(&tls::TlsSource::Files { cert, key }).await?;
+            Some(tokio_rustls::TlsAcceptor::from(cfg.server_config))
+        }
+        _ => None,
+    };
+    tokio::spawn(throughput::serve(
+        listener,
+        #[cfg(feature = "tls")]
+        acceptor,
+    ));
+    if !args.wakeup_probe_interval.is_zero() {
+        tokio::spawn(wakeup::probe(args.wakeup_probe_interval));
+    }
+    health.set_ready();
+
+    health::drain_on_signal(health, args.drain_grace).await;
+    info!("hermit-lite stopped");
+    Ok(())
+}
@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+// Copyright (c) 2026 Jared Redh. All rights reserved.
+
+use clap::Parser;
+use hermit_server::{build_info, health, proxy};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+/// A TCP proxy to put in front of another hermit, measuring what the
+/// proxy tier itself adds. Forwards bytes untouched, so gRPC, TLS and
+/// throughput tests all pass through to the upstream; compare results
+/// through the proxy with results direct, and see the proxy's share in
+/// its /metrics. Builds with `--no-default-features`.
+#[derive(Parser, Debug)]
+#[command(
+    name = "hermit-proxy",
+    version,
+    about = "Hermit forwarding proxy with per-hop timing"
+)]
+struct Args {
+    /// Accept connections on this port.
+    #[arg(short, long, default_value_t = 9190)]
+    port: u16,
+
+    /// The hermit to forward to, HOST:PORT.
+    #[arg(long)]
+    upstream: String,
+
+    /// Name this proxy gives itself in throughput tests' hop lists.
+    #[arg(long, default_value = "hermit-proxy")]
+    name: String,
+
+    /// Serve /healthz, /readyz and Prometheus /metrics over HTTP on this
+    /// port.
+    #[arg(long)]
+    health_port: Option<u16>,
+
+    /// After SIGTERM, fail readiness for this long before shutting down.
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    drain_grace: Duration,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "hermit_server=info,hermit_proxy=info".into()),
+        )
+        .init();
+    let args = Args::parse();
+    info!(
+        version = build_info::VERSION,
+        target = build_info::TARGET,
+        "hermit-proxy starting"
+    );
+
+    let health = Arc::new(health::Health::new());
+    if let Some(port) = args.health_port {
+        let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+        tokio::spawn(health::serve(listener, health.clone()));
+    }
+
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", args.port)).await?;
+    let config = Arc::new(proxy::Config {
+        upstream: args.upstream,
+        name: args.name,
+    });
+    tokio::spawn(proxy::serve(listener, config));
+    health.set_ready();
+
+    health::drain_on_signal(health, args.drain_grace).await;
+    info!("hermit-proxy stopped");
+    Ok(())
+}
@@ -0,0 +1,276 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::metrics::{BreakerMetrics, METRICS};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Calls go through; failures are counted.
+    Closed,
+    /// Calls are refused until `open_for` has passed.
+    Open,
+    /// A few probe calls go through; their outcome closes or reopens the
+    /// breaker.
+    HalfOpen,
+}
+
+impl State {
+    pub const ALL: [State; 3] = [State::Closed, State::HalfOpen, State::Open];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Closed => "closed",
+            State::Open => "open",
+            State::HalfOpen => "half-open",
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Consecutive failures that open the breaker.
+    pub failure_threshold: u32,
+    /// How long it stays open before probing.
+    pub open_for: Duration,
+    /// Probes allowed at once when half-open, all of which must succeed
+    /// to close it again.
+    pub half_open_probes: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            failure_threshold: 5,
+            open_for: Duration::from_secs(10),
+            half_open_probes: 1,
+        }
+    }
+}
+
+/// A circuit breaker for calls to one destination. After
+/// `failure_threshold` failures in a row it opens and refuses calls, so
+/// a dead peer costs nothing instead of a timeout per call; once
+/// `open_for` has passed it lets probes through and closes if they
+/// succeed. State changes are logged and exported as
+/// `hermit_circuit_breaker_*` metrics, labelled with the breaker's name.
+pub struct Breaker {
+    name: String,
+    config: Config,
+    inner: Mutex<Inner>,
+    metrics: Arc<BreakerMetrics>,
+}
+
+struct Inner {
+    state: State,
+    failures: u32,
+    opened_at: Instant,
+    probes_in_flight: u32,
+    probes_passed: u32,
+}
+
+/// Leave to make one call; report how it went with `success` or
+/// `failure`. Dropping it unreported (say, the call was cancelled)
+/// counts as neither.
+#[must_use]
+pub struct Permit<'a> {
+    breaker: &'a Breaker,
+    probe: bool,
+    reported: bool,
+}
+
+impl Breaker {
+    pub fn new(name: impl Into<String>, config: Config) -> Breaker {
+        let name = name.into();
+        Breaker {
+            metrics: METRICS.register_breaker(&name),
+            name,
+            config: Config {
+                failure_threshold: config.failure_threshold.max(1),
+                half_open_probes: config.half_open_probes.max(1),
+                ..config
+            },
+            inner: Mutex::new(Inner {
+                state: State::Closed,
+                failures: 0,
+                opened_at: Instant::now(),
+                probes_in_flight: 0,
+                probes_passed: 0,
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> State {
+        self.inner.lock().unwrap().state
+    }
+
+    /// A permit for one call, or how long until the breaker will next let
+    /// one through.
+    pub fn allow(&self) -> Result<Permit<'_>, Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == State::Open {
+            let elapsed = inner.opened_at.elapsed();
+            if elapsed < self.config.open_for {
+                self.metrics.rejected();
+                return Err(self.config.open_for - elapsed);
+            }
+            inner.probes_in_flight = 0;
+            inner.probes_passed = 0;
+            self.transition(&mut inner, State::HalfOpen);
+        }
+        if inner.state == State::HalfOpen {
+            if inner.probes_in_flight >= self.config.half_open_probes {
+                self.metrics.rejected();
+                return Err(Duration::ZERO);
+            }
+            inner.probes_in_flight += 1;
+        }
+        Ok(Permit {
+            breaker: self,
+            probe: inner.state == State::HalfOpen,
+            reported: false,
+        })
+    }
+
+    fn record(&self, probe: bool, ok: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            State::Closed if ok => inner.failures = 0,
+            State::Closed => {
+                inner.failures += 1;
+                if inner.failures >= self.config.failure_threshold {
+                    self.open(&mut inner);
+                }
+            }
+            // A permit taken before the state last changed.
+            _ if !probe => {}
+            State::HalfOpen if ok => {
+                inner.probes_in_flight = inner.probes_in_flight.saturating_sub(1);
+                inner.probes_passed += 1;
+                if inner.probes_passed >= self.config.half_open_probes {
+                    inner.failures = 0;
+                    self.transition(&mut inner, State::Closed);
+                }
+            }
+            State::HalfOpen => self.open(&mut inner),
+            State::Open => {}
+        }
+    }
+
+    fn release(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == State::HalfOpen {
+            inner.probes_in_flight = inner.probes_in_flight.saturating_sub(1);
+        }
+    }
+
+    fn open(&self, inner: &mut Inner) {
+        inner.opened_at = Instant::now();
+        self.transition(inner, State::Open);
+    }
+
+    fn transition(&self, inner: &mut Inner, to: State) {
+        let from = std::mem::replace(&mut inner.state, to);
+        self.metrics.transition(to);
+        match to {
+            State::Open => warn!(
+                breaker = %self.name,
+                %from,
+                failures = inner.failures,
+                "circuit breaker opened for {:?}",
+                self.config.open_for
+            ),
+            _ => info!(breaker = %self.name, %from, "circuit breaker {}", to),
+        }
+    }
+}
+
+impl Drop for Breaker {
+    fn drop(&mut self) {
+        METRICS.unregister_breaker(&self.metrics);
+    }
+}
+
+impl Permit<'_> {
+    pub fn success(mut self) {
+        self.reported = true;
+        self.breaker.record(self.probe, true);
+    }
+
+    pub fn failure(mut self) {
+        self.reported = true;
+        self.breaker.record(self.probe, false);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe && !self.reported {
+            self.breaker.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_probes_and_closes() {
+        let breaker = Breaker::new(
+            "test:probes",
+            Config {
+                failure_threshold: 2,
+                open_for: Duration::from_secs(5),
+                half_open_probes: 1,
+            },
+        );
+        breaker.allow().unwrap().failure();
+        breaker.allow().unwrap().success();
+        breaker.allow().unwrap().failure();
+        assert_eq!(breaker.state(), State::Closed);
+        breaker.allow().unwrap().failure();
+        assert_eq!(breaker.state(), State::Open);
+        assert_eq!(breaker.allow().err(), Some(Duration::from_secs(5)));
+
+        // Half-open: one probe at a time, and a failed one reopens.
+        tokio::time::advance(Duration::from_secs(5)).await;
+        let probe = breaker.allow().unwrap();
+        assert_eq!(breaker.state(), State::HalfOpen);
+        assert_eq!(breaker.allow().err(), Some(Duration::ZERO));
+        probe.failure();
+        assert_eq!(breaker.state(), State::Open);
+
+        // A cancelled probe frees its slot; a successful one closes.
+        tokio::time::advance(Duration::from_secs(5)).await;
+        drop(breaker.allow().unwrap());
+        breaker.allow().unwrap().success();
+        assert_eq!(breaker.state(), State::Closed);
+
+        let text = METRICS.render_prometheus();
+        for line in [
+            "hermit_circuit_breaker_state{breaker=\"test:probes\"} 0",
+            "hermit_circuit_breaker_transitions_total{breaker=\"test:probes\",to=\"open\"} 2",
+            "hermit_circuit_breaker_transitions_total{breaker=\"test:probes\",to=\"half-open\"} 2",
+            "hermit_circuit_breaker_transitions_total{breaker=\"test:probes\",to=\"closed\"} 1",
+            "hermit_circuit_breaker_rejected_total{breaker=\"test:probes\"} 2",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?}", line);
+        }
+        drop(breaker);
+        assert!(!METRICS.render_prometheus().contains("test:probes"));
+    }
+}
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Commit the binary was built from, or "unknown" outside a git checkout
+/// (set HERMIT_GIT_SHA at build time to override).
+pub const GIT_SHA: &str = env!("HERMIT_GIT_SHA");
+
+/// `rustc --version` of the compiler that built the binary.
+pub const RUSTC_VERSION: &str = env!("HERMIT_RUSTC_VERSION");
+
+/// Target triple, e.g. x86_64-unknown-linux-musl.
+pub const TARGET: &str = env!("HERMIT_TARGET");
+
+/// Whether the C runtime is linked statically (the default on musl), so
+/// the binary runs in an empty container.
+pub const STATIC: bool = cfg!(target_feature = "crt-static");
+
+/// Fingerprint (FNV-1a, hex) of proto/hermit.proto. Two builds speak the
+/// same schema exactly when these match.
+pub const PROTO_SCHEMA_VERSION: &str = env!("HERMIT_PROTO_SCHEMA");
+
+/// Optional cargo features compiled in.
+pub const FEATURES: &[&str] = &[
+    #[cfg(feature = "alloc-audit")]
+    "alloc-audit",
+    #[cfg(feature = "geoip")]
+    "geoip",
+    #[cfg(feature = "grpc")]
+    "grpc",
+    #[cfg(feature = "ldap")]
+    "ldap",
+    #[cfg(feature = "pq")]
+    "pq",
+    #[cfg(feature = "redis")]
+    "redis",
+    #[cfg(feature = "tls")]
+    "tls",
+];
+
+/// When the build script last ran, or SOURCE_DATE_EPOCH if it was set.
+pub fn built_at() -> SystemTime {
+    let secs = env!("HERMIT_BUILD_TIMESTAMP").parse().unwrap_or(0);
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Everything above as one JSON object, for `version --json`.
+pub fn json() -> serde_json::Value {
+    serde_json::json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": VERSION,
+        "git_sha": GIT_SHA,
+        "build_timestamp": humantime::format_rfc3339_seconds(built_at()).to_string(),
+        "rustc_version": RUSTC_VERSION,
+        "target": TARGET,
+        "static": STATIC,
+        "features": FEATURES,
+        "proto_schema_version": PROTO_SCHEMA_VERSION,
+    })
+}
@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::bench;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where handlers and the timing layer read nanosecond timestamps from.
+/// Production uses `SystemClock`; tests inject a `MockClock` so timestamp
+/// math and stats can be checked exactly. RPC deadlines run on tokio's
+/// timer instead, which tests control with a paused runtime.
+pub trait ClockSource: Send + Sync {
+    fn now_ns(&self) -> i64;
+
+    /// Wall-clock nanoseconds since the Unix epoch. Only comparable
+    /// across hosts to the extent their clocks are synchronized; see
+    /// `sync_status`.
+    fn realtime_ns(&self) -> i64;
+
+    /// Reported as `clock_source` in responses.
+    fn name(&self) -> &'static str;
+}
+
+/// `bench::now_ns`: CLOCK_MONOTONIC or, once enabled, the TSC.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl ClockSource for SystemClock {
+    #[inline(always)]
+    fn now_ns(&self) -> i64 {
+        bench::now_ns()
+    }
+
+    fn realtime_ns(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as i64)
+    }
+
+    fn name(&self) -> &'static str {
+        bench::clock_source()
+    }
+}
+
+/// A clock that only moves when told to. Each read returns the current
+/// value and then advances it by `step`, so back-to-back reads are exactly
+/// `step` apart; with a step of 0 time stands still until `advance`.
+/// Wall-clock reads see the same value but don't step it.
+#[derive(Debug)]
+pub struct MockClock {
+    now: AtomicI64,
+    step: i64,
+}
+
+impl MockClock {
+    pub fn new(start_ns: i64) -> Self {
+        Self::with_step(start_ns, Duration::ZERO)
+    }
+
+    pub fn with_step(start_ns: i64, step: Duration) -> Self {
+        MockClock {
+            now: AtomicI64::new(start_ns),
+            step: step.as_nanos() as i64,
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.fetch_add(by.as_nanos() as i64, Ordering::SeqCst);
+    }
+
+    pub fn set(&self, ns: i64) {
+        self.now.store(ns, Ordering::SeqCst);
+    }
+}
+
+impl ClockSource for MockClock {
+    fn now_ns(&self) -> i64 {
+        self.now.fetch_add(self.step, Ordering::SeqCst)
+    }
+
+    fn realtime_ns(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+}
+
+/// How closely the kernel wall clock is disciplined by NTP or PTP, which
+/// bounds the error of any one-way delay computed from it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyncStatus {
+    pub synchronized: bool,
+    pub max_error: Duration,
+    pub est_error: Duration,
+}
+
+/// Read-only `adjtimex`; `None` where unsupported.
+#[cfg(target_os = "linux")]
+pub fn sync_status() -> Option<SyncStatus> {
+    // SAFETY: timex is plain integers, so all-zero is a valid value, and
+    // modes = 0 makes adjtimex only read the kernel state into it.
+    let mut tx: libc::timex = unsafe { std::mem::zeroed() };
+    // SAFETY: `tx` is valid for reads and writes for the call.
+    let state = unsafe { libc::adjtimex(&mut tx) };
+    if state < 0 {
+        return None;
+    }
+    Some(SyncStatus {
+        synchronized: state != libc::TIME_ERROR && tx.status & libc::STA_UNSYNC == 0,
+        max_error: Duration::from_micros(tx.maxerror.max(0) as u64),
+        est_error: Duration::from_micros(tx.esterror.max(0) as u64),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sync_status() -> Option<SyncStatus> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock_steps_per_read() {
+        let clock = MockClock::with_step(1_000, Duration::from_nanos(25));
+        assert_eq!(clock.now_ns(), 1_000);
+        assert_eq!(clock.now_ns(), 1_025);
+        clock.advance(Duration::from_micros(1));
+        assert_eq!(clock.now_ns(), 2_050);
+        clock.set(7);
+        assert_eq!(clock.now_ns(), 7);
+    }
+
+    #[test]
+    fn mock_clock_without_step_stands_still() {
+        let clock = MockClock::new(42);
+        assert_eq!(clock.now_ns(), 42);
+        assert_eq!(clock.now_ns(), 42);
+    }
+}
@@ -31,6 +31,12 @@ pub struct QueryResult {
     pub pending_writes: u64,
 }
 
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Database {
     pub fn new() -> Self {
         Database {
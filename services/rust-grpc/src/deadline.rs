@@ -0,0 +1,205 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tonic::body::BoxBody;
+use tonic::codegen::http;
+use tonic::Status;
+use tower::{Layer, Service};
+use tracing::warn;
+
+/// Server-side time limits per RPC, applied whether or not the client sent
+/// a `grpc-timeout`. Methods are keyed by their bare name (`Ping`), so the
+/// table works across services.
+#[derive(Clone, Debug)]
+pub struct Deadlines {
+    default: Duration,
+    per_method: HashMap<String, Duration>,
+    /// Fleet-wide limits from etcd, which win over the rest. Shared by
+    /// every clone, so they can change while serving.
+    fleet: Arc<RwLock<HashMap<String, Duration>>>,
+}
+
+impl Deadlines {
+    /// Built-in limits: quick probes are short, Benchmark may legitimately
+    /// run for a while, everything else gets `default`.
+    pub fn new(default: Duration) -> Self {
+        let per_method = [
+            ("Ping", Duration::from_secs(1)),
+            ("ServerInfo", Duration::from_secs(5)),
+            ("GetCertInfo", Duration::from_secs(5)),
+            ("Capabilities", Duration::from_secs(5)),
+            ("GetSchema", Duration::from_secs(5)),
+            ("Benchmark", Duration::from_secs(120)),
+            ("ReplayBenchmark", Duration::from_secs(120)),
+        ]
+        .into_iter()
+        .map(|(m, d)| (m.to_string(), d))
+        .collect();
+        Deadlines {
+            default,
+            per_method,
+            fleet: Arc::default(),
+        }
+    }
+
+    pub fn set(&mut self, method: String, limit: Duration) {
+        self.per_method.insert(method, limit);
+    }
+
+    /// Replace the fleet-wide limits.
+    pub fn set_fleet(&self, limits: HashMap<String, Duration>) {
+        *self.fleet.write().unwrap() = limits;
+    }
+
+    /// `path` is the HTTP/2 `:path`, e.g. `/hermit.Hermit/Ping`.
+    pub(crate) fn for_path(&self, path: &str) -> Duration {
+        let method = path.rsplit('/').next().unwrap_or(path);
+        if let Some(&limit) = self.fleet.read().unwrap().get(method) {
+            return limit;
+        }
+        self.per_method.get(method).copied().unwrap_or(self.default)
+    }
+}
+
+/// Parse a `--rpc-timeout` value of the form `Method=duration`.
+pub fn parse_override(s: &str) -> Result<(String, Duration), String> {
+    let (method, limit) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected METHOD=DURATION, got {:?}", s))?;
+    if method.is_empty() {
+        return Err("method name is empty".to_string());
+    }
+    let limit = humantime::parse_duration(limit).map_err(|e| e.to_string())?;
+    Ok((method.to_string(), limit))
+}
+
+/// Cancels the handler once its method's limit elapses and answers
+/// DEADLINE_EXCEEDED. Unary handlers run inside the response future, so
+/// dropping it stops the work.
+#[derive(Clone, Debug)]
+pub struct DeadlineLayer {
+    deadlines: Arc<Deadlines>,
+}
+
+impl DeadlineLayer {
+    pub fn new(deadlines: Deadlines) -> Self {
+        DeadlineLayer {
+            deadlines: Arc::new(deadlines),
+        }
+    }
+}
+
+impl<S> Layer<S> for DeadlineLayer {
+    type Service = DeadlineService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DeadlineService {
+            inner,
+            deadlines: self.deadlines.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DeadlineService<S> {
+    inner: S,
+    deadlines: Arc<Deadlines>,
+}
+
+impl<S, B> Service<http::Request<B>> for DeadlineService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let path = req.uri().path().to_string();
+        let limit = self.deadlines.for_path(&path);
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            match tokio::time::timeout(limit, fut).await {
+                Ok(resp) => resp,
+                Err(_) => {
+                    warn!(%path, ?limit, "RPC exceeded server deadline");
+                    Ok(Status::deadline_exceeded(format!(
+                        "server deadline of {:?} exceeded",
+                        limit
+                    ))
+                    .into_http())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    fn request(path: &str) -> http::Request<()> {
+        http::Request::builder().uri(path).body(()).unwrap()
+    }
+
+    /// A handler that takes `delay` of (tokio) time to answer OK.
+    fn slow(
+        delay: Duration,
+    ) -> impl Service<
+        http::Request<()>,
+        Response = http::Response<BoxBody>,
+        Error = std::convert::Infallible,
+        Future = impl Send + 'static,
+    > + Clone {
+        tower::service_fn(move |_req: http::Request<()>| async move {
+            tokio::time::sleep(delay).await;
+            Ok(http::Response::new(tonic::body::empty_body()))
+        })
+    }
+
+    fn grpc_status(resp: &http::Response<BoxBody>) -> Option<&str> {
+        resp.headers()
+            .get("grpc-status")
+            .and_then(|v| v.to_str().ok())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_handler_gets_deadline_exceeded() {
+        let svc = DeadlineLayer::new(Deadlines::new(Duration::from_secs(30)))
+            .layer(slow(Duration::from_secs(2)));
+        let resp = svc.oneshot(request("/hermit.Hermit/Ping")).await.unwrap();
+        assert_eq!(grpc_status(&resp), Some("4"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handler_within_limit_completes() {
+        let svc = DeadlineLayer::new(Deadlines::new(Duration::from_secs(30)))
+            .layer(slow(Duration::from_secs(2)));
+        let resp = svc.oneshot(request("/hermit.Hermit/KvGet")).await.unwrap();
+        assert_eq!(grpc_status(&resp), None);
+    }
+
+    #[test]
+    fn override_replaces_builtin_limit() {
+        let mut deadlines = Deadlines::new(Duration::from_secs(30));
+        let (method, limit) = parse_override("Ping=250ms").unwrap();
+        deadlines.set(method, limit);
+        assert_eq!(
+            deadlines.for_path("/hermit.Hermit/Ping"),
+            Duration::from_millis(250)
+        );
+        assert!(parse_override("=1s").is_err());
+        assert!(parse_override("Ping").is_err());
+    }
+}
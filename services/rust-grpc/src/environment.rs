@@ -0,0 +1,293 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::numa;
+use std::net::IpAddr;
+
+/// Host settings that change what a benchmark measures, captured with
+/// each run so results from different machines (or the same machine
+/// after a tuning change) aren't compared blindly. Fields the platform
+/// doesn't expose are left empty; only Linux fills any in.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    /// `uname -r`.
+    pub kernel_release: String,
+    /// cpufreq scaling governor of CPU 0, e.g. "performance".
+    pub cpu_governor: String,
+    /// Whether turbo/boost frequencies are allowed; `None` if the
+    /// frequency driver doesn't say.
+    pub turbo: Option<bool>,
+    pub nic: Option<Nic>,
+    pub cgroup: Option<CgroupLimits>,
+    /// NUMA nodes with CPUs; 0 when unknown.
+    pub numa_nodes: u32,
+    /// Node of the CPU the snapshot was taken on.
+    pub numa_node: Option<u32>,
+    /// Node the server's threads are pinned to (--numa-node).
+    pub pinned_numa_node: Option<u32>,
+}
+
+/// The interface a connection arrived on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Nic {
+    pub name: String,
+    /// Kernel driver, e.g. "ixgbe"; empty for virtual interfaces.
+    pub driver: String,
+    /// Configured descriptor ring sizes; 0 if the driver doesn't report
+    /// them.
+    pub rx_ring: u32,
+    pub tx_ring: u32,
+}
+
+/// Limits of the cgroup this process runs in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CgroupLimits {
+    /// 1 or 2.
+    pub version: u8,
+    /// CPU quota in CPUs (quota / period); `None` if unlimited.
+    pub cpus: Option<f64>,
+    /// `None` if unlimited.
+    pub memory_bytes: Option<u64>,
+}
+
+/// The host as it is now; `local` is the address a benchmark's
+/// connection arrived on, to find its NIC.
+#[cfg(target_os = "linux")]
+pub fn snapshot(local: Option<IpAddr>) -> Snapshot {
+    let read = |path: &str| {
+        std::fs::read_to_string(path)
+            .map(|s| s.trim().to_string())
+            .ok()
+    };
+    let turbo = match read("/sys/devices/system/cpu/intel_pstate/no_turbo") {
+        Some(no_turbo) => Some(no_turbo == "0"),
+        None => read("/sys/devices/system/cpu/cpufreq/boost").map(|boost| boost == "1"),
+    };
+    Snapshot {
+        kernel_release: read("/proc/sys/kernel/osrelease").unwrap_or_default(),
+        cpu_governor: read("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+            .unwrap_or_default(),
+        turbo,
+        nic: local.and_then(interface_of).map(nic),
+        cgroup: cgroup_limits(),
+        numa_nodes: numa::nodes().len() as u32,
+        numa_node: numa::current_node(),
+        pinned_numa_node: numa::pinned(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn snapshot(_local: Option<IpAddr>) -> Snapshot {
+    Snapshot::default()
+}
+
+/// The cgroup limits of this process, if it's in a cgroup that has any
+/// controllers mounted. On hybrid hosts, where the unified hierarchy is
+/// empty, the v1 controllers are used.
+#[cfg(target_os = "linux")]
+pub fn cgroup_limits() -> Option<CgroupLimits> {
+    let membership = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    // Inside a cgroup namespace the mount is already our cgroup and the
+    // listed path may not resolve under it; fall back to the mount root.
+    let dir = |mount: &str, controllers: &str| {
+        let path = membership.lines().find_map(|l| {
+            let (_, rest) = l.split_once(':')?;
+            let (names, path) = rest.split_once(':')?;
+            (names.split(',').any(|n| n == controllers)).then_some(path)
+        })?;
+        [format!("{}{}", mount, path), mount.to_string()]
+            .into_iter()
+            .find(|d| std::path::Path::new(d).is_dir())
+    };
+    let read = |dir: &Option<String>, file: &str| {
+        std::fs::read_to_string(format!("{}/{}", dir.as_ref()?, file)).ok()
+    };
+
+    let unified = dir("/sys/fs/cgroup", "")
+        .filter(|d| std::path::Path::new(&format!("{}/cgroup.controllers", d)).exists());
+    if unified.is_some() {
+        return Some(CgroupLimits {
+            version: 2,
+            cpus: read(&unified, "cpu.max").and_then(|s| parse_cpu_max(&s)),
+            memory_bytes: read(&unified, "memory.max").and_then(|s| parse_limit(&s)),
+        });
+    }
+    let cpu = dir("/sys/fs/cgroup/cpu", "cpu");
+    let memory = dir("/sys/fs/cgroup/memory", "memory");
+    if cpu.is_none() && memory.is_none() {
+        return None;
+    }
+    let cpus = read(&cpu, "cpu.cfs_quota_us")
+        .zip(read(&cpu, "cpu.cfs_period_us"))
+        .and_then(|(quota, period)| parse_cpu_max(&format!("{} {}", quota.trim(), period)));
+    Some(CgroupLimits {
+        version: 1,
+        cpus,
+        memory_bytes: read(&memory, "memory.limit_in_bytes").and_then(|s| parse_limit(&s)),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn cgroup_limits() -> Option<CgroupLimits> {
+    None
+}
+
+/// Threads that can run at once without exceeding the CPU quota of
+/// `limits`: the quota rounded up, at most the number of CPUs.
+pub fn usable_cpus(limits: Option<CgroupLimits>) -> usize {
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    limits
+        .and_then(|l| l.cpus)
+        .map_or(cpus, |quota| (quota.ceil() as usize).clamp(1, cpus))
+}
+
+/// `cpu.max` ("QUOTA PERIOD", QUOTA "max" when unlimited) in CPUs. v1's
+/// quota is -1 when unlimited.
+fn parse_cpu_max(s: &str) -> Option<f64> {
+    let mut fields = s.split_whitespace();
+    let quota: i64 = fields.next()?.parse().ok()?;
+    let period: i64 = fields.next()?.parse().ok()?;
+    (quota > 0 && period > 0).then(|| quota as f64 / period as f64)
+}
+
+/// A byte limit, "max" or (v1) a page-rounded i64::MAX when unlimited.
+fn parse_limit(s: &str) -> Option<u64> {
+    let limit: u64 = s.trim().parse().ok()?;
+    (limit < 1 << 62).then_some(limit)
+}
+
+/// Name of the interface that has `ip`.
+#[cfg(target_os = "linux")]
+fn interface_of(ip: IpAddr) -> Option<String> {
+    let ip = ip.to_canonical();
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs stores a list it allocated in `addrs`, which is
+    // freed below.
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return None;
+    }
+    let mut found = None;
+    let mut cur = addrs;
+    while !cur.is_null() {
+        // SAFETY: `cur` is a node of the list getifaddrs returned.
+        let ifa = unsafe { &*cur };
+        // SAFETY: ifa_addr, when set, points at a sockaddr of the family
+        // it names, which is all the closure reads it as.
+        let addr = unsafe { ifa.ifa_addr.as_ref() }.and_then(|sa| unsafe {
+            match sa.sa_family as i32 {
+                libc::AF_INET => {
+                    let sin = &*(sa as *const libc::sockaddr).cast::<libc::sockaddr_in>();
+                    Some(IpAddr::from(sin.sin_addr.s_addr.to_ne_bytes()))
+                }
+                libc::AF_INET6 => {
+                    let sin6 = &*(sa as *const libc::sockaddr).cast::<libc::sockaddr_in6>();
+                    Some(IpAddr::from(sin6.sin6_addr.s6_addr))
+                }
+                _ => None,
+            }
+        });
+        if addr == Some(ip) {
+            // SAFETY: ifa_name is a NUL-terminated string owned by the list.
+            let name = unsafe { std::ffi::CStr::from_ptr(ifa.ifa_name) };
+            found = Some(name.to_string_lossy().into_owned());
+            break;
+        }
+        cur = ifa.ifa_next;
+    }
+    // SAFETY: `addrs` came from getifaddrs and isn't used after this.
+    unsafe { libc::freeifaddrs(addrs) };
+    found
+}
+
+#[cfg(target_os = "linux")]
+fn nic(name: String) -> Nic {
+    let driver = std::fs::read_link(format!("/sys/class/net/{}/device/driver", name))
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    let (rx_ring, tx_ring) = ring_sizes(&name).unwrap_or_default();
+    Nic {
+        name,
+        driver,
+        rx_ring,
+        tx_ring,
+    }
+}
+
+/// Configured RX and TX ring sizes, as `ethtool -g` shows them.
+#[cfg(target_os = "linux")]
+fn ring_sizes(name: &str) -> Option<(u32, u32)> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    const ETHTOOL_GRINGPARAM: u32 = 0x10;
+    /// struct ethtool_ringparam.
+    #[repr(C)]
+    #[derive(Default)]
+    struct RingParam {
+        cmd: u32,
+        rx_max_pending: u32,
+        rx_mini_max_pending: u32,
+        rx_jumbo_max_pending: u32,
+        tx_max_pending: u32,
+        rx_pending: u32,
+        rx_mini_pending: u32,
+        rx_jumbo_pending: u32,
+        tx_pending: u32,
+    }
+
+    if name.len() >= libc::IFNAMSIZ {
+        return None;
+    }
+    // SAFETY: socket takes no pointers.
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return None;
+    }
+    // SAFETY: `fd` is a socket we just created and own.
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut ring = RingParam {
+        cmd: ETHTOOL_GRINGPARAM,
+        ..Default::default()
+    };
+    // SAFETY: ifreq is plain data, so all-zero is a valid value.
+    let mut ifr: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, &src) in ifr.ifr_name.iter_mut().zip(name.as_bytes()) {
+        *dst = src as libc::c_char;
+    }
+    ifr.ifr_ifru.ifru_data = (&mut ring as *mut RingParam).cast();
+    // SAFETY: SIOCETHTOOL reads the command from, and writes the result
+    // to, `ring`, which outlives the call. The request type is c_ulong on
+    // glibc but c_int on musl, hence the cast.
+    let rc = unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCETHTOOL as _, &mut ifr) };
+    (rc == 0).then_some((ring.rx_pending, ring.tx_pending))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cgroup_limits() {
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max("250000 100000\n"), Some(2.5));
+        assert_eq!(parse_cpu_max("-1 100000"), None);
+        assert_eq!(parse_limit("max\n"), None);
+        assert_eq!(parse_limit("536870912\n"), Some(512 << 20));
+        assert_eq!(parse_limit("9223372036854771712"), None);
+    }
+
+    #[test]
+    fn usable_cpus_rounds_quota_up() {
+        let cpus = std::thread::available_parallelism().unwrap().get();
+        let quota = |cpus| {
+            Some(CgroupLimits {
+                version: 2,
+                cpus,
+                memory_bytes: None,
+            })
+        };
+        assert_eq!(usable_cpus(None), cpus);
+        assert_eq!(usable_cpus(quota(None)), cpus);
+        assert_eq!(usable_cpus(quota(Some(0.5))), 1);
+        assert_eq!(usable_cpus(quota(Some(1e6))), cpus);
+    }
+}
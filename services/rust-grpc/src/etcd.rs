@@ -0,0 +1,399 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::deadline::Deadlines;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Lease on our registry entry; it disappears this long after we stop
+/// renewing it.
+const LEASE_TTL_SECS: u64 = 10;
+
+/// Wait before retrying after etcd fails or a watch ends.
+const RETRY: Duration = Duration::from_secs(1);
+
+/// Config key family for per-RPC server time limits, as with
+/// --rpc-timeout: `<prefix>config/rpc-timeout/Ping` = `500ms`.
+const RPC_TIMEOUT_KEYS: &str = "config/rpc-timeout/";
+
+/// A hermit instance in the registry.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisteredPeer {
+    pub region: String,
+    pub grpc_addr: String,
+    pub version: String,
+}
+
+/// Fleet-wide state in etcd under one key prefix: `peers/` holds an entry
+/// per running instance, tied to a lease so it vanishes when the instance
+/// does, and `config/` holds settings every instance applies as they
+/// change. Only `config/rpc-timeout/<Method>` is interpreted so far; it
+/// overrides --rpc-timeout and the built-in limits on every instance.
+///
+/// Talks to etcd's v3 JSON gateway, so it needs nothing beyond HTTP;
+/// client certificate auth isn't supported.
+pub struct Registry {
+    http: reqwest::Client,
+    endpoint: String,
+    prefix: String,
+    me: RegisteredPeer,
+    peers: Mutex<BTreeMap<String, RegisteredPeer>>,
+    config: Mutex<BTreeMap<String, String>>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    #[serde(default)]
+    revision: String,
+}
+
+#[derive(Deserialize)]
+struct KeyValue {
+    key: String,
+    #[serde(default)]
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct RangeResponse {
+    header: Header,
+    #[serde(default)]
+    kvs: Vec<KeyValue>,
+}
+
+#[derive(Deserialize)]
+struct LeaseResponse {
+    #[serde(rename = "ID", default)]
+    id: String,
+    #[serde(rename = "TTL", default)]
+    ttl: String,
+}
+
+#[derive(Deserialize)]
+struct KeepAliveResponse {
+    result: Option<LeaseResponse>,
+}
+
+#[derive(Deserialize)]
+struct WatchMessage {
+    result: Option<WatchResult>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct WatchResult {
+    #[serde(default)]
+    events: Vec<Event>,
+    #[serde(default)]
+    canceled: bool,
+}
+
+#[derive(Deserialize)]
+struct Event {
+    /// Absent for PUT, the enum's zero value.
+    #[serde(rename = "type", default)]
+    kind: String,
+    kv: KeyValue,
+}
+
+impl Registry {
+    /// `endpoint` is an etcd client URL, e.g. http://127.0.0.1:2379.
+    pub fn new(endpoint: &str, prefix: &str, me: RegisteredPeer) -> Result<Registry, String> {
+        if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+            return Err(format!(
+                "etcd endpoint {:?} must be an http(s) URL",
+                endpoint
+            ));
+        }
+        Ok(Registry {
+            http: reqwest::Client::new(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            prefix: prefix.to_string(),
+            me,
+            peers: Mutex::new(BTreeMap::new()),
+            config: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// This instance's entry.
+    pub fn local(&self) -> &RegisteredPeer {
+        &self.me
+    }
+
+    /// Registered instances, including ourselves once registered.
+    pub fn peers(&self) -> Vec<RegisteredPeer> {
+        self.peers.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Keeps our entry registered and `deadlines` in step with the fleet
+    /// config, in the background.
+    pub fn start(self: Arc<Self>, deadlines: Deadlines) {
+        info!(endpoint = %self.endpoint, prefix = %self.prefix, "etcd registry starting");
+        let registrar = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = registrar.register().await {
+                    warn!("etcd registration failed: {}", e);
+                }
+                tokio::time::sleep(RETRY).await;
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = self.sync(&deadlines).await {
+                    warn!("etcd watch failed: {}", e);
+                }
+                tokio::time::sleep(RETRY).await;
+            }
+        });
+    }
+
+    /// Puts our entry under a fresh lease and renews it until renewing
+    /// fails.
+    async fn register(&self) -> Result<(), String> {
+        let lease: LeaseResponse = self
+            .call("lease/grant", json!({ "TTL": LEASE_TTL_SECS }))
+            .await?;
+        let value = serde_json::to_string(&self.me).map_err(|e| e.to_string())?;
+        let key = format!("{}peers/{}", self.prefix, self.me.grpc_addr);
+        let _: serde_json::Value = self
+            .call(
+                "kv/put",
+                json!({ "key": BASE64.encode(&key), "value": BASE64.encode(value), "lease": lease.id }),
+            )
+            .await?;
+        info!(%key, "registered in etcd");
+        loop {
+            tokio::time::sleep(Duration::from_secs(LEASE_TTL_SECS / 3)).await;
+            let renewed: KeepAliveResponse = self
+                .call("lease/keepalive", json!({ "ID": lease.id }))
+                .await?;
+            if renewed
+                .result
+                .is_none_or(|r| r.ttl.is_empty() || r.ttl == "0")
+            {
+                return Err("lease expired".to_string());
+            }
+        }
+    }
+
+    /// Loads everything under the prefix, then applies changes as they're
+    /// watched, until the watch ends.
+    async fn sync(&self, deadlines: &Deadlines) -> Result<(), String> {
+        let range_end = prefix_end(&self.prefix);
+        let all: RangeResponse = self
+            .call(
+                "kv/range",
+                json!({ "key": BASE64.encode(&self.prefix), "range_end": BASE64.encode(&range_end) }),
+            )
+            .await?;
+        self.peers.lock().unwrap().clear();
+        self.config.lock().unwrap().clear();
+        for kv in &all.kvs {
+            self.apply("PUT", kv);
+        }
+        self.apply_config(deadlines);
+        let revision: i64 = all.header.revision.parse().unwrap_or(0);
+
+        let watch = json!({ "create_request": {
+            "key": BASE64.encode(&self.prefix),
+            "range_end": BASE64.encode(&range_end),
+            "start_revision": (revision + 1).to_string(),
+        }});
+        let mut resp = self
+            .http
+            .post(format!("{}/v3/watch", self.endpoint))
+            .json(&watch)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("watch: {}", e))?;
+        // The gateway streams one JSON object per line.
+        let mut buf = Vec::new();
+        while let Some(chunk) = resp.chunk().await.map_err(|e| format!("watch: {}", e))? {
+            buf.extend_from_slice(&chunk);
+            while let Some(end) = buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=end).collect();
+                if line.trim_ascii().is_empty() {
+                    continue;
+                }
+                let msg: WatchMessage = serde_json::from_slice(&line)
+                    .map_err(|e| format!("decode watch event: {}", e))?;
+                if let Some(e) = msg.error {
+                    return Err(format!("watch: {}", e));
+                }
+                let Some(result) = msg.result else { continue };
+                if result.canceled {
+                    return Err("watch canceled".to_string());
+                }
+                let mut config_changed = false;
+                for event in &result.events {
+                    config_changed |= self.apply(&event.kind, &event.kv);
+                }
+                if config_changed {
+                    self.apply_config(deadlines);
+                }
+            }
+        }
+        Err("watch ended".to_string())
+    }
+
+    /// Applies one PUT or DELETE; true if it was a config key.
+    fn apply(&self, kind: &str, kv: &KeyValue) -> bool {
+        let decode = |s: &str| {
+            BASE64
+                .decode(s)
+                .ok()
+                .and_then(|b| String::from_utf8(b).ok())
+        };
+        let Some(key) = decode(&kv.key) else {
+            return false;
+        };
+        let Some(rest) = key.strip_prefix(&self.prefix) else {
+            return false;
+        };
+        let deleted = kind == "DELETE";
+        if let Some(name) = rest.strip_prefix("peers/") {
+            let mut peers = self.peers.lock().unwrap();
+            if deleted {
+                if peers.remove(name).is_some() {
+                    info!(peer = %name, "etcd peer gone");
+                }
+                return false;
+            }
+            match decode(&kv.value).map(|v| serde_json::from_str::<RegisteredPeer>(&v)) {
+                Some(Ok(peer)) => {
+                    if peers.insert(name.to_string(), peer).is_none() {
+                        info!(peer = %name, "etcd peer registered");
+                    }
+                }
+                _ => debug!(%key, "ignoring malformed peer entry"),
+            }
+            false
+        } else if let Some(name) = rest.strip_prefix("config/") {
+            let mut config = self.config.lock().unwrap();
+            let key = format!("config/{}", name);
+            match decode(&kv.value) {
+                Some(value) if !deleted => {
+                    config.insert(key, value);
+                }
+                _ => {
+                    config.remove(&key);
+                }
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Pushes the rpc-timeout keys to `deadlines`.
+    fn apply_config(&self, deadlines: &Deadlines) {
+        let config = self.config.lock().unwrap();
+        let limits = rpc_timeouts(&config);
+        info!(
+            overrides = limits.len(),
+            "applied fleet RPC timeouts from etcd"
+        );
+        deadlines.set_fleet(limits);
+    }
+
+    async fn call<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        body: serde_json::Value,
+    ) -> Result<T, String> {
+        self.http
+            .post(format!("{}/v3/{}", self.endpoint, method))
+            .json(&body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("etcd {}: {}", method, e))?
+            .json::<T>()
+            .await
+            .map_err(|e| format!("decode etcd {}: {}", method, e))
+    }
+}
+
+/// Per-method limits from `config/rpc-timeout/<Method>` keys; values that
+/// don't parse as durations are skipped with a warning.
+fn rpc_timeouts(config: &BTreeMap<String, String>) -> HashMap<String, Duration> {
+    config
+        .iter()
+        .filter_map(|(key, value)| {
+            let method = key.strip_prefix(RPC_TIMEOUT_KEYS)?;
+            match humantime::parse_duration(value.trim()) {
+                Ok(limit) => Some((method.to_string(), limit)),
+                Err(e) => {
+                    warn!(%key, "ignoring fleet RPC timeout {:?}: {}", value, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// The range_end that selects every key starting with `prefix`.
+fn prefix_end(prefix: &str) -> Vec<u8> {
+    let mut end = prefix.as_bytes().to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    // All 0xff (or empty): to the end of the keyspace.
+    vec![0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_watched_peers_and_config() {
+        let me = RegisteredPeer {
+            region: "us-west1".to_string(),
+            grpc_addr: "https://10.0.0.1:50051".to_string(),
+            version: "test".to_string(),
+        };
+        let registry = Registry::new("http://127.0.0.1:2379", "/hermit/", me.clone()).unwrap();
+        let kv = |key: &str, value: &str| KeyValue {
+            key: BASE64.encode(key),
+            value: BASE64.encode(value),
+        };
+        let entry = serde_json::to_string(&me).unwrap();
+        registry.apply("", &kv("/hermit/peers/https://10.0.0.1:50051", &entry));
+        registry.apply("", &kv("/hermit/config/rpc-timeout/Ping", "250ms"));
+        registry.apply("", &kv("/hermit/config/rpc-timeout/Benchmark", "soon"));
+        registry.apply("", &kv("/other/config/rpc-timeout/Login", "1s"));
+        assert_eq!(registry.peers(), vec![me]);
+
+        let deadlines = Deadlines::new(Duration::from_secs(30));
+        registry.apply_config(&deadlines);
+        assert_eq!(
+            deadlines.for_path("/hermit.Hermit/Ping"),
+            Duration::from_millis(250)
+        );
+        assert_eq!(
+            deadlines.for_path("/hermit.Hermit/Benchmark"),
+            Duration::from_secs(120)
+        );
+
+        registry.apply("DELETE", &kv("/hermit/peers/https://10.0.0.1:50051", ""));
+        registry.apply("DELETE", &kv("/hermit/config/rpc-timeout/Ping", ""));
+        registry.apply_config(&deadlines);
+        assert!(registry.peers().is_empty());
+        assert_eq!(
+            deadlines.for_path("/hermit.Hermit/Ping"),
+            Duration::from_secs(1)
+        );
+        assert_eq!(prefix_end("/hermit/"), b"/hermit0".to_vec());
+    }
+}
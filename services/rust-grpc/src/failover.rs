@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::health::Health;
+use crate::hermit::hermit_client::HermitClient;
+use crate::hermit::{HaPair, ServerInfoRequest};
+use prost_types::Timestamp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::time::MissedTickBehavior;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+use tracing::{debug, info, warn};
+
+/// Which half of an active/standby pair this instance is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Always serves; only watches the standby to report on it.
+    Active,
+    /// Fails readiness while the active answers, and takes over when it
+    /// stops.
+    Standby,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Active => "active",
+            Role::Standby => "standby",
+        }
+    }
+}
+
+/// One half of a pair of hermit instances behind the same virtual
+/// address. Each checks the other with ServerInfo; a peer that doesn't
+/// answer, or is draining, for `failure_threshold` checks in a row is
+/// down, and one that answers that many in a row is up again.
+///
+/// The standby takes traffic by passing readiness, so whatever routes to
+/// the pair (a load balancer, a keepalived check script) moves the address
+/// over. It stands down again once the active is back, rather than both
+/// serving. With only two members there is no quorum: a partition between
+/// them looks like a failure to the standby, and both serve until it
+/// heals.
+pub struct Pair {
+    role: Role,
+    peer: String,
+    client: HermitClient<Channel>,
+    failure_threshold: u32,
+    state: Mutex<State>,
+}
+
+struct State {
+    // Unknown until `failure_threshold` checks agree.
+    peer_healthy: Option<bool>,
+    serving: bool,
+    failovers: u64,
+    changed_at: SystemTime,
+    last_error: String,
+    // The latest check and how many in a row have had its outcome.
+    last_check: Option<bool>,
+    streak: u32,
+}
+
+impl Pair {
+    /// `peer` is the other instance's gRPC address, http://HOST:PORT or
+    /// https://HOST:PORT trusting the PEM `ca_cert`. A check that takes
+    /// longer than `timeout` counts as failed.
+    pub fn new(
+        role: Role,
+        peer: &str,
+        ca_cert: Option<&str>,
+        timeout: Duration,
+        failure_threshold: u32,
+    ) -> Result<Pair, String> {
+        let mut endpoint = Endpoint::from_shared(peer.to_string())
+            .map_err(|e| format!("HA peer address {:?}: {}", peer, e))?
+            .connect_timeout(timeout)
+            .timeout(timeout);
+        if peer.starts_with("https://") {
+            let path = ca_cert.ok_or("an https HA peer requires --ha-peer-ca-cert")?;
+            let pem = std::fs::read(path).map_err(|e| format!("read {}: {}", path, e))?;
+            let tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(pem));
+            endpoint = endpoint.tls_config(tls).map_err(|e| e.to_string())?;
+        }
+        Ok(Pair {
+            role,
+            peer: peer.to_string(),
+            client: HermitClient::new(endpoint.connect_lazy()),
+            failure_threshold: failure_threshold.max(1),
+            state: Mutex::new(State {
+                peer_healthy: None,
+                serving: role == Role::Active,
+                failovers: 0,
+                changed_at: SystemTime::now(),
+                last_error: String::new(),
+                last_check: None,
+                streak: 0,
+            }),
+        })
+    }
+
+    /// Holds `health` in standby if this is the standby, then checks the
+    /// peer every `interval` in the background.
+    pub fn start(self: Arc<Self>, health: Arc<Health>, interval: Duration) {
+        health.set_standby(self.role == Role::Standby);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let check = self.check().await;
+                if let Some(serving) = self.observe(check) {
+                    health.set_standby(!serving);
+                }
+            }
+        });
+    }
+
+    /// The pair as reported in ServerInfo.
+    pub fn snapshot(&self) -> HaPair {
+        let st = self.state.lock().unwrap();
+        HaPair {
+            role: self.role.name().to_string(),
+            peer: self.peer.clone(),
+            peer_healthy: st.peer_healthy == Some(true),
+            serving: st.serving,
+            failovers: st.failovers,
+            changed_at: Some(Timestamp::from(st.changed_at)),
+            last_check_error: st.last_error.clone(),
+        }
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        let info = self
+            .client
+            .clone()
+            .server_info(ServerInfoRequest {})
+            .await
+            .map_err(|s| s.message().to_string())?
+            .into_inner();
+        if info.draining {
+            return Err("peer is draining".to_string());
+        }
+        Ok(())
+    }
+
+    /// Records one check of the peer. Returns whether this instance should
+    /// now serve, when that changed.
+    fn observe(&self, check: Result<(), String>) -> Option<bool> {
+        let mut st = self.state.lock().unwrap();
+        let healthy = check.is_ok();
+        match check {
+            Ok(()) => st.last_error.clear(),
+            Err(e) => {
+                debug!(peer = %self.peer, "HA peer check failed: {}", e);
+                st.last_error = e;
+            }
+        }
+        if st.last_check == Some(healthy) {
+            st.streak += 1;
+        } else {
+            st.last_check = Some(healthy);
+            st.streak = 1;
+        }
+        if st.streak < self.failure_threshold || st.peer_healthy == Some(healthy) {
+            return None;
+        }
+        st.peer_healthy = Some(healthy);
+        if healthy {
+            info!(peer = %self.peer, "HA peer is up");
+        } else {
+            warn!(peer = %self.peer, "HA peer is down: {}", st.last_error);
+        }
+        // The standby serves exactly when the active doesn't.
+        if self.role == Role::Active || st.serving != healthy {
+            return None;
+        }
+        st.serving = !healthy;
+        st.changed_at = SystemTime::now();
+        if st.serving {
+            st.failovers += 1;
+            warn!(peer = %self.peer, "active is down, standby taking over");
+        } else {
+            info!(peer = %self.peer, "active is back, returning to standby");
+        }
+        Some(st.serving)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(role: Role) -> Pair {
+        Pair::new(role, "http://127.0.0.1:1", None, Duration::from_secs(1), 2).unwrap()
+    }
+
+    #[tokio::test]
+    async fn standby_takes_over_after_consecutive_failures() {
+        let standby = pair(Role::Standby);
+        let down = || Err("connection refused".to_string());
+        assert_eq!(standby.observe(down()), None);
+        assert_eq!(standby.observe(Ok(())), None);
+        assert_eq!(standby.observe(Ok(())), None);
+        assert!(standby.snapshot().peer_healthy);
+        assert!(!standby.snapshot().serving);
+
+        assert_eq!(standby.observe(down()), None);
+        assert_eq!(standby.observe(Ok(())), None);
+        assert_eq!(standby.observe(down()), None);
+        assert_eq!(standby.observe(down()), Some(true));
+        let info = standby.snapshot();
+        assert!(info.serving && !info.peer_healthy);
+        assert_eq!(info.failovers, 1);
+        assert_eq!(info.last_check_error, "connection refused");
+
+        assert_eq!(standby.observe(Ok(())), None);
+        assert_eq!(standby.observe(Ok(())), Some(false));
+        assert!(!standby.snapshot().serving);
+
+        // A standby whose active never came up takes over too.
+        let alone = pair(Role::Standby);
+        assert_eq!(alone.observe(down()), None);
+        assert_eq!(alone.observe(down()), Some(true));
+
+        let active = pair(Role::Active);
+        assert_eq!(active.observe(down()), None);
+        assert_eq!(active.observe(down()), None);
+        assert!(active.snapshot().serving);
+        assert_eq!(active.snapshot().failovers, 0);
+    }
+}
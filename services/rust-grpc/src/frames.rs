@@ -0,0 +1,439 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Payload bytes of each frame kept for its hex dump and decoding.
+const DUMP_BYTES: usize = 64;
+
+/// Frames logged per second across all connections. Turning tracing on
+/// for a busy server shouldn't flood its log; frames over the limit are
+/// counted and the count logged once the second is up.
+const FRAMES_PER_SEC: u32 = 100;
+
+const FRAME_HEADER_LEN: usize = 9;
+
+/// "PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n", which starts every client's side
+/// and isn't a frame.
+const CLIENT_PREFACE_LEN: usize = 24;
+
+static WINDOW: Mutex<Window> = Mutex::new(Window {
+    start: None,
+    logged: 0,
+    suppressed: 0,
+});
+
+struct Window {
+    start: Option<Instant>,
+    logged: u32,
+    suppressed: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// From the client.
+    Recv,
+    Send,
+}
+
+impl Direction {
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::Recv => "recv",
+            Direction::Send => "send",
+        }
+    }
+}
+
+/// Logs the first frames each way of one HTTP/2 connection, for
+/// `--trace-frames`: when each began (since the connection was accepted),
+/// its decoded header and well-known payload fields, and a hex dump of
+/// the header and the start of the payload. Fed the plaintext bytes, so
+/// TLS connections are traced after decryption.
+pub struct FrameTrace {
+    peer: SocketAddr,
+    accepted: Instant,
+    recv: Parser,
+    send: Parser,
+}
+
+impl FrameTrace {
+    /// Traces up to `limit` frames each way.
+    pub fn new(peer: SocketAddr, accepted: Instant, limit: usize) -> FrameTrace {
+        FrameTrace {
+            peer,
+            accepted,
+            recv: Parser::new(limit, CLIENT_PREFACE_LEN),
+            send: Parser::new(limit, 0),
+        }
+    }
+
+    /// Bytes that just went `direction` on the connection.
+    pub fn observe(&mut self, direction: Direction, data: &[u8]) {
+        let at = self.accepted.elapsed();
+        let parser = match direction {
+            Direction::Recv => &mut self.recv,
+            Direction::Send => &mut self.send,
+        };
+        for frame in parser.feed(data, at) {
+            if !admit() {
+                continue;
+            }
+            info!(
+                peer = %self.peer,
+                direction = direction.name(),
+                frame = frame.index,
+                at_us = frame.at.as_micros() as u64,
+                "{}\n{}",
+                frame.describe(),
+                frame.hex_dump()
+            );
+        }
+    }
+
+    /// Whether both directions have had all their frames traced.
+    pub fn done(&self) -> bool {
+        self.recv.done() && self.send.done()
+    }
+}
+
+/// Whether another frame may be logged this second.
+fn admit() -> bool {
+    let Ok(mut window) = WINDOW.lock() else {
+        return false;
+    };
+    let now = Instant::now();
+    if window
+        .start
+        .is_none_or(|s| now.duration_since(s) >= Duration::from_secs(1))
+    {
+        if window.suppressed > 0 {
+            info!(
+                "--trace-frames skipped {} frames over its limit of {}/s",
+                window.suppressed, FRAMES_PER_SEC
+            );
+        }
+        *window = Window {
+            start: Some(now),
+            logged: 0,
+            suppressed: 0,
+        };
+    }
+    if window.logged < FRAMES_PER_SEC {
+        window.logged += 1;
+        true
+    } else {
+        window.suppressed += 1;
+        false
+    }
+}
+
+/// Splits one direction's bytes into frames, keeping only the start of
+/// each payload so large DATA frames cost nothing to skip.
+struct Parser {
+    limit: usize,
+    /// Preface bytes still to skip.
+    preface: usize,
+    header: Vec<u8>,
+    frame: Option<Frame>,
+    /// Frames completed so far.
+    seen: usize,
+}
+
+impl Parser {
+    fn new(limit: usize, preface: usize) -> Parser {
+        Parser {
+            limit,
+            preface,
+            header: Vec::with_capacity(FRAME_HEADER_LEN),
+            frame: None,
+            seen: 0,
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.seen >= self.limit
+    }
+
+    /// The frames `data` completes, up to the limit; `at` is when it
+    /// arrived.
+    fn feed(&mut self, mut data: &[u8], at: Duration) -> Vec<Frame> {
+        let mut complete = Vec::new();
+        let skip = self.preface.min(data.len());
+        self.preface -= skip;
+        data = &data[skip..];
+        while !data.is_empty() && !self.done() {
+            let Some(frame) = &mut self.frame else {
+                let take = (FRAME_HEADER_LEN - self.header.len()).min(data.len());
+                self.header.extend_from_slice(&data[..take]);
+                data = &data[take..];
+                if self.header.len() == FRAME_HEADER_LEN {
+                    let header: [u8; FRAME_HEADER_LEN] = self.header[..].try_into().unwrap();
+                    self.header.clear();
+                    self.frame = Some(Frame::new(header, self.seen + 1, at));
+                }
+                if let Some(frame) = self.frame.take_if(|f| f.remaining == 0) {
+                    self.seen += 1;
+                    complete.push(frame);
+                }
+                continue;
+            };
+            let take = frame.remaining.min(data.len());
+            let keep = (DUMP_BYTES - frame.payload.len()).min(take);
+            frame.payload.extend_from_slice(&data[..keep]);
+            frame.remaining -= take;
+            data = &data[take..];
+            if frame.remaining == 0 {
+                self.seen += 1;
+                complete.extend(self.frame.take());
+            }
+        }
+        complete
+    }
+}
+
+/// One traced frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Frame {
+    header: [u8; FRAME_HEADER_LEN],
+    length: usize,
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    /// Up to `DUMP_BYTES` of the payload.
+    payload: Vec<u8>,
+    /// Payload bytes still to come.
+    remaining: usize,
+    /// Counting from 1 in its direction.
+    index: usize,
+    /// Since the connection was accepted, when the frame began.
+    at: Duration,
+}
+
+impl Frame {
+    fn new(header: [u8; FRAME_HEADER_LEN], index: usize, at: Duration) -> Frame {
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        Frame {
+            header,
+            length,
+            kind: header[3],
+            flags: header[4],
+            stream: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff,
+            payload: Vec::new(),
+            remaining: length,
+            index,
+            at,
+        }
+    }
+
+    /// e.g. "SETTINGS len=12 stream=0 flags=0x00 MAX_CONCURRENT_STREAMS=100
+    /// INITIAL_WINDOW_SIZE=1048576"
+    fn describe(&self) -> String {
+        let p = &self.payload;
+        let u32_at = |i: usize| {
+            p.get(i..i + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let (name, flag_names): (&str, &[(u8, &str)]) = match self.kind {
+            0 => ("DATA", &[(0x1, "END_STREAM"), (0x8, "PADDED")]),
+            1 => (
+                "HEADERS",
+                &[
+                    (0x1, "END_STREAM"),
+                    (0x4, "END_HEADERS"),
+                    (0x8, "PADDED"),
+                    (0x20, "PRIORITY"),
+                ],
+            ),
+            2 => ("PRIORITY", &[]),
+            3 => ("RST_STREAM", &[]),
+            4 => ("SETTINGS", &[(0x1, "ACK")]),
+            5 => ("PUSH_PROMISE", &[(0x4, "END_HEADERS"), (0x8, "PADDED")]),
+            6 => ("PING", &[(0x1, "ACK")]),
+            7 => ("GOAWAY", &[]),
+            8 => ("WINDOW_UPDATE", &[]),
+            9 => ("CONTINUATION", &[(0x4, "END_HEADERS")]),
+            _ => ("UNKNOWN", &[]),
+        };
+        let flags: Vec<&str> = flag_names
+            .iter()
+            .filter(|(bit, _)| self.flags & bit != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        let mut text = format!(
+            "{} len={} stream={} flags={}",
+            name,
+            self.length,
+            self.stream,
+            if flags.is_empty() {
+                format!("{:#04x}", self.flags)
+            } else {
+                flags.join("|")
+            }
+        );
+        match self.kind {
+            0x1 | 0x5 | 0x9 => {
+                let _ = write!(text, " (HPACK-encoded)");
+            }
+            0x3 => {
+                if let Some(code) = u32_at(0) {
+                    let _ = write!(text, " error={}", error_name(code));
+                }
+            }
+            0x4 => {
+                for setting in p.chunks_exact(6) {
+                    let id = u16::from_be_bytes([setting[0], setting[1]]);
+                    let value =
+                        u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+                    let _ = write!(text, " {}={}", setting_name(id), value);
+                }
+            }
+            0x6 => {
+                let _ = write!(text, " opaque={}", hex(p));
+            }
+            0x7 => {
+                if let (Some(last), Some(code)) = (u32_at(0), u32_at(4)) {
+                    let _ = write!(
+                        text,
+                        " last_stream={} error={}",
+                        last & 0x7fff_ffff,
+                        error_name(code)
+                    );
+                }
+                if p.len() > 8 {
+                    let _ = write!(text, " debug={:?}", String::from_utf8_lossy(&p[8..]));
+                }
+            }
+            0x8 => {
+                if let Some(increment) = u32_at(0) {
+                    let _ = write!(text, " increment={}", increment & 0x7fff_ffff);
+                }
+            }
+            _ => {}
+        }
+        text
+    }
+
+    /// The header and the kept payload, 16 bytes a line with an ASCII
+    /// column, noting how much of the payload was left out.
+    fn hex_dump(&self) -> String {
+        let bytes: Vec<u8> = self.header.iter().chain(&self.payload).copied().collect();
+        let mut out = String::new();
+        for (i, line) in bytes.chunks(16).enumerate() {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = line
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            let _ = writeln!(out, "  {:04x}  {:47}  |{}|", i * 16, hex.join(" "), ascii);
+        }
+        let cut = self.length - self.payload.len();
+        if cut > 0 {
+            let _ = writeln!(out, "  ... {} more payload bytes", cut);
+        }
+        out.pop();
+        out
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn setting_name(id: u16) -> String {
+    match id {
+        1 => "HEADER_TABLE_SIZE".to_string(),
+        2 => "ENABLE_PUSH".to_string(),
+        3 => "MAX_CONCURRENT_STREAMS".to_string(),
+        4 => "INITIAL_WINDOW_SIZE".to_string(),
+        5 => "MAX_FRAME_SIZE".to_string(),
+        6 => "MAX_HEADER_LIST_SIZE".to_string(),
+        8 => "ENABLE_CONNECT_PROTOCOL".to_string(),
+        id => format!("{:#06x}", id),
+    }
+}
+
+fn error_name(code: u32) -> String {
+    const NAMES: [&str; 14] = [
+        "NO_ERROR",
+        "PROTOCOL_ERROR",
+        "INTERNAL_ERROR",
+        "FLOW_CONTROL_ERROR",
+        "SETTINGS_TIMEOUT",
+        "STREAM_CLOSED",
+        "FRAME_SIZE_ERROR",
+        "REFUSED_STREAM",
+        "CANCEL",
+        "COMPRESSION_ERROR",
+        "CONNECT_ERROR",
+        "ENHANCE_YOUR_CALM",
+        "INADEQUATE_SECURITY",
+        "HTTP_1_1_REQUIRED",
+    ];
+    match NAMES.get(code as usize) {
+        Some(name) => name.to_string(),
+        None => format!("{:#x}", code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_decodes_and_dumps_frames() {
+        let mut wire = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".to_vec();
+        // SETTINGS: MAX_CONCURRENT_STREAMS=100, INITIAL_WINDOW_SIZE=65535.
+        wire.extend_from_slice(&[0, 0, 12, 4, 0, 0, 0, 0, 0]);
+        wire.extend_from_slice(&[0, 3, 0, 0, 0, 100, 0, 4, 0, 0, 0xff, 0xff]);
+        // DATA on stream 1, END_STREAM, with a payload past the dump.
+        wire.extend_from_slice(&[0, 0, 100, 0, 1, 0, 0, 0, 1]);
+        wire.extend_from_slice(&[b'x'; 100]);
+        // GOAWAY, never reached: the limit is two frames.
+        wire.extend_from_slice(&[0, 0, 8, 7, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+
+        // Byte by byte, as the worst case of short reads.
+        let mut parser = Parser::new(2, CLIENT_PREFACE_LEN);
+        let frames: Vec<Frame> = wire
+            .chunks(1)
+            .flat_map(|b| parser.feed(b, Duration::ZERO))
+            .collect();
+        assert!(parser.done());
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            frames[0].describe(),
+            "SETTINGS len=12 stream=0 flags=0x00 MAX_CONCURRENT_STREAMS=100 INITIAL_WINDOW_SIZE=65535"
+        );
+        assert_eq!(
+            frames[1].describe(),
+            "DATA len=100 stream=1 flags=END_STREAM"
+        );
+        assert_eq!(frames[1].payload.len(), DUMP_BYTES);
+        let dump = frames[1].hex_dump();
+        assert!(dump.starts_with(
+            "  0000  00 00 64 00 01 00 00 00 01 78 78 78 78 78 78 78  |..d......xxxxxxx|\n"
+        ));
+        assert!(dump.ends_with("  ... 36 more payload bytes"));
+
+        let mut parser = Parser::new(5, 0);
+        let goaway = parser.feed(
+            &[
+                0, 0, 12, 7, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 11, b'b', b'y', b'e', b'!',
+            ],
+            Duration::ZERO,
+        );
+        assert_eq!(
+            goaway[0].describe(),
+            "GOAWAY len=12 stream=0 flags=0x00 last_stream=3 error=ENHANCE_YOUR_CALM debug=\"bye!\""
+        );
+    }
+}
@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::net::IpAddr;
+
+/// Where a client address is registered, as far as the loaded databases
+/// know. Empty/zero fields were not found or had no database.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Origin {
+    /// ISO 3166-1 alpha-2 code, e.g. "DE".
+    pub country: String,
+    pub asn: u32,
+    pub as_org: String,
+}
+
+/// MaxMind GeoIP2/GeoLite2 lookups from local .mmdb files: a Country (or
+/// City) database and an ASN database, either of which may be omitted.
+/// The files are read into memory once; lookups never touch the disk.
+pub struct GeoIp {
+    #[cfg(feature = "geoip")]
+    country: Option<maxminddb::Reader<Vec<u8>>>,
+    #[cfg(feature = "geoip")]
+    asn: Option<maxminddb::Reader<Vec<u8>>>,
+}
+
+#[cfg(feature = "geoip")]
+impl GeoIp {
+    pub fn open(country: Option<&str>, asn: Option<&str>) -> Result<GeoIp, String> {
+        let open = |path: &str| {
+            maxminddb::Reader::open_readfile(path).map_err(|e| format!("open {}: {}", path, e))
+        };
+        Ok(GeoIp {
+            country: country.map(open).transpose()?,
+            asn: asn.map(open).transpose()?,
+        })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Origin {
+        use maxminddb::geoip2;
+
+        let mut origin = Origin::default();
+        if let Some(db) = &self.country {
+            if let Ok(found) = db.lookup::<geoip2::Country>(ip) {
+                // Anycast and satellite ranges may only have the country
+                // the block is registered to.
+                let country = found.country.or(found.registered_country);
+                if let Some(code) = country.and_then(|c| c.iso_code) {
+                    origin.country = code.to_string();
+                }
+            }
+        }
+        if let Some(db) = &self.asn {
+            if let Ok(found) = db.lookup::<geoip2::Asn>(ip) {
+                origin.asn = found.autonomous_system_number.unwrap_or(0);
+                origin.as_org = found
+                    .autonomous_system_organization
+                    .unwrap_or_default()
+                    .to_string();
+            }
+        }
+        origin
+    }
+}
+
+#[cfg(not(feature = "geoip"))]
+impl GeoIp {
+    pub fn open(_country: Option<&str>, _asn: Option<&str>) -> Result<GeoIp, String> {
+        Err("GeoIP lookups require building with --features geoip".to_string())
+    }
+
+    pub fn lookup(&self, _ip: IpAddr) -> Origin {
+        Origin::default()
+    }
+}
@@ -0,0 +1,663 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+/// Largest datagram we send or accept.
+const MAX_PACKET: usize = 64 * 1024;
+
+/// Membership updates piggybacked on each message.
+const MAX_PIGGYBACK: usize = 8;
+
+/// Members sent to a sender we haven't heard from before, so a newcomer
+/// learns the fleet without waiting for it to trickle in.
+const MAX_SYNC: usize = 64;
+
+/// Members asked to probe a target on our behalf when it misses a direct
+/// ping.
+const INDIRECT_PROBES: usize = 3;
+
+/// Each update is piggybacked this many times log2(fleet size), which is
+/// enough to reach every member with high probability.
+const RETRANSMIT_MULT: u32 = 3;
+
+/// Dead members are still listed for this many suspect timeouts, then
+/// forgotten.
+const DEAD_RETENTION: u32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Alive,
+    /// Missed a direct and indirect probe; dead unless it refutes within
+    /// the suspect timeout.
+    Suspect,
+    Dead,
+}
+
+impl Status {
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Alive => "alive",
+            Status::Suspect => "suspect",
+            Status::Dead => "dead",
+        }
+    }
+}
+
+/// One instance as the fleet knows it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    /// Where gossip reaches it; identifies the member.
+    pub addr: SocketAddr,
+    pub region: String,
+    /// Its gRPC service, e.g. https://10.0.0.2:50051.
+    pub grpc_addr: String,
+    pub status: Status,
+    /// Raised only by the member itself, to refute being suspected. Starts
+    /// at the Unix time it started, so a restarted member outranks what
+    /// the fleet remembers about its previous run.
+    pub incarnation: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    Ping {
+        seq: u64,
+    },
+    /// Ping `target` for us and forward its ack.
+    PingReq {
+        seq: u64,
+        target: SocketAddr,
+    },
+    Ack {
+        seq: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Packet {
+    /// The sender's own record.
+    from: Member,
+    message: Message,
+    updates: Vec<Member>,
+}
+
+pub struct Config {
+    /// Address to receive gossip on.
+    pub bind: SocketAddr,
+    /// Address the others reach us at, if not `bind`.
+    pub advertise: Option<SocketAddr>,
+    pub region: String,
+    pub grpc_addr: String,
+    /// HOST:PORT gossip addresses to join through.
+    pub seeds: Vec<String>,
+    /// Protocol period: one member is probed per interval.
+    pub interval: Duration,
+    pub suspect_timeout: Duration,
+}
+
+/// SWIM-style membership (Das, Gupta and Motivala, 2002) over UDP. Every
+/// interval one member is pinged in turn; if it doesn't ack within a third
+/// of the interval, a few others are asked to ping it, and if none of
+/// those acks arrive by the end of the interval it becomes suspect. A
+/// suspect that doesn't refute (by gossiping a higher incarnation) within
+/// the suspect timeout is dead. Changes spread by piggybacking on the
+/// probe traffic, so no member needs the full list configured: given a
+/// seed or two, everyone learns about everyone.
+///
+/// Packets are JSON and unauthenticated; gossip belongs on a trusted
+/// network.
+pub struct Gossip {
+    me: SocketAddr,
+    region: String,
+    grpc_addr: String,
+    seeds: Vec<String>,
+    interval: Duration,
+    suspect_timeout: Duration,
+    socket: UdpSocket,
+    state: Mutex<State>,
+}
+
+struct State {
+    incarnation: u64,
+    /// Everyone but us.
+    members: HashMap<SocketAddr, Entry>,
+    /// Updates still to piggyback, with how many more times to send each.
+    broadcasts: Vec<(Member, u32)>,
+    seq: u64,
+    waiting: HashMap<u64, (Waiter, Instant)>,
+    /// Round-robin position in the probe order.
+    next: usize,
+}
+
+struct Entry {
+    member: Member,
+    changed: Instant,
+}
+
+enum Waiter {
+    /// One of our probes.
+    Probe(oneshot::Sender<()>),
+    /// A ping we sent for `to`; its ack goes back as `seq`.
+    Relay { to: SocketAddr, seq: u64 },
+}
+
+impl Gossip {
+    pub async fn bind(config: Config) -> Result<Gossip, String> {
+        // The protocol period drives a tokio interval, which panics on zero.
+        if config.interval.is_zero() {
+            return Err("gossip interval must be more than zero".to_string());
+        }
+        let advertise = config.advertise.unwrap_or(config.bind);
+        if advertise.ip().is_unspecified() {
+            return Err(format!(
+                "gossip bound to {} needs an address to advertise",
+                config.bind
+            ));
+        }
+        let socket = UdpSocket::bind(config.bind)
+            .await
+            .map_err(|e| format!("bind gossip {}: {}", config.bind, e))?;
+        let advertise = if advertise.port() == 0 {
+            let port = socket.local_addr().map_err(|e| e.to_string())?.port();
+            SocketAddr::new(advertise.ip(), port)
+        } else {
+            advertise
+        };
+        let incarnation = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Ok(Gossip {
+            me: advertise,
+            region: config.region,
+            grpc_addr: config.grpc_addr,
+            seeds: config.seeds,
+            interval: config.interval,
+            suspect_timeout: config.suspect_timeout,
+            socket,
+            state: Mutex::new(State {
+                incarnation,
+                members: HashMap::new(),
+                broadcasts: Vec::new(),
+                seq: 0,
+                waiting: HashMap::new(),
+                next: 0,
+            }),
+        })
+    }
+
+    /// Our gossip address as the others see it.
+    pub fn addr(&self) -> SocketAddr {
+        self.me
+    }
+
+    /// Everyone we know of, ourselves first.
+    pub fn members(&self) -> Vec<Member> {
+        let st = self.state.lock().unwrap();
+        let mut others: Vec<Member> = st.members.values().map(|e| e.member.clone()).collect();
+        others.sort_by_key(|m| m.addr);
+        let mut members = vec![self.own_record(&st)];
+        members.extend(others);
+        members
+    }
+
+    /// Members currently believed alive, not counting ourselves.
+    pub fn alive(&self) -> Vec<Member> {
+        let st = self.state.lock().unwrap();
+        st.members
+            .values()
+            .filter(|e| e.member.status == Status::Alive)
+            .map(|e| e.member.clone())
+            .collect()
+    }
+
+    /// Receives gossip and probes members until the returned task is
+    /// aborted.
+    pub fn start(self: Arc<Self>) -> JoinHandle<()> {
+        info!(addr = %self.me, seeds = ?self.seeds, "gossip starting");
+        tokio::spawn(async move {
+            tokio::join!(self.receive(), self.probe_loop());
+        })
+    }
+
+    async fn receive(&self) {
+        let mut buf = vec![0u8; MAX_PACKET];
+        loop {
+            let (n, src) = match self.socket.recv_from(&mut buf).await {
+                Ok(got) => got,
+                Err(e) => {
+                    debug!("gossip receive failed: {}", e);
+                    continue;
+                }
+            };
+            match serde_json::from_slice::<Packet>(&buf[..n]) {
+                Ok(packet) => self.handle(packet, src).await,
+                Err(e) => debug!(%src, "malformed gossip packet: {}", e),
+            }
+        }
+    }
+
+    async fn handle(&self, packet: Packet, src: SocketAddr) {
+        let (reply, sync) = {
+            let mut st = self.state.lock().unwrap();
+            let known = st.members.contains_key(&packet.from.addr);
+            // Having heard from it directly, tell it if we think it's
+            // down so it can refute.
+            let stale = st
+                .members
+                .get(&packet.from.addr)
+                .filter(|e| {
+                    e.member.status != Status::Alive
+                        && e.member.incarnation >= packet.from.incarnation
+                })
+                .map(|e| e.member.clone());
+            self.merge(&mut st, packet.from.clone());
+            for update in packet.updates {
+                self.merge(&mut st, update);
+            }
+            let reply = match packet.message {
+                Message::Ping { seq } => Some((src, Message::Ack { seq })),
+                Message::PingReq { seq, target } => {
+                    let relay = next_seq(&mut st);
+                    st.waiting
+                        .insert(relay, (Waiter::Relay { to: src, seq }, Instant::now()));
+                    Some((target, Message::Ping { seq: relay }))
+                }
+                Message::Ack { seq } => match st.waiting.remove(&seq) {
+                    Some((Waiter::Probe(done), _)) => {
+                        let _ = done.send(());
+                        None
+                    }
+                    Some((Waiter::Relay { to, seq }, _)) => Some((to, Message::Ack { seq })),
+                    None => None,
+                },
+            };
+            let mut sync = Vec::new();
+            if !known {
+                sync.extend(
+                    st.members
+                        .values()
+                        .filter(|e| e.member.addr != packet.from.addr)
+                        .take(MAX_SYNC)
+                        .map(|e| e.member.clone()),
+                );
+            }
+            sync.extend(stale);
+            (reply, sync)
+        };
+        if let Some((to, message)) = reply {
+            self.send(to, message, sync).await;
+        }
+    }
+
+    /// Applies an update under SWIM's precedence rules, queueing it for
+    /// gossip if it was news.
+    fn merge(&self, st: &mut State, update: Member) {
+        if update.addr == self.me {
+            if update.status != Status::Alive && update.incarnation >= st.incarnation {
+                st.incarnation = update.incarnation + 1;
+                info!(status = update.status.name(), "refuting gossip about us");
+                let me = self.own_record(st);
+                queue(st, me);
+            }
+            return;
+        }
+        let newer = match st.members.get(&update.addr).map(|e| &e.member) {
+            None => update.status != Status::Dead,
+            Some(cur) => match update.status {
+                Status::Alive => update.incarnation > cur.incarnation,
+                Status::Suspect => {
+                    update.incarnation > cur.incarnation
+                        || (update.incarnation == cur.incarnation && cur.status == Status::Alive)
+                }
+                Status::Dead => update.incarnation >= cur.incarnation && cur.status != Status::Dead,
+            },
+        };
+        if !newer {
+            return;
+        }
+        let previous = st.members.get(&update.addr).map(|e| e.member.status);
+        if previous != Some(update.status) {
+            match update.status {
+                Status::Alive => {
+                    info!(member = %update.addr, region = %update.region, "gossip member alive")
+                }
+                Status::Suspect => info!(member = %update.addr, "gossip member suspect"),
+                Status::Dead => warn!(member = %update.addr, "gossip member dead"),
+            }
+        }
+        st.members.insert(
+            update.addr,
+            Entry {
+                member: update.clone(),
+                changed: Instant::now(),
+            },
+        );
+        queue(st, update);
+    }
+
+    fn own_record(&self, st: &State) -> Member {
+        Member {
+            addr: self.me,
+            region: self.region.clone(),
+            grpc_addr: self.grpc_addr.clone(),
+            status: Status::Alive,
+            incarnation: st.incarnation,
+        }
+    }
+
+    async fn send(&self, to: SocketAddr, message: Message, mut updates: Vec<Member>) {
+        let from = {
+            let mut st = self.state.lock().unwrap();
+            let room = MAX_PIGGYBACK.saturating_sub(updates.len());
+            updates.extend(piggyback(&mut st, room));
+            self.own_record(&st)
+        };
+        let packet = Packet {
+            from,
+            message,
+            updates,
+        };
+        let bytes = match serde_json::to_vec(&packet) {
+            Ok(bytes) if bytes.len() <= MAX_PACKET => bytes,
+            Ok(_) => {
+                warn!(%to, "gossip packet too large, dropped");
+                return;
+            }
+            Err(e) => {
+                warn!("encode gossip packet: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = self.socket.send_to(&bytes, to).await {
+            debug!(%to, "gossip send failed: {}", e);
+        }
+    }
+
+    async fn probe_loop(&self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            self.expire();
+            let Some((target, helpers)) = self.next_target() else {
+                self.join().await;
+                continue;
+            };
+            if !self.probe(target, &helpers).await {
+                self.suspect(target);
+            }
+        }
+    }
+
+    /// Pings every seed; their acks carry the membership they know.
+    async fn join(&self) {
+        for seed in &self.seeds {
+            let addrs = match tokio::net::lookup_host(seed.as_str()).await {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    debug!(%seed, "resolve gossip seed: {}", e);
+                    continue;
+                }
+            };
+            for addr in addrs.filter(|a| *a != self.me) {
+                let seq = next_seq(&mut self.state.lock().unwrap());
+                self.send(addr, Message::Ping { seq }, Vec::new()).await;
+            }
+        }
+    }
+
+    /// Whether `target` acked a direct ping, or one relayed by `helpers`,
+    /// within the protocol period.
+    async fn probe(&self, target: SocketAddr, helpers: &[SocketAddr]) -> bool {
+        let direct_timeout = self.interval / 3;
+        let (seq, acked) = self.expect_ack();
+        self.send(target, Message::Ping { seq }, Vec::new()).await;
+        if tokio::time::timeout(direct_timeout, acked).await.is_ok() {
+            return true;
+        }
+        if helpers.is_empty() {
+            return false;
+        }
+        let (seq, acked) = self.expect_ack();
+        for &helper in helpers {
+            self.send(helper, Message::PingReq { seq, target }, Vec::new())
+                .await;
+        }
+        tokio::time::timeout(self.interval - direct_timeout, acked)
+            .await
+            .is_ok()
+    }
+
+    fn expect_ack(&self) -> (u64, oneshot::Receiver<()>) {
+        let (tx, rx) = oneshot::channel();
+        let mut st = self.state.lock().unwrap();
+        let seq = next_seq(&mut st);
+        st.waiting.insert(seq, (Waiter::Probe(tx), Instant::now()));
+        (seq, rx)
+    }
+
+    /// The next live member in round-robin order and up to
+    /// `INDIRECT_PROBES` others to relay through.
+    fn next_target(&self) -> Option<(SocketAddr, Vec<SocketAddr>)> {
+        let mut st = self.state.lock().unwrap();
+        let mut candidates: Vec<SocketAddr> = st
+            .members
+            .values()
+            .filter(|e| e.member.status != Status::Dead)
+            .map(|e| e.member.addr)
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        candidates.sort();
+        let i = st.next % candidates.len();
+        st.next = st.next.wrapping_add(1);
+        let helpers = (1..candidates.len())
+            .map(|k| candidates[(i + k) % candidates.len()])
+            .take(INDIRECT_PROBES)
+            .collect();
+        Some((candidates[i], helpers))
+    }
+
+    fn suspect(&self, addr: SocketAddr) {
+        let mut st = self.state.lock().unwrap();
+        let Some(entry) = st.members.get(&addr) else {
+            return;
+        };
+        if entry.member.status != Status::Alive {
+            return;
+        }
+        let update = Member {
+            status: Status::Suspect,
+            ..entry.member.clone()
+        };
+        self.merge(&mut st, update);
+    }
+
+    /// Declares suspects past the timeout dead, and forgets long-dead
+    /// members and acks that never came.
+    fn expire(&self) {
+        let mut st = self.state.lock().unwrap();
+        let expired: Vec<Member> = st
+            .members
+            .values()
+            .filter(|e| {
+                e.member.status == Status::Suspect && e.changed.elapsed() >= self.suspect_timeout
+            })
+            .map(|e| Member {
+                status: Status::Dead,
+                ..e.member.clone()
+            })
+            .collect();
+        for dead in expired {
+            self.merge(&mut st, dead);
+        }
+        let retention = self.suspect_timeout * DEAD_RETENTION;
+        st.members
+            .retain(|_, e| e.member.status != Status::Dead || e.changed.elapsed() < retention);
+        let interval = self.interval;
+        st.waiting
+            .retain(|_, (_, sent)| sent.elapsed() < interval * 2);
+    }
+}
+
+fn next_seq(st: &mut State) -> u64 {
+    st.seq = st.seq.wrapping_add(1);
+    st.seq
+}
+
+fn queue(st: &mut State, update: Member) {
+    let fleet = st.members.len() as u32 + 1;
+    let transmits = RETRANSMIT_MULT * (u32::BITS - fleet.leading_zeros());
+    st.broadcasts.retain(|(m, _)| m.addr != update.addr);
+    st.broadcasts.push((update, transmits));
+}
+
+/// Up to `n` queued updates, least-sent first.
+fn piggyback(st: &mut State, n: usize) -> Vec<Member> {
+    st.broadcasts
+        .sort_by_key(|(_, left)| std::cmp::Reverse(*left));
+    let picked: Vec<Member> = st
+        .broadcasts
+        .iter_mut()
+        .take(n)
+        .map(|(m, left)| {
+            *left -= 1;
+            m.clone()
+        })
+        .collect();
+    st.broadcasts.retain(|(_, left)| *left > 0);
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(seeds: Vec<String>) -> Config {
+        Config {
+            bind: "127.0.0.1:0".parse().unwrap(),
+            advertise: None,
+            region: "test".to_string(),
+            grpc_addr: String::new(),
+            seeds,
+            interval: Duration::from_millis(30),
+            suspect_timeout: Duration::from_millis(150),
+        }
+    }
+
+    async fn node(seeds: Vec<String>) -> Arc<Gossip> {
+        Arc::new(Gossip::bind(config(seeds)).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn rejects_a_zero_interval() {
+        let config = Config {
+            interval: Duration::ZERO,
+            ..config(Vec::new())
+        };
+        assert!(Gossip::bind(config).await.is_err());
+    }
+
+    async fn until(what: &str, cond: impl Fn() -> bool) {
+        for _ in 0..200 {
+            if cond() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("timed out waiting until {}", what);
+    }
+
+    #[tokio::test]
+    async fn members_learn_each_other_and_detect_failure() {
+        let seed = node(Vec::new()).await;
+        let seed_addr = seed.addr().to_string();
+        let a = node(vec![seed_addr.clone()]).await;
+        let b = node(vec![seed_addr]).await;
+        let b_addr = b.addr();
+        let _seed_task = seed.clone().start();
+        let _a_task = a.clone().start();
+        let b_task = b.start();
+
+        // a and b only know the seed, and learn each other through it.
+        until("all members are alive", || {
+            [&seed, &a].iter().all(|g| g.alive().len() == 2)
+                && a.alive().iter().any(|m| m.addr == b_addr)
+        })
+        .await;
+
+        b_task.abort();
+        until("b is declared dead", || {
+            [&seed, &a].iter().all(|g| {
+                g.members()
+                    .iter()
+                    .any(|m| m.addr == b_addr && m.status == Status::Dead)
+            })
+        })
+        .await;
+        assert_eq!(a.alive().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn merge_follows_incarnation_precedence() {
+        let gossip = node(Vec::new()).await;
+        let apply = |update: Member| {
+            let mut st = gossip.state.lock().unwrap();
+            gossip.merge(&mut st, update);
+        };
+        let peer = Member {
+            addr: "127.0.0.1:9".parse().unwrap(),
+            region: "test".to_string(),
+            grpc_addr: String::new(),
+            status: Status::Alive,
+            incarnation: 5,
+        };
+        let with = |status, incarnation| Member {
+            status,
+            incarnation,
+            ..peer.clone()
+        };
+        let status = || gossip.members()[1].status;
+
+        apply(with(Status::Dead, 9));
+        assert_eq!(gossip.members().len(), 1, "dead strangers aren't added");
+        apply(peer.clone());
+        apply(with(Status::Suspect, 5));
+        assert_eq!(status(), Status::Suspect);
+        // An alive at the same incarnation doesn't clear a suspicion; the
+        // member itself refutes with a higher one.
+        apply(with(Status::Alive, 5));
+        assert_eq!(status(), Status::Suspect);
+        apply(with(Status::Alive, 6));
+        apply(with(Status::Dead, 5));
+        assert_eq!(status(), Status::Alive);
+        apply(with(Status::Dead, 6));
+        assert_eq!(status(), Status::Dead);
+
+        // Rumours of our own suspicion are refuted.
+        let before = gossip.members()[0].incarnation;
+        apply(Member {
+            status: Status::Suspect,
+            ..gossip.members()[0].clone()
+        });
+        let st = gossip.state.lock().unwrap();
+        assert_eq!(st.incarnation, before + 1);
+        assert!(st
+            .broadcasts
+            .iter()
+            .any(|(m, _)| m.addr == gossip.addr() && m.status == Status::Alive));
+    }
+}
@@ -1,22 +1,140 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+use crate::alloc_audit::{self, HotPath};
+use crate::attest::Signer;
+use crate::auth::{totp, AuthBackend, AuthError, User};
+use crate::bench;
+use crate::build_info;
+use crate::clock::{self, ClockSource};
+use crate::db::Database;
+use crate::deadline::{DeadlineLayer, Deadlines};
+use crate::environment;
+use crate::etcd::Registry;
+use crate::failover::Pair;
+use crate::geoip::{GeoIp, Origin};
+use crate::gossip::Gossip;
+use crate::health::Health;
 use crate::hermit::{
     hermit_server::{Hermit, HermitServer},
-    BenchmarkRequest, BenchmarkResponse, DbStatsRequest, DbStatsResponse,
-    KvGetRequest, KvGetResponse, KvListRequest, KvListResponse,
-    KvSetRequest, KvSetResponse, LoginRequest, LoginResponse,
-    PingRequest, PingResponse, ServerInfoRequest, ServerInfoResponse,
-    SqlInsertRequest, SqlInsertResponse, SqlQueryRequest, SqlQueryResponse, SqlRow,
+    AllocStatsRequest, AllocStatsResponse, BenchmarkRequest, BenchmarkResponse,
+    CapabilitiesRequest, CapabilitiesResponse, CertInfoRequest, CertInfoResponse, DbStatsRequest,
+    DbStatsResponse, EnrollTotpRequest, EnrollTotpResponse, Environment, FailedHandshake,
+    GetSweepRequest, GetSweepResponse, HandshakeFailureCount, HotPathAllocs, Http2Settings,
+    KvGetRequest, KvGetResponse, KvListRequest, KvListResponse, KvSetRequest, KvSetResponse, Label,
+    LatencyInterval, ListPeersRequest, ListPeersResponse, ListSessionsRequest,
+    ListSessionsResponse, ListenerDiagnostics, ListenerDiagnosticsRequest,
+    ListenerDiagnosticsResponse, LoginRequest, LoginResponse, Outlier, Peer, PeerLatency,
+    Percentile, PingRequest, PingResponse, ReplayBenchmarkRequest, RevokeSessionRequest,
+    RevokeSessionResponse, RunCounters, SchemaRequest, SchemaResponse, ServerInfoRequest,
+    ServerInfoResponse, SessionInfo, SqlInsertRequest, SqlInsertResponse, SqlQueryRequest,
+    SqlQueryResponse, SqlRow, Thermal,
 };
-use crate::bench;
-use crate::db::Database;
-use crate::tls::TlsConfig;
+use crate::hops::{self, Hop};
+use crate::hugepage;
+use crate::inflight::InFlightLayer;
+use crate::listener::{self, ConnInfo, Keepalive};
+use crate::metrics::{HandshakeFailure, ListenerMetrics, METRICS};
+use crate::notify::Webhook;
+use crate::numa;
+use crate::runlog::RunLog;
+use crate::session::{Session, SessionStore};
+use crate::shadow::Shadow;
+use crate::sweep::Sweeper;
+use crate::thermal;
+use crate::threadstat;
+use crate::timing::{RequestArrival, TimingLayer};
+use crate::tls::{ReloadableCert, TlsConfig};
 
 use prost_types::Timestamp;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
-use tracing::info;
+use tracing::{info, warn};
+
+/// Upper bound on `BenchmarkRequest.percentiles`.
+const MAX_PERCENTILES: usize = 32;
+
+/// Bounds on `BenchmarkRequest.interval_ns` and on the intervals reported,
+/// each of which costs a histogram of at least 16KB.
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_INTERVALS: usize = 1_000;
+
+/// Upper bound on `BenchmarkResponse.outliers`.
+const MAX_OUTLIERS: usize = 100;
+
+/// How often `BenchmarkResponse.thermal` reads the CPUs during a run.
+const THERMAL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Limits on `BenchmarkRequest.labels`.
+const MAX_LABELS: usize = 16;
+const MAX_LABEL_KEY_LEN: usize = 64;
+const MAX_LABEL_VALUE_LEN: usize = 256;
+
+/// Login and EnrollTotp error while a user is locked out of TOTP.
+const TOTP_LOCKED_OUT: &str = "too many invalid TOTP codes; try again later";
+
+/// Benchmarks running across all instances in the process, which share
+/// its CPUs.
+static BENCHMARKS_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a benchmark in `BENCHMARKS_RUNNING` until dropped.
+struct BenchmarkRunning;
+
+impl BenchmarkRunning {
+    /// Also returns how many are running now, this one included.
+    fn start() -> (BenchmarkRunning, usize) {
+        let running = BENCHMARKS_RUNNING.fetch_add(1, Ordering::Relaxed) + 1;
+        (BenchmarkRunning, running)
+    }
+}
+
+impl Drop for BenchmarkRunning {
+    fn drop(&mut self) {
+        BENCHMARKS_RUNNING.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Incremented whenever RPCs or fields are added to hermit.proto; see
+/// ServerInfoResponse.protocol_version.
+pub const PROTOCOL_VERSION: u32 = 18;
+
+/// HTTP/2 settings advertised on every connection. These are hyper's
+/// defaults, spelled out so Benchmark can report what clients were sent.
+const H2_STREAM_WINDOW: u32 = 1024 * 1024;
+const H2_CONNECTION_WINDOW: u32 = 1024 * 1024;
+const H2_MAX_FRAME_SIZE: u32 = 16 * 1024;
+const H2_MAX_HEADER_LIST_SIZE: u32 = 16 * 1024;
+
+/// Methods of the Hermit service, as listed by Capabilities.
+const RPCS: &[&str] = &[
+    "Ping",
+    "Benchmark",
+    "ReplayBenchmark",
+    "Login",
+    "EnrollTotp",
+    "RevokeSession",
+    "ListSessions",
+    "ServerInfo",
+    "WatchServerInfo",
+    "GetCertInfo",
+    "Capabilities",
+    "GetSchema",
+    "KvSet",
+    "KvGet",
+    "KvList",
+    "SqlInsert",
+    "SqlQuery",
+    "DbStats",
+    "AllocStats",
+    "ListPeers",
+    "GetSweep",
+    "GetListenerDiagnostics",
+];
 
 pub struct ServerState {
     pub version: String,
@@ -24,117 +142,1052 @@ pub struct ServerState {
     pub started_at: SystemTime,
     pub start_instant: Instant,
     pub grpc_port: u16,
+    pub session_ttl_secs: u64,
+    pub deadlines: Deadlines,
+    pub keepalive: Keepalive,
+    /// DSCP codepoint set on accepted sockets, so replies carry it.
+    pub dscp: Option<u8>,
+    /// TCP_MAXSEG for accepted connections.
+    pub tcp_maxseg: Option<u32>,
+    /// Also serve health/metrics and throughput tests on the TLS port,
+    /// chosen by ALPN.
+    pub alpn_multiplex: bool,
+    /// HTTP/2 frames each way to log per connection; 0 for none.
+    pub trace_frames: usize,
+    pub clock: Arc<dyn ClockSource>,
+    pub health: Arc<Health>,
+    /// Set when running as half of an active/standby pair.
+    pub pair: Option<Arc<Pair>>,
+}
+
+/// Pluggable dependencies, built in main from command-line flags.
+#[derive(Clone)]
+pub struct Backends {
+    pub db: Arc<Database>,
+    pub auth: Arc<dyn AuthBackend>,
+    pub sessions: Arc<dyn SessionStore>,
+    /// Shared by every instance, like the session store.
+    pub totp: Arc<totp::Totp>,
+    pub signer: Option<Arc<Signer>>,
+    pub webhook: Option<Arc<Webhook>>,
+    pub geoip: Option<Arc<GeoIp>>,
+    pub shadow: Option<Arc<Shadow>>,
+    pub gossip: Option<Arc<Gossip>>,
+    pub registry: Option<Arc<Registry>>,
+    pub sweeper: Option<Arc<Sweeper>>,
+    /// Every Benchmark run, for ReplayBenchmark.
+    pub runs: Arc<RunLog>,
 }
 
 pub struct HermitService {
     state: Arc<ServerState>,
     tls_enabled: bool,
     db: Arc<Database>,
+    auth: Arc<dyn AuthBackend>,
+    totp: Arc<totp::Totp>,
+    sessions: Arc<dyn SessionStore>,
+    signer: Option<Arc<Signer>>,
+    webhook: Option<Arc<Webhook>>,
+    geoip: Option<Arc<GeoIp>>,
+    shadow: Option<Arc<Shadow>>,
+    gossip: Option<Arc<Gossip>>,
+    registry: Option<Arc<Registry>>,
+    sweeper: Option<Arc<Sweeper>>,
+    runs: Arc<RunLog>,
+    listener: Arc<ListenerMetrics>,
+    certs: Option<Arc<ReloadableCert>>,
 }
 
-#[tonic::async_trait]
-impl Hermit for HermitService {
-    async fn ping(&self, req: Request<PingRequest>) -> Result<Response<PingResponse>, Status> {
-        let recv = bench::now_ns();
+/// What Benchmark's timed loop saw.
+struct Measured {
+    latencies: Vec<i64>,
+    /// When each iteration started, relative to the loop.
+    starts: Vec<i64>,
+    /// Each iteration's counter deltas, when looking for outliers.
+    counters: Vec<Option<threadstat::Counters>>,
+    run_counters: Option<RunCounters>,
+    run_start: SystemTime,
+    /// The whole loop, bookkeeping included.
+    overhead_ns: i64,
+}
+
+/// Benchmark's timed loop. Blocks, so it belongs on a blocking thread,
+/// whose counters are the ones sampled.
+fn measure(
+    clock: &dyn ClockSource,
+    payload: &hugepage::Buffer,
+    iterations: usize,
+    sample_each: bool,
+) -> Measured {
+    let mut latencies: Vec<i64> = Vec::with_capacity(iterations);
+    let mut starts: Vec<i64> = Vec::with_capacity(iterations);
+    // Counter deltas across the run, and across each sample when
+    // looking for outliers, snapshotted right next to the timestamps
+    // so bookkeeping between samples isn't attributed.
+    let mut sampler = threadstat::Sampler::open();
+    let run_before = sampler.as_mut().and_then(threadstat::Sampler::sample);
+    let mut outlier_sampler = if sample_each { sampler.as_mut() } else { None };
+    let mut counters = Vec::new();
+    if outlier_sampler.is_some() {
+        counters.reserve(iterations);
+    }
+    let run_start = SystemTime::now();
+    let overhead_start = clock.now_ns();
+
+    for _ in 0..iterations {
+        let before = outlier_sampler.as_mut().and_then(|s| s.sample());
+        let t0 = clock.now_ns();
+        // Simulate minimal processing: touch the payload
+        if !payload.is_empty() {
+            std::hint::black_box(payload);
+        }
+        let t1 = clock.now_ns();
+        if let Some(sampler) = &mut outlier_sampler {
+            let after = sampler.sample();
+            counters.push(before.zip(after).map(|(b, a)| a.since(&b)));
+        }
+        latencies.push(t1 - t0);
+        starts.push(t0 - overhead_start);
+    }
+
+    let overhead_ns = clock.now_ns() - overhead_start;
+    let run_counters = run_before
+        .zip(sampler.as_mut().and_then(threadstat::Sampler::sample))
+        .map(|(b, a)| run_counters(&a.since(&b)));
+    Measured {
+        latencies,
+        starts,
+        counters,
+        run_counters,
+        run_start,
+        overhead_ns,
+    }
+}
+
+/// "TLS 1.3" rather than rustls's `TLSv1_3`.
+fn tls_version_name(version: rustls::ProtocolVersion) -> String {
+    match version {
+        rustls::ProtocolVersion::TLSv1_2 => "TLS 1.2".to_string(),
+        rustls::ProtocolVersion::TLSv1_3 => "TLS 1.3".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+fn run_counters(delta: &threadstat::Counters) -> RunCounters {
+    RunCounters {
+        run_delay_ns: delta.run_delay_ns,
+        voluntary_switches: delta.voluntary_switches,
+        involuntary_switches: delta.involuntary_switches,
+        minor_faults: delta.minor_faults,
+        major_faults: delta.major_faults,
+        user_cpu_ns: delta.user_cpu_ns,
+        system_cpu_ns: delta.system_cpu_ns,
+    }
+}
+
+fn environment(env: environment::Snapshot) -> Environment {
+    let nic = env.nic.unwrap_or_default();
+    let cgroup = env.cgroup.unwrap_or_default();
+    Environment {
+        kernel_release: env.kernel_release,
+        cpu_governor: env.cpu_governor,
+        turbo_enabled: env.turbo,
+        nic: nic.name,
+        nic_driver: nic.driver,
+        nic_rx_ring: nic.rx_ring,
+        nic_tx_ring: nic.tx_ring,
+        cgroup_version: cgroup.version.into(),
+        cgroup_cpu_limit: cgroup.cpus.unwrap_or_default(),
+        cgroup_memory_limit_bytes: cgroup.memory_bytes.unwrap_or_default(),
+        numa_nodes: env.numa_nodes,
+        numa_node: env.numa_node,
+        numa_pinned_node: env.pinned_numa_node,
+        payload_numa_node: None,
+    }
+}
+
+fn thermal(report: &thermal::Report) -> Thermal {
+    Thermal {
+        samples: report.samples,
+        min_frequency_khz: report.min_frequency_khz,
+        max_frequency_khz: report.max_frequency_khz,
+        max_temperature_millicelsius: report.max_temperature_mc,
+        throttle_events: report.throttle_events,
+        throttled: report.throttled,
+        frequency_scaled: report.frequency_scaled,
+    }
+}
+
+/// What `serve` configures the HTTP/2 server with. Concurrent streams are
+/// left unlimited.
+fn http2_settings(keepalive: &Keepalive) -> Http2Settings {
+    let ns = |d: Duration| d.as_nanos() as i64;
+    Http2Settings {
+        initial_stream_window_size: H2_STREAM_WINDOW,
+        initial_connection_window_size: H2_CONNECTION_WINDOW,
+        max_concurrent_streams: 0,
+        max_frame_size: H2_MAX_FRAME_SIZE,
+        max_header_list_size: H2_MAX_HEADER_LIST_SIZE,
+        keepalive_interval_ns: keepalive.http2_interval.map_or(0, ns),
+        keepalive_timeout_ns: keepalive
+            .http2_interval
+            .map_or(0, |_| ns(keepalive.http2_timeout)),
+        max_connection_age_ns: keepalive.max_age.map_or(0, ns),
+        max_connection_age_grace_ns: keepalive.max_age.map_or(0, |_| ns(keepalive.max_age_grace)),
+        max_connection_idle_ns: keepalive.max_idle.map_or(0, ns),
+    }
+}
+
+/// The stats part of a PeerLatency.
+fn peer_latency(stats: &bench::Stats) -> PeerLatency {
+    PeerLatency {
+        samples: stats.count as u32,
+        min_ns: stats.min,
+        p50_ns: stats.p50,
+        p99_ns: stats.p99,
+        max_ns: stats.max,
+        ..Default::default()
+    }
+}
+
+/// Validate benchmark labels and sort them by key, so the signed
+/// response doesn't depend on the order the client sent them in.
+fn check_labels(mut labels: Vec<Label>) -> Result<Vec<Label>, String> {
+    if labels.len() > MAX_LABELS {
+        return Err(format!("at most {} labels may be set", MAX_LABELS));
+    }
+    for label in &labels {
+        let key_ok = !label.key.is_empty()
+            && label.key.len() <= MAX_LABEL_KEY_LEN
+            && label
+                .key
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'-'));
+        if !key_ok {
+            return Err(format!("invalid label key {:?}", label.key));
+        }
+        if label.value.len() > MAX_LABEL_VALUE_LEN {
+            return Err(format!(
+                "label {} is longer than {} bytes",
+                label.key, MAX_LABEL_VALUE_LEN
+            ));
+        }
+    }
+    labels.sort_by(|a, b| a.key.cmp(&b.key));
+    if let Some(dup) = labels.windows(2).find(|w| w[0].key == w[1].key) {
+        return Err(format!("label {} is set more than once", dup[0].key));
+    }
+    Ok(labels)
+}
+
+/// Everything ServerInfo reports, detached from the service so
+/// WatchServerInfo streams can outlive the call that opened them.
+#[derive(Clone)]
+struct InfoSource {
+    state: Arc<ServerState>,
+    tls_enabled: bool,
+    certs: Option<Arc<ReloadableCert>>,
+    signer: Option<Arc<Signer>>,
+    listener: Arc<ListenerMetrics>,
+}
+
+impl InfoSource {
+    fn snapshot(&self) -> ServerInfoResponse {
+        let uptime = self.state.start_instant.elapsed().as_secs() as i64;
+        let since_epoch = self
+            .state
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let cert = self.certs.as_ref().map(|c| c.summary()).unwrap_or_default();
+        let sync = clock::sync_status();
+        let cgroup = environment::cgroup_limits().unwrap_or_default();
+
+        ServerInfoResponse {
+            version: self.state.version.clone(),
+            region: self.state.region.clone(),
+            started_at: Some(Timestamp {
+                seconds: since_epoch.as_secs() as i64,
+                nanos: since_epoch.subsec_nanos() as i32,
+            }),
+            uptime_seconds: uptime,
+            rust_version: env!("CARGO_PKG_VERSION").to_string(),
+            tls_enabled: self.tls_enabled,
+            grpc_port: self.state.grpc_port as u32,
+            signing_public_key: self
+                .signer
+                .as_ref()
+                .map(|s| s.public_key().to_vec())
+                .unwrap_or_default(),
+            signing_key_id: self
+                .signer
+                .as_ref()
+                .map(|s| s.key_id().to_string())
+                .unwrap_or_default(),
+            connections_reaped: METRICS.connections_reaped(),
+            clock_source: self.state.clock.name().to_string(),
+            connections_open: self.listener.connections_open(),
+            rpcs_in_flight: self.listener.rpcs_in_flight(),
+            accept_queue_depth: self.listener.accept_queue_depth(),
+            accept_queue_wait_ns: self.listener.accept_queue_wait().as_nanos() as i64,
+            dscp: self.state.dscp.map(u32::from),
+            send_mss: 0,
+            path_mtu: 0,
+            clock_synchronized: sync.is_some_and(|s| s.synchronized),
+            clock_max_error_ns: sync.map_or(0, |s| s.max_error.as_nanos() as i64),
+            clock_est_error_ns: sync.map_or(0, |s| s.est_error.as_nanos() as i64),
+            tls_handshake_ns: 0,
+            first_request_ns: 0,
+            tls_kx_group: String::new(),
+            git_sha: build_info::GIT_SHA.to_string(),
+            build_timestamp: Some(build_info::built_at().into()),
+            rustc_version: build_info::RUSTC_VERSION.to_string(),
+            cargo_features: build_info::FEATURES.iter().map(|f| f.to_string()).collect(),
+            proto_schema_version: build_info::PROTO_SCHEMA_VERSION.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            ha_pair: self.state.pair.as_ref().map(|p| p.snapshot()),
+            cgroup_version: cgroup.version.into(),
+            cgroup_cpu_limit: cgroup.cpus.unwrap_or_default(),
+            cgroup_memory_limit_bytes: cgroup.memory_bytes.unwrap_or_default(),
+            worker_threads: tokio::runtime::Handle::current().metrics().num_workers() as u32,
+            ready: self.state.health.is_ready(),
+            draining: self.state.health.is_draining(),
+            tls_cert_sha256: cert.sha256,
+            tls_cert_not_after: (cert.not_after_unix != 0).then_some(Timestamp {
+                seconds: cert.not_after_unix,
+                nanos: 0,
+            }),
+        }
+    }
+}
+
+/// Send a snapshot now and again after every certificate swap or
+/// readiness change. The stream ends once the server starts draining, so
+/// graceful shutdown isn't held open by watchers.
+async fn push_server_info(info: InfoSource, tx: mpsc::Sender<Result<ServerInfoResponse, Status>>) {
+    let mut health = info.state.health.subscribe();
+    let mut certs = info.certs.as_ref().map(|c| c.subscribe());
+    loop {
+        if tx.send(Ok(info.snapshot())).await.is_err() {
+            return;
+        }
+        if info.state.health.is_draining() {
+            return;
+        }
+        let cert_changed = async {
+            match certs.as_mut() {
+                Some(rx) => rx.changed().await.is_ok(),
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            changed = health.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            changed = cert_changed => {
+                if !changed {
+                    certs = None;
+                }
+            }
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+impl HermitService {
+    /// First-factor check shared by Login and EnrollTotp. The inner `Err`
+    /// is a rejection message for the response body; the outer one is a
+    /// backend failure surfaced as UNAVAILABLE.
+    async fn authenticate(
+        &self,
+        username: &str,
+        token: &str,
+    ) -> Result<Result<User, String>, Status> {
+        // No username means the token is a bearer token rather than a password.
+        let result = if username.is_empty() {
+            self.auth.validate_token(token).await
+        } else {
+            self.auth.verify_credentials(username, token).await
+        };
+        match result {
+            Ok(user) => Ok(Ok(user)),
+            Err(AuthError::Backend(e)) => {
+                warn!(
+                    username = %username,
+                    backend = self.auth.name(),
+                    "auth backend error: {}",
+                    e
+                );
+                Err(Status::unavailable("authentication backend unavailable"))
+            }
+            Err(e) => {
+                info!(
+                    username = %username,
+                    backend = self.auth.name(),
+                    "authentication rejected: {}",
+                    e
+                );
+                Ok(Err(e.to_string()))
+            }
+        }
+    }
+
+    fn info_source(&self) -> InfoSource {
+        InfoSource {
+            state: self.state.clone(),
+            tls_enabled: self.tls_enabled,
+            certs: self.certs.clone(),
+            signer: self.signer.clone(),
+            listener: self.listener.clone(),
+        }
+    }
+
+    /// Resolve the session named by the `x-hermit-session` header.
+    async fn caller_session<T>(&self, req: &Request<T>) -> Result<Session, Status> {
+        let id = req
+            .metadata()
+            .get("x-hermit-session")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| Status::unauthenticated("missing x-hermit-session header"))?;
+        self.sessions
+            .get(id)
+            .await
+            .map_err(Status::unavailable)?
+            .ok_or_else(|| Status::unauthenticated("unknown or expired session"))
+    }
+
+    /// The Ping handler; `ping` counts its allocations.
+    async fn ping_inner(
+        &self,
+        req: Request<PingRequest>,
+    ) -> Result<Response<PingResponse>, Status> {
+        let clock = &self.state.clock;
+        let recv = clock.now_ns();
+        let recv_realtime = clock.realtime_ns();
+        // Falls back to handler entry if the timing layer isn't installed.
+        let stack_recv = req
+            .extensions()
+            .get::<RequestArrival>()
+            .map_or(recv, |a| a.0);
+        // Tiers that forward the request append themselves to this; we
+        // add the last hop and hand the list back.
+        let hops = req
+            .metadata()
+            .get(hops::METADATA_KEY)
+            .map(|v| hops::from_metadata(v.to_str().map_err(|e| e.to_string())?))
+            .transpose()
+            .map_err(|e| Status::invalid_argument(format!("{}: {}", hops::METADATA_KEY, e)))?;
         let inner = req.into_inner();
-        let send = bench::now_ns();
-        Ok(Response::new(PingResponse {
+        let send_realtime = clock.realtime_ns();
+        let send = clock.now_ns();
+        let mut response = Response::new(PingResponse {
             client_send_ns: inner.client_send_ns,
             server_recv_ns: recv,
             server_send_ns: send,
-        }))
+            server_stack_recv_ns: stack_recv,
+            stack_overhead_ns: recv - stack_recv,
+            handler_ns: send - recv,
+            server_recv_realtime_ns: recv_realtime,
+            server_send_realtime_ns: send_realtime,
+            upstream_ns: (inner.client_send_realtime_ns != 0)
+                .then(|| recv_realtime - inner.client_send_realtime_ns),
+        });
+        if let Some(mut hops) = hops {
+            hops.truncate(hops::MAX_HOPS - 1);
+            hops.push(Hop {
+                name: "server".to_string(),
+                recv_ns: recv_realtime as u64,
+                send_ns: send_realtime as u64,
+            });
+            if let Ok(v) = hops::to_metadata(&hops).parse() {
+                response.metadata_mut().insert(hops::METADATA_KEY, v);
+            }
+        }
+        Ok(response)
     }
 
-    async fn benchmark(
+    /// The Benchmark handler; `benchmark` mirrors it to the shadow, and
+    /// ReplayBenchmark runs recorded requests through it again.
+    async fn benchmark_inner(
         &self,
         req: Request<BenchmarkRequest>,
+        replay_of: Option<String>,
     ) -> Result<Response<BenchmarkResponse>, Status> {
-        let inner = req.into_inner();
-        let iterations = inner.iterations.max(1).min(10_000) as usize;
+        let conn = req.extensions().get::<ConnInfo>().cloned();
+        let mut inner = req.into_inner();
+        let iterations = inner.iterations.clamp(1, 10_000) as usize;
+        let labels =
+            check_labels(std::mem::take(&mut inner.labels)).map_err(Status::invalid_argument)?;
         let payload_bytes = inner.payload_bytes as usize;
+        let payload_content: bench::PayloadContent = inner
+            .payload_content
+            .parse()
+            .map_err(Status::invalid_argument)?;
+        if inner.percentiles.len() > MAX_PERCENTILES {
+            return Err(Status::invalid_argument(format!(
+                "at most {} percentiles may be requested",
+                MAX_PERCENTILES
+            )));
+        }
+        if let Some(p) = inner
+            .percentiles
+            .iter()
+            .find(|p| !(0.0..=100.0).contains(*p))
+        {
+            return Err(Status::invalid_argument(format!(
+                "percentile {} is outside [0, 100]",
+                p
+            )));
+        }
+        if inner.interval_ns != 0
+            && !(MIN_INTERVAL.as_nanos()..=i64::MAX as u128).contains(&inner.interval_ns.into())
+        {
+            return Err(Status::invalid_argument(format!(
+                "interval_ns must be 0 or between {} and {}",
+                MIN_INTERVAL.as_nanos(),
+                i64::MAX
+            )));
+        }
+        if inner.outlier_threshold.is_nan() || inner.outlier_threshold < 0.0 {
+            return Err(Status::invalid_argument(
+                "outlier_threshold must not be negative",
+            ));
+        }
 
-        // Allocate payload once if needed (simulates processing)
-        let _payload: Vec<u8> = if payload_bytes > 0 {
-            vec![0xAB; payload_bytes]
-        } else {
-            Vec::new()
+        let seed = match inner.seed {
+            0 => bench::DEFAULT_SEED,
+            seed => seed,
         };
+        let run_id = self.runs.record(
+            BenchmarkRequest {
+                labels: labels.clone(),
+                payload_content: payload_content.name().to_string(),
+                seed,
+                ..inner.clone()
+            },
+            replay_of.clone(),
+        );
 
-        let mut latencies: Vec<i64> = Vec::with_capacity(iterations);
-        let overhead_start = bench::now_ns();
+        // Allocate payload once if needed (simulates processing)
+        let mut _payload = hugepage::Buffer::new(payload_bytes);
+        bench::fill_payload_seeded(payload_content, seed, &mut _payload);
+        // Read before the run so the file reads and ioctl don't disturb it.
+        let env = environment::snapshot(conn.as_ref().and_then(ConnInfo::local).map(|a| a.ip()));
+        let payload_node = numa::node_of(&_payload);
+        let (_running, concurrent) = BenchmarkRunning::start();
+        let cpus = environment::usable_cpus(env.cgroup);
+        if concurrent > cpus {
+            warn!(
+                concurrent,
+                cpus, "more benchmarks running than usable CPUs; timings include contention"
+            );
+        }
 
-        for _ in 0..iterations {
-            let t0 = bench::now_ns();
-            // Simulate minimal processing: touch the payload
-            if payload_bytes > 0 {
-                std::hint::black_box(&_payload);
+        let clock = &self.state.clock;
+        let timer_overhead = bench::timer_overhead_ns(clock.as_ref());
+        // Looking for outliers samples the thread's counters around every
+        // iteration, up to 20,000 blocking syscalls, and the thermal
+        // monitor reads /sys, spawns a thread and joins it, so the run
+        // gets a blocking thread rather than stalling one of the runtime's.
+        let sample_each = inner.outlier_threshold > 0.0;
+        let (run, thermal_report, _payload) = {
+            let clock = clock.clone();
+            tokio::task::spawn_blocking(move || {
+                let monitor = thermal::Monitor::start(THERMAL_INTERVAL);
+                let run = measure(clock.as_ref(), &_payload, iterations, sample_each);
+                let thermal_report = monitor.and_then(thermal::Monitor::stop);
+                (run, thermal_report, _payload)
+            })
+        }
+        .await
+        .map_err(|e| Status::internal(format!("benchmark run failed: {}", e)))?;
+        let Measured {
+            mut latencies,
+            starts,
+            counters,
+            run_counters,
+            run_start,
+            overhead_ns,
+        } = run;
+        if let Some(r) = thermal_report.filter(|r| r.throttled || r.frequency_scaled) {
+            warn!(
+                throttle_events = r.throttle_events,
+                min_frequency_khz = r.min_frequency_khz,
+                max_frequency_khz = r.max_frequency_khz,
+                "CPU speed changed during benchmark; its timings are suspect"
+            );
+        }
+        if inner.subtract_timer_overhead {
+            for l in &mut latencies {
+                *l = (*l - timer_overhead).max(0);
             }
-            let t1 = bench::now_ns();
-            latencies.push(t1 - t0);
         }
-
-        let overhead_end = bench::now_ns();
+        let outliers = if inner.outlier_threshold > 0.0 {
+            let mut flagged = bench::outliers(&latencies, inner.outlier_threshold);
+            flagged.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+            flagged.truncate(MAX_OUTLIERS);
+            flagged
+                .into_iter()
+                .map(|(i, score)| {
+                    let delta = counters.get(i).copied().flatten().unwrap_or_default();
+                    Outlier {
+                        iteration: i as u32,
+                        latency_ns: latencies[i],
+                        score,
+                        probable_cause: delta.probable_cause().unwrap_or_default().to_string(),
+                        run_delay_ns: delta.run_delay_ns,
+                        involuntary_switches: delta.involuntary_switches,
+                        minor_faults: delta.minor_faults,
+                        major_faults: delta.major_faults,
+                    }
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let (intervals, interval_log) = if inner.interval_ns > 0 {
+            let width = Duration::from_nanos(inner.interval_ns);
+            let samples: Vec<(i64, i64)> =
+                starts.into_iter().zip(latencies.iter().copied()).collect();
+            let intervals = bench::intervals(&samples, width, MAX_INTERVALS);
+            let log =
+                bench::interval_log(&intervals, width, run_start).map_err(Status::internal)?;
+            let summaries = intervals
+                .iter()
+                .map(|i| LatencyInterval {
+                    start_ns: i.start_ns,
+                    duration_ns: width.as_nanos() as i64,
+                    count: i.histogram.len(),
+                    p50_ns: i.histogram.value_at_quantile(0.5) as i64,
+                    p99_ns: i.histogram.value_at_quantile(0.99) as i64,
+                    max_ns: i.histogram.max() as i64,
+                })
+                .collect();
+            (summaries, log)
+        } else {
+            (Vec::new(), String::new())
+        };
         latencies.sort_unstable();
 
         let stats = bench::Stats::from_sorted(&latencies);
+        let requested = if inner.percentiles.is_empty() {
+            bench::DEFAULT_PERCENTILES
+        } else {
+            &inner.percentiles
+        };
+        let percentiles = requested
+            .iter()
+            .map(|&p| Percentile {
+                percentile: p,
+                value_ns: bench::percentile(&latencies, p),
+            })
+            .collect();
 
-        Ok(Response::new(BenchmarkResponse {
+        let mut resp = BenchmarkResponse {
             latencies_ns: latencies,
             min_ns: stats.min,
             max_ns: stats.max,
             mean_ns: stats.mean,
             p50_ns: stats.p50,
             p99_ns: stats.p99,
-            processing_overhead_ns: overhead_end - overhead_start,
+            processing_overhead_ns: overhead_ns,
             tls_active: self.tls_enabled,
-            tls_version: if self.tls_enabled {
-                "TLS 1.3".to_string()
-            } else {
+            tls_version: conn
+                .as_ref()
+                .and_then(ConnInfo::tls_version)
+                .map(tls_version_name)
+                .unwrap_or_default(),
+            signature: Vec::new(),
+            signing_key_id: String::new(),
+            clock_source: clock.name().to_string(),
+            timer_overhead_ns: timer_overhead,
+            timer_overhead_subtracted: inner.subtract_timer_overhead,
+            intervals,
+            interval_log,
+            percentiles,
+            outliers,
+            labels,
+            peer_addr: conn
+                .as_ref()
+                .map(|c| c.peer().to_string())
+                .unwrap_or_default(),
+            transport: if self.tls_enabled { "tls" } else { "h2c" }.to_string(),
+            alpn: conn
+                .as_ref()
+                .and_then(ConnInfo::alpn)
+                .map(|p| String::from_utf8_lossy(p).into_owned())
+                .unwrap_or_default(),
+            http2_settings: Some(http2_settings(&self.state.keepalive)),
+            counters: run_counters,
+            payload_content: payload_content.name().to_string(),
+            environment: Some(Environment {
+                payload_numa_node: payload_node,
+                ..environment(env)
+            }),
+            thermal: thermal_report.as_ref().map(thermal),
+            concurrent_benchmarks: concurrent as u32,
+            payload_backing: if _payload.is_empty() {
                 String::new()
+            } else {
+                _payload.backing().name().to_string()
             },
-        }))
+            run_id,
+            seed,
+            replay_of: replay_of.unwrap_or_default(),
+            ..Default::default()
+        };
+        if let (Some(geoip), Some(conn)) = (&self.geoip, &conn) {
+            let Origin {
+                country,
+                asn,
+                as_org,
+            } = geoip.lookup(conn.peer().ip().to_canonical());
+            resp.peer_country = country;
+            resp.peer_asn = asn;
+            resp.peer_as_org = as_org;
+        }
+        if let Some(signer) = &self.signer {
+            signer.sign(&mut resp);
+        }
+        if let Some(webhook) = &self.webhook {
+            webhook.benchmark_completed(&resp);
+        }
+        Ok(Response::new(resp))
+    }
+}
+
+#[tonic::async_trait]
+impl Hermit for HermitService {
+    async fn ping(&self, req: Request<PingRequest>) -> Result<Response<PingResponse>, Status> {
+        let Some(shadow) = &self.shadow else {
+            return alloc_audit::track(HotPath::Ping, self.ping_inner(req)).await;
+        };
+        let mirrored = *req.get_ref();
+        let resp = alloc_audit::track(HotPath::Ping, self.ping_inner(req)).await;
+        let ours = resp.as_ref().map(|r| *r.get_ref());
+        shadow.ping(mirrored, ours.map_err(Status::code));
+        resp
+    }
+
+    async fn benchmark(
+        &self,
+        req: Request<BenchmarkRequest>,
+    ) -> Result<Response<BenchmarkResponse>, Status> {
+        let Some(shadow) = &self.shadow else {
+            return self.benchmark_inner(req, None).await;
+        };
+        let mirrored = req.get_ref().clone();
+        let resp = self.benchmark_inner(req, None).await;
+        let ours = resp.as_ref().map(|r| r.get_ref().clone());
+        shadow.benchmark(mirrored, ours.map_err(Status::code));
+        resp
+    }
+
+    async fn replay_benchmark(
+        &self,
+        req: Request<ReplayBenchmarkRequest>,
+    ) -> Result<Response<BenchmarkResponse>, Status> {
+        let conn = req.extensions().get::<ConnInfo>().cloned();
+        let run_id = req.into_inner().run_id;
+        let Some(run) = self.runs.get(&run_id) else {
+            return Err(Status::not_found(format!("no benchmark run {:?}", run_id)));
+        };
+        let mut replay = Request::new(run.request.unwrap_or_default());
+        if let Some(conn) = conn {
+            replay.extensions_mut().insert(conn);
+        }
+        self.benchmark_inner(replay, Some(run_id)).await
     }
 
     async fn login(&self, req: Request<LoginRequest>) -> Result<Response<LoginResponse>, Status> {
         let inner = req.into_inner();
-        info!(username = %inner.username, "login attempt (hardcoded success)");
+        let user = match self.authenticate(&inner.username, &inner.token).await? {
+            Ok(user) => user,
+            Err(error) => {
+                return Ok(Response::new(LoginResponse {
+                    error,
+                    ..Default::default()
+                }))
+            }
+        };
+
+        // Second factor: mandatory once enrolled, and for every admin.
+        let enrolled = self
+            .totp
+            .is_enrolled(&user.username)
+            .await
+            .map_err(Status::unavailable)?;
+        if enrolled || user.is_admin() {
+            let error = if !enrolled {
+                Some("admin accounts need a TOTP second factor provisioned by an operator")
+            } else {
+                match self
+                    .totp
+                    .verify(&user.username, &inner.totp_code)
+                    .await
+                    .map_err(Status::unavailable)?
+                {
+                    totp::Verify::Ok => None,
+                    totp::Verify::LockedOut => Some(TOTP_LOCKED_OUT),
+                    _ if inner.totp_code.is_empty() => Some("TOTP code required"),
+                    _ => Some("invalid TOTP code"),
+                }
+            };
+            if let Some(error) = error {
+                info!(username = %user.username, "login rejected: {}", error);
+                return Ok(Response::new(LoginResponse {
+                    error: error.to_string(),
+                    totp_required: true,
+                    ..Default::default()
+                }));
+            }
+        }
 
-        // TODO: Real auth. For now, always succeed.
-        let session_id = uuid::Uuid::new_v4().to_string();
+        info!(
+            username = %user.username,
+            admin = user.is_admin(),
+            totp = enrolled,
+            backend = self.auth.name(),
+            sessions = self.sessions.name(),
+            "login succeeded"
+        );
+        let session = Session::new(user.username, user.roles, self.state.session_ttl_secs);
+        self.sessions
+            .create(&session)
+            .await
+            .map_err(Status::unavailable)?;
         Ok(Response::new(LoginResponse {
             success: true,
-            session_id,
+            session_id: session.id,
+            error: String::new(),
+            totp_required: false,
+        }))
+    }
+
+    async fn enroll_totp(
+        &self,
+        req: Request<EnrollTotpRequest>,
+    ) -> Result<Response<EnrollTotpResponse>, Status> {
+        let inner = req.into_inner();
+        let user = match self.authenticate(&inner.username, &inner.token).await? {
+            Ok(user) => user,
+            Err(error) => {
+                return Ok(Response::new(EnrollTotpResponse {
+                    error,
+                    ..Default::default()
+                }))
+            }
+        };
+
+        // Replacing a factor needs the old one, or a stolen password could
+        // silently take over the second factor too. For the same reason an
+        // admin's first factor is provisioned out of band (`hermit-server
+        // enroll-totp`): admins can't log in without one, so a password
+        // alone must not be enough to get one.
+        let enrolled = self
+            .totp
+            .is_enrolled(&user.username)
+            .await
+            .map_err(Status::unavailable)?;
+        if !enrolled && user.is_admin() {
+            info!(username = %user.username, "TOTP self-enrollment refused for admin");
+            return Ok(Response::new(EnrollTotpResponse {
+                error: "admin TOTP must be provisioned by an operator".to_string(),
+                ..Default::default()
+            }));
+        }
+        if enrolled {
+            match self
+                .totp
+                .verify(&user.username, &inner.totp_code)
+                .await
+                .map_err(Status::unavailable)?
+            {
+                totp::Verify::Ok => {}
+                totp::Verify::LockedOut => {
+                    return Ok(Response::new(EnrollTotpResponse {
+                        error: TOTP_LOCKED_OUT.to_string(),
+                        ..Default::default()
+                    }))
+                }
+                _ => {
+                    return Ok(Response::new(EnrollTotpResponse {
+                        error: "current TOTP code required to re-enroll".to_string(),
+                        ..Default::default()
+                    }))
+                }
+            }
+        }
+
+        let otpauth_uri = self
+            .totp
+            .enroll(&user.username)
+            .await
+            .map_err(Status::unavailable)?;
+        info!(username = %user.username, "TOTP enrolled");
+        Ok(Response::new(EnrollTotpResponse {
+            success: true,
+            otpauth_uri,
             error: String::new(),
         }))
     }
 
+    async fn revoke_session(
+        &self,
+        req: Request<RevokeSessionRequest>,
+    ) -> Result<Response<RevokeSessionResponse>, Status> {
+        let caller = self.caller_session(&req).await?;
+        let target_id = req.into_inner().session_id;
+
+        if target_id != caller.id && !caller.is_admin() {
+            let target = self
+                .sessions
+                .get(&target_id)
+                .await
+                .map_err(Status::unavailable)?;
+            // Don't reveal whether someone else's session exists.
+            if target.is_none_or(|t| t.username != caller.username) {
+                return Err(Status::permission_denied("cannot revoke this session"));
+            }
+        }
+
+        let revoked = self
+            .sessions
+            .revoke(&target_id)
+            .await
+            .map_err(Status::unavailable)?;
+        info!(caller = %caller.username, revoked, "session revoke");
+        Ok(Response::new(RevokeSessionResponse { revoked }))
+    }
+
+    async fn list_sessions(
+        &self,
+        req: Request<ListSessionsRequest>,
+    ) -> Result<Response<ListSessionsResponse>, Status> {
+        let caller = self.caller_session(&req).await?;
+        let requested = req.into_inner().username;
+        let filter = if !caller.is_admin() {
+            Some(caller.username.as_str())
+        } else if requested.is_empty() {
+            None
+        } else {
+            Some(requested.as_str())
+        };
+
+        let sessions = self
+            .sessions
+            .list(filter)
+            .await
+            .map_err(Status::unavailable)?
+            .into_iter()
+            .map(|s| SessionInfo {
+                session_id: s.id,
+                username: s.username,
+                roles: s.roles,
+                created_at_unix: s.created_at_unix,
+                expires_at_unix: s.expires_at_unix,
+            })
+            .collect();
+        Ok(Response::new(ListSessionsResponse { sessions }))
+    }
+
     async fn server_info(
         &self,
-        _req: Request<ServerInfoRequest>,
+        req: Request<ServerInfoRequest>,
     ) -> Result<Response<ServerInfoResponse>, Status> {
-        let uptime = self.state.start_instant.elapsed().as_secs() as i64;
-        let since_epoch = self.state.started_at
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default();
+        let mut info = self.info_source().snapshot();
+        if let Some(conn) = req.extensions().get::<ConnInfo>() {
+            if let Some(path) = conn.path_sizes() {
+                info.send_mss = path.send_mss;
+                info.path_mtu = path.pmtu;
+            }
+            let setup = conn.setup_times();
+            let ns = |d: Option<Duration>| d.map_or(0, |d| d.as_nanos() as i64);
+            info.tls_handshake_ns = ns(setup.handshake);
+            info.first_request_ns = ns(setup.first_request);
+            if let Some(group) = conn.kx_group() {
+                info.tls_kx_group = format!("{:?}", group);
+            }
+        }
+        Ok(Response::new(info))
+    }
 
-        Ok(Response::new(ServerInfoResponse {
-            version: self.state.version.clone(),
-            region: self.state.region.clone(),
-            started_at: Some(Timestamp {
-                seconds: since_epoch.as_secs() as i64,
-                nanos: since_epoch.subsec_nanos() as i32,
-            }),
-            uptime_seconds: uptime,
-            rust_version: env!("CARGO_PKG_VERSION").to_string(),
-            tls_enabled: self.tls_enabled,
-            grpc_port: self.state.grpc_port as u32,
+    type WatchServerInfoStream = ReceiverStream<Result<ServerInfoResponse, Status>>;
+
+    async fn watch_server_info(
+        &self,
+        _req: Request<ServerInfoRequest>,
+    ) -> Result<Response<Self::WatchServerInfoStream>, Status> {
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(push_server_info(self.info_source(), tx));
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn capabilities(
+        &self,
+        _req: Request<CapabilitiesRequest>,
+    ) -> Result<Response<CapabilitiesResponse>, Status> {
+        let mut transports = vec![if self.tls_enabled { "tls" } else { "h2c" }];
+        if self.tls_enabled && self.state.alpn_multiplex {
+            transports.extend(["http/1.1", "hermit-throughput/1"]);
+        }
+        let mut features = Vec::new();
+        if self.signer.is_some() {
+            features.push("benchmark-signing");
+        }
+        if bench::clock_source() == "tsc" {
+            features.push("tsc-clock");
+        }
+        if self.geoip.is_some() {
+            features.push("geoip");
+        }
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect();
+        Ok(Response::new(CapabilitiesResponse {
+            protocol_version: PROTOCOL_VERSION,
+            rpcs: strings(RPCS),
+            compression: strings(&["identity"]),
+            transports: strings(&transports),
+            features: strings(&features),
+        }))
+    }
+
+    async fn get_schema(
+        &self,
+        _req: Request<SchemaRequest>,
+    ) -> Result<Response<SchemaResponse>, Status> {
+        Ok(Response::new(SchemaResponse {
+            file_descriptor_set: crate::hermit::FILE_DESCRIPTOR_SET.to_vec(),
+            proto_schema_version: build_info::PROTO_SCHEMA_VERSION.to_string(),
+            protocol_version: PROTOCOL_VERSION,
         }))
     }
 
-    async fn kv_set(
+    async fn get_cert_info(
         &self,
-        req: Request<KvSetRequest>,
-    ) -> Result<Response<KvSetResponse>, Status> {
+        req: Request<CertInfoRequest>,
+    ) -> Result<Response<CertInfoResponse>, Status> {
+        let certs = self
+            .certs
+            .as_ref()
+            .ok_or_else(|| Status::failed_precondition("TLS is disabled"))?;
+        let server_name = req.into_inner().server_name;
+        let cert = certs
+            .details(Some(server_name.as_str()).filter(|n| !n.is_empty()))
+            .map_err(Status::internal)?;
+        let timestamp = |seconds| Timestamp { seconds, nanos: 0 };
+        Ok(Response::new(CertInfoResponse {
+            leaf_der: cert.der,
+            sha256: cert.sha256,
+            spki_sha256: cert.spki_sha256,
+            sans: cert.sans,
+            not_before: Some(timestamp(cert.not_before_unix)),
+            not_after: Some(timestamp(cert.not_after_unix)),
+        }))
+    }
+
+    async fn kv_set(&self, req: Request<KvSetRequest>) -> Result<Response<KvSetResponse>, Status> {
         let inner = req.into_inner();
         match self.db.kv_set(inner.key, inner.value) {
             Ok(()) => Ok(Response::new(KvSetResponse {
@@ -148,10 +1201,7 @@ impl Hermit for HermitService {
         }
     }
 
-    async fn kv_get(
-        &self,
-        req: Request<KvGetRequest>,
-    ) -> Result<Response<KvGetResponse>, Status> {
+    async fn kv_get(&self, req: Request<KvGetRequest>) -> Result<Response<KvGetResponse>, Status> {
         let inner = req.into_inner();
         match self.db.kv_get(&inner.key) {
             Ok(Some(value)) => Ok(Response::new(KvGetResponse {
@@ -239,43 +1289,654 @@ impl Hermit for HermitService {
             rel_pending_writes: rel_pending,
         }))
     }
+
+    async fn alloc_stats(
+        &self,
+        req: Request<AllocStatsRequest>,
+    ) -> Result<Response<AllocStatsResponse>, Status> {
+        let caller = self.caller_session(&req).await?;
+        if !caller.is_admin() {
+            return Err(Status::permission_denied(
+                "AllocStats requires an admin session",
+            ));
+        }
+        let stats = alloc_audit::stats().ok_or_else(|| {
+            Status::failed_precondition(
+                "allocation counting requires building with --features alloc-audit",
+            )
+        })?;
+        let paths = stats
+            .into_iter()
+            .map(|(path, s)| HotPathAllocs {
+                path: path.name().to_string(),
+                requests: s.requests,
+                allocations: s.allocations,
+                allocated_bytes: s.bytes,
+                allocations_per_request: s.allocations_per_request(),
+            })
+            .collect();
+        Ok(Response::new(AllocStatsResponse { paths }))
+    }
+
+    async fn list_peers(
+        &self,
+        req: Request<ListPeersRequest>,
+    ) -> Result<Response<ListPeersResponse>, Status> {
+        self.caller_session(&req).await?;
+        let Some(gossip) = &self.gossip else {
+            let registry = self.registry.as_ref().ok_or_else(|| {
+                Status::failed_precondition("neither gossip nor the etcd registry is enabled")
+            })?;
+            let me = &registry.local().grpc_addr;
+            let peers = registry
+                .peers()
+                .into_iter()
+                .map(|p| Peer {
+                    local: &p.grpc_addr == me,
+                    region: p.region,
+                    grpc_addr: p.grpc_addr,
+                    status: "registered".to_string(),
+                    ..Default::default()
+                })
+                .collect();
+            return Ok(Response::new(ListPeersResponse { peers }));
+        };
+        let me = gossip.addr();
+        let peers = gossip
+            .members()
+            .into_iter()
+            .map(|m| Peer {
+                gossip_addr: m.addr.to_string(),
+                region: m.region,
+                grpc_addr: m.grpc_addr,
+                status: m.status.name().to_string(),
+                incarnation: m.incarnation,
+                local: m.addr == me,
+            })
+            .collect();
+        Ok(Response::new(ListPeersResponse { peers }))
+    }
+
+    async fn get_sweep(
+        &self,
+        req: Request<GetSweepRequest>,
+    ) -> Result<Response<GetSweepResponse>, Status> {
+        self.caller_session(&req).await?;
+        let sweeper = self.sweeper.as_ref().ok_or_else(|| {
+            Status::failed_precondition("sweeps are not enabled (--sweep-interval)")
+        })?;
+        let mut resp = GetSweepResponse {
+            leader: sweeper.leader().to_string(),
+            is_leader: sweeper.is_leader(),
+            ..Default::default()
+        };
+        if let Some(sweep) = sweeper.last() {
+            resp.started_at = Some(sweep.started_at.into());
+            resp.duration_ns = sweep.elapsed.as_nanos() as i64;
+            resp.peers = sweep
+                .peers
+                .iter()
+                .map(|p| PeerLatency {
+                    region: p.region.clone(),
+                    grpc_addr: p.grpc_addr.clone(),
+                    error: p.error.clone().unwrap_or_default(),
+                    ..peer_latency(&p.stats())
+                })
+                .collect();
+            resp.fleet = Some(peer_latency(&sweep.stats()));
+        }
+        Ok(Response::new(resp))
+    }
+
+    async fn get_listener_diagnostics(
+        &self,
+        req: Request<ListenerDiagnosticsRequest>,
+    ) -> Result<Response<ListenerDiagnosticsResponse>, Status> {
+        self.caller_session(&req).await?;
+        let listeners = METRICS
+            .listeners()
+            .iter()
+            .map(|l| ListenerDiagnostics {
+                addr: l.addr().to_string(),
+                tls: l.is_tls(),
+                failure_counts: HandshakeFailure::ALL
+                    .into_iter()
+                    .map(|reason| (reason, l.handshake_failures(reason)))
+                    .filter(|&(_, count)| count > 0)
+                    .map(|(reason, count)| HandshakeFailureCount {
+                        reason: reason.name().to_string(),
+                        count,
+                    })
+                    .collect(),
+                recent_failures: l
+                    .recent_handshake_failures()
+                    .into_iter()
+                    .map(|f| FailedHandshake {
+                        at: Some(f.at.into()),
+                        peer: f.peer.to_string(),
+                        reason: f.reason.name().to_string(),
+                        sni: f.sni.unwrap_or_default(),
+                        detail: f.detail,
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(Response::new(ListenerDiagnosticsResponse { listeners }))
+    }
 }
 
 pub async fn serve(
-    port: u16,
+    tcp: tokio::net::TcpListener,
     state: Arc<ServerState>,
     tls_cfg: Option<TlsConfig>,
-    db: Arc<Database>,
+    backends: Backends,
+    shutdown: impl Future<Output = ()>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let addr = format!("0.0.0.0:{}", port).parse()?;
+    let addr: SocketAddr = tcp.local_addr()?;
+    if let Some(mss) = state.tcp_maxseg {
+        listener::set_max_segment(&tcp, mss)?;
+    }
     let tls_enabled = tls_cfg.is_some();
+    let deadlines = DeadlineLayer::new(state.deadlines.clone());
+    let keepalive = state.keepalive;
+    let dscp = state.dscp;
+    let trace_frames = state.trace_frames;
+    let multiplex = state.alpn_multiplex.then(|| state.health.clone());
+    let timing = TimingLayer::new(state.clock.clone());
+    let gauges = METRICS.register_listener(addr, tls_enabled);
     let svc = HermitService {
         state,
         tls_enabled,
-        db,
+        db: backends.db,
+        auth: backends.auth,
+        totp: backends.totp,
+        sessions: backends.sessions,
+        signer: backends.signer,
+        webhook: backends.webhook,
+        geoip: backends.geoip,
+        shadow: backends.shadow,
+        gossip: backends.gossip,
+        registry: backends.registry,
+        sweeper: backends.sweeper,
+        runs: backends.runs,
+        listener: gauges.clone(),
+        certs: tls_cfg.as_ref().map(|cfg| cfg.certs.clone()),
     };
 
     let grpc_svc = HermitServer::with_interceptor(svc, crate::auth::secret_interceptor);
 
-    match tls_cfg {
+    let mut builder = tonic::transport::Server::builder();
+    if let Some(age) = keepalive.max_age {
+        builder = builder.max_connection_age(age);
+    }
+    let router = builder
+        .initial_stream_window_size(H2_STREAM_WINDOW)
+        .initial_connection_window_size(H2_CONNECTION_WINDOW)
+        .max_frame_size(H2_MAX_FRAME_SIZE)
+        .http2_max_header_list_size(H2_MAX_HEADER_LIST_SIZE)
+        .http2_keepalive_interval(keepalive.http2_interval)
+        .http2_keepalive_timeout(Some(keepalive.http2_timeout))
+        .layer(timing)
+        .layer(InFlightLayer::new(gauges.clone()))
+        .layer(deadlines)
+        .add_service(grpc_svc);
+
+    let result = match tls_cfg {
         Some(cfg) => {
-            let identity = tonic::transport::Identity::from_pem(&cfg.cert_pem, &cfg.key_pem);
-            let tls = tonic::transport::ServerTlsConfig::new().identity(identity);
-            info!(%addr, "gRPC server listening (TLS)");
-            tonic::transport::Server::builder()
-                .tls_config(tls)?
-                .add_service(grpc_svc)
-                .serve(addr)
-                .await?;
+            let mut server_config = cfg.server_config;
+            if multiplex.is_some() {
+                let mut with_alpn = (*server_config).clone();
+                with_alpn.alpn_protocols.extend([
+                    listener::ALPN_HTTP.to_vec(),
+                    listener::ALPN_THROUGHPUT.to_vec(),
+                ]);
+                server_config = Arc::new(with_alpn);
+            }
+            let acceptor = tokio_rustls::TlsAcceptor::from(server_config);
+            info!(%addr, multiplex = multiplex.is_some(), "gRPC server listening (TLS)");
+            let incoming = listener::tls_incoming(
+                tcp,
+                acceptor,
+                keepalive,
+                dscp,
+                gauges.clone(),
+                multiplex,
+                trace_frames,
+            );
+            router
+                .serve_with_incoming_shutdown(incoming, shutdown)
+                .await
         }
         None => {
             info!(%addr, "gRPC server listening (plaintext h2c)");
-            tonic::transport::Server::builder()
-                .add_service(grpc_svc)
-                .serve(addr)
-                .await?;
+            router
+                .serve_with_incoming_shutdown(
+                    listener::tcp_incoming(tcp, keepalive, dscp, gauges.clone(), trace_frames),
+                    shutdown,
+                )
+                .await
         }
-    }
+    };
+    METRICS.unregister_listener(&gauges);
+    result?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AllowAllBackend;
+    use crate::clock::MockClock;
+    use crate::session::MemorySessionStore;
+
+    /// Handlers on a clock that advances exactly `step` per read.
+    fn service(step: Duration) -> HermitService {
+        HermitService {
+            state: Arc::new(ServerState {
+                version: "test".to_string(),
+                region: "test".to_string(),
+                started_at: SystemTime::now(),
+                start_instant: Instant::now(),
+                grpc_port: 0,
+                session_ttl_secs: 3600,
+                deadlines: Deadlines::new(Duration::from_secs(30)),
+                keepalive: Keepalive {
+                    tcp: None,
+                    http2_interval: None,
+                    http2_timeout: Duration::from_secs(10),
+                    max_age: None,
+                    max_age_grace: Duration::ZERO,
+                    max_idle: None,
+                },
+                dscp: None,
+                tcp_maxseg: None,
+                alpn_multiplex: false,
+                trace_frames: 0,
+                clock: Arc::new(MockClock::with_step(1_000, step)),
+                health: Arc::new(Health::new()),
+                pair: None,
+            }),
+            tls_enabled: false,
+            db: Arc::new(Database::new()),
+            auth: Arc::new(AllowAllBackend),
+            totp: Arc::new(totp::Totp::new(Arc::new(totp::MemoryTotpStore::new()))),
+            sessions: Arc::new(MemorySessionStore::new()),
+            signer: None,
+            webhook: None,
+            geoip: None,
+            shadow: None,
+            gossip: None,
+            registry: None,
+            sweeper: None,
+            runs: Arc::new(RunLog::new()),
+            listener: Arc::new(ListenerMetrics::new(([127, 0, 0, 1], 0).into(), false)),
+            certs: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn ping_splits_stack_and_handler_time() {
+        let svc = service(Duration::from_nanos(10));
+        let mut req = Request::new(PingRequest {
+            client_send_ns: 5,
+            client_send_realtime_ns: 0,
+        });
+        req.extensions_mut().insert(RequestArrival(900));
+        let resp = svc.ping(req).await.unwrap().into_inner();
+        assert_eq!(resp.client_send_ns, 5);
+        assert_eq!(resp.server_stack_recv_ns, 900);
+        assert_eq!(resp.server_recv_ns, 1_000);
+        assert_eq!(resp.server_send_ns, 1_010);
+        assert_eq!(resp.stack_overhead_ns, 100);
+        assert_eq!(resp.handler_ns, 10);
+        assert_eq!(resp.upstream_ns, None);
+    }
+
+    #[tokio::test]
+    async fn ping_reports_upstream_delay_from_wall_clocks() {
+        let svc = service(Duration::from_nanos(10));
+        let resp = svc
+            .ping(Request::new(PingRequest {
+                client_send_ns: 5,
+                client_send_realtime_ns: 700,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        // The monotonic read at 1_000 steps the mock clock to 1_010.
+        assert_eq!(resp.server_recv_realtime_ns, 1_010);
+        assert_eq!(resp.server_send_realtime_ns, 1_010);
+        assert_eq!(resp.upstream_ns, Some(310));
+    }
+
+    #[tokio::test]
+    async fn alloc_stats_requires_admin_and_feature() {
+        let svc = service(Duration::from_nanos(10));
+        let request = |session: &Session| {
+            let mut req = Request::new(AllocStatsRequest {});
+            req.metadata_mut()
+                .insert("x-hermit-session", session.id.parse().unwrap());
+            req
+        };
+        let user = Session::new("alice".to_string(), Vec::new(), 60);
+        let admin = Session::new("root".to_string(), vec!["admin".to_string()], 60);
+        svc.sessions.create(&user).await.unwrap();
+        svc.sessions.create(&admin).await.unwrap();
+
+        let err = svc.alloc_stats(request(&user)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+
+        svc.ping(Request::new(PingRequest::default()))
+            .await
+            .unwrap();
+        let resp = svc.alloc_stats(request(&admin)).await;
+        if cfg!(feature = "alloc-audit") {
+            let paths = resp.unwrap().into_inner().paths;
+            let ping = paths.iter().find(|p| p.path == "ping").unwrap();
+            assert!(ping.requests >= 1);
+        } else {
+            assert_eq!(resp.unwrap_err().code(), tonic::Code::FailedPrecondition);
+        }
+    }
+
+    #[tokio::test]
+    async fn benchmark_stats_on_mock_clock() {
+        let svc = service(Duration::from_nanos(10));
+        let resp = svc
+            .benchmark(Request::new(BenchmarkRequest {
+                iterations: 4,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.latencies_ns, vec![10; 4]);
+        assert_eq!((resp.min_ns, resp.max_ns, resp.mean_ns), (10, 10, 10));
+        assert_eq!((resp.p50_ns, resp.p99_ns), (10, 10));
+        assert_eq!(resp.timer_overhead_ns, 10);
+        // One read to start, two per iteration, one to finish.
+        assert_eq!(resp.processing_overhead_ns, 90);
+        assert_eq!(resp.clock_source, "mock");
+    }
+
+    #[tokio::test]
+    async fn benchmark_subtracts_timer_overhead() {
+        let svc = service(Duration::from_nanos(10));
+        let resp = svc
+            .benchmark(Request::new(BenchmarkRequest {
+                iterations: 3,
+                subtract_timer_overhead: true,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.latencies_ns, vec![0; 3]);
+        assert!(resp.timer_overhead_subtracted);
+    }
+
+    #[tokio::test]
+    async fn benchmark_reports_intervals() {
+        let svc = service(Duration::from_micros(250));
+        let resp = svc
+            .benchmark(Request::new(BenchmarkRequest {
+                iterations: 10,
+                interval_ns: 1_000_000,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        // Iterations start 500us apart, beginning 250us into the run.
+        let starts: Vec<i64> = resp.intervals.iter().map(|i| i.start_ns).collect();
+        assert_eq!(starts, vec![0, 1_000_000, 2_000_000, 3_000_000, 4_000_000]);
+        assert_eq!(resp.intervals.iter().map(|i| i.count).sum::<u64>(), 10);
+        // Histogram values are good to 3 significant figures.
+        assert!(resp
+            .intervals
+            .iter()
+            .all(|i| (i.max_ns - 250_000).abs() <= 250));
+        assert!(resp.interval_log.contains("#[BaseTime:"));
+
+        // Too fine a width would cost a histogram per sample.
+        for interval_ns in [1, 999_999, u64::MAX] {
+            let err = svc
+                .benchmark(Request::new(BenchmarkRequest {
+                    iterations: 10,
+                    interval_ns,
+                    ..Default::default()
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn benchmark_reports_requested_percentiles() {
+        let svc = service(Duration::from_nanos(10));
+        let request = |percentiles: Vec<f64>| {
+            Request::new(BenchmarkRequest {
+                iterations: 4,
+                percentiles,
+                ..Default::default()
+            })
+        };
+
+        let resp = svc.benchmark(request(vec![99.9, 50.0])).await.unwrap();
+        let got: Vec<(f64, i64)> = resp
+            .into_inner()
+            .percentiles
+            .iter()
+            .map(|p| (p.percentile, p.value_ns))
+            .collect();
+        assert_eq!(got, vec![(99.9, 10), (50.0, 10)]);
+
+        let resp = svc.benchmark(request(Vec::new())).await.unwrap();
+        assert_eq!(resp.into_inner().percentiles.len(), 5);
+
+        let err = svc.benchmark(request(vec![101.0])).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn benchmark_flags_outliers() {
+        let svc = service(Duration::from_nanos(10));
+        let resp = svc
+            .benchmark(Request::new(BenchmarkRequest {
+                iterations: 8,
+                outlier_threshold: 3.5,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        // Every sample on a stepping mock clock takes exactly one step.
+        assert!(resp.outliers.is_empty());
+
+        let err = svc
+            .benchmark(Request::new(BenchmarkRequest {
+                iterations: 8,
+                outlier_threshold: -1.0,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_benchmarks() {
+        let svc = service(Duration::from_nanos(10));
+        let resp = svc
+            .benchmark(Request::new(BenchmarkRequest {
+                iterations: 3,
+                payload_bytes: 64,
+                payload_content: "random".to_string(),
+                labels: vec![
+                    Label {
+                        key: "z".to_string(),
+                        value: "1".to_string(),
+                    },
+                    Label {
+                        key: "a".to_string(),
+                        value: "2".to_string(),
+                    },
+                ],
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.seed, bench::DEFAULT_SEED);
+        assert_eq!(resp.run_id.len(), 16);
+        let run = svc.runs.get(&resp.run_id).unwrap();
+        let recorded = run.request.unwrap();
+        assert_eq!(
+            (recorded.seed, recorded.iterations),
+            (bench::DEFAULT_SEED, 3)
+        );
+        assert_eq!(recorded.labels, resp.labels);
+
+        let replay = svc
+            .replay_benchmark(Request::new(ReplayBenchmarkRequest {
+                run_id: resp.run_id.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(replay.replay_of, resp.run_id);
+        assert_ne!(replay.run_id, resp.run_id);
+        assert_eq!(replay.latencies_ns.len(), 3);
+        assert_eq!(
+            (replay.seed, replay.payload_content, replay.labels),
+            (resp.seed, resp.payload_content, resp.labels)
+        );
+        let again = svc.runs.get(&replay.run_id).unwrap();
+        assert_eq!(again.request, Some(recorded));
+
+        let err = svc
+            .replay_benchmark(Request::new(ReplayBenchmarkRequest {
+                run_id: "0000000000000000".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn benchmark_reports_run_counters() {
+        let svc = service(Duration::from_nanos(10));
+        let resp = svc
+            .benchmark(Request::new(BenchmarkRequest {
+                iterations: 1000,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let available = std::path::Path::new("/proc/thread-self/schedstat").exists();
+        assert_eq!(resp.counters.is_some(), available);
+    }
+
+    #[tokio::test]
+    async fn benchmark_selects_payload_content() {
+        let svc = service(Duration::from_nanos(10));
+        let run = |content: &str| {
+            svc.benchmark(Request::new(BenchmarkRequest {
+                iterations: 2,
+                payload_bytes: 1024,
+                payload_content: content.to_string(),
+                ..Default::default()
+            }))
+        };
+        assert_eq!(run("").await.unwrap().into_inner().payload_content, "fixed");
+        let resp = run("incompressible").await.unwrap().into_inner();
+        assert_eq!(resp.payload_content, "incompressible");
+        let err = run("noise").await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn benchmark_echoes_sorted_labels() {
+        let svc = service(Duration::from_nanos(10));
+        let label = |key: &str, value: &str| Label {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let request = |labels: Vec<Label>| {
+            Request::new(BenchmarkRequest {
+                iterations: 2,
+                labels,
+                ..Default::default()
+            })
+        };
+
+        let resp = svc
+            .benchmark(request(vec![label("sha", "3f2a9c"), label("env", "ci")]))
+            .await
+            .unwrap();
+        assert_eq!(
+            resp.into_inner().labels,
+            vec![label("env", "ci"), label("sha", "3f2a9c")]
+        );
+
+        for bad in [
+            vec![label("env", "ci"), label("env", "prod")],
+            vec![label("", "x")],
+            vec![label("has space", "x")],
+            vec![label("k", &"v".repeat(MAX_LABEL_VALUE_LEN + 1))],
+        ] {
+            let err = svc.benchmark(request(bad)).await.unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn capabilities_list_every_rpc_in_the_proto() {
+        let proto = include_str!("../proto/hermit.proto");
+        let declared: Vec<&str> = proto
+            .lines()
+            .filter_map(|l| l.trim().strip_prefix("rpc "))
+            .filter_map(|l| l.split('(').next())
+            .collect();
+
+        let svc = service(Duration::from_nanos(10));
+        let caps = svc
+            .capabilities(Request::new(CapabilitiesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(caps.rpcs, declared);
+        assert_eq!(caps.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(caps.transports, vec!["h2c".to_string()]);
+        assert!(!caps.features.contains(&"benchmark-signing".to_string()));
+    }
+
+    #[tokio::test]
+    async fn schema_describes_the_served_service() {
+        use prost::Message;
+
+        let svc = service(Duration::from_nanos(10));
+        let schema = svc
+            .get_schema(Request::new(SchemaRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let set =
+            prost_types::FileDescriptorSet::decode(schema.file_descriptor_set.as_slice()).unwrap();
+        let hermit = set
+            .file
+            .iter()
+            .find(|f| f.name() == "hermit.proto")
+            .expect("hermit.proto in descriptor set");
+        let methods: Vec<&str> = hermit.service[0].method.iter().map(|m| m.name()).collect();
+        assert_eq!(methods, RPCS);
+        assert!(set
+            .file
+            .iter()
+            .any(|f| f.name() == "google/protobuf/timestamp.proto"));
+    }
+}
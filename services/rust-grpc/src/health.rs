@@ -0,0 +1,218 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::metrics::METRICS;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+/// Probes get this long to send their request line and headers.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Upper bound on what we read of a probe request; probes send a few
+/// hundred bytes at most.
+const MAX_REQUEST_BYTES: usize = 4096;
+
+/// Pause after a failed accept, which would otherwise be retried in a
+/// tight loop.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Lifecycle as seen by orchestrators. Liveness only says the process is
+/// responsive; readiness additionally requires the gRPC listener to be
+/// serving and the server neither to be draining nor a standby whose
+/// active is up.
+pub struct Health {
+    ready: AtomicBool,
+    draining: AtomicBool,
+    standby: AtomicBool,
+    changes: watch::Sender<()>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Health {
+            ready: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            standby: AtomicBool::new(false),
+            changes: watch::channel(()).0,
+        }
+    }
+
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::Relaxed);
+        self.changes.send_replace(());
+    }
+
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+        self.changes.send_replace(());
+    }
+
+    /// Fail readiness while `standby`, without otherwise affecting the
+    /// lifecycle.
+    pub fn set_standby(&self, standby: bool) {
+        self.standby.store(standby, Ordering::Relaxed);
+        self.changes.send_replace(());
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed) && !self.standby.load(Ordering::Relaxed)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Notified whenever readiness or drain state changes.
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.changes.subscribe()
+    }
+
+    fn readiness(&self) -> (u16, &'static str) {
+        if self.draining.load(Ordering::Relaxed) {
+            (503, "draining")
+        } else if self.standby.load(Ordering::Relaxed) {
+            (503, "standby")
+        } else if self.ready.load(Ordering::Relaxed) {
+            (200, "ready")
+        } else {
+            (503, "starting")
+        }
+    }
+}
+
+/// Resolves once SIGTERM or Ctrl-C arrives and the drain grace period has
+/// passed. Readiness fails for the whole grace period so load balancers
+/// stop routing here before in-flight RPCs are cut off.
+pub async fn drain_on_signal(health: Arc<Health>, grace: Duration) {
+    wait_for_signal().await;
+    info!(?grace, "shutdown requested, draining");
+    health.start_draining();
+    tokio::time::sleep(grace).await;
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut term) => {
+            tokio::select! {
+                _ = term.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        Err(e) => {
+            warn!("cannot listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Serve `GET /healthz` and `GET /readyz` over plain HTTP/1.1, so container
+/// runtimes can probe hermit with curl/wget instead of a gRPC client.
+/// `GET /metrics` exports `METRICS` for Prometheus on the same port.
+pub async fn serve(listener: TcpListener, health: Arc<Health>) {
+    if let Ok(addr) = listener.local_addr() {
+        info!(%addr, "health probes listening");
+    }
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("health accept failed: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let health = health.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, &health).await {
+                debug!(%peer, "health probe failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Answer one probe on an already-accepted connection, e.g. one handed
+/// over from the TLS port by ALPN.
+pub async fn serve_connection<S>(stream: S, health: &Health) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    tokio::time::timeout(REQUEST_TIMEOUT, handle(stream, health))
+        .await
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))?
+}
+
+async fn handle<S>(mut stream: S, health: &Health) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Read the whole header block so closing doesn't reset the connection
+    // with unread data before the probe sees our response.
+    let mut buf = Vec::with_capacity(512);
+    let mut chunk = [0u8; 512];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < MAX_REQUEST_BYTES {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let request_line = buf.split(|&b| b == b'\r').next().unwrap_or_default();
+    let request_line = String::from_utf8_lossy(request_line);
+    let mut parts = request_line.split(' ');
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+
+    let (code, content_type, body) = match (method, path) {
+        ("GET" | "HEAD", "/metrics") => (
+            200,
+            "text/plain; version=0.0.4",
+            METRICS.render_prometheus(),
+        ),
+        _ => {
+            let (code, status) = match (method, path) {
+                ("GET" | "HEAD", "/healthz") => (200, "ok"),
+                ("GET" | "HEAD", "/readyz") => health.readiness(),
+                ("GET" | "HEAD", _) => (404, "not found"),
+                _ => (405, "method not allowed"),
+            };
+            let body = format!("{{\"status\":\"{}\"}}\n", status);
+            (code, "application/json", body)
+        }
+    };
+    let reason = match code {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+    let mut resp = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        code,
+        reason,
+        content_type,
+        body.len()
+    );
+    if method != "HEAD" {
+        resp.push_str(&body);
+    }
+    stream.write_all(resp.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::output;
+use std::fmt::Write;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// gRPC metadata carrying a request's hops: `name;recv_ns;send_ns`
+/// entries, comma-separated, in the order they were crossed.
+pub const METADATA_KEY: &str = "x-hermit-hops";
+
+/// Hop lists longer than this are refused, so a loop of proxies can't
+/// grow one without bound.
+pub const MAX_HOPS: usize = 16;
+
+/// Longest hop name kept; longer ones are truncated.
+const MAX_NAME: usize = 64;
+
+/// One tier a request passed through on its way to the server, with
+/// wall-clock timestamps (ns since the Unix epoch) of when the request
+/// arrived there and when it was sent on. Timestamps from different
+/// hosts are only as comparable as their clocks are synchronized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hop {
+    pub name: String,
+    /// 0 for the client, which originates rather than receives.
+    pub recv_ns: u64,
+    pub send_ns: u64,
+}
+
+impl Hop {
+    /// A hop whose request arrived at `recv_ns` and leaves now. Names are
+    /// limited to 64 bytes, with ',' and ';' replaced so they survive
+    /// the metadata encoding.
+    pub fn departing(name: &str, recv_ns: u64) -> Hop {
+        let mut name: String = name
+            .chars()
+            .map(|c| match c {
+                ',' | ';' => '_',
+                c if c.is_ascii_graphic() || c == ' ' => c,
+                _ => '?',
+            })
+            .collect();
+        name.truncate(MAX_NAME);
+        Hop {
+            name,
+            recv_ns,
+            send_ns: now_ns(),
+        }
+    }
+}
+
+/// Wall-clock now in ns since the Unix epoch.
+pub fn now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// The echo protocol encoding: a count byte, then per hop a name length
+/// byte, the name, and the two timestamps as big-endian u64s.
+pub fn encode(hops: &[Hop]) -> Vec<u8> {
+    let mut out = vec![hops.len().min(MAX_HOPS) as u8];
+    for hop in hops.iter().take(MAX_HOPS) {
+        let name = &hop.name.as_bytes()[..hop.name.len().min(MAX_NAME)];
+        out.push(name.len() as u8);
+        out.extend_from_slice(name);
+        out.extend_from_slice(&hop.recv_ns.to_be_bytes());
+        out.extend_from_slice(&hop.send_ns.to_be_bytes());
+    }
+    out
+}
+
+/// Reads an `encode`d hop list.
+pub async fn read<R: AsyncRead + Unpin>(r: &mut R) -> io::Result<Vec<Hop>> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let count = r.read_u8().await? as usize;
+    if count > MAX_HOPS {
+        return Err(invalid(format!("{} hops, at most {}", count, MAX_HOPS)));
+    }
+    let mut hops = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len = r.read_u8().await? as usize;
+        if len > MAX_NAME {
+            return Err(invalid(format!("hop name of {} bytes", len)));
+        }
+        let mut name = vec![0; len];
+        r.read_exact(&mut name).await?;
+        hops.push(Hop {
+            name: String::from_utf8_lossy(&name).into_owned(),
+            recv_ns: r.read_u64().await?,
+            send_ns: r.read_u64().await?,
+        });
+    }
+    Ok(hops)
+}
+
+pub fn to_metadata(hops: &[Hop]) -> String {
+    hops.iter()
+        .map(|h| format!("{};{};{}", h.name, h.recv_ns, h.send_ns))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+pub fn from_metadata(s: &str) -> Result<Vec<Hop>, String> {
+    let hops: Vec<Hop> = s
+        .split(',')
+        .filter(|e| !e.trim().is_empty())
+        .map(|entry| {
+            let mut fields = entry.trim().splitn(3, ';');
+            let name = fields.next().unwrap_or_default();
+            let mut ts = || -> Result<u64, String> {
+                fields
+                    .next()
+                    .and_then(|f| f.parse().ok())
+                    .ok_or_else(|| format!("malformed hop {:?}", entry))
+            };
+            Ok(Hop {
+                name: name.to_string(),
+                recv_ns: ts()?,
+                send_ns: ts()?,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    if hops.len() > MAX_HOPS {
+        return Err(format!("{} hops, at most {}", hops.len(), MAX_HOPS));
+    }
+    Ok(hops)
+}
+
+/// One line per hop: the time in transit from the previous hop, the time
+/// held at this one, and the total so far.
+pub fn waterfall(hops: &[Hop]) -> String {
+    let span = |a: u64, b: u64| output::duration(b as i64 - a as i64);
+    let width = hops.iter().map(|h| h.name.len()).max().unwrap_or(0);
+    let origin = hops.first().map_or(0, |h| h.send_ns);
+    let mut out = String::new();
+    let mut prev_send = None;
+    for hop in hops {
+        let transit = prev_send.map_or(String::new(), |p| {
+            format!("+{} in transit, ", span(p, hop.recv_ns))
+        });
+        let held = if hop.recv_ns == 0 {
+            String::new()
+        } else {
+            format!("{} held, ", span(hop.recv_ns, hop.send_ns))
+        };
+        let _ = writeln!(
+            out,
+            "{:width$}  {}{}{} total",
+            hop.name,
+            transit,
+            held,
+            span(origin, hop.send_ns),
+        );
+        prev_send = Some(hop.send_ns);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trips_both_encodings() {
+        let hops = vec![
+            Hop {
+                name: "client".to_string(),
+                recv_ns: 0,
+                send_ns: 1_000_000,
+            },
+            Hop::departing("edge;proxy,1", 1_050_000),
+        ];
+        assert_eq!(hops[1].name, "edge_proxy_1");
+        let mut wire = &encode(&hops)[..];
+        assert_eq!(read(&mut wire).await.unwrap(), hops);
+        assert_eq!(from_metadata(&to_metadata(&hops)).unwrap(), hops);
+        assert!(from_metadata("a;1").is_err());
+        assert_eq!(from_metadata("").unwrap(), vec![]);
+
+        let lines = waterfall(&[
+            hops[0].clone(),
+            Hop {
+                name: "server".to_string(),
+                recv_ns: 1_050_000,
+                send_ns: 1_060_000,
+            },
+        ]);
+        assert_eq!(
+            lines,
+            "client  0ns total\nserver  +50.0µs in transit, 10.0µs held, 60.0µs total\n"
+        );
+    }
+}
@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by --hugepages.
+static PREFERRED: AtomicBool = AtomicBool::new(false);
+
+/// Allocate benchmark payloads and throughput blocks from hugepages from
+/// now on.
+pub fn set_preferred(preferred: bool) {
+    PREFERRED.store(preferred, Ordering::Relaxed);
+}
+
+/// Free pages in the explicit hugepage pool; `None` when the kernel has
+/// none configured (or off Linux).
+pub fn pool_free() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let field = |name: &str| -> Option<u64> {
+        let line = meminfo.lines().find(|l| l.starts_with(name))?;
+        line.split_whitespace().nth(1)?.parse().ok()
+    };
+    (field("HugePages_Total:")? > 0).then(|| field("HugePages_Free:"))?
+}
+
+/// What backs a `Buffer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backing {
+    /// Ordinary pages.
+    Regular,
+    /// Explicit hugepages from the kernel's pool (vm.nr_hugepages).
+    Hugetlb,
+    /// Transparent hugepages the kernel chose to use after madvise.
+    Transparent,
+}
+
+impl Backing {
+    pub fn name(self) -> &'static str {
+        match self {
+            Backing::Regular => "regular",
+            Backing::Hugetlb => "hugetlb",
+            Backing::Transparent => "transparent",
+        }
+    }
+}
+
+/// A zeroed byte buffer that, with --hugepages, is mapped from hugepages
+/// to spare the TLB on large memory-bound workloads. It tries the
+/// explicit hugepage pool first, then madvise for transparent hugepages,
+/// then ordinary memory, so it always allocates; `backing` says which
+/// the kernel actually provided. Mappings are rounded up to whole
+/// hugepages.
+pub struct Buffer {
+    inner: Inner,
+}
+
+enum Inner {
+    Heap(Vec<u8>),
+    #[cfg(target_os = "linux")]
+    Mapped {
+        ptr: *mut u8,
+        len: usize,
+        map_len: usize,
+        hugetlb: bool,
+    },
+}
+
+// SAFETY: a Mapped buffer owns its mapping exclusively, like a Vec.
+unsafe impl Send for Buffer {}
+// SAFETY: shared references only read the mapping, like a Vec's.
+unsafe impl Sync for Buffer {}
+
+impl Buffer {
+    pub fn new(len: usize) -> Buffer {
+        #[cfg(target_os = "linux")]
+        if len > 0 && PREFERRED.load(Ordering::Relaxed) {
+            if let Some(inner) = map(len) {
+                return Buffer { inner };
+            }
+        }
+        Buffer {
+            inner: Inner::Heap(vec![0; len]),
+        }
+    }
+
+    /// Reads /proc/self/smaps for transparent hugepages, so call it after
+    /// the buffer has been written, and not on a hot path.
+    pub fn backing(&self) -> Backing {
+        match self.inner {
+            Inner::Heap(_) => Backing::Regular,
+            #[cfg(target_os = "linux")]
+            Inner::Mapped { hugetlb: true, .. } => Backing::Hugetlb,
+            #[cfg(target_os = "linux")]
+            Inner::Mapped { ptr, .. } => {
+                let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap_or_default();
+                if anon_huge_kb(&smaps, ptr as usize).unwrap_or(0) > 0 {
+                    Backing::Transparent
+                } else {
+                    Backing::Regular
+                }
+            }
+        }
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.inner {
+            Inner::Heap(v) => v,
+            #[cfg(target_os = "linux")]
+            Inner::Mapped { ptr, len, .. } => {
+                // SAFETY: `ptr` is page-aligned and non-null, and the
+                // mapping is readable, zero-filled and at least `len`
+                // bytes long for as long as we own it.
+                unsafe { std::slice::from_raw_parts(*ptr, *len) }
+            }
+        }
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match &mut self.inner {
+            Inner::Heap(v) => v,
+            #[cfg(target_os = "linux")]
+            Inner::Mapped { ptr, len, .. } => {
+                // SAFETY: as in deref; the mapping is also writable, and
+                // `&mut self` makes this the only reference to it.
+                unsafe { std::slice::from_raw_parts_mut(*ptr, *len) }
+            }
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Inner::Mapped { ptr, map_len, .. } = self.inner {
+            // SAFETY: `ptr` and `map_len` are exactly what mmap returned
+            // and took, and nothing refers to the mapping any more.
+            unsafe { libc::munmap(ptr.cast(), map_len) };
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn map(len: usize) -> Option<Inner> {
+    let huge = hugepage_size();
+    let map_len = len.div_ceil(huge) * huge;
+    let anon = |extra: libc::c_int| {
+        // SAFETY: an anonymous private mapping at an address of the
+        // kernel's choosing aliases nothing.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | extra,
+                -1,
+                0,
+            )
+        };
+        (ptr != libc::MAP_FAILED).then_some(ptr.cast::<u8>())
+    };
+    if let Some(ptr) = anon(libc::MAP_HUGETLB) {
+        return Some(Inner::Mapped {
+            ptr,
+            len,
+            map_len,
+            hugetlb: true,
+        });
+    }
+    let ptr = anon(0)?;
+    // SAFETY: `ptr` is the mapping just created, `map_len` bytes long.
+    // Failure (THP disabled) leaves ordinary pages, which `backing` shows.
+    unsafe { libc::madvise(ptr.cast(), map_len, libc::MADV_HUGEPAGE) };
+    Some(Inner::Mapped {
+        ptr,
+        len,
+        map_len,
+        hugetlb: false,
+    })
+}
+
+/// The default hugepage size from /proc/meminfo, 2 MiB if unreadable.
+#[cfg(target_os = "linux")]
+fn hugepage_size() -> usize {
+    std::fs::read_to_string("/proc/meminfo")
+        .ok()
+        .and_then(|m| {
+            let line = m.lines().find(|l| l.starts_with("Hugepagesize:"))?;
+            let kb: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+            Some(kb * 1024)
+        })
+        .unwrap_or(2 << 20)
+}
+
+/// AnonHugePages of the mapping starting at `start`, in kB.
+fn anon_huge_kb(smaps: &str, start: usize) -> Option<u64> {
+    let mut lines = smaps.lines();
+    lines.find(|l| {
+        l.split_once('-')
+            .and_then(|(s, _)| usize::from_str_radix(s, 16).ok())
+            == Some(start)
+    })?;
+    lines
+        .take_while(|l| {
+            l.split_whitespace()
+                .next()
+                .is_some_and(|k| k.ends_with(':'))
+        })
+        .find_map(|l| l.strip_prefix("AnonHugePages:"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_anon_huge_pages_of_one_mapping() {
+        let smaps = "\
+7f0000000000-7f0000400000 rw-p 00000000 00:00 0
+Size:               4096 kB
+AnonHugePages:      4096 kB
+VmFlags: rd wr mr mw me ac hg
+7f0000400000-7f0000401000 rw-p 00000000 00:00 0
+Size:                  4 kB
+AnonHugePages:         0 kB
+";
+        assert_eq!(anon_huge_kb(smaps, 0x7f00_0000_0000), Some(4096));
+        assert_eq!(anon_huge_kb(smaps, 0x7f00_0040_0000), Some(0));
+        assert_eq!(anon_huge_kb(smaps, 0x1000), None);
+    }
+
+    #[test]
+    fn falls_back_to_a_usable_buffer() {
+        set_preferred(true);
+        let mut buf = Buffer::new(3 << 20);
+        buf.fill(0x5a);
+        assert_eq!(buf.len(), 3 << 20);
+        assert!(buf.iter().all(|&b| b == 0x5a));
+        let _ = buf.backing();
+        set_preferred(false);
+        assert_eq!(Buffer::new(16).backing(), Backing::Regular);
+    }
+}
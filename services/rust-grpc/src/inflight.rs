@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::listener::ConnInfo;
+use crate::metrics::ListenerMetrics;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::codegen::http;
+use tower::{Layer, Service};
+
+/// Counts requests in `ListenerMetrics::rpcs_in_flight` from the moment
+/// they enter the stack until their response future completes or is
+/// dropped (client cancel, deadline). Also marks their connection busy,
+/// so it isn't closed as idle under them.
+#[derive(Clone, Debug)]
+pub struct InFlightLayer {
+    metrics: Arc<ListenerMetrics>,
+}
+
+impl InFlightLayer {
+    pub fn new(metrics: Arc<ListenerMetrics>) -> Self {
+        InFlightLayer { metrics }
+    }
+}
+
+impl<S> Layer<S> for InFlightLayer {
+    type Service = InFlightService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        InFlightService {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InFlightService<S> {
+    inner: S,
+    metrics: Arc<ListenerMetrics>,
+}
+
+impl<S, B> Service<http::Request<B>> for InFlightService<S>
+where
+    S: Service<http::Request<B>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let guard = self.metrics.rpc_started();
+        let conn = req
+            .extensions()
+            .get::<ConnInfo>()
+            .map(ConnInfo::rpc_started);
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let resp = fut.await;
+            drop((guard, conn));
+            resp
+        })
+    }
+}
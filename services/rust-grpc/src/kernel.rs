@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+/// Kernel facilities that vary between hosts. A static binary runs on
+/// whatever kernel the edge host has, and container runtimes commonly
+/// block io_uring with seccomp, so these are probed rather than assumed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Support {
+    pub io_uring: bool,
+    /// The TLS upper-layer protocol (kTLS) can be attached to sockets.
+    pub ktls: bool,
+}
+
+impl Support {
+    /// Probe with syscalls that fail before creating anything, so this is
+    /// cheap and leaves nothing behind. Call before the seccomp filter is
+    /// installed.
+    #[cfg(target_os = "linux")]
+    pub fn probe() -> Support {
+        Support {
+            io_uring: io_uring(),
+            ktls: ktls(),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn probe() -> Support {
+        Support::default()
+    }
+}
+
+/// io_uring_setup with zero entries fails with EINVAL once the kernel has
+/// accepted the call; ENOSYS (kernel too old) and EPERM (seccomp or the
+/// io_uring_disabled sysctl) mean it's unavailable.
+#[cfg(target_os = "linux")]
+fn io_uring() -> bool {
+    // struct io_uring_params is 120 bytes; the kernel only reads it.
+    let mut params = [0u64; 15];
+    // SAFETY: `params` is large enough and suitably aligned for the
+    // struct, and with zero entries no ring is created.
+    let ret = unsafe { libc::syscall(libc::SYS_io_uring_setup, 0u32, params.as_mut_ptr()) };
+    ret == -1 && std::io::Error::last_os_error().raw_os_error() == Some(libc::EINVAL)
+}
+
+/// The TLS ULP only attaches to connected sockets, so on a fresh one
+/// setting TCP_ULP fails with ENOTCONN when it exists and ENOENT when the
+/// tls module is neither loaded nor loadable.
+#[cfg(target_os = "linux")]
+fn ktls() -> bool {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    // SAFETY: socket takes no pointers.
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return false;
+    }
+    // SAFETY: `fd` is a socket we just created and own.
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+    // SAFETY: the option value points at 3 readable bytes.
+    let ret = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_ULP,
+            b"tls".as_ptr().cast(),
+            3,
+        )
+    };
+    ret == -1 && std::io::Error::last_os_error().raw_os_error() == Some(libc::ENOTCONN)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ktls_probe_agrees_with_proc() {
+        // With the module loaded the ULP is listed; without it the probe
+        // may still succeed by autoloading, so only one direction holds.
+        let listed = std::fs::read_to_string("/proc/sys/net/ipv4/tcp_available_ulp")
+            .is_ok_and(|ulps| ulps.split_whitespace().any(|u| u == "tls"));
+        if listed {
+            assert!(Support::probe().ktls);
+        }
+    }
+}
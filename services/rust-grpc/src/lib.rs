@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+// Copyright (c) 2026 Jared Redh. All rights reserved.
+
+#[cfg(feature = "grpc")]
+pub mod hermit {
+    tonic::include_proto!("hermit");
+
+    /// Serialized `FileDescriptorSet` of hermit.proto and its imports.
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("hermit_descriptor");
+}
+
+pub mod alloc_audit;
+#[cfg(feature = "grpc")]
+pub mod attest;
+#[cfg(feature = "grpc")]
+pub mod auth;
+pub mod bench;
+pub mod breaker;
+pub mod build_info;
+pub mod clock;
+pub mod db;
+#[cfg(feature = "grpc")]
+pub mod deadline;
+#[cfg(feature = "grpc")]
+pub mod egress;
+pub mod environment;
+#[cfg(feature = "grpc")]
+pub mod etcd;
+#[cfg(feature = "grpc")]
+pub mod failover;
+#[cfg(feature = "grpc")]
+pub mod frames;
+#[cfg(feature = "grpc")]
+pub mod geoip;
+#[cfg(feature = "grpc")]
+pub mod gossip;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod health;
+pub mod hops;
+pub mod hugepage;
+#[cfg(feature = "grpc")]
+pub mod inflight;
+pub mod kernel;
+#[cfg(feature = "grpc")]
+pub mod listener;
+pub mod metrics;
+#[cfg(feature = "grpc")]
+pub mod notify;
+pub mod numa;
+pub mod output;
+pub mod proxy;
+#[cfg(feature = "grpc")]
+pub mod retry;
+#[cfg(feature = "grpc")]
+pub mod runlog;
+pub mod sandbox;
+#[cfg(feature = "tls")]
+pub mod secrets;
+#[cfg(feature = "grpc")]
+pub mod session;
+#[cfg(feature = "grpc")]
+pub mod shadow;
+pub mod sniff;
+#[cfg(feature = "tls")]
+pub mod spiffe;
+#[cfg(feature = "grpc")]
+pub mod sweep;
+#[cfg(feature = "grpc")]
+pub mod test_harness;
+pub mod thermal;
+pub mod threadstat;
+pub mod throughput;
+#[cfg(feature = "grpc")]
+pub mod timing;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod wakeup;
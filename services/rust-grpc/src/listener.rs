@@ -0,0 +1,1076 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::frames::{Direction, FrameTrace};
+use crate::health::{self, Health};
+use crate::metrics::{FailedHandshake, HandshakeFailure, ListenerMetrics, Port, METRICS};
+use crate::sniff::{self, Protocol};
+use crate::throughput;
+use rustls::{NamedGroup, ProtocolVersion};
+use socket2::{SockRef, Socket, TcpKeepalive};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::Sleep;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::Connected;
+use tracing::{debug, info, warn};
+
+/// Handshakes that take longer than this are abandoned so a slow or
+/// malicious client can't pin a task forever.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after a failed accept. Failures such as running out of file
+/// descriptors persist, and retrying at once would spin a core.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Weight of the newest gap in the running mean time between accepts.
+const ACCEPT_GAP_WEIGHT: f64 = 0.1;
+
+/// ALPN ids served next to gRPC (`h2`) on the TLS port when multiplexing.
+pub const ALPN_HTTP: &[u8] = b"http/1.1";
+pub const ALPN_THROUGHPUT: &[u8] = b"hermit-throughput/1";
+
+/// Unanswered TCP keepalive probes before the kernel drops the connection.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const KEEPALIVE_RETRIES: u32 = 3;
+
+/// Liveness and lifetime settings for accepted connections. TCP keepalive
+/// catches peers whose host vanished; HTTP/2 PINGs (applied on the tonic
+/// builder) catch peers whose kernel still ACKs but whose process is gone
+/// or wedged, and keep NAT mappings of idle connections alive.
+#[derive(Clone, Copy, Debug)]
+pub struct Keepalive {
+    pub tcp: Option<Duration>,
+    pub http2_interval: Option<Duration>,
+    pub http2_timeout: Duration,
+    /// Connections this old are sent a GOAWAY (by tonic) so their clients
+    /// reconnect, which lets load balancers spread them out again.
+    pub max_age: Option<Duration>,
+    /// How long RPCs still running at `max_age` get to finish before the
+    /// connection is closed regardless.
+    pub max_age_grace: Duration,
+    /// Connections with no RPC running for this long are closed.
+    pub max_idle: Option<Duration>,
+}
+
+/// Accept TCP connections and terminate TLS ourselves, yielding finished
+/// streams to tonic. Each handshake runs on its own task so one stalled
+/// client doesn't hold up the accept loop.
+///
+/// With `multiplex`, connections that negotiated `ALPN_HTTP` get the
+/// health/metrics endpoints and `ALPN_THROUGHPUT` the throughput test
+/// instead, so one port serves everything; the acceptor must offer those
+/// ids. `h2` or no ALPN means gRPC.
+///
+/// The first `trace_frames` HTTP/2 frames each way of every gRPC
+/// connection are logged; see `crate::frames`.
+pub fn tls_incoming(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    keepalive: Keepalive,
+    dscp: Option<u8>,
+    gauges: Arc<ListenerMetrics>,
+    multiplex: Option<Arc<Health>>,
+    trace_frames: usize,
+) -> ReceiverStream<Result<Tracked<TlsStream<TcpStream>>, io::Error>> {
+    let (tx, rx) = mpsc::channel(128);
+    tokio::spawn(async move {
+        let mut pacing = AcceptPacing::default();
+        loop {
+            let (tcp, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("accept failed: {}", e);
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            if tx.is_closed() {
+                return;
+            }
+            let accepted = Instant::now();
+            pacing.accepted(&listener, &gauges);
+            configure(&tcp, keepalive, dscp);
+            let socket = SockRef::from(&tcp).try_clone().ok();
+            let raw_socket = throughput::Socket::of(&tcp);
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            let gauges = gauges.clone();
+            let multiplex = multiplex.clone();
+            tokio::spawn(async move {
+                let mut hello = Hello::default();
+                let handshake = async {
+                    hello = read_hello(&tcp).await;
+                    if let Some(sent) = hello.plaintext {
+                        return Err((tcp, sent));
+                    }
+                    Ok(acceptor.accept(tcp).await)
+                };
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+                    Ok(Ok(Ok(stream))) => {
+                        let conn = stream.get_ref().1;
+                        let tls = TlsSetup {
+                            handshake: accepted.elapsed(),
+                            version: conn.protocol_version(),
+                            kx_group: conn.negotiated_key_exchange_group().map(|g| g.name()),
+                            alpn: conn.alpn_protocol().map(<[u8]>::to_vec),
+                        };
+                        match (multiplex, tls.alpn.as_deref()) {
+                            (Some(health), Some(ALPN_HTTP)) => {
+                                if let Err(e) = health::serve_connection(stream, &health).await {
+                                    debug!(%peer, "health probe failed: {}", e);
+                                }
+                            }
+                            (Some(_), Some(ALPN_THROUGHPUT)) => {
+                                throughput::serve_connection(stream, raw_socket, peer).await;
+                            }
+                            _ => {
+                                let setup = ConnSetup::new(accepted, peer, Some(tls));
+                                let tracked = Tracked::new(
+                                    stream,
+                                    socket,
+                                    setup,
+                                    keepalive,
+                                    gauges,
+                                    trace_frames,
+                                );
+                                let _ = tx.send(Ok(tracked)).await;
+                            }
+                        }
+                    }
+                    Ok(Ok(Err(e))) => {
+                        let (reason, detail) = classify(&hello, &e);
+                        handshake_failed(&gauges, peer, reason, hello.sni, detail);
+                    }
+                    Ok(Err((tcp, sent))) => {
+                        // Logged by refuse.
+                        gauges.handshake_failed(FailedHandshake {
+                            at: SystemTime::now(),
+                            peer,
+                            reason: HandshakeFailure::Plaintext,
+                            sni: None,
+                            detail: format!("client sent {} to the TLS port", sent.describe()),
+                        });
+                        sniff::refuse(tcp, peer, Port::Grpc, sent, Protocol::Tls).await;
+                    }
+                    Err(_) => {
+                        let detail = "no handshake within 10s".to_string();
+                        handshake_failed(
+                            &gauges,
+                            peer,
+                            HandshakeFailure::Timeout,
+                            hello.sni,
+                            detail,
+                        );
+                    }
+                }
+            });
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+fn handshake_failed(
+    gauges: &ListenerMetrics,
+    peer: SocketAddr,
+    reason: HandshakeFailure,
+    sni: Option<String>,
+    detail: String,
+) {
+    info!(
+        %peer,
+        reason = reason.name(),
+        sni = sni.as_deref().unwrap_or(""),
+        "TLS handshake failed: {}",
+        detail
+    );
+    gauges.handshake_failed(FailedHandshake {
+        at: SystemTime::now(),
+        peer,
+        reason,
+        sni,
+        detail,
+    });
+}
+
+/// What a connection's first bytes say about the client, read before the
+/// TLS handshake so failures can be explained.
+#[derive(Debug, Default)]
+struct Hello {
+    /// What the client spoke instead of TLS, if it wasn't TLS.
+    plaintext: Option<Protocol>,
+    sni: Option<String>,
+    /// Highest TLS version offered, as the wire value (0x0304 for 1.3).
+    max_version: Option<u16>,
+}
+
+/// Peeks at the ClientHello without consuming it. It usually arrives in
+/// one segment, but a post-quantum key share can push it past one, so a
+/// short record gets another look or two.
+async fn read_hello(tcp: &TcpStream) -> Hello {
+    let mut buf = vec![0u8; 4096];
+    let mut n = 0;
+    for _ in 0..3 {
+        n = match tcp.peek(&mut buf).await {
+            Ok(n) => n,
+            Err(_) => return Hello::default(),
+        };
+        let record = buf
+            .get(3..5)
+            .map(|len| 5 + u16::from_be_bytes([len[0], len[1]]) as usize);
+        if n == 0 || buf[0] != 0x16 || record.is_some_and(|len| n >= len) || n == buf.len() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    parse_hello(&buf[..n])
+}
+
+fn parse_hello(data: &[u8]) -> Hello {
+    let mut hello = Hello::default();
+    match data.first() {
+        None | Some(0x16) => {}
+        Some(_) => {
+            hello.plaintext = Protocol::detect(data);
+            return hello;
+        }
+    }
+    // Record header (5), handshake header (4), then the ClientHello body.
+    let Some(body) = data.get(9..).filter(|_| data.get(5) == Some(&1)) else {
+        return hello;
+    };
+    let mut r = Reader(body);
+    let Some(legacy) = r.u16() else {
+        return hello;
+    };
+    hello.max_version = Some(legacy);
+    let extensions = (|| {
+        r.skip(32)?;
+        let session = r.u8()? as usize;
+        r.skip(session)?;
+        let suites = r.u16()? as usize;
+        r.skip(suites)?;
+        let compression = r.u8()? as usize;
+        r.skip(compression)?;
+        let len = r.u16()? as usize;
+        Some(Reader(r.take(len).unwrap_or(r.0)))
+    })();
+    let Some(mut extensions) = extensions else {
+        return hello;
+    };
+    while let (Some(kind), Some(len)) = (extensions.u16(), extensions.u16()) {
+        let Some(data) = extensions.take(len as usize) else {
+            break;
+        };
+        let mut ext = Reader(data);
+        match kind {
+            // server_name: list length, then (type 0, length, host name).
+            0x0000 => {
+                let name = ext.skip(2).and_then(|_| ext.u8()).filter(|&t| t == 0);
+                if let Some(len) = name.and_then(|_| ext.u16()) {
+                    hello.sni = ext
+                        .take(len as usize)
+                        .map(|n| String::from_utf8_lossy(n).into_owned());
+                }
+            }
+            // supported_versions: a length byte, then versions.
+            0x002b => {
+                let len = ext.u8().unwrap_or(0) as usize;
+                let versions = ext.take(len).unwrap_or_default();
+                hello.max_version = versions
+                    .chunks_exact(2)
+                    .map(|v| u16::from_be_bytes([v[0], v[1]]))
+                    // Skipping GREASE values and drafts.
+                    .filter(|&v| v & 0x0f0f != 0x0a0a && v <= 0x0304)
+                    .max()
+                    .or(hello.max_version);
+            }
+            _ => {}
+        }
+    }
+    hello
+}
+
+/// Cursor over ClientHello bytes; every read is checked.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+fn tls_version_name(version: u16) -> String {
+    match version {
+        0x0300 => "SSL 3.0".to_string(),
+        0x0301..=0x0304 => format!("TLS 1.{}", version - 0x0301),
+        v => format!("version {:#06x}", v),
+    }
+}
+
+/// Sorts a failed handshake into a `HandshakeFailure`, with a line saying
+/// what happened.
+fn classify(hello: &Hello, e: &io::Error) -> (HandshakeFailure, String) {
+    use rustls::{AlertDescription as Alert, Error as Tls, PeerIncompatible as Incompatible};
+
+    if let Some(v) = hello.max_version.filter(|&v| v < 0x0303) {
+        return (
+            HandshakeFailure::ProtocolVersion,
+            format!(
+                "client offered at most {}; TLS 1.2 is the minimum",
+                tls_version_name(v)
+            ),
+        );
+    }
+    let reason = match e.get_ref().and_then(|e| e.downcast_ref::<Tls>()) {
+        Some(Tls::PeerIncompatible(p)) => match p {
+            Incompatible::SupportedVersionsExtensionRequired
+            | Incompatible::Tls12NotOffered
+            | Incompatible::Tls12NotOfferedOrEnabled => HandshakeFailure::ProtocolVersion,
+            _ => HandshakeFailure::NoSharedParameters,
+        },
+        Some(Tls::AlertReceived(alert)) => match alert {
+            Alert::BadCertificate
+            | Alert::UnsupportedCertificate
+            | Alert::CertificateRevoked
+            | Alert::CertificateExpired
+            | Alert::CertificateUnknown
+            | Alert::UnknownCA
+            | Alert::AccessDenied
+            | Alert::UnrecognisedName => HandshakeFailure::CertificateRejected,
+            Alert::ProtocolVersion => HandshakeFailure::ProtocolVersion,
+            Alert::HandshakeFailure | Alert::InsufficientSecurity => {
+                HandshakeFailure::NoSharedParameters
+            }
+            _ => HandshakeFailure::Other,
+        },
+        Some(Tls::NoCertificatesPresented | Tls::InvalidCertificate(_)) => {
+            HandshakeFailure::ClientCertificate
+        }
+        Some(_) => HandshakeFailure::Other,
+        None => match e.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::BrokenPipe => HandshakeFailure::PeerClosed,
+            _ => HandshakeFailure::Other,
+        },
+    };
+    let detail = match (reason, &hello.sni) {
+        (HandshakeFailure::CertificateRejected, Some(sni)) => {
+            format!("{} (client asked for {:?})", e, sni)
+        }
+        _ => e.to_string(),
+    };
+    (reason, detail)
+}
+
+/// Plaintext counterpart of `tls_incoming`, so both paths share socket
+/// options and reap accounting. Clients that open with TLS, HTTP/1 or
+/// throughput test frames instead of HTTP/2 are refused in their own
+/// protocol (see `crate::sniff`); anything else goes to tonic.
+pub fn tcp_incoming(
+    listener: TcpListener,
+    keepalive: Keepalive,
+    dscp: Option<u8>,
+    gauges: Arc<ListenerMetrics>,
+    trace_frames: usize,
+) -> ReceiverStream<Result<Tracked<TcpStream>, io::Error>> {
+    let (tx, rx) = mpsc::channel(128);
+    tokio::spawn(async move {
+        let mut pacing = AcceptPacing::default();
+        loop {
+            let (tcp, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("accept failed: {}", e);
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            if tx.is_closed() {
+                return;
+            }
+            let setup = ConnSetup::new(Instant::now(), peer, None);
+            pacing.accepted(&listener, &gauges);
+            configure(&tcp, keepalive, dscp);
+            let tx = tx.clone();
+            let gauges = gauges.clone();
+            tokio::spawn(async move {
+                let first = tokio::time::timeout(HANDSHAKE_TIMEOUT, sniff::peek(&tcp)).await;
+                if let Ok(Some(sent @ (Protocol::Tls | Protocol::Http1 | Protocol::Throughput))) =
+                    first
+                {
+                    sniff::refuse(tcp, peer, Port::Grpc, sent, Protocol::H2c).await;
+                    return;
+                }
+                let socket = SockRef::from(&tcp).try_clone().ok();
+                let tracked = Tracked::new(tcp, socket, setup, keepalive, gauges, trace_frames);
+                let _ = tx.send(Ok(tracked)).await;
+            });
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+/// Estimates how long connections sit in the kernel's accept queue. By
+/// Little's law the wait is the queue depth times the mean time between
+/// accepts; while a backlog exists, accepts return back to back, so that
+/// mean tracks how fast we drain it.
+#[derive(Default)]
+struct AcceptPacing {
+    last: Option<Instant>,
+    mean_gap_ns: f64,
+}
+
+impl AcceptPacing {
+    fn accepted(&mut self, listener: &TcpListener, gauges: &ListenerMetrics) {
+        let now = Instant::now();
+        if let Some(last) = self.last.replace(now) {
+            let gap = now.duration_since(last).as_nanos() as f64;
+            self.mean_gap_ns = if self.mean_gap_ns == 0.0 {
+                gap
+            } else {
+                self.mean_gap_ns + (gap - self.mean_gap_ns) * ACCEPT_GAP_WEIGHT
+            };
+        }
+        let depth = accept_queue_depth(listener).unwrap_or(0);
+        let wait = Duration::from_nanos((f64::from(depth) * self.mean_gap_ns) as u64);
+        gauges.set_accept_queue(depth, wait);
+    }
+}
+
+/// Connections completed by the kernel but not yet accepted. For a
+/// listening socket Linux reports the accept queue length in
+/// `tcpi_unacked`.
+#[cfg(target_os = "linux")]
+fn accept_queue_depth(listener: &TcpListener) -> Option<u32> {
+    use std::os::fd::AsRawFd;
+
+    tcp_info(listener.as_raw_fd()).map(|info| info.tcpi_unacked)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn accept_queue_depth(_listener: &TcpListener) -> Option<u32> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn tcp_info(fd: std::os::fd::RawFd) -> Option<libc::tcp_info> {
+    // SAFETY: tcp_info is plain integers, so all-zero is a valid value.
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    // SAFETY: `info` and `len` are valid for writes and `len` holds the
+    // size of `info`, as getsockopt requires.
+    let rc = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            (&mut info as *mut libc::tcp_info).cast(),
+            &mut len,
+        )
+    };
+    (rc == 0).then_some(info)
+}
+
+/// Cap the MSS advertised in the SYN-ACK, and so the segment size, of
+/// every connection accepted from `listener` afterwards.
+pub fn set_max_segment(listener: &TcpListener, mss: u32) -> io::Result<()> {
+    SockRef::from(listener).set_mss(mss)
+}
+
+/// Segment and path MTU sizes of one connection, as the kernel currently
+/// sees them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PathSizes {
+    /// MSS used for sending (after options and PMTU discovery).
+    pub send_mss: u32,
+    /// Path MTU discovered for the peer.
+    pub pmtu: u32,
+}
+
+/// How long a connection took to become usable, as the server saw it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SetupTimes {
+    /// TLS handshake, from accept to handshake done; `None` for h2c.
+    pub handshake: Option<Duration>,
+    /// From accept to the first request entering the stack; `None` until
+    /// one has.
+    pub first_request: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct ConnSetup {
+    accepted: Instant,
+    peer: SocketAddr,
+    tls: Option<TlsSetup>,
+    first_request: OnceLock<Instant>,
+    /// RPCs running on the connection, and when the last one ended.
+    rpcs: AtomicUsize,
+    last_rpc: Mutex<Instant>,
+}
+
+/// What the TLS handshake settled on.
+#[derive(Debug)]
+struct TlsSetup {
+    handshake: Duration,
+    version: Option<ProtocolVersion>,
+    kx_group: Option<NamedGroup>,
+    alpn: Option<Vec<u8>>,
+}
+
+impl ConnSetup {
+    fn new(accepted: Instant, peer: SocketAddr, tls: Option<TlsSetup>) -> Self {
+        ConnSetup {
+            accepted,
+            peer,
+            tls,
+            first_request: OnceLock::new(),
+            rpcs: AtomicUsize::new(0),
+            last_rpc: Mutex::new(accepted),
+        }
+    }
+
+    /// Since when no RPC has been running; `None` while one is.
+    fn idle_since(&self) -> Option<Instant> {
+        let last = *self.last_rpc.lock().unwrap();
+        (self.rpcs.load(Ordering::Acquire) == 0).then_some(last)
+    }
+}
+
+/// Handed to handlers as the request's connect info, so they can inspect
+/// the socket the request arrived on. Holds no reference that would keep
+/// the connection open.
+#[derive(Clone, Debug)]
+pub struct ConnInfo {
+    socket: Weak<Socket>,
+    setup: Arc<ConnSetup>,
+}
+
+impl ConnInfo {
+    /// `None` once the connection has closed, or off Linux.
+    pub fn path_sizes(&self) -> Option<PathSizes> {
+        let socket = self.socket.upgrade()?;
+        path_sizes(&socket)
+    }
+
+    /// Called as each request enters the stack; only the first counts.
+    pub fn request_arrived(&self) {
+        self.setup.first_request.get_or_init(Instant::now);
+    }
+
+    /// Marks the connection busy until the guard is dropped, for
+    /// `Keepalive::max_idle`.
+    pub fn rpc_started(&self) -> ConnRpc {
+        self.setup.rpcs.fetch_add(1, Ordering::AcqRel);
+        ConnRpc(self.setup.clone())
+    }
+
+    /// The client's address.
+    pub fn peer(&self) -> SocketAddr {
+        self.setup.peer
+    }
+
+    /// The server's address on this connection; `None` once it has
+    /// closed.
+    pub fn local(&self) -> Option<SocketAddr> {
+        self.socket.upgrade()?.local_addr().ok()?.as_socket()
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.setup.tls.is_some()
+    }
+
+    /// TLS version the handshake settled on; `None` for h2c.
+    pub fn tls_version(&self) -> Option<ProtocolVersion> {
+        self.setup.tls.as_ref()?.version
+    }
+
+    /// Key exchange group the TLS handshake settled on; `None` for h2c.
+    pub fn kx_group(&self) -> Option<NamedGroup> {
+        self.setup.tls.as_ref()?.kx_group
+    }
+
+    /// ALPN protocol the TLS handshake settled on; `None` for h2c or when
+    /// the client offered none.
+    pub fn alpn(&self) -> Option<&[u8]> {
+        self.setup.tls.as_ref()?.alpn.as_deref()
+    }
+
+    pub fn setup_times(&self) -> SetupTimes {
+        SetupTimes {
+            handshake: self.setup.tls.as_ref().map(|tls| tls.handshake),
+            first_request: self
+                .setup
+                .first_request
+                .get()
+                .map(|t| t.duration_since(self.setup.accepted)),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn path_sizes(socket: &Socket) -> Option<PathSizes> {
+    use std::os::fd::AsRawFd;
+
+    tcp_info(socket.as_raw_fd()).map(|info| PathSizes {
+        send_mss: info.tcpi_snd_mss,
+        pmtu: info.tcpi_pmtu,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn path_sizes(_socket: &Socket) -> Option<PathSizes> {
+    None
+}
+
+/// Parse a DSCP codepoint: a number from 0 to 63 or a standard class
+/// name (`EF`, `AF41`, `CS1`, ...).
+pub fn parse_dscp(s: &str) -> Result<u8, String> {
+    let name = s.to_ascii_uppercase();
+    let dscp = if name == "EF" {
+        46
+    } else if let Some(class) = name.strip_prefix("CS") {
+        match class.parse::<u8>() {
+            Ok(c) if c <= 7 => c << 3,
+            _ => return Err(format!("unknown DSCP class {:?}", s)),
+        }
+    } else if let Some(class) = name.strip_prefix("AF") {
+        let digits = class.as_bytes();
+        match digits {
+            [c @ b'1'..=b'4', d @ b'1'..=b'3'] => ((c - b'0') << 3) | ((d - b'0') << 1),
+            _ => return Err(format!("unknown DSCP class {:?}", s)),
+        }
+    } else {
+        s.parse::<u8>()
+            .map_err(|_| format!("expected 0-63 or a class name, got {:?}", s))?
+    };
+    if dscp > 63 {
+        return Err(format!("DSCP {} is out of range 0-63", dscp));
+    }
+    Ok(dscp)
+}
+
+fn configure(tcp: &TcpStream, keepalive: Keepalive, dscp: Option<u8>) {
+    let _ = tcp.set_nodelay(true);
+    if let Some(dscp) = dscp {
+        // DSCP is the top six bits of the IPv4 ToS / IPv6 traffic class.
+        let tos = u32::from(dscp) << 2;
+        let sock = SockRef::from(tcp);
+        let res = match tcp.local_addr() {
+            Ok(addr) if addr.is_ipv6() => sock.set_tclass_v6(tos),
+            _ => sock.set_tos(tos),
+        };
+        if let Err(e) = res {
+            debug!("failed to set DSCP {}: {}", dscp, e);
+        }
+    }
+    let Some(idle) = keepalive.tcp else {
+        return;
+    };
+    let params = TcpKeepalive::new().with_time(idle).with_interval(idle);
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    let params = params.with_retries(KEEPALIVE_RETRIES);
+    if let Err(e) = SockRef::from(tcp).set_tcp_keepalive(&params) {
+        debug!("failed to enable TCP keepalive: {}", e);
+    }
+}
+
+/// An RPC in progress on a connection; see `ConnInfo::rpc_started`.
+pub struct ConnRpc(Arc<ConnSetup>);
+
+impl Drop for ConnRpc {
+    fn drop(&mut self) {
+        *self.0.last_rpc.lock().unwrap() = Instant::now();
+        self.0.rpcs.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Connection wrapper that keeps the listener's open-connection gauge and
+/// counts reaped connections. With HTTP/2 PINGs enabled a live peer sends
+/// something (at least a PING ack) within interval + timeout, so a
+/// connection that ends without EOF from the peer after a longer silence,
+/// or on a keepalive ETIMEDOUT, was half-open.
+///
+/// It also enforces the connection's lifetime: past `max_age` plus its
+/// grace, or after `max_idle` without an RPC, reads end as if the peer
+/// had closed, and the server drops the connection.
+pub struct Tracked<S> {
+    inner: S,
+    last_read: Instant,
+    stale_after: Option<Duration>,
+    peer_closed: bool,
+    timed_out: bool,
+    gauges: Arc<ListenerMetrics>,
+    /// Duplicate of the connection's fd, for `ConnInfo`. Dropped with the
+    /// stream so it never outlives the connection.
+    socket: Option<Arc<Socket>>,
+    setup: Arc<ConnSetup>,
+    /// When the connection is closed however busy it is.
+    close_at: Option<Instant>,
+    max_idle: Option<Duration>,
+    /// Fires at the next time `close_at` or `max_idle` may have passed.
+    lifetime: Option<Pin<Box<Sleep>>>,
+    /// Until it has logged all the frames it was asked for.
+    frames: Option<FrameTrace>,
+}
+
+impl<S> Tracked<S> {
+    fn new(
+        inner: S,
+        socket: Option<Socket>,
+        setup: ConnSetup,
+        keepalive: Keepalive,
+        gauges: Arc<ListenerMetrics>,
+        trace_frames: usize,
+    ) -> Self {
+        gauges.connection_opened();
+        let frames =
+            (trace_frames > 0).then(|| FrameTrace::new(setup.peer, setup.accepted, trace_frames));
+        let close_at = keepalive
+            .max_age
+            .map(|age| setup.accepted + age + keepalive.max_age_grace);
+        let first_check = [close_at, keepalive.max_idle.map(|i| setup.accepted + i)]
+            .into_iter()
+            .flatten()
+            .min();
+        Tracked {
+            close_at,
+            max_idle: keepalive.max_idle,
+            lifetime: first_check.map(|at| Box::pin(tokio::time::sleep_until(at.into()))),
+            frames,
+            inner,
+            socket: socket.map(Arc::new),
+            setup: Arc::new(setup),
+            last_read: Instant::now(),
+            stale_after: keepalive
+                .http2_interval
+                .map(|i| i + keepalive.http2_timeout),
+            peer_closed: false,
+            timed_out: false,
+            gauges,
+        }
+    }
+
+    /// Whether the connection has outlived `close_at` or `max_idle`;
+    /// otherwise arms the timer for the next check.
+    fn poll_expired(&mut self, cx: &mut Context<'_>) -> bool {
+        let Some(timer) = self.lifetime.as_mut() else {
+            return false;
+        };
+        while timer.as_mut().poll(cx).is_ready() {
+            let now = Instant::now();
+            if self.close_at.is_some_and(|at| now >= at) {
+                debug!(peer = %self.setup.peer, "closing connection past its maximum age");
+                return true;
+            }
+            let idle_until = self
+                .max_idle
+                .map(|idle| self.setup.idle_since().unwrap_or(now) + idle);
+            if idle_until.is_some_and(|at| now >= at) {
+                debug!(peer = %self.setup.peer, "closing idle connection");
+                return true;
+            }
+            let next = [self.close_at, idle_until].into_iter().flatten().min();
+            match next {
+                Some(at) => timer.as_mut().reset(at.into()),
+                None => return false,
+            }
+        }
+        false
+    }
+
+    fn trace(&mut self, direction: Direction, data: &[u8]) {
+        if let Some(frames) = &mut self.frames {
+            frames.observe(direction, data);
+            if frames.done() {
+                self.frames = None;
+            }
+        }
+    }
+
+    fn observe<T>(&mut self, res: &io::Result<T>) {
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => self.timed_out = true,
+            Err(_) => self.peer_closed = true,
+            Ok(_) => {}
+        }
+    }
+}
+
+impl<S> Drop for Tracked<S> {
+    fn drop(&mut self) {
+        self.gauges.connection_closed();
+        let stale = self
+            .stale_after
+            .is_some_and(|after| self.last_read.elapsed() >= after);
+        if !self.peer_closed && (self.timed_out || stale) {
+            METRICS.connection_reaped();
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Tracked<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.poll_expired(cx) {
+            self.peer_closed = true;
+            return Poll::Ready(Ok(()));
+        }
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(r) = &res {
+            self.observe(r);
+            if r.is_ok() {
+                if buf.filled().len() > before {
+                    self.last_read = Instant::now();
+                    self.trace(Direction::Recv, &buf.filled()[before..]);
+                } else if buf.remaining() > 0 {
+                    self.peer_closed = true;
+                }
+            }
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Tracked<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(r) = &res {
+            self.observe(r);
+            if let Ok(n) = r {
+                self.trace(Direction::Send, &buf[..*n]);
+            }
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let res = Pin::new(&mut self.inner).poll_flush(cx);
+        if let Poll::Ready(r) = &res {
+            self.observe(r);
+        }
+        res
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl<S> Connected for Tracked<S> {
+    type ConnectInfo = ConnInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        ConnInfo {
+            socket: self.socket.as_ref().map_or_else(Weak::new, Arc::downgrade),
+            setup: self.setup.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dscp_numbers_and_class_names() {
+        assert_eq!(parse_dscp("0"), Ok(0));
+        assert_eq!(parse_dscp("63"), Ok(63));
+        assert_eq!(parse_dscp("ef"), Ok(46));
+        assert_eq!(parse_dscp("CS1"), Ok(8));
+        assert_eq!(parse_dscp("AF41"), Ok(34));
+        assert_eq!(parse_dscp("AF13"), Ok(14));
+        for bad in ["64", "CS8", "AF14", "AF51", "bulk", "-1"] {
+            assert!(parse_dscp(bad).is_err(), "{}", bad);
+        }
+    }
+
+    /// A TLS record holding a ClientHello with `legacy` as its version,
+    /// then the server_name and supported_versions extensions if given.
+    fn client_hello(legacy: u16, sni: Option<&str>, versions: &[u16]) -> Vec<u8> {
+        let mut ext = Vec::new();
+        if let Some(name) = sni {
+            let n = name.len() as u16;
+            ext.extend_from_slice(&[0, 0]);
+            ext.extend_from_slice(&(n + 5).to_be_bytes());
+            ext.extend_from_slice(&(n + 3).to_be_bytes());
+            ext.push(0);
+            ext.extend_from_slice(&n.to_be_bytes());
+            ext.extend_from_slice(name.as_bytes());
+        }
+        if !versions.is_empty() {
+            let n = versions.len() as u16 * 2;
+            ext.extend_from_slice(&[0, 0x2b]);
+            ext.extend_from_slice(&(n + 1).to_be_bytes());
+            ext.push(n as u8);
+            versions
+                .iter()
+                .for_each(|v| ext.extend_from_slice(&v.to_be_bytes()));
+        }
+        let mut body = legacy.to_be_bytes().to_vec();
+        body.extend_from_slice(&[0; 32]);
+        // No session id, one cipher suite, null compression.
+        body.extend_from_slice(&[0, 0, 2, 0x13, 0x01, 1, 0]);
+        body.extend_from_slice(&(ext.len() as u16).to_be_bytes());
+        body.extend_from_slice(&ext);
+        let mut handshake = vec![1, 0];
+        handshake.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        handshake.extend_from_slice(&body);
+        let mut record = vec![0x16, 3, 1];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn classifies_failed_handshakes() {
+        use crate::metrics::RECENT_HANDSHAKE_FAILURES;
+
+        let eof = || io::Error::from(io::ErrorKind::UnexpectedEof);
+        let tls = |e: rustls::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+
+        let plaintext = |data: &[u8]| parse_hello(data).plaintext;
+        assert_eq!(
+            plaintext(b"GET /healthz HTTP/1.1\r\n"),
+            Some(Protocol::Http1)
+        );
+        assert_eq!(plaintext(b"PRI * HTTP/2.0\r\n"), Some(Protocol::H2c));
+        assert_eq!(plaintext(throughput::MAGIC), Some(Protocol::Throughput));
+
+        // TLS 1.3 with GREASE, asking for a name the certificate lacks.
+        let hello = parse_hello(&client_hello(0x0303, Some("db.example"), &[0x1a1a, 0x0304]));
+        assert_eq!(hello.plaintext, None);
+        assert_eq!(hello.sni.as_deref(), Some("db.example"));
+        assert_eq!(hello.max_version, Some(0x0304));
+        let rejected = tls(rustls::Error::AlertReceived(
+            rustls::AlertDescription::BadCertificate,
+        ));
+        let (reason, detail) = classify(&hello, &rejected);
+        assert_eq!(reason, HandshakeFailure::CertificateRejected);
+        assert!(
+            detail.ends_with("(client asked for \"db.example\")"),
+            "{}",
+            detail
+        );
+        assert_eq!(classify(&hello, &eof()).0, HandshakeFailure::PeerClosed);
+
+        // An old client with no supported_versions extension.
+        let hello = parse_hello(&client_hello(0x0301, None, &[]));
+        assert_eq!(
+            (hello.sni.as_deref(), hello.max_version),
+            (None, Some(0x0301))
+        );
+        let (reason, detail) = classify(&hello, &eof());
+        assert_eq!(reason, HandshakeFailure::ProtocolVersion);
+        assert_eq!(
+            detail,
+            "client offered at most TLS 1.0; TLS 1.2 is the minimum"
+        );
+
+        // A truncated hello still yields what it got to.
+        let mut cut = client_hello(0x0303, Some("a.example"), &[0x0304]);
+        cut.truncate(20);
+        assert_eq!(parse_hello(&cut).max_version, Some(0x0303));
+
+        let addr: SocketAddr = ([127, 0, 0, 1], 65_003).into();
+        let gauges = ListenerMetrics::new(addr, true);
+        for _ in 0..RECENT_HANDSHAKE_FAILURES + 2 {
+            handshake_failed(
+                &gauges,
+                addr,
+                HandshakeFailure::Timeout,
+                None,
+                "slow".into(),
+            );
+        }
+        assert_eq!(
+            gauges.handshake_failures(HandshakeFailure::Timeout),
+            RECENT_HANDSHAKE_FAILURES as u64 + 2
+        );
+        assert_eq!(
+            gauges.recent_handshake_failures().len(),
+            RECENT_HANDSHAKE_FAILURES
+        );
+    }
+
+    #[tokio::test]
+    async fn closes_idle_and_aged_connections() {
+        use tokio::io::AsyncReadExt;
+
+        let addr: SocketAddr = ([127, 0, 0, 1], 65_002).into();
+        let gauges = Arc::new(ListenerMetrics::new(addr, false));
+        let keepalive = Keepalive {
+            tcp: None,
+            http2_interval: None,
+            http2_timeout: Duration::from_secs(10),
+            max_age: None,
+            max_age_grace: Duration::ZERO,
+            max_idle: Some(Duration::from_millis(50)),
+        };
+        let track = |keepalive| {
+            let (client, server) = tokio::io::duplex(64);
+            let setup = ConnSetup::new(Instant::now(), addr, None);
+            let conn = Tracked::new(server, None, setup, keepalive, gauges.clone(), 0);
+            (client, conn)
+        };
+        let mut buf = [0u8; 8];
+        let wait = Duration::from_millis(150);
+
+        // Idle only counts while no RPC is running.
+        let (_client, mut conn) = track(keepalive);
+        let rpc = conn.connect_info().rpc_started();
+        let read = tokio::time::timeout(wait, conn.read(&mut buf)).await;
+        assert!(read.is_err(), "closed while busy");
+        drop(rpc);
+        let start = Instant::now();
+        assert_eq!(conn.read(&mut buf).await.unwrap(), 0);
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        // Past the maximum age and its grace, even a busy one is closed.
+        let (_client, mut conn) = track(Keepalive {
+            max_age: Some(Duration::from_millis(30)),
+            max_age_grace: Duration::from_millis(20),
+            max_idle: None,
+            ..keepalive
+        });
+        let _rpc = conn.connect_info().rpc_started();
+        let read = tokio::time::timeout(wait, conn.read(&mut buf)).await;
+        assert_eq!(read.unwrap().unwrap(), 0);
+    }
+}
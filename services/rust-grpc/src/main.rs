@@ -1,22 +1,316 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 // Copyright (c) 2026 Jared Redh. All rights reserved.
 
-pub mod hermit {
-    tonic::include_proto!("hermit");
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use hermit_server::auth::totp;
+use hermit_server::hermit::{
+    hermit_client::HermitClient, BenchmarkRequest, BenchmarkResponse, PingRequest,
+    ReplayBenchmarkRequest, ServerInfoRequest,
+};
+use hermit_server::{
+    attest, auth, bench, breaker, build_info, clock, db, deadline, egress, environment, etcd,
+    failover, geoip, gossip, grpc, health, hops, hugepage, kernel, listener, notify, numa, output,
+    retry, runlog, sandbox, secrets, session, shadow, sweep, throughput, tls, wakeup,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::transport::Channel;
+use tracing::{error, info, warn};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "hermit-server",
+    version,
+    about = "Hermit high-performance server",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Without a subcommand, serve with these flags (as `serve`).
+    #[command(flatten)]
+    serve: Args,
 }
 
-mod auth;
-mod bench;
-mod db;
-mod grpc;
-mod tls;
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the server (the default without a subcommand).
+    Serve(Args),
+    /// Validate the configuration and exit instead of serving.
+    ///
+    /// Loads TLS material (checking each key matches its certificate), SNI
+    /// certificates, the OCSP staple, signing key, auth backend and
+    /// session store, and test-binds every port. Exits non-zero if any
+    /// check fails.
+    Check(Args),
+    /// Measure this host's clock as the server would see it.
+    ///
+    /// Reports the clock source, its resolution and the cost of one read.
+    Bench(BenchArgs),
+    /// Provision a user's TOTP second factor and print its otpauth:// URI.
+    ///
+    /// This is how an admin's first factor is set up: admins can't enroll
+    /// themselves over gRPC without one. Needs a persistent store, so
+    /// either --totp-file or --session-store redis.
+    EnrollTotp(EnrollTotpArgs),
+    /// Talk to a running server.
+    #[command(subcommand)]
+    Client(ClientCommand),
+    /// Print version information.
+    Version(VersionArgs),
+    /// Print a shell completion script.
+    ///
+    /// For example `hermit-server completions bash >
+    /// /etc/bash_completion.d/hermit-server`, or into a directory on
+    /// $fpath as _hermit-server for zsh, or
+    /// ~/.config/fish/completions/hermit-server.fish.
+    Completions(CompletionsArgs),
+    /// Print the manpage, in roff.
+    ///
+    /// For example `hermit-server manpage > hermit-server.1`.
+    Manpage,
+}
 
-use clap::Parser;
-use std::sync::Arc;
-use tracing::{info, error};
+#[derive(clap::Args, Debug)]
+struct CompletionsArgs {
+    #[arg(value_enum)]
+    shell: clap_complete::Shell,
+}
 
-#[derive(Parser, Debug)]
-#[command(name = "hermit-server", version, about = "Hermit high-performance server")]
+#[derive(clap::Args, Debug)]
+struct EnrollTotpArgs {
+    /// User to enroll; replaces any existing enrollment.
+    username: String,
+    #[command(flatten)]
+    serve: Args,
+}
+
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
+    /// Clock to measure; see `serve --clock`.
+    #[arg(long, value_enum, default_value_t = ClockKind::Monotonic)]
+    clock: ClockKind,
+}
+
+#[derive(Subcommand, Debug)]
+enum ClientCommand {
+    /// Send Pings and print each round trip, then a summary.
+    Ping(PingArgs),
+    /// Run a verified echo test against a throughput port, checking every
+    /// echoed byte and the server's checksum of what it received.
+    Echo(EchoArgs),
+    /// Prompt for commands against a server: pings, benchmarks and server
+    /// info, switching servers as needed.
+    Repl(ReplArgs),
+    /// Measure what hedging buys: send Pings both plain and hedged,
+    /// interleaved, and compare their tail latency and the extra calls
+    /// the hedges cost.
+    Hedge(HedgeArgs),
+}
+
+/// A gRPC server to talk to and how to reach it.
+#[derive(clap::Args, Clone, Debug)]
+struct Target {
+    /// Server URL: http://HOST:PORT for h2c, https://HOST:PORT for TLS.
+    #[arg(long, default_value = "https://localhost:9090")]
+    addr: String,
+
+    /// PEM CA certificate to trust for https (e.g. the server's own
+    /// self-signed certificate).
+    #[arg(long)]
+    ca_cert: Option<String>,
+
+    /// Name to verify the server certificate against, if not the host in
+    /// --addr.
+    #[arg(long)]
+    tls_domain: Option<String>,
+
+    /// Connect through this proxy: socks5://[USER:PASS@]HOST:PORT or
+    /// http://[USER:PASS@]HOST:PORT for HTTP CONNECT.
+    #[arg(long, env = "HERMIT_CLIENT_PROXY")]
+    proxy: Option<egress::Proxy>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReplArgs {
+    #[command(flatten)]
+    target: Target,
+
+    /// How to print results: table (colored on a terminal), or json or
+    /// yaml for scripts.
+    #[arg(long, short = 'o', value_enum, default_value_t = output::Format::Table)]
+    output: output::Format,
+}
+
+#[derive(clap::Args, Debug)]
+struct PingArgs {
+    #[command(flatten)]
+    target: Target,
+
+    /// How to print results: table (colored on a terminal), or json or
+    /// yaml for scripts.
+    #[arg(long, short = 'o', value_enum, default_value_t = output::Format::Table)]
+    output: output::Format,
+
+    /// Number of pings to send.
+    #[arg(long, short = 'c', default_value_t = 10)]
+    count: u32,
+
+    /// Pause between pings.
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    interval: Duration,
+
+    /// Ask every tier on the way for its timestamps (x-hermit-hops) and
+    /// print each ping's per-hop waterfall.
+    #[arg(long, default_value_t = false)]
+    trace_hops: bool,
+
+    #[command(flatten)]
+    retry: RetryArgs,
+
+    /// Keep pinging through failures, behind a circuit breaker that opens
+    /// after this many in a row. While it is open pings are refused
+    /// without being sent; state changes are logged.
+    #[arg(long)]
+    breaker_failures: Option<u32>,
+
+    /// How long the breaker stays open before letting a probe ping
+    /// through.
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    breaker_open_for: Duration,
+}
+
+#[derive(clap::Args, Debug)]
+struct RetryArgs {
+    /// Retry a failed call up to this many times. Retried calls are
+    /// summarized apart from those answered first time, so they don't
+    /// skew the percentiles.
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+
+    /// Wait before the first retry, doubling for each one after.
+    #[arg(long, default_value = "50ms", value_parser = humantime::parse_duration)]
+    retry_backoff: Duration,
+
+    /// Longest wait between retries.
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    retry_max_backoff: Duration,
+
+    /// Fraction (0-1) of each wait that is randomized away.
+    #[arg(long, default_value_t = 0.2)]
+    retry_jitter: f64,
+
+    /// Status codes worth retrying, by name or number.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "unavailable",
+        value_parser = retry::parse_code
+    )]
+    retry_on: Vec<tonic::Code>,
+
+    /// Hedge instead of waiting for failures: send another copy of a call
+    /// that has gone this long unanswered (up to --retries copies) and
+    /// take whichever answers first.
+    #[arg(long, value_parser = humantime::parse_duration, requires = "retries")]
+    hedge_after: Option<Duration>,
+}
+
+#[derive(clap::Args, Debug)]
+struct HedgeArgs {
+    #[command(flatten)]
+    target: Target,
+
+    /// How to print results: table (colored on a terminal), or json or
+    /// yaml for scripts.
+    #[arg(long, short = 'o', value_enum, default_value_t = output::Format::Table)]
+    output: output::Format,
+
+    /// Pings to send in each mode.
+    #[arg(long, short = 'c', default_value_t = 1000)]
+    count: u32,
+
+    /// Pings in flight at once.
+    #[arg(long, default_value_t = 4)]
+    concurrency: u32,
+
+    /// Send a duplicate of a Ping unanswered for this long. Somewhere
+    /// around the plain p95 is the usual starting point.
+    #[arg(long, default_value = "1ms", value_parser = humantime::parse_duration)]
+    hedge_after: Duration,
+
+    /// Most copies of one Ping, the original included.
+    #[arg(long, default_value_t = 2)]
+    copies: u32,
+}
+
+impl RetryArgs {
+    fn policy(&self) -> retry::Policy {
+        retry::Policy {
+            max_attempts: self.retries.saturating_add(1),
+            initial_backoff: self.retry_backoff,
+            max_backoff: self.retry_max_backoff,
+            jitter: self.retry_jitter,
+            retry_on: self.retry_on.clone(),
+            hedge_after: self.hedge_after,
+            ..retry::Policy::default()
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct EchoArgs {
+    /// Throughput port (`serve --throughput-port`) as HOST:PORT.
+    #[arg(long, default_value = "localhost:9091")]
+    addr: String,
+
+    /// Connect over TLS, trusting this PEM CA certificate. Without it the
+    /// connection is plaintext, for servers run with --no-tls.
+    #[arg(long)]
+    ca_cert: Option<String>,
+
+    /// Name to verify the server certificate against, if not the host in
+    /// --addr.
+    #[arg(long)]
+    tls_domain: Option<String>,
+
+    /// Connect through this proxy: socks5://[USER:PASS@]HOST:PORT or
+    /// http://[USER:PASS@]HOST:PORT for HTTP CONNECT.
+    #[arg(long, env = "HERMIT_CLIENT_PROXY")]
+    proxy: Option<egress::Proxy>,
+
+    /// Payload size in bytes.
+    #[arg(long, default_value_t = 64 << 20)]
+    bytes: usize,
+
+    /// Read/write size on both ends.
+    #[arg(long, default_value_t = 128 << 10)]
+    block: usize,
+
+    /// Payload content: fixed, zeros, text, random or incompressible.
+    #[arg(long, default_value = "incompressible")]
+    content: bench::PayloadContent,
+
+    /// Have the server and every hermit-proxy on the way add their
+    /// timestamps, and print the per-hop waterfall.
+    #[arg(long, default_value_t = false)]
+    trace_hops: bool,
+
+    /// How to print results: table (colored on a terminal), or json or
+    /// yaml for scripts.
+    #[arg(long, short = 'o', value_enum, default_value_t = output::Format::Table)]
+    output: output::Format,
+}
+
+#[derive(clap::Args, Debug)]
+struct VersionArgs {
+    /// Print as JSON.
+    #[arg(long, default_value_t = false)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
     /// gRPC listen port
     #[arg(long, default_value_t = 9090)]
@@ -26,6 +320,13 @@ struct Args {
     #[arg(long, default_value = "us-west1")]
     region: String,
 
+    /// Also serve a virtual instance as `REGION=PORT` (repeatable), so one
+    /// host can stand in for a multi-region deployment. Instances share the
+    /// runtime, TLS identity, auth, sessions and KV store; each reports its
+    /// own region and port.
+    #[arg(long = "instance", value_parser = parse_instance)]
+    instances: Vec<(String, u16)>,
+
     /// Path to TLS certificate (PEM). Auto-generates self-signed if absent.
     #[arg(long)]
     tls_cert: Option<String>,
@@ -34,56 +335,1869 @@ struct Args {
     #[arg(long)]
     tls_key: Option<String>,
 
+    /// Base URL of the nexus secrets service, e.g. http://secrets:8081
+    #[arg(long, env = "HERMIT_SECRETS_URL")]
+    secrets_url: Option<String>,
+
+    /// Serve a different certificate to clients requesting this hostname
+    /// via SNI, as `NAME=CERT,KEY` PEM paths (repeatable). Other names get
+    /// the main certificate.
+    #[arg(long = "sni-cert", value_parser = tls::parse_sni_cert)]
+    sni_certs: Vec<tls::SniCert>,
+
+    /// Secret ID holding the TLS certificate chain (PEM). Requires --secrets-url.
+    #[arg(long)]
+    tls_cert_secret: Option<String>,
+
+    /// Secret ID holding the TLS private key (PEM). Requires --secrets-url.
+    #[arg(long)]
+    tls_key_secret: Option<String>,
+
+    /// How often to re-fetch TLS material from the secrets service.
+    #[arg(long, default_value_t = 300)]
+    tls_rotation_secs: u64,
+
+    /// DER OCSP response for the server certificate, stapled to
+    /// handshakes and re-read every --tls-rotation-secs. Refresh it
+    /// whenever the certificate rotates.
+    #[arg(long)]
+    ocsp_staple: Option<String>,
+
+    /// Append TLS session secrets to this file in NSS key log format
+    /// (SSLKEYLOGFILE) so captures can be decrypted in Wireshark.
+    /// Debugging only: the file decrypts all traffic.
+    #[arg(long)]
+    tls_keylog: Option<String>,
+
+    /// Prefer the X25519MLKEM768 post-quantum hybrid key exchange, so its
+    /// handshake cost shows up in tls_handshake_ns. Requires the `pq`
+    /// feature; ServerInfo reports the group each connection negotiated.
+    #[arg(long, default_value_t = false)]
+    post_quantum: bool,
+
+    /// SPIFFE Workload API socket (unix:///path, or npipe:name on Windows).
+    /// When set, the server identity is an X.509 SVID and clients must
+    /// present one too (mTLS).
+    #[arg(long, env = "SPIFFE_ENDPOINT_SOCKET")]
+    spiffe_socket: Option<String>,
+
+    /// SPIFFE ID allowed to connect; repeatable. A bare trust domain
+    /// (spiffe://example.org) admits all of its workloads. Empty admits any
+    /// ID in the trust bundle.
+    #[arg(long = "spiffe-allowed-id")]
+    spiffe_allowed_ids: Vec<String>,
+
+    /// CRL file (PEM or DER) for client certificates in the SPIFFE mTLS
+    /// path; repeatable. Re-read every --tls-rotation-secs.
+    #[arg(long = "client-crl")]
+    client_crls: Vec<String>,
+
+    /// Ed25519 PKCS#8 key (PEM or DER) used to sign benchmark results.
+    #[arg(long)]
+    signing_key: Option<String>,
+
+    /// Secret ID holding the PEM signing key. Requires --secrets-url.
+    #[arg(long)]
+    signing_key_secret: Option<String>,
+
     /// Disable TLS (serve plaintext h2c). Required for Cloud Run.
     #[arg(long, default_value_t = false)]
     no_tls: bool,
+
+    /// Authentication backend used by Login.
+    #[arg(long, value_enum, default_value_t = AuthBackendKind::None)]
+    auth_backend: AuthBackendKind,
+
+    /// Users file for the static backend (username:pbkdf2 hash:roles per line).
+    #[arg(long)]
+    auth_users_file: Option<String>,
+
+    /// LDAP server URL, e.g. ldaps://ldap.example.org
+    #[arg(long)]
+    ldap_url: Option<String>,
+
+    /// LDAP user DN template with a {username} placeholder.
+    #[arg(long, default_value = "uid={username},ou=people,dc=example,dc=org")]
+    ldap_user_dn: String,
+
+    /// LDAP search base for group (role) lookups.
+    #[arg(long, default_value = "dc=example,dc=org")]
+    ldap_base_dn: String,
+
+    /// OAuth 2.0 token introspection endpoint (RFC 7662).
+    #[arg(long)]
+    oidc_introspection_url: Option<String>,
+
+    /// Client ID used to authenticate to the introspection endpoint.
+    #[arg(long, env = "HERMIT_OIDC_CLIENT_ID", default_value = "")]
+    oidc_client_id: String,
+
+    /// Client secret used to authenticate to the introspection endpoint.
+    #[arg(
+        long,
+        env = "HERMIT_OIDC_CLIENT_SECRET",
+        default_value = "",
+        hide_env_values = true
+    )]
+    oidc_client_secret: String,
+
+    /// Where Login sessions are stored.
+    #[arg(long, value_enum, default_value_t = SessionStoreKind::Memory)]
+    session_store: SessionStoreKind,
+
+    /// Redis URL for the redis session store.
+    #[arg(
+        long,
+        env = "HERMIT_REDIS_URL",
+        default_value = "redis://127.0.0.1:6379"
+    )]
+    redis_url: String,
+
+    /// File TOTP secrets are kept in, one `username:BASE32SECRET` per line.
+    ///
+    /// Without it, secrets live in Redis with --session-store redis and
+    /// otherwise only in memory, lost on restart. Under --sandbox the file
+    /// can be read but new enrollments can't be saved.
+    #[arg(long)]
+    totp_file: Option<std::path::PathBuf>,
+
+    /// Session lifetime in seconds.
+    #[arg(long, default_value_t = 86_400)]
+    session_ttl_secs: u64,
+
+    /// Server-side time limit for RPCs without a per-method override.
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    default_rpc_timeout: Duration,
+
+    /// Per-method time limit, e.g. `Benchmark=5m` (repeatable). Built-in
+    /// defaults: Ping=1s, ServerInfo=5s, Benchmark=120s.
+    #[arg(long = "rpc-timeout", value_parser = deadline::parse_override)]
+    rpc_timeouts: Vec<(String, Duration)>,
+
+    /// Idle time before TCP keepalive probes start; 0s disables.
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    tcp_keepalive: Duration,
+
+    /// Interval between HTTP/2 PINGs on idle connections; 0s disables.
+    #[arg(long, default_value = "20s", value_parser = humantime::parse_duration)]
+    http2_keepalive: Duration,
+
+    /// How long to wait for a PING ack before closing the connection.
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    http2_keepalive_timeout: Duration,
+
+    /// Send connections a GOAWAY at this age so clients reconnect and load
+    /// balancers can rebalance them; 0s disables.
+    #[arg(long, default_value = "0s", value_parser = humantime::parse_duration)]
+    max_connection_age: Duration,
+
+    /// How long RPCs still running at --max-connection-age get to finish
+    /// before the connection is closed.
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    max_connection_age_grace: Duration,
+
+    /// Close connections that have had no RPC running for this long; 0s
+    /// disables.
+    #[arg(long, default_value = "0s", value_parser = humantime::parse_duration)]
+    max_connection_idle: Duration,
+
+    /// DSCP codepoint for replies, 0-63 or a class name such as EF or
+    /// AF41, to measure how a QoS class is treated along the path.
+    #[arg(long, value_parser = listener::parse_dscp)]
+    dscp: Option<u8>,
+
+    /// Cap the TCP segment size (TCP_MAXSEG) of accepted connections, to
+    /// see how smaller segments affect latency. ServerInfo reports the
+    /// resulting MSS and path MTU per connection.
+    #[arg(long)]
+    tcp_maxseg: Option<u32>,
+
+    /// Append every Benchmark's parameters (seed, payload, iterations
+    /// and the rest) to this file, so ReplayBenchmark can repeat runs from
+    /// before a restart. Without it runs are only remembered in memory.
+    #[arg(long)]
+    benchmark_log: Option<String>,
+
+    /// POST a notification here whenever a Benchmark RPC completes.
+    #[arg(long, env = "HERMIT_WEBHOOK_URL")]
+    webhook_url: Option<String>,
+
+    /// Mirror Ping and Benchmark RPCs to this hermit instance (e.g. a
+    /// canary build) and compare its responses in the background:
+    /// http://HOST:PORT or https://HOST:PORT. Results are in the
+    /// hermit_shadow_requests_total metric; mismatches are logged.
+    #[arg(long)]
+    shadow_addr: Option<String>,
+
+    /// PEM CA certificate to trust for an https --shadow-addr.
+    #[arg(long)]
+    shadow_ca_cert: Option<String>,
+
+    /// Run as one half of an active/standby pair with --ha-peer. The
+    /// standby fails readiness while the active answers and passes it,
+    /// taking the pair's traffic, while the active is down.
+    #[arg(long, requires = "ha_peer")]
+    ha_role: Option<HaRole>,
+
+    /// gRPC address of the other half of the pair: http://HOST:PORT or
+    /// https://HOST:PORT.
+    #[arg(long, requires = "ha_role")]
+    ha_peer: Option<String>,
+
+    /// PEM CA certificate to trust for an https --ha-peer.
+    #[arg(long)]
+    ha_peer_ca_cert: Option<String>,
+
+    /// How often to check the HA peer; a check slower than this fails.
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    ha_check_interval: Duration,
+
+    /// Consecutive failed (or successful) checks before the HA peer
+    /// counts as down (or up again).
+    #[arg(long, default_value_t = 3)]
+    ha_failure_threshold: u32,
+
+    /// Gossip with other hermit instances on this UDP address (HOST:PORT)
+    /// to learn the fleet's membership, listed by the ListPeers RPC.
+    #[arg(long)]
+    gossip_addr: Option<std::net::SocketAddr>,
+
+    /// Address the other instances reach our gossip at, when
+    /// --gossip-addr is 0.0.0.0 or behind NAT.
+    #[arg(long, requires = "gossip_addr")]
+    gossip_advertise: Option<std::net::SocketAddr>,
+
+    /// Gossip addresses (HOST:PORT, comma-separated) to join the fleet
+    /// through. One reachable member is enough.
+    #[arg(long, value_delimiter = ',', requires = "gossip_addr")]
+    gossip_seeds: Vec<String>,
+
+    /// gRPC address to advertise to peers (gossip and the etcd
+    /// registry). Default: the gossip address's host with --grpc-port,
+    /// https unless --no-tls.
+    #[arg(long)]
+    advertise_grpc_addr: Option<String>,
+
+    /// Gossip protocol period: one peer is probed per interval.
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    gossip_interval: Duration,
+
+    /// How long a peer that missed its probes has to refute before it's
+    /// declared dead.
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    gossip_suspect_timeout: Duration,
+
+    /// etcd client URL (http://HOST:2379) to register this instance in and
+    /// take fleet-wide config from, e.g. RPC timeouts under
+    /// <prefix>config/rpc-timeout/<Method>.
+    #[arg(long, env = "HERMIT_ETCD_ENDPOINT")]
+    etcd_endpoint: Option<String>,
+
+    /// Run a latency sweep of the fleet this often, from whichever member
+    /// gossip elects as leader (GetSweep RPC). Requires --gossip-addr; 0
+    /// disables.
+    #[arg(long, default_value = "0s", value_parser = humantime::parse_duration)]
+    sweep_interval: Duration,
+
+    /// Pings sent to each peer per sweep.
+    #[arg(long, default_value_t = 20)]
+    sweep_pings: u32,
+
+    /// PEM CA certificate to trust for peers serving https.
+    #[arg(long)]
+    sweep_ca_cert: Option<String>,
+
+    /// Key prefix for hermit's entries in etcd.
+    #[arg(long, default_value = "/hermit/")]
+    etcd_prefix: String,
+
+    /// MaxMind Country or City database (.mmdb), to tag Benchmark results
+    /// with the client's country. Requires --features geoip.
+    #[arg(long)]
+    geoip_country_db: Option<String>,
+
+    /// MaxMind ASN database (.mmdb), to tag Benchmark results with the
+    /// client's network. Requires --features geoip.
+    #[arg(long)]
+    geoip_asn_db: Option<String>,
+
+    /// Serve /healthz, /readyz and Prometheus /metrics over HTTP on this
+    /// port.
+    #[arg(long)]
+    health_port: Option<u16>,
+
+    /// Serve bulk TCP throughput tests on this port, over TLS unless
+    /// --no-tls.
+    #[arg(long)]
+    throughput_port: Option<u16>,
+
+    /// TCP congestion control for throughput test connections (reno,
+    /// cubic, bbr; Linux only, and the kernel must provide it). Tests can
+    /// still ask for another per connection. Default: the system's.
+    #[arg(long)]
+    throughput_congestion: Option<throughput::CongestionControl>,
+
+    /// Also serve the health/metrics endpoints (ALPN http/1.1) and
+    /// throughput tests (ALPN hermit-throughput/1) on the gRPC TLS port,
+    /// for deployments that can only expose one port. Clients that offer
+    /// h2, or no ALPN, get gRPC.
+    #[arg(long, default_value_t = false)]
+    alpn_multiplex: bool,
+
+    /// Log the first N HTTP/2 frames each way on every gRPC connection,
+    /// with timing, decoded fields and a hex dump, to debug other
+    /// clients' protocol handling without packet captures. At most 100
+    /// frames a second are logged across connections. 0 disables.
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    trace_frames: usize,
+
+    /// After SIGTERM, fail readiness for this long before shutting down
+    /// so load balancers stop sending traffic first.
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    drain_grace: Duration,
+
+    /// How often to measure the runtime's timer and task wakeup latency
+    /// for the hermit_runtime_wakeup_latency_seconds metric. 0 disables.
+    #[arg(long, default_value = "100ms", value_parser = humantime::parse_duration)]
+    wakeup_probe_interval: Duration,
+
+    /// Drop to this user (name or numeric uid) after binding and loading
+    /// key material.
+    #[arg(long)]
+    user: Option<String>,
+
+    /// Drop to this group, name or numeric gid (defaults to the --user's
+    /// primary group).
+    #[arg(long)]
+    group: Option<String>,
+
+    /// Linux only: deny filesystem writes (Landlock) and dangerous
+    /// syscalls such as execve and ptrace (seccomp).
+    #[arg(long, default_value_t = false)]
+    sandbox: bool,
+
+    /// Linux only: run the runtime's threads on this NUMA node's CPUs and
+    /// allocate from its memory first, so benchmark payloads stay on the
+    /// node the requests are handled on.
+    #[arg(long)]
+    numa_node: Option<u32>,
+
+    /// Linux only: allocate benchmark payloads and throughput test buffers
+    /// from hugepages, the vm.nr_hugepages pool or else transparent
+    /// hugepages, falling back to ordinary pages. Results report which
+    /// were used.
+    #[arg(long, default_value_t = false)]
+    hugepages: bool,
+
+    /// Clock for server-side timestamps. `tsc` reads the CPU timestamp
+    /// counter directly and falls back to `monotonic` if it isn't
+    /// invariant or fails calibration.
+    #[arg(long, value_enum, default_value_t = ClockKind::Monotonic)]
+    clock: ClockKind,
+
+    /// Webhook payload format.
+    #[arg(long, value_enum, default_value_t = WebhookFormat::Json)]
+    webhook_format: WebhookFormat,
+
+    /// Expected benchmark p99; slower runs are reported as regressions.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    baseline_p99: Option<Duration>,
+
+    /// How far above --baseline-p99 (in percent) counts as a regression.
+    #[arg(long, default_value_t = 20.0)]
+    regression_tolerance_pct: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ClockKind {
+    /// CLOCK_MONOTONIC (or the platform equivalent) via `Instant`.
+    Monotonic,
+    /// Invariant TSC, calibrated against CLOCK_MONOTONIC at startup.
+    Tsc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum HaRole {
+    /// Serves whenever it's up.
+    Active,
+    /// Serves only while the active is down.
+    Standby,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum WebhookFormat {
+    /// Generic JSON object with the result fields.
+    Json,
+    /// Slack incoming-webhook `{"text": ...}` message.
+    Slack,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum SessionStoreKind {
+    /// Process-local; sessions are lost on restart.
+    Memory,
+    /// Shared Redis (requires the `redis` feature).
+    Redis,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum AuthBackendKind {
+    /// Dev mode: every login succeeds.
+    None,
+    /// Static users file.
+    Static,
+    /// LDAP simple bind (requires the `ldap` feature).
+    Ldap,
+    /// OIDC token introspection.
+    Oidc,
+}
+
+fn parse_instance(s: &str) -> Result<(String, u16), String> {
+    let (region, port) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected REGION=PORT, got {:?}", s))?;
+    if region.is_empty() {
+        return Err("region is empty".to_string());
+    }
+    let port = port
+        .parse()
+        .map_err(|e| format!("bad port {:?}: {}", port, e))?;
+    Ok((region.to_string(), port))
+}
+
+/// One gRPC listener and the region it answers as.
+struct Instance {
+    region: String,
+    listener: std::net::TcpListener,
+}
+
+fn tls_source(args: &Args) -> Result<tls::TlsSource, Box<dyn std::error::Error>> {
+    if let Some(socket) = &args.spiffe_socket {
+        return Ok(tls::TlsSource::Spiffe {
+            socket: socket.clone(),
+            allowed_ids: args.spiffe_allowed_ids.clone(),
+            crl_paths: args.client_crls.clone(),
+        });
+    }
+    match (&args.tls_cert_secret, &args.tls_key_secret) {
+        (Some(cert_id), Some(key_id)) => {
+            let url = args
+                .secrets_url
+                .as_deref()
+                .ok_or("--tls-cert-secret/--tls-key-secret require --secrets-url")?;
+            return Ok(tls::TlsSource::Secrets {
+                client: secrets::SecretsClient::new(url),
+                cert_id: cert_id.clone(),
+                key_id: key_id.clone(),
+            });
+        }
+        (None, None) => {}
+        _ => return Err("--tls-cert-secret and --tls-key-secret must be set together".into()),
+    }
+    Ok(match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => tls::TlsSource::Files {
+            cert: cert.clone(),
+            key: key.clone(),
+        },
+        _ => tls::TlsSource::SelfSigned,
+    })
+}
+
+async fn load_signer(args: &Args) -> Result<Option<attest::Signer>, Box<dyn std::error::Error>> {
+    let key = match (&args.signing_key, &args.signing_key_secret) {
+        (Some(_), Some(_)) => {
+            return Err("--signing-key and --signing-key-secret are mutually exclusive".into())
+        }
+        (Some(path), None) => std::fs::read(path)?,
+        (None, Some(id)) => {
+            let url = args
+                .secrets_url
+                .as_deref()
+                .ok_or("--signing-key-secret requires --secrets-url")?;
+            secrets::SecretsClient::new(url)
+                .fetch(id)
+                .await?
+                .into_bytes()
+        }
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(attest::Signer::from_pkcs8(&key)?))
+}
+
+fn load_shadow(args: &Args) -> Result<Option<Arc<shadow::Shadow>>, String> {
+    args.shadow_addr
+        .as_deref()
+        .map(|addr| shadow::Shadow::new(addr, args.shadow_ca_cert.as_deref()).map(Arc::new))
+        .transpose()
+}
+
+fn load_pair(args: &Args) -> Result<Option<Arc<failover::Pair>>, String> {
+    let (Some(role), Some(peer)) = (args.ha_role, args.ha_peer.as_deref()) else {
+        return Ok(None);
+    };
+    // It's the period of a tokio interval, which panics on zero.
+    if args.ha_check_interval.is_zero() {
+        return Err("--ha-check-interval must be more than zero".to_string());
+    }
+    let role = match role {
+        HaRole::Active => failover::Role::Active,
+        HaRole::Standby => failover::Role::Standby,
+    };
+    failover::Pair::new(
+        role,
+        peer,
+        args.ha_peer_ca_cert.as_deref(),
+        args.ha_check_interval,
+        args.ha_failure_threshold,
+    )
+    .map(|pair| Some(Arc::new(pair)))
+}
+
+async fn load_gossip(args: &Args) -> Result<Option<Arc<gossip::Gossip>>, String> {
+    let Some(bind) = args.gossip_addr else {
+        return Ok(None);
+    };
+    let config = gossip::Config {
+        bind,
+        advertise: args.gossip_advertise,
+        region: args.region.clone(),
+        grpc_addr: advertised_grpc_addr(args).unwrap_or_default(),
+        seeds: args.gossip_seeds.clone(),
+        interval: args.gossip_interval,
+        suspect_timeout: args.gossip_suspect_timeout,
+    };
+    gossip::Gossip::bind(config)
+        .await
+        .map(|g| Some(Arc::new(g)))
+}
+
+/// Where peers should reach our gRPC service: --advertise-grpc-addr, or
+/// the gossip address's host with --grpc-port.
+fn advertised_grpc_addr(args: &Args) -> Option<String> {
+    if let Some(addr) = &args.advertise_grpc_addr {
+        return Some(addr.clone());
+    }
+    let host = args.gossip_advertise.or(args.gossip_addr)?.ip();
+    let scheme = if args.no_tls { "http" } else { "https" };
+    let addr = std::net::SocketAddr::new(host, args.grpc_port);
+    Some(format!("{}://{}", scheme, addr))
+}
+
+fn load_sweeper(
+    args: &Args,
+    gossip: Option<&Arc<gossip::Gossip>>,
+) -> Result<Option<Arc<sweep::Sweeper>>, String> {
+    if args.sweep_interval.is_zero() {
+        return Ok(None);
+    }
+    let gossip = gossip.ok_or("--sweep-interval requires --gossip-addr")?;
+    sweep::Sweeper::new(
+        gossip.clone(),
+        args.sweep_pings,
+        args.sweep_ca_cert.as_deref(),
+    )
+    .map(|s| Some(Arc::new(s)))
+}
+
+fn load_registry(args: &Args) -> Result<Option<Arc<etcd::Registry>>, String> {
+    let Some(endpoint) = &args.etcd_endpoint else {
+        return Ok(None);
+    };
+    let grpc_addr = advertised_grpc_addr(args)
+        .ok_or("--etcd-endpoint needs --advertise-grpc-addr (or --gossip-addr)")?;
+    let me = etcd::RegisteredPeer {
+        region: args.region.clone(),
+        grpc_addr,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    etcd::Registry::new(endpoint, &args.etcd_prefix, me).map(|r| Some(Arc::new(r)))
+}
+
+fn load_geoip(args: &Args) -> Result<Option<Arc<geoip::GeoIp>>, String> {
+    let country = args.geoip_country_db.as_deref();
+    let asn = args.geoip_asn_db.as_deref();
+    if country.is_none() && asn.is_none() {
+        return Ok(None);
+    }
+    geoip::GeoIp::open(country, asn).map(|db| Some(Arc::new(db)))
+}
+
+fn build_auth_backend(
+    args: &Args,
+) -> Result<Arc<dyn auth::AuthBackend>, Box<dyn std::error::Error>> {
+    let backend: Arc<dyn auth::AuthBackend> = match args.auth_backend {
+        AuthBackendKind::None => Arc::new(auth::AllowAllBackend),
+        AuthBackendKind::Static => {
+            let path = args
+                .auth_users_file
+                .as_deref()
+                .ok_or("--auth-users-file is required for the static backend")?;
+            Arc::new(auth::StaticFileBackend::load(path)?)
+        }
+        #[cfg(feature = "ldap")]
+        AuthBackendKind::Ldap => {
+            let url = args
+                .ldap_url
+                .clone()
+                .ok_or("--ldap-url is required for the ldap backend")?;
+            Arc::new(auth::LdapBackend::new(
+                url,
+                args.ldap_user_dn.clone(),
+                args.ldap_base_dn.clone(),
+            ))
+        }
+        #[cfg(not(feature = "ldap"))]
+        AuthBackendKind::Ldap => {
+            return Err("ldap backend requires building with --features ldap".into())
+        }
+        AuthBackendKind::Oidc => {
+            let url = args
+                .oidc_introspection_url
+                .clone()
+                .ok_or("--oidc-introspection-url is required for the oidc backend")?;
+            Arc::new(auth::OidcIntrospectionBackend::new(
+                url,
+                args.oidc_client_id.clone(),
+                args.oidc_client_secret.clone(),
+            ))
+        }
+    };
+    Ok(backend)
+}
+
+async fn build_session_store(
+    args: &Args,
+) -> Result<Arc<dyn session::SessionStore>, Box<dyn std::error::Error>> {
+    let store: Arc<dyn session::SessionStore> = match args.session_store {
+        SessionStoreKind::Memory => Arc::new(session::MemorySessionStore::new()),
+        #[cfg(feature = "redis")]
+        SessionStoreKind::Redis => {
+            Arc::new(session::RedisSessionStore::connect(&args.redis_url).await?)
+        }
+        #[cfg(not(feature = "redis"))]
+        SessionStoreKind::Redis => {
+            return Err("redis session store requires building with --features redis".into())
+        }
+    };
+    Ok(store)
+}
+
+async fn build_totp(args: &Args) -> Result<Arc<totp::Totp>, Box<dyn std::error::Error>> {
+    let store: Arc<dyn totp::TotpStore> = match (&args.totp_file, args.session_store) {
+        (Some(path), _) => Arc::new(totp::FileTotpStore::open(path)?),
+        #[cfg(feature = "redis")]
+        (None, SessionStoreKind::Redis) => {
+            Arc::new(totp::RedisTotpStore::connect(&args.redis_url).await?)
+        }
+        #[cfg(not(feature = "redis"))]
+        (None, SessionStoreKind::Redis) => {
+            return Err("redis TOTP store requires building with --features redis".into())
+        }
+        (None, SessionStoreKind::Memory) => Arc::new(totp::MemoryTotpStore::new()),
+    };
+    Ok(Arc::new(totp::Totp::new(store)))
+}
+
+async fn enroll_totp(args: EnrollTotpArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let totp = build_totp(&args.serve).await?;
+    if totp.store_name() == "memory" {
+        return Err("enroll-totp needs --totp-file or --session-store redis".into());
+    }
+    println!("{}", totp.enroll(&args.username).await?);
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let logs = tracing_subscriber::fmt().with_env_filter(
+        tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| "hermit_server=info,tower=warn".into()),
+    );
+    // Client commands print reports on stdout, which logs mustn't mix into.
+    if matches!(cli.command, Some(Command::Client(_))) {
+        logs.with_writer(std::io::stderr).init();
+    } else {
+        logs.init();
+    }
+
+    let args = match cli.command {
+        None => cli.serve,
+        Some(Command::Serve(args)) => args,
+        Some(Command::Check(args)) => return block_on(check(&args)),
+        Some(Command::EnrollTotp(args)) => return block_on(enroll_totp(args)),
+        Some(Command::Bench(args)) => {
+            bench_clock(args.clock);
+            return Ok(());
+        }
+        Some(Command::Client(ClientCommand::Ping(args))) => return block_on(client_ping(args)),
+        Some(Command::Client(ClientCommand::Echo(args))) => return block_on(client_echo(args)),
+        Some(Command::Client(ClientCommand::Repl(args))) => return block_on(client_repl(args)),
+        Some(Command::Client(ClientCommand::Hedge(args))) => return block_on(client_hedge(args)),
+        Some(Command::Version(args)) => {
+            print_version(args.json);
+            return Ok(());
+        }
+        Some(Command::Completions(args)) => {
+            clap_complete::generate(
+                args.shell,
+                &mut Cli::command(),
+                "hermit-server",
+                &mut std::io::stdout(),
+            );
+            return Ok(());
+        }
+        Some(Command::Manpage) => {
+            clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?;
+            return Ok(());
+        }
+    };
+
+    // Bind while still privileged so ports below 1024 work with --user.
+    let mut instances = Vec::new();
+    let primary = std::iter::once((args.region.clone(), args.grpc_port));
+    for (region, port) in primary.chain(args.instances.iter().cloned()) {
+        if instances.iter().any(|i: &Instance| i.region == region) {
+            return Err(format!("region {} is configured twice", region).into());
+        }
+        let listener = std::net::TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        instances.push(Instance { region, listener });
+    }
+    let bind_extra = |port: Option<u16>| -> std::io::Result<_> {
+        port.map(|port| {
+            let l = std::net::TcpListener::bind(("0.0.0.0", port))?;
+            l.set_nonblocking(true)?;
+            Ok(l)
+        })
+        .transpose()
+    };
+    let health_listener = bind_extra(args.health_port)?;
+    let throughput_listener = bind_extra(args.throughput_port)?;
+    if let (Some(l), Some(cc)) = (&throughput_listener, args.throughput_congestion) {
+        throughput::set_listener_congestion(l, cc)?;
+    }
+    let key_log = args
+        .tls_keylog
+        .as_deref()
+        .map(tls::open_key_log)
+        .transpose()?;
+    let runs = match &args.benchmark_log {
+        Some(path) => runlog::RunLog::open(std::path::Path::new(path))?,
+        None => runlog::RunLog::new(),
+    };
+
+    // Landlock only covers the calling thread and its future children, so
+    // it has to be in place before the runtime spawns its workers.
+    if args.sandbox {
+        sandbox::restrict_filesystem()?;
+    }
+
+    // One worker per CPU the container may use, not per CPU on the host,
+    // so workers aren't throttled by a quota they collectively exceed.
+    let cgroup = environment::cgroup_limits();
+    let mut workers = environment::usable_cpus(cgroup);
+    if let Some(quota) = cgroup.and_then(|c| c.cpus) {
+        info!(quota, workers, "sizing runtime to cgroup CPU quota");
+    }
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(id) = args.numa_node {
+        let node = numa::node(id)?;
+        workers = workers.min(node.cpus.len());
+        // Bound here too so the first binding error stops startup rather
+        // than being logged from every worker.
+        numa::bind_thread(&node)?;
+        info!(node = id, workers, "pinned to NUMA node");
+        runtime.on_thread_start(move || {
+            if let Err(e) = numa::bind_thread(&node) {
+                warn!("{}", e);
+            }
+        });
+    }
+    runtime
+        .worker_threads(workers)
+        .enable_all()
+        .build()?
+        .block_on(run(
+            args,
+            instances,
+            health_listener,
+            throughput_listener,
+            key_log,
+            runs,
+        ))
+}
+
+/// Run a one-shot subcommand on a single-threaded runtime.
+fn block_on<F>(fut: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: std::future::Future<Output = Result<(), Box<dyn std::error::Error>>>,
+{
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(fut)
+}
+
+fn print_version(json: bool) {
+    if json {
+        println!("{}", build_info::json());
+    } else {
+        println!(
+            "hermit-server {} ({})",
+            build_info::VERSION,
+            build_info::GIT_SHA
+        );
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "hermit_server=info,tower=warn".into()),
+fn bench_clock(kind: ClockKind) {
+    if kind == ClockKind::Tsc {
+        match bench::enable_tsc() {
+            Ok(drift_ppm) => println!("tsc drift:      {:.3} ppm", drift_ppm),
+            Err(e) => println!("tsc unavailable, using monotonic: {}", e),
+        }
+    }
+    println!("clock source:   {}", bench::clock_source());
+    println!(
+        "resolution:     {}",
+        output::duration(bench::clock_resolution_ns())
+    );
+    println!(
+        "read overhead:  {}",
+        output::duration(bench::timer_overhead_ns(&clock::SystemClock))
+    );
+}
+
+async fn connect(target: &Target) -> Result<HermitClient<Channel>, Box<dyn std::error::Error>> {
+    let mut endpoint = tonic::transport::Endpoint::from_shared(target.addr.clone())?;
+    if target.addr.starts_with("https://") {
+        let path = target
+            .ca_cert
+            .as_deref()
+            .ok_or("https requires --ca-cert")?;
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let mut tls = tonic::transport::ClientTlsConfig::new().ca_certificate(
+            tonic::transport::Certificate::from_pem(std::fs::read(path)?),
+        );
+        if let Some(domain) = &target.tls_domain {
+            tls = tls.domain_name(domain.clone());
+        }
+        endpoint = endpoint.tls_config(tls)?;
+    }
+    let channel = match target.proxy.clone() {
+        Some(proxy) => {
+            let https = target.addr.starts_with("https://");
+            endpoint
+                .connect_with_connector(tower::service_fn(move |uri: tonic::transport::Uri| {
+                    let proxy = proxy.clone();
+                    async move {
+                        let host = uri.host().unwrap_or_default();
+                        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+                        let stream = proxy.connect(host, port).await?;
+                        Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(stream))
+                    }
+                }))
+                .await?
+        }
+        None => endpoint.connect().await?,
+    };
+    Ok(HermitClient::new(channel))
+}
+
+async fn client_ping(args: PingArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let client = connect(&args.target).await?;
+    let out = output::Output::new(args.output);
+    let mut report = output::Report::new("ping");
+    let policy = args.retry.policy();
+    let mut columns = vec!["seq", "rtt_ns", "server_ns"];
+    if policy.retries() {
+        columns.push("attempts");
+    }
+    if args.trace_hops {
+        columns.push("hops");
+    }
+    report.rows("pings", &columns);
+
+    // Pings answered first time and those that took retries or hedges are
+    // kept apart, so the second don't inflate the first's percentiles.
+    let (mut rtts, mut retried, mut extra_attempts) = (Vec::new(), Vec::new(), 0);
+    // Ctrl-C or a failed ping ends the run early, but what was measured
+    // up to then is still reported, marked partial.
+    let (mut interrupted, mut failed) = (false, None);
+    let breaker = args.breaker_failures.map(|failures| {
+        breaker::Breaker::new(
+            format!("client:{}", args.target.addr),
+            breaker::Config {
+                failure_threshold: failures,
+                open_for: args.breaker_open_for,
+                half_open_probes: 1,
+            },
         )
-        .init();
+    });
+    let (mut failures, mut refused) = (0, 0);
+    let interrupt = tokio::signal::ctrl_c();
+    tokio::pin!(interrupt);
+    for seq in 1..=args.count {
+        let ping = async {
+            if seq > 1 {
+                tokio::time::sleep(args.interval).await;
+            }
+            let permit = match breaker.as_ref().map(breaker::Breaker::allow).transpose() {
+                Ok(permit) => permit,
+                Err(_) => return Ok(None),
+            };
+            let sent = std::time::Instant::now();
+            let attempted = policy
+                .call(|_| {
+                    let mut client = client.clone();
+                    let mut request = tonic::Request::new(PingRequest {
+                        client_send_ns: 0,
+                        client_send_realtime_ns: 0,
+                    });
+                    if args.trace_hops {
+                        let hop = [hops::Hop::departing("client", 0)];
+                        if let Ok(value) = hops::to_metadata(&hop).parse() {
+                            request.metadata_mut().insert(hops::METADATA_KEY, value);
+                        }
+                    }
+                    async move { client.ping(request).await }
+                })
+                .await;
+            match (permit, &attempted.result) {
+                (Some(permit), Ok(_)) => permit.success(),
+                (Some(permit), Err(_)) => permit.failure(),
+                (None, _) => {}
+            }
+            let response = attempted.result?;
+            let rtt = sent.elapsed().as_nanos() as i64;
+            let trace = response
+                .metadata()
+                .get(hops::METADATA_KEY)
+                .map(|v| hops::from_metadata(v.to_str().map_err(|e| e.to_string())?))
+                .transpose()?;
+            Ok::<_, Box<dyn std::error::Error>>(Some((
+                rtt,
+                attempted.attempts,
+                trace,
+                response.into_inner(),
+            )))
+        };
+        let (rtt, attempts, trace, pong) = tokio::select! {
+            result = ping => match result {
+                Ok(Some(done)) => done,
+                Ok(None) => {
+                    refused += 1;
+                    continue;
+                }
+                Err(e) if breaker.is_some() => {
+                    eprintln!("ping {}: {}", seq, e);
+                    failures += 1;
+                    continue;
+                }
+                Err(e) => {
+                    failed = Some(e);
+                    break;
+                }
+            },
+            _ = &mut interrupt => {
+                interrupted = true;
+                break;
+            }
+        };
+        if attempts > 1 {
+            retried.push(rtt);
+            extra_attempts += attempts - 1;
+        } else {
+            rtts.push(rtt);
+        }
+        let mut row = vec![
+            seq.into(),
+            output::Cell::nanos(rtt),
+            output::Cell::nanos(pong.server_send_ns - pong.server_recv_ns),
+        ];
+        if policy.retries() {
+            row.push(attempts.into());
+        }
+        if args.trace_hops {
+            row.push(hops_cell(trace.as_deref().unwrap_or_default()));
+        }
+        report.stream_row(&out, row);
+        if let Some(trace) = trace.filter(|_| out.human()) {
+            print!("{}", hops::waterfall(&trace));
+        }
+    }
+    let distribution = output::Cell::distribution(&rtts);
+    rtts.sort_unstable();
+    let stats = bench::Stats::from_sorted(&rtts);
+    report
+        .field("target", args.target.addr.as_str())
+        .field("count", rtts.len())
+        .field("min_ns", output::Cell::nanos(stats.min))
+        .field("mean_ns", output::Cell::nanos(stats.mean))
+        .field("p50_ns", output::Cell::nanos(stats.p50))
+        .field("p99_ns", output::Cell::nanos(stats.p99))
+        .field("max_ns", output::Cell::nanos(stats.max))
+        .field("distribution", distribution);
+    if policy.retries() {
+        retried.sort_unstable();
+        let stats = bench::Stats::from_sorted(&retried);
+        report
+            .field("retried", retried.len())
+            .field("retries", extra_attempts)
+            .field("retried_p50_ns", output::Cell::nanos(stats.p50))
+            .field("retried_max_ns", output::Cell::nanos(stats.max));
+    }
+    if let Some(breaker) = &breaker {
+        report
+            .field("failed", failures)
+            .field("refused", refused)
+            .field("breaker", breaker.state().name());
+    }
+    let progress = format!(
+        "after {} of {} pings",
+        rtts.len() + retried.len() + failures + refused,
+        args.count
+    );
+    match failed {
+        Some(e) if rtts.is_empty() => return Err(e),
+        Some(e) => {
+            report.partial(format!("failed {}: {}", progress, e));
+            out.print(&report);
+            return Err(e);
+        }
+        None if interrupted => {
+            report.partial(format!("interrupted {}", progress));
+        }
+        None => {}
+    }
+    out.print(&report);
+    Ok(())
+}
+
+/// Latencies of one mode of `client hedge`, plus what its hedges cost.
+#[derive(Default)]
+struct HedgeSamples {
+    /// Round trips in nanoseconds, sorted once a worker is done.
+    plain: Vec<i64>,
+    /// Likewise for hedged Pings.
+    hedged: Vec<i64>,
+    /// Copies beyond the first that hedged Pings sent.
+    extra: u64,
+    /// Hedged Pings answered by a copy rather than the original.
+    won: u64,
+}
+
+async fn client_hedge(args: HedgeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let client = connect(&args.target).await?;
+    let hedged = retry::Policy {
+        max_attempts: args.copies.max(2),
+        hedge_after: Some(args.hedge_after),
+        ..retry::Policy::default()
+    };
+    let plain = retry::Policy::default();
+
+    // Each worker alternates plain and hedged Pings, so both modes see
+    // the same server and network conditions.
+    let concurrency = args.concurrency.clamp(1, args.count.max(1));
+    let mut workers = tokio::task::JoinSet::new();
+    for worker in 0..concurrency {
+        let pings = args.count / concurrency + u32::from(worker < args.count % concurrency);
+        let (client, plain, hedged) = (client.clone(), plain.clone(), hedged.clone());
+        workers.spawn(async move {
+            let mut samples = HedgeSamples::default();
+            for i in 0..pings * 2 {
+                let hedge = (i + worker) % 2 == 1;
+                let sent = std::time::Instant::now();
+                let done = if hedge { &hedged } else { &plain }
+                    .call(|_| {
+                        let mut client = client.clone();
+                        async move {
+                            client
+                                .ping(PingRequest {
+                                    client_send_ns: 0,
+                                    client_send_realtime_ns: 0,
+                                })
+                                .await
+                        }
+                    })
+                    .await;
+                done.result?;
+                let rtt = sent.elapsed().as_nanos() as i64;
+                if hedge {
+                    samples.hedged.push(rtt);
+                    samples.extra += u64::from(done.attempts - 1);
+                    samples.won += u64::from(done.answered_by > 1);
+                } else {
+                    samples.plain.push(rtt);
+                }
+            }
+            samples.plain.sort_unstable();
+            samples.hedged.sort_unstable();
+            Ok::<_, tonic::Status>(samples)
+        });
+    }
+    let mut all = HedgeSamples::default();
+    while let Some(joined) = workers.join_next().await {
+        let samples = joined??;
+        all.plain = bench::merge_sorted(&all.plain, &samples.plain);
+        all.hedged = bench::merge_sorted(&all.hedged, &samples.hedged);
+        all.extra += samples.extra;
+        all.won += samples.won;
+    }
+
+    let out = output::Output::new(args.output);
+    let mut report = output::Report::new("hedge");
+    report.rows(
+        "modes",
+        &["mode", "mean_ns", "p50_ns", "p99_ns", "p999_ns", "max_ns"],
+    );
+    let mut p99 = [0; 2];
+    for (i, (mode, rtts)) in [("plain", &all.plain), ("hedged", &all.hedged)]
+        .into_iter()
+        .enumerate()
+    {
+        let stats = bench::Stats::from_sorted(rtts);
+        p99[i] = stats.p99;
+        report.row(vec![
+            mode.into(),
+            output::Cell::nanos(stats.mean),
+            output::Cell::nanos(stats.p50),
+            output::Cell::nanos(stats.p99),
+            output::Cell::nanos(bench::percentile(rtts, 99.9)),
+            output::Cell::nanos(stats.max),
+        ]);
+    }
+    let pings = all.hedged.len().max(1) as f64;
+    let improvement = if p99[0] > 0 {
+        100.0 * (p99[0] - p99[1]) as f64 / p99[0] as f64
+    } else {
+        0.0
+    };
+    let extra_load = 100.0 * all.extra as f64 / pings;
+    report
+        .field("target", args.target.addr.as_str())
+        .field("pings_per_mode", all.hedged.len())
+        .field(
+            "hedge_after_ns",
+            output::Cell::nanos(args.hedge_after.as_nanos() as i64),
+        )
+        .field(
+            "p99_improvement_pct",
+            output::Cell::with_human(improvement, format!("{:.1}%", improvement)),
+        )
+        .field(
+            "extra_load_pct",
+            output::Cell::with_human(extra_load, format!("+{:.1}%", extra_load)),
+        )
+        .field("hedges_won", all.won);
+    out.print(&report);
+    Ok(())
+}
+
+async fn client_echo(args: EchoArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let payload = bench::payload(args.content, args.bytes);
+    let tcp = match &args.proxy {
+        Some(proxy) => {
+            let (host, port) = args
+                .addr
+                .rsplit_once(':')
+                .ok_or("--addr must be HOST:PORT")?;
+            proxy.connect(host, port.parse()?).await?
+        }
+        None => tokio::net::TcpStream::connect(&args.addr).await?,
+    };
+    tcp.set_nodelay(true)?;
+    let echoed = match &args.ca_cert {
+        Some(path) => {
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            let mut roots = rustls::RootCertStore::empty();
+            let mut pem = std::io::BufReader::new(std::fs::File::open(path)?);
+            for cert in rustls_pemfile::certs(&mut pem) {
+                roots.add(cert?)?;
+            }
+            let config = rustls::ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            let host = match &args.tls_domain {
+                Some(domain) => domain.clone(),
+                None => args
+                    .addr
+                    .rsplit_once(':')
+                    .map_or(&*args.addr, |(h, _)| h)
+                    .trim_matches(['[', ']'])
+                    .to_string(),
+            };
+            let name = rustls::pki_types::ServerName::try_from(host)?;
+            let tls = tokio_rustls::TlsConnector::from(Arc::new(config))
+                .connect(name, tcp)
+                .await?;
+            throughput::verify_echo(tls, &payload, args.block, args.trace_hops).await?
+        }
+        None => throughput::verify_echo(tcp, &payload, args.block, args.trace_hops).await?,
+    };
+    let elapsed = echoed.elapsed;
+    let gbit = payload.len() as f64 * 8.0 / elapsed.as_secs_f64().max(1e-9) / 1e9;
+    let out = output::Output::new(args.output);
+    let mut report = output::Report::new("echo");
+    report
+        .field("target", args.addr.as_str())
+        .field("bytes", output::Cell::bytes(payload.len() as u64))
+        .field("content", args.content.name())
+        .field("verified", true)
+        .field("elapsed_ns", output::Cell::nanos(elapsed.as_nanos() as i64))
+        .field(
+            "gbit_per_sec",
+            output::Cell::with_human(gbit, format!("{:.2}", gbit)),
+        );
+    if args.trace_hops {
+        report.field("hops", hops_cell(&echoed.hops));
+    }
+    out.print(&report);
+    if args.trace_hops && out.human() {
+        print!("{}", hops::waterfall(&echoed.hops));
+    }
+    Ok(())
+}
+
+/// Hops for scripts as objects, for people as the path taken.
+fn hops_cell(trace: &[hops::Hop]) -> output::Cell {
+    let value: Vec<serde_json::Value> = trace
+        .iter()
+        .map(|h| serde_json::json!({"name": h.name, "recv_ns": h.recv_ns, "send_ns": h.send_ns}))
+        .collect();
+    let path: Vec<&str> = trace.iter().map(|h| h.name.as_str()).collect();
+    output::Cell::with_human(value, path.join(" > "))
+}
+
+const REPL_HELP: &str = "\
+commands:
+  ping [COUNT]                      send COUNT pings (default 1)
+  bench [ITERATIONS] [BYTES]        run a Benchmark (default 100 x 0 bytes)
+  replay RUN_ID                     run a recorded Benchmark again
+  info                              show ServerInfo
+  connect URL                       switch to another server, same TLS and proxy options
+  help                              show this
+  quit                              leave (or end of input)
+Ctrl-C stops a ping early, reporting the pings so far.";
+
+/// `client repl`: one command per line from stdin until quit or EOF. A
+/// failed command prints its error and leaves the session open.
+async fn client_repl(args: ReplArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Write;
+    use tokio::io::AsyncBufReadExt;
 
-    let args = Args::parse();
+    let mut target = args.target;
+    let out = output::Output::new(args.output);
+    let mut client = connect(&target).await?;
+    // Prompts go to stderr so json and yaml output stays parseable.
+    eprintln!("connected to {}; \"help\" lists commands", target.addr);
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        eprint!("{}> ", target.addr);
+        std::io::stderr().flush()?;
+        // Ctrl-C abandons the line being typed, as in a shell.
+        let line = tokio::select! {
+            line = lines.next_line() => line?,
+            _ = tokio::signal::ctrl_c() => {
+                eprintln!();
+                continue;
+            }
+        };
+        let Some(line) = line else {
+            eprintln!();
+            return Ok(());
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            [] => Ok(()),
+            ["quit" | "exit"] => return Ok(()),
+            ["help" | "?"] => {
+                println!("{}", REPL_HELP);
+                Ok(())
+            }
+            ["connect", addr] => {
+                let next = Target {
+                    addr: addr.to_string(),
+                    ..target.clone()
+                };
+                match connect(&next).await {
+                    Ok(c) => {
+                        (client, target) = (c, next);
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            ["ping", rest @ ..] if rest.len() <= 1 => repl_ping(&mut client, &out, rest).await,
+            ["bench", rest @ ..] if rest.len() <= 2 => repl_bench(&mut client, &out, rest).await,
+            ["replay", run_id] => repl_replay(&mut client, &out, run_id).await,
+            ["info"] => repl_info(&mut client, &out).await,
+            _ => Err(format!("unknown command {:?}; try \"help\"", line.trim()).into()),
+        };
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+        }
+    }
+}
+
+async fn repl_ping(
+    client: &mut HermitClient<Channel>,
+    out: &output::Output,
+    args: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let count: u32 = args.first().map_or(Ok(1), |n| n.parse())?;
+    let mut report = output::Report::new("ping");
+    report.rows("pings", &["seq", "rtt_ns", "server_ns"]);
+    for seq in 1..=count {
+        let sent = std::time::Instant::now();
+        let ping = client.ping(PingRequest {
+            client_send_ns: 0,
+            client_send_realtime_ns: 0,
+        });
+        let pong = tokio::select! {
+            pong = ping => pong?.into_inner(),
+            _ = tokio::signal::ctrl_c() => {
+                report.partial(format!("interrupted after {} of {} pings", seq - 1, count));
+                break;
+            }
+        };
+        let rtt = sent.elapsed().as_nanos() as i64;
+        report.row(vec![
+            seq.into(),
+            output::Cell::nanos(rtt),
+            output::Cell::nanos(pong.server_send_ns - pong.server_recv_ns),
+        ]);
+    }
+    out.print(&report);
+    Ok(())
+}
+
+async fn repl_bench(
+    client: &mut HermitClient<Channel>,
+    out: &output::Output,
+    args: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let iterations = args.first().map_or(Ok(100), |n| n.parse())?;
+    let payload_bytes = args.get(1).map_or(Ok(0), |n| n.parse())?;
+    let r = client
+        .benchmark(BenchmarkRequest {
+            iterations,
+            payload_bytes,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    out.print(&benchmark_report(r, Some(payload_bytes)));
+    Ok(())
+}
+
+async fn repl_replay(
+    client: &mut HermitClient<Channel>,
+    out: &output::Output,
+    run_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let r = client
+        .replay_benchmark(ReplayBenchmarkRequest {
+            run_id: run_id.to_string(),
+        })
+        .await?
+        .into_inner();
+    out.print(&benchmark_report(r, None));
+    Ok(())
+}
+
+/// `payload_bytes` is what a `bench` asked for; a replay doesn't know.
+fn benchmark_report(r: BenchmarkResponse, payload_bytes: Option<u32>) -> output::Report {
+    let mut report = output::Report::new("benchmark");
+    report
+        .field("run_id", r.run_id)
+        .field("iterations", r.latencies_ns.len());
+    if let Some(bytes) = payload_bytes {
+        report.field("payload_bytes", output::Cell::bytes(bytes.into()));
+    }
+    report
+        .field("min_ns", output::Cell::nanos(r.min_ns))
+        .field("mean_ns", output::Cell::nanos(r.mean_ns))
+        .field("p50_ns", output::Cell::nanos(r.p50_ns))
+        .field("p99_ns", output::Cell::nanos(r.p99_ns))
+        .field("max_ns", output::Cell::nanos(r.max_ns))
+        .field("distribution", output::Cell::distribution(&r.latencies_ns))
+        .field("clock_source", r.clock_source);
+    if !r.replay_of.is_empty() {
+        report.field("replay_of", r.replay_of);
+    }
+    report
+}
+
+async fn repl_info(
+    client: &mut HermitClient<Channel>,
+    out: &output::Output,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let info = client.server_info(ServerInfoRequest {}).await?.into_inner();
+    let state = match (info.ready, info.draining) {
+        (_, true) => "draining",
+        (true, false) => "ready",
+        (false, false) => "not ready",
+    };
+    let mut report = output::Report::new("server_info");
+    report
+        .field("version", info.version)
+        .field("git_sha", info.git_sha)
+        .field("region", info.region)
+        .field("uptime_seconds", info.uptime_seconds)
+        .field("protocol_version", info.protocol_version)
+        .field("tls_enabled", info.tls_enabled)
+        .field("state", state)
+        .field("connections_open", info.connections_open)
+        .field("rpcs_in_flight", info.rpcs_in_flight)
+        .field("worker_threads", info.worker_threads);
+    out.print(&report);
+    Ok(())
+}
+
+type CheckResult = Result<String, Box<dyn std::error::Error>>;
+
+/// `check`: everything `run` would load or bind, reported one line each.
+async fn check(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut failed = 0;
+    let mut report = |what: &str, result: CheckResult| match result {
+        Ok(detail) => info!("ok: {}{}", what, detail),
+        Err(e) => {
+            error!("FAILED: {}: {}", what, e);
+            failed += 1;
+        }
+    };
+
+    let mut regions = Vec::new();
+    let primary = std::iter::once((args.region.clone(), args.grpc_port));
+    for (region, port) in primary.chain(args.instances.iter().cloned()) {
+        let result = if regions.contains(&region) {
+            Err(format!("region {} is configured twice", region).into())
+        } else {
+            check_bind(port)
+        };
+        report(&format!("gRPC port {} ({})", port, region), result);
+        regions.push(region);
+    }
+    for (name, port) in [
+        ("health port", args.health_port),
+        ("throughput port", args.throughput_port),
+    ] {
+        if let Some(port) = port {
+            report(&format!("{} {}", name, port), check_bind(port));
+        }
+    }
+
+    if !args.no_tls {
+        report("TLS", check_tls(args).await);
+    }
+    report(
+        "signing key",
+        load_signer(args).await.map(|signer| match signer {
+            Some(s) => format!(" (key id {})", s.key_id()),
+            None => " (not configured)".to_string(),
+        }),
+    );
+    report(
+        "auth backend",
+        build_auth_backend(args).map(|b| format!(" ({})", b.name())),
+    );
+    report(
+        "session store",
+        build_session_store(args)
+            .await
+            .map(|s| format!(" ({})", s.name())),
+    );
+    report(
+        "TOTP store",
+        build_totp(args)
+            .await
+            .map(|t| format!(" ({})", t.store_name())),
+    );
+
+    report(
+        "GeoIP databases",
+        load_geoip(args)
+            .map(|db| match db {
+                Some(_) => String::new(),
+                None => " (not configured)".to_string(),
+            })
+            .map_err(Into::into),
+    );
+
+    report(
+        "shadow backend",
+        load_shadow(args)
+            .map(|shadow| match shadow {
+                Some(_) => String::new(),
+                None => " (not configured)".to_string(),
+            })
+            .map_err(Into::into),
+    );
+
+    report(
+        "gossip",
+        load_gossip(args)
+            .await
+            .map(|gossip| match gossip {
+                Some(g) => format!(" ({}, {} seed(s))", g.addr(), args.gossip_seeds.len()),
+                None => " (not configured)".to_string(),
+            })
+            .map_err(Into::into),
+    );
+
+    report(
+        "NUMA node",
+        match args.numa_node {
+            None => Ok(format!(" (not pinned, {} node(s))", numa::nodes().len())),
+            Some(id) => numa::node(id)
+                .map(|n| format!(" ({}, {} CPUs)", n.id, n.cpus.len()))
+                .map_err(Into::into),
+        },
+    );
+
+    report(
+        "hugepages",
+        Ok(match (args.hugepages, hugepage::pool_free()) {
+            (false, _) => " (off)".to_string(),
+            (true, Some(free)) => format!(" ({} free in the hugetlb pool)", free),
+            (true, None) => " (no hugetlb pool, transparent hugepages only)".to_string(),
+        }),
+    );
+
+    report(
+        "fleet sweeps",
+        match (args.sweep_interval.is_zero(), args.gossip_addr) {
+            (true, _) => Ok(" (not configured)".to_string()),
+            (false, None) => Err("--sweep-interval requires --gossip-addr".into()),
+            (false, Some(_)) => Ok(format!(
+                " (every {})",
+                humantime::format_duration(args.sweep_interval)
+            )),
+        },
+    );
+
+    report(
+        "etcd registry",
+        load_registry(args)
+            .map(|registry| match registry {
+                Some(_) => format!(" ({})", args.etcd_prefix),
+                None => " (not configured)".to_string(),
+            })
+            .map_err(Into::into),
+    );
+
+    report(
+        "HA peer",
+        load_pair(args)
+            .map(|pair| match pair {
+                Some(_) => String::new(),
+                None => " (not configured)".to_string(),
+            })
+            .map_err(Into::into),
+    );
+
+    let kernel = kernel::Support::probe();
+    let available = |yes| if yes { "available" } else { "unavailable" };
+    report(
+        "platform",
+        Ok(format!(
+            " ({}{}, io_uring {}, kTLS {})",
+            build_info::TARGET,
+            if build_info::STATIC { ", static" } else { "" },
+            available(kernel.io_uring),
+            available(kernel.ktls),
+        )),
+    );
+
+    if failed > 0 {
+        return Err(format!("{} check(s) failed", failed).into());
+    }
+    info!("configuration OK");
+    Ok(())
+}
+
+fn check_bind(port: u16) -> CheckResult {
+    std::net::TcpListener::bind(("0.0.0.0", port))?;
+    Ok(String::new())
+}
+
+async fn check_tls(args: &Args) -> CheckResult {
+    let _ = tls::crypto_provider(args.post_quantum)?.install_default();
+    let cfg = tls::resolve_tls_config(&tls_source(args)?).await?;
+    tls::load_sni_certs(&cfg, &args.sni_certs)?;
+    if let Some(path) = &args.ocsp_staple {
+        if std::fs::read(path)?.is_empty() {
+            return Err(format!("OCSP staple {} is empty", path).into());
+        }
+    }
+    let cert = cfg.certs.details(None)?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as i64;
+    if cert.not_after_unix <= now {
+        return Err(format!("certificate {} has expired", cert.sha256).into());
+    }
+    Ok(format!(
+        " (certificate {}, {}, expires in {} days, {} SNI certificate(s))",
+        cert.sha256,
+        cert.sans.join(" "),
+        (cert.not_after_unix - now) / 86_400,
+        args.sni_certs.len()
+    ))
+}
+
+async fn run(
+    args: Args,
+    instances: Vec<Instance>,
+    health_listener: Option<std::net::TcpListener>,
+    throughput_listener: Option<std::net::TcpListener>,
+    key_log: Option<std::fs::File>,
+    runs: runlog::RunLog,
+) -> Result<(), Box<dyn std::error::Error>> {
     let start_time = std::time::Instant::now();
     let started_at = std::time::SystemTime::now();
 
-    let server_state = Arc::new(grpc::ServerState {
-        version: env!("CARGO_PKG_VERSION").to_string(),
-        region: args.region.clone(),
-        started_at,
-        start_instant: start_time,
-        grpc_port: args.grpc_port,
-    });
+    let mut deadlines = deadline::Deadlines::new(args.default_rpc_timeout);
+    for (method, limit) in &args.rpc_timeouts {
+        deadlines.set(method.clone(), *limit);
+    }
+
+    let health = Arc::new(health::Health::new());
+    hugepage::set_preferred(args.hugepages);
 
     // Resolve TLS config unless --no-tls is set
     let tls_cfg = if args.no_tls {
         info!("TLS disabled (--no-tls), serving plaintext h2c");
         None
     } else {
-        // Install ring as the default crypto provider for rustls
-        rustls::crypto::ring::default_provider()
+        // Install the default crypto provider for rustls: ring, or
+        // aws-lc-rs with --post-quantum
+        tls::crypto_provider(args.post_quantum)?
             .install_default()
             .expect("failed to install rustls crypto provider");
-        Some(tls::resolve_tls_config(args.tls_cert.as_deref(), args.tls_key.as_deref())?)
+        if args.post_quantum {
+            info!("preferring X25519MLKEM768 key exchange");
+        }
+        let source = tls_source(&args)?;
+        let mut cfg = tls::resolve_tls_config(&source).await?;
+        if let Some(file) = key_log {
+            tls::log_keys(&mut cfg, file);
+        }
+        tls::load_sni_certs(&cfg, &args.sni_certs)?;
+        if let Some(path) = &args.ocsp_staple {
+            tls::staple_ocsp(
+                &cfg,
+                path.clone(),
+                Duration::from_secs(args.tls_rotation_secs.max(1)),
+            )?;
+        }
+        tls::spawn_rotation(
+            source,
+            &cfg,
+            Duration::from_secs(args.tls_rotation_secs.max(1)),
+        );
+        Some(cfg)
     };
 
+    if args.clock == ClockKind::Tsc {
+        match bench::enable_tsc() {
+            Ok(drift_ppm) => info!(drift_ppm, "using TSC clock"),
+            Err(e) => warn!("TSC clock unavailable, using monotonic: {}", e),
+        }
+    }
+    let resolution_ns = bench::clock_resolution_ns();
+    let overhead_ns = bench::timer_overhead_ns(&clock::SystemClock);
+    if resolution_ns > 1_000 {
+        warn!(
+            resolution_ns,
+            "clock is coarser than 1us; sub-tick latencies will read as 0"
+        );
+    } else {
+        info!(
+            resolution_ns,
+            overhead_ns,
+            source = bench::clock_source(),
+            "clock calibrated"
+        );
+    }
+
     info!(
         grpc_port = args.grpc_port,
         region = %args.region,
         tls = tls_cfg.is_some(),
+        instances = instances.len(),
         "hermit-server starting"
     );
+    let kernel = kernel::Support::probe();
+    info!(
+        target = build_info::TARGET,
+        static_binary = build_info::STATIC,
+        io_uring = kernel.io_uring,
+        ktls = kernel.ktls,
+        "platform"
+    );
+
+    let auth_backend = build_auth_backend(&args)?;
+    info!(backend = auth_backend.name(), "auth backend configured");
+
+    let signer = load_signer(&args).await?.map(Arc::new);
+    if let Some(s) = &signer {
+        info!(key_id = s.key_id(), "benchmark result signing enabled");
+    }
+
+    let session_store = build_session_store(&args).await?;
+    info!(store = session_store.name(), "session store configured");
 
-    let database = Arc::new(db::Database::new());
+    let totp = build_totp(&args).await?;
+    if totp.store_name() == "memory" {
+        warn!("TOTP enrollments are kept in memory and lost on restart; see --totp-file");
+    }
+    info!(store = totp.store_name(), "TOTP store configured");
 
-    // Run gRPC server (only listener for Cloud Run single-port)
-    if let Err(e) = grpc::serve(args.grpc_port, server_state, tls_cfg, database).await {
-        error!("gRPC server exited with error: {:?}", e);
+    let webhook_format = match args.webhook_format {
+        WebhookFormat::Json => notify::Format::Json,
+        WebhookFormat::Slack => notify::Format::Slack,
+    };
+    if args.webhook_url.is_some() {
+        info!(format = ?webhook_format, "benchmark webhook enabled");
+    }
+
+    let geoip = load_geoip(&args)?;
+    if geoip.is_some() {
+        info!("GeoIP tagging of benchmark results enabled");
+    }
+
+    let shadow = load_shadow(&args)?;
+    if let Some(addr) = &args.shadow_addr {
+        info!(shadow = %addr, "mirroring Ping and Benchmark to shadow backend");
+    }
+
+    let gossip = load_gossip(&args).await?;
+    let registry = load_registry(&args)?;
+    let sweeper = load_sweeper(&args, gossip.as_ref())?;
+
+    let pair = load_pair(&args)?;
+    if let (Some(role), Some(peer)) = (args.ha_role, &args.ha_peer) {
+        info!(?role, %peer, "active/standby pairing enabled");
+    }
+
+    let backends = grpc::Backends {
+        db: Arc::new(db::Database::new()),
+        auth: auth_backend,
+        sessions: session_store,
+        totp,
+        signer,
+        webhook: None,
+        geoip,
+        shadow,
+        gossip: gossip.clone(),
+        registry: registry.clone(),
+        sweeper: sweeper.clone(),
+        runs: Arc::new(runs),
+    };
+
+    // Everything that may need root (key files, secrets) has been read.
+    sandbox::drop_privileges(args.user.as_deref(), args.group.as_deref())?;
+    if args.sandbox {
+        sandbox::install_seccomp()?;
+    }
+
+    if let Some(l) = health_listener {
+        tokio::spawn(health::serve(
+            tokio::net::TcpListener::from_std(l)?,
+            health.clone(),
+        ));
+    }
+    if let Some(l) = throughput_listener {
+        let acceptor = tls_cfg
+            .as_ref()
+            .map(|cfg| tokio_rustls::TlsAcceptor::from(cfg.server_config.clone()));
+        tokio::spawn(throughput::serve(
+            tokio::net::TcpListener::from_std(l)?,
+            acceptor,
+        ));
+    }
+    if !args.wakeup_probe_interval.is_zero() {
+        tokio::spawn(wakeup::probe(args.wakeup_probe_interval));
+    }
+
+    // Cloud Run only routes to the primary; --instance is for local
+    // multi-region setups.
+    let (stop, _) = tokio::sync::watch::channel(false);
+    let mut servers = Vec::new();
+    for Instance { region, listener } in instances {
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let state = Arc::new(grpc::ServerState {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            region: region.clone(),
+            started_at,
+            start_instant: start_time,
+            grpc_port: listener.local_addr()?.port(),
+            session_ttl_secs: args.session_ttl_secs,
+            deadlines: deadlines.clone(),
+            keepalive: listener::Keepalive {
+                tcp: Some(args.tcp_keepalive).filter(|d| !d.is_zero()),
+                http2_interval: Some(args.http2_keepalive).filter(|d| !d.is_zero()),
+                http2_timeout: args.http2_keepalive_timeout,
+                max_age: Some(args.max_connection_age).filter(|d| !d.is_zero()),
+                max_age_grace: args.max_connection_age_grace,
+                max_idle: Some(args.max_connection_idle).filter(|d| !d.is_zero()),
+            },
+            dscp: args.dscp,
+            tcp_maxseg: args.tcp_maxseg,
+            alpn_multiplex: args.alpn_multiplex,
+            trace_frames: args.trace_frames,
+            clock: Arc::new(clock::SystemClock),
+            health: health.clone(),
+            pair: pair.clone(),
+        });
+        let backends = grpc::Backends {
+            webhook: args.webhook_url.clone().map(|url| {
+                Arc::new(notify::Webhook::new(
+                    url,
+                    webhook_format,
+                    region.clone(),
+                    args.baseline_p99.map(|d| d.as_nanos() as i64),
+                    args.regression_tolerance_pct,
+                ))
+            }),
+            ..backends.clone()
+        };
+        let mut stopped = stop.subscribe();
+        let shutdown = async move {
+            let _ = stopped.wait_for(|stopped| *stopped).await;
+        };
+        let tls_cfg = tls_cfg.clone();
+        servers.push(tokio::spawn(async move {
+            let result = grpc::serve(listener, state, tls_cfg, backends, shutdown).await;
+            (region, result)
+        }));
+    }
+    if let Some(gossip) = gossip {
+        gossip.start();
+    }
+    if let Some(sweeper) = sweeper {
+        sweeper.start(args.sweep_interval);
+    }
+    if let Some(registry) = registry {
+        registry.start(deadlines.clone());
+    }
+    if let Some(pair) = pair {
+        pair.start(health.clone(), args.ha_check_interval);
+    }
+    health.set_ready();
+
+    tokio::spawn(async move {
+        health::drain_on_signal(health, args.drain_grace).await;
+        let _ = stop.send(true);
+    });
+    for server in servers {
+        match server.await {
+            Ok((region, Ok(()))) => info!(%region, "gRPC server drained and stopped"),
+            Ok((region, Err(e))) => error!(%region, "gRPC server exited with error: {:?}", e),
+            Err(e) => error!("gRPC server task failed: {}", e),
+        }
     }
 
     Ok(())
@@ -0,0 +1,706 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::breaker::State as BreakerState;
+use crate::sniff::Protocol;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Process-wide counters. Listener tasks have no handle on server state,
+/// so these live in a static rather than in `ServerState`.
+pub struct Metrics {
+    connections_reaped: AtomicU64,
+    listeners: Mutex<Vec<Arc<ListenerMetrics>>>,
+    timer_wakeup: Histogram,
+    task_wakeup: Histogram,
+    /// Indexed by `ShadowRpc`, then `ShadowResult`.
+    shadow: [[AtomicU64; 4]; 2],
+    proxy_connect: Histogram,
+    /// Indexed by `ProxyDirection`.
+    proxy_forward: [Histogram; 2],
+    breakers: Mutex<Vec<Arc<BreakerMetrics>>>,
+    /// Indexed by `Port`, then `Protocol`.
+    wrong_protocol: [[AtomicU64; Protocol::ALL.len()]; 2],
+}
+
+pub static METRICS: Metrics = Metrics {
+    connections_reaped: AtomicU64::new(0),
+    listeners: Mutex::new(Vec::new()),
+    timer_wakeup: Histogram::new(),
+    task_wakeup: Histogram::new(),
+    shadow: [const { [const { AtomicU64::new(0) }; 4] }; 2],
+    proxy_connect: Histogram::new(),
+    proxy_forward: [const { Histogram::new() }; 2],
+    breakers: Mutex::new(Vec::new()),
+    wrong_protocol: [const { [const { AtomicU64::new(0) }; Protocol::ALL.len()] }; 2],
+};
+
+/// What a runtime wakeup latency was measured on; see `crate::wakeup`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wakeup {
+    /// A timer firing after its deadline.
+    Timer,
+    /// A newly spawned task being polled.
+    Task,
+}
+
+/// An RPC mirrored to the shadow backend; see `crate::shadow`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowRpc {
+    Ping,
+    Benchmark,
+}
+
+/// How a mirrored request turned out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowResult {
+    /// The shadow's response agreed with ours.
+    Match,
+    Mismatch,
+    /// The shadow couldn't be reached, or timed out.
+    Error,
+    /// Not sent because too many mirrors were already in flight.
+    Dropped,
+}
+
+/// Which way `crate::proxy` forwarded a chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyDirection {
+    /// Client to upstream.
+    Upstream,
+    Downstream,
+}
+
+/// A port a client can speak the wrong protocol to; see `crate::sniff`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Port {
+    Grpc,
+    Throughput,
+}
+
+impl Port {
+    pub const ALL: [Port; 2] = [Port::Grpc, Port::Throughput];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Port::Grpc => "grpc",
+            Port::Throughput => "throughput",
+        }
+    }
+}
+
+/// Why a TLS handshake on a gRPC listener failed; see `crate::listener`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeFailure {
+    /// The client spoke plaintext (HTTP/1, h2c, echo frames) to the TLS
+    /// port.
+    Plaintext,
+    /// The client only offered TLS versions the server doesn't accept.
+    ProtocolVersion,
+    /// No cipher suite, key exchange group or signature scheme in common.
+    NoSharedParameters,
+    /// The client refused the server's certificate: usually a name (SNI)
+    /// it doesn't cover, or a CA the client doesn't trust.
+    CertificateRejected,
+    /// A client certificate was required but missing or invalid.
+    ClientCertificate,
+    /// The client went quiet before the handshake finished.
+    Timeout,
+    /// The client hung up mid-handshake.
+    PeerClosed,
+    Other,
+}
+
+impl HandshakeFailure {
+    pub const ALL: [HandshakeFailure; 8] = [
+        HandshakeFailure::Plaintext,
+        HandshakeFailure::ProtocolVersion,
+        HandshakeFailure::NoSharedParameters,
+        HandshakeFailure::CertificateRejected,
+        HandshakeFailure::ClientCertificate,
+        HandshakeFailure::Timeout,
+        HandshakeFailure::PeerClosed,
+        HandshakeFailure::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HandshakeFailure::Plaintext => "plaintext",
+            HandshakeFailure::ProtocolVersion => "protocol_version",
+            HandshakeFailure::NoSharedParameters => "no_shared_parameters",
+            HandshakeFailure::CertificateRejected => "certificate_rejected",
+            HandshakeFailure::ClientCertificate => "client_certificate",
+            HandshakeFailure::Timeout => "timeout",
+            HandshakeFailure::PeerClosed => "peer_closed",
+            HandshakeFailure::Other => "other",
+        }
+    }
+}
+
+/// One failed handshake, as kept in `ListenerMetrics`.
+#[derive(Clone, Debug)]
+pub struct FailedHandshake {
+    pub at: SystemTime,
+    pub peer: SocketAddr,
+    pub reason: HandshakeFailure,
+    /// The server name the client asked for, if its ClientHello got far
+    /// enough to say.
+    pub sni: Option<String>,
+    pub detail: String,
+}
+
+/// Failed handshakes each listener remembers.
+pub const RECENT_HANDSHAKE_FAILURES: usize = 32;
+
+impl ShadowResult {
+    const ALL: [ShadowResult; 4] = [
+        ShadowResult::Match,
+        ShadowResult::Mismatch,
+        ShadowResult::Error,
+        ShadowResult::Dropped,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ShadowResult::Match => "match",
+            ShadowResult::Mismatch => "mismatch",
+            ShadowResult::Error => "error",
+            ShadowResult::Dropped => "dropped",
+        }
+    }
+}
+
+impl Metrics {
+    /// A connection was torn down because the peer stopped responding (TCP
+    /// keepalive or HTTP/2 PING timeout) rather than closing cleanly.
+    pub fn connection_reaped(&self) {
+        self.connections_reaped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connections_reaped(&self) -> u64 {
+        self.connections_reaped.load(Ordering::Relaxed)
+    }
+
+    pub fn wakeup_latency(&self, kind: Wakeup, latency: Duration) {
+        match kind {
+            Wakeup::Timer => self.timer_wakeup.observe(latency),
+            Wakeup::Task => self.task_wakeup.observe(latency),
+        }
+    }
+
+    pub fn shadow_result(&self, rpc: ShadowRpc, result: ShadowResult) {
+        self.shadow[rpc as usize][result as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn shadow_results(&self, rpc: ShadowRpc, result: ShadowResult) -> u64 {
+        self.shadow[rpc as usize][result as usize].load(Ordering::Relaxed)
+    }
+
+    /// How long `crate::proxy` took to connect to its upstream.
+    pub fn proxy_connected(&self, latency: Duration) {
+        self.proxy_connect.observe(latency);
+    }
+
+    /// How long a chunk spent in `crate::proxy`, from being read to being
+    /// written on.
+    pub fn proxy_forwarded(&self, direction: ProxyDirection, latency: Duration) {
+        self.proxy_forward[direction as usize].observe(latency);
+    }
+
+    /// A client spoke `sent` to `port` and was turned away.
+    pub fn wrong_protocol(&self, port: Port, sent: Protocol) {
+        self.wrong_protocol[port as usize][sent as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn wrong_protocols(&self, port: Port, sent: Protocol) -> u64 {
+        self.wrong_protocol[port as usize][sent as usize].load(Ordering::Relaxed)
+    }
+
+    /// Gauges for a gRPC listener, exported until `unregister_listener`.
+    pub fn register_listener(&self, addr: SocketAddr, tls: bool) -> Arc<ListenerMetrics> {
+        let listener = Arc::new(ListenerMetrics::new(addr, tls));
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.push(listener.clone());
+        }
+        listener
+    }
+
+    pub fn unregister_listener(&self, listener: &Arc<ListenerMetrics>) {
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.retain(|l| !Arc::ptr_eq(l, listener));
+        }
+    }
+
+    /// The registered gRPC listeners.
+    pub fn listeners(&self) -> Vec<Arc<ListenerMetrics>> {
+        self.listeners.lock().map(|l| l.clone()).unwrap_or_default()
+    }
+
+    /// State and transition counters for a `crate::breaker::Breaker`,
+    /// exported until `unregister_breaker`.
+    pub fn register_breaker(&self, name: &str) -> Arc<BreakerMetrics> {
+        let breaker = Arc::new(BreakerMetrics::new(name));
+        if let Ok(mut breakers) = self.breakers.lock() {
+            breakers.push(breaker.clone());
+        }
+        breaker
+    }
+
+    pub fn unregister_breaker(&self, breaker: &Arc<BreakerMetrics>) {
+        if let Ok(mut breakers) = self.breakers.lock() {
+            breakers.retain(|b| !Arc::ptr_eq(b, breaker));
+        }
+    }
+
+    /// Prometheus text exposition format (version 0.0.4).
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP hermit_connections_reaped_total Connections dropped because the peer stopped responding."
+        );
+        let _ = writeln!(out, "# TYPE hermit_connections_reaped_total counter");
+        let _ = writeln!(
+            out,
+            "hermit_connections_reaped_total {}",
+            self.connections_reaped()
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP hermit_wrong_protocol_total Connections turned away for speaking another protocol than their port's, by what they spoke."
+        );
+        let _ = writeln!(out, "# TYPE hermit_wrong_protocol_total counter");
+        for port in Port::ALL {
+            for sent in Protocol::ALL {
+                let _ = writeln!(
+                    out,
+                    "hermit_wrong_protocol_total{{port=\"{}\",sent=\"{}\"}} {}",
+                    port.name(),
+                    sent.name(),
+                    self.wrong_protocols(port, sent)
+                );
+            }
+        }
+
+        let name = "hermit_runtime_wakeup_latency_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} How late the async runtime woke a timer or first polled a spawned task.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        self.timer_wakeup.render(&mut out, name, "kind=\"timer\"");
+        self.task_wakeup.render(&mut out, name, "kind=\"task\"");
+
+        let _ = writeln!(
+            out,
+            "# HELP hermit_shadow_requests_total Requests mirrored to the shadow backend, by outcome."
+        );
+        let _ = writeln!(out, "# TYPE hermit_shadow_requests_total counter");
+        for (rpc, name) in [
+            (ShadowRpc::Ping, "Ping"),
+            (ShadowRpc::Benchmark, "Benchmark"),
+        ] {
+            for result in ShadowResult::ALL {
+                let _ = writeln!(
+                    out,
+                    "hermit_shadow_requests_total{{rpc=\"{}\",result=\"{}\"}} {}",
+                    name,
+                    result.name(),
+                    self.shadow_results(rpc, result)
+                );
+            }
+        }
+
+        let name = "hermit_proxy_connect_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} How long the proxy took to connect to its upstream.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        self.proxy_connect.render(&mut out, name, "");
+        let name = "hermit_proxy_forward_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} Time each chunk spent in the proxy, from read to write.",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (direction, label) in [
+            (ProxyDirection::Upstream, "direction=\"upstream\""),
+            (ProxyDirection::Downstream, "direction=\"downstream\""),
+        ] {
+            self.proxy_forward[direction as usize].render(&mut out, name, label);
+        }
+
+        let breakers = self.breakers.lock().map(|b| b.clone()).unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "# HELP hermit_circuit_breaker_state Circuit breaker state: 0 closed, 1 half-open, 2 open."
+        );
+        let _ = writeln!(out, "# TYPE hermit_circuit_breaker_state gauge");
+        for b in &breakers {
+            let _ = writeln!(
+                out,
+                "hermit_circuit_breaker_state{{breaker=\"{}\"}} {}",
+                b.name,
+                b.state.load(Ordering::Relaxed)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP hermit_circuit_breaker_transitions_total Circuit breaker state changes, by the state entered."
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE hermit_circuit_breaker_transitions_total counter"
+        );
+        for b in &breakers {
+            for (i, state) in BreakerState::ALL.into_iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "hermit_circuit_breaker_transitions_total{{breaker=\"{}\",to=\"{}\"}} {}",
+                    b.name,
+                    state,
+                    b.transitions[i].load(Ordering::Relaxed)
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP hermit_circuit_breaker_rejected_total Calls refused while a circuit breaker was open."
+        );
+        let _ = writeln!(out, "# TYPE hermit_circuit_breaker_rejected_total counter");
+        for b in &breakers {
+            let _ = writeln!(
+                out,
+                "hermit_circuit_breaker_rejected_total{{breaker=\"{}\"}} {}",
+                b.name,
+                b.rejected.load(Ordering::Relaxed)
+            );
+        }
+
+        let listeners = match self.listeners.lock() {
+            Ok(listeners) => listeners.clone(),
+            Err(_) => return out,
+        };
+        let gauges: [(&str, &str, GaugeValue); 4] = [
+            (
+                "hermit_connections_open",
+                "Accepted connections not yet closed.",
+                |l| l.connections_open().to_string(),
+            ),
+            (
+                "hermit_rpcs_in_flight",
+                "RPCs currently being handled.",
+                |l| l.rpcs_in_flight().to_string(),
+            ),
+            (
+                "hermit_accept_queue_depth",
+                "Connections waiting in the kernel accept queue at the last accept.",
+                |l| l.accept_queue_depth().to_string(),
+            ),
+            (
+                "hermit_accept_queue_wait_seconds",
+                "Estimated time a new connection waits in the accept queue.",
+                |l| format!("{:.9}", l.accept_queue_wait().as_secs_f64()),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            for l in &listeners {
+                let _ = writeln!(
+                    out,
+                    "{}{{listener=\"{}\",tls=\"{}\"}} {}",
+                    name,
+                    l.addr,
+                    l.tls,
+                    value(l)
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP hermit_tls_handshake_failures_total Failed TLS handshakes, by reason."
+        );
+        let _ = writeln!(out, "# TYPE hermit_tls_handshake_failures_total counter");
+        for l in listeners.iter().filter(|l| l.tls) {
+            for reason in HandshakeFailure::ALL {
+                let _ = writeln!(
+                    out,
+                    "hermit_tls_handshake_failures_total{{listener=\"{}\",reason=\"{}\"}} {}",
+                    l.addr,
+                    reason.name(),
+                    l.handshake_failures(reason)
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Upper bounds of the `Histogram` buckets, in nanoseconds: 10µs to 100ms.
+const BUCKETS_NS: [u64; 13] = [
+    10_000,
+    25_000,
+    50_000,
+    100_000,
+    250_000,
+    500_000,
+    1_000_000,
+    2_500_000,
+    5_000_000,
+    10_000_000,
+    25_000_000,
+    50_000_000,
+    100_000_000,
+];
+
+/// A Prometheus histogram of durations with fixed `BUCKETS_NS` bounds.
+pub struct Histogram {
+    /// Per-bucket (not cumulative) counts; the last is +Inf.
+    buckets: [AtomicU64; BUCKETS_NS.len() + 1],
+    sum_ns: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS_NS.len() + 1],
+            sum_ns: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, d: Duration) {
+        let ns = d.as_nanos().min(u128::from(u64::MAX)) as u64;
+        let i = BUCKETS_NS.partition_point(|&le| le < ns);
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        self.sum_ns.fetch_add(ns, Ordering::Relaxed);
+    }
+
+    /// `_bucket`, `_sum` and `_count` series with `labels` (already
+    /// formatted, without braces; may be empty).
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = match BUCKETS_NS.get(i) {
+                Some(&ns) => (ns as f64 / 1e9).to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, sep, le, cumulative
+            );
+        }
+        let sum = self.sum_ns.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(out, "{}_sum{{{}}} {:.9}", name, labels, sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, cumulative);
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+/// Formats one listener's value of a gauge.
+type GaugeValue = fn(&ListenerMetrics) -> String;
+
+/// Current load on one gRPC listener, so overload shows up as queueing
+/// here before it shows up as noise in benchmark results.
+#[derive(Debug)]
+pub struct ListenerMetrics {
+    addr: SocketAddr,
+    tls: bool,
+    connections_open: AtomicU64,
+    rpcs_in_flight: AtomicU64,
+    accept_queue_depth: AtomicU64,
+    accept_queue_wait_ns: AtomicU64,
+    /// Indexed by `HandshakeFailure`.
+    handshake_failures: [AtomicU64; HandshakeFailure::ALL.len()],
+    recent_handshake_failures: Mutex<VecDeque<FailedHandshake>>,
+}
+
+impl ListenerMetrics {
+    /// Unregistered gauges; see `Metrics::register_listener`.
+    pub fn new(addr: SocketAddr, tls: bool) -> Self {
+        ListenerMetrics {
+            addr,
+            tls,
+            connections_open: AtomicU64::new(0),
+            rpcs_in_flight: AtomicU64::new(0),
+            accept_queue_depth: AtomicU64::new(0),
+            accept_queue_wait_ns: AtomicU64::new(0),
+            handshake_failures: Default::default(),
+            recent_handshake_failures: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    pub fn connection_opened(&self) {
+        self.connections_open.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self) {
+        self.connections_open.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn connections_open(&self) -> u64 {
+        self.connections_open.load(Ordering::Relaxed)
+    }
+
+    /// Counts an RPC as in flight until the guard is dropped.
+    pub fn rpc_started(self: &Arc<Self>) -> RpcGuard {
+        self.rpcs_in_flight.fetch_add(1, Ordering::Relaxed);
+        RpcGuard(self.clone())
+    }
+
+    pub fn rpcs_in_flight(&self) -> u64 {
+        self.rpcs_in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn set_accept_queue(&self, depth: u32, wait: Duration) {
+        self.accept_queue_depth
+            .store(u64::from(depth), Ordering::Relaxed);
+        self.accept_queue_wait_ns
+            .store(wait.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn accept_queue_depth(&self) -> u64 {
+        self.accept_queue_depth.load(Ordering::Relaxed)
+    }
+
+    pub fn accept_queue_wait(&self) -> Duration {
+        Duration::from_nanos(self.accept_queue_wait_ns.load(Ordering::Relaxed))
+    }
+
+    pub fn handshake_failed(&self, failure: FailedHandshake) {
+        self.handshake_failures[failure.reason as usize].fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut recent) = self.recent_handshake_failures.lock() {
+            if recent.len() == RECENT_HANDSHAKE_FAILURES {
+                recent.pop_front();
+            }
+            recent.push_back(failure);
+        }
+    }
+
+    pub fn handshake_failures(&self, reason: HandshakeFailure) -> u64 {
+        self.handshake_failures[reason as usize].load(Ordering::Relaxed)
+    }
+
+    /// The latest failed handshakes, oldest first.
+    pub fn recent_handshake_failures(&self) -> Vec<FailedHandshake> {
+        self.recent_handshake_failures
+            .lock()
+            .map(|r| r.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Exported state of one circuit breaker.
+pub struct BreakerMetrics {
+    name: String,
+    /// Index into `BreakerState::ALL`, which is also the exported value.
+    state: AtomicU64,
+    /// Transitions into each state, indexed the same way.
+    transitions: [AtomicU64; 3],
+    rejected: AtomicU64,
+}
+
+impl BreakerMetrics {
+    fn new(name: &str) -> Self {
+        BreakerMetrics {
+            name: name.replace(['"', '\\', '\n'], "_"),
+            state: AtomicU64::new(0),
+            transitions: Default::default(),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn transition(&self, to: BreakerState) {
+        let i = BreakerState::ALL.iter().position(|&s| s == to).unwrap_or(0);
+        self.state.store(i as u64, Ordering::Relaxed);
+        self.transitions[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub struct RpcGuard(Arc<ListenerMetrics>);
+
+impl Drop for RpcGuard {
+    fn drop(&mut self) {
+        self.0.rpcs_in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listener_gauges_are_exported_while_registered() {
+        let addr: SocketAddr = ([127, 0, 0, 1], 65_001).into();
+        let gauges = METRICS.register_listener(addr, true);
+        gauges.connection_opened();
+        let guard = gauges.rpc_started();
+        gauges.set_accept_queue(3, Duration::from_micros(250));
+
+        let text = METRICS.render_prometheus();
+        let labels = "{listener=\"127.0.0.1:65001\",tls=\"true\"}";
+        for line in [
+            format!("hermit_connections_open{} 1", labels),
+            format!("hermit_rpcs_in_flight{} 1", labels),
+            format!("hermit_accept_queue_depth{} 3", labels),
+            format!("hermit_accept_queue_wait_seconds{} 0.000250000", labels),
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?}", line);
+        }
+
+        drop(guard);
+        assert_eq!(gauges.rpcs_in_flight(), 0);
+        METRICS.unregister_listener(&gauges);
+        assert!(!METRICS.render_prometheus().contains(labels));
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let h = Histogram::new();
+        h.observe(Duration::from_micros(5));
+        h.observe(Duration::from_micros(30));
+        h.observe(Duration::from_secs(1));
+
+        let mut text = String::new();
+        h.render(&mut text, "h", "kind=\"x\"");
+        for line in [
+            "h_bucket{kind=\"x\",le=\"0.00001\"} 1",
+            "h_bucket{kind=\"x\",le=\"0.000025\"} 1",
+            "h_bucket{kind=\"x\",le=\"0.00005\"} 2",
+            "h_bucket{kind=\"x\",le=\"0.1\"} 2",
+            "h_bucket{kind=\"x\",le=\"+Inf\"} 3",
+            "h_sum{kind=\"x\"} 1.000035000",
+            "h_count{kind=\"x\"} 3",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?}", line);
+        }
+    }
+}
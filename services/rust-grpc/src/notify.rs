@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::hermit::BenchmarkResponse;
+use serde_json::json;
+use std::time::Duration;
+use tracing::warn;
+
+const POST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug)]
+pub enum Format {
+    /// Flat JSON object with the result fields.
+    Json,
+    /// `{"text": ...}`, accepted by Slack incoming webhooks and most
+    /// Slack-compatible chat bridges.
+    Slack,
+}
+
+/// Fires a POST when a benchmark completes, or a regression notice when
+/// its p99 exceeds the configured baseline by more than `tolerance_pct`.
+/// Delivery is best-effort: the RPC never waits on it and failures are
+/// only logged.
+pub struct Webhook {
+    http: reqwest::Client,
+    url: String,
+    format: Format,
+    region: String,
+    baseline_p99_ns: Option<i64>,
+    tolerance_pct: f64,
+}
+
+impl Webhook {
+    pub fn new(
+        url: String,
+        format: Format,
+        region: String,
+        baseline_p99_ns: Option<i64>,
+        tolerance_pct: f64,
+    ) -> Self {
+        Webhook {
+            http: reqwest::Client::builder()
+                .timeout(POST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            url,
+            format,
+            region,
+            baseline_p99_ns,
+            tolerance_pct,
+        }
+    }
+
+    fn is_regression(&self, p99_ns: i64) -> bool {
+        self.baseline_p99_ns
+            .is_some_and(|base| p99_ns as f64 > base as f64 * (1.0 + self.tolerance_pct / 100.0))
+    }
+
+    pub fn benchmark_completed(&self, resp: &BenchmarkResponse) {
+        let regression = self.is_regression(resp.p99_ns);
+        let event = if regression {
+            "benchmark.regression"
+        } else {
+            "benchmark.completed"
+        };
+        let body = match self.format {
+            Format::Json => json!({
+                "event": event,
+                "region": self.region,
+                "iterations": resp.latencies_ns.len(),
+                "min_ns": resp.min_ns,
+                "max_ns": resp.max_ns,
+                "mean_ns": resp.mean_ns,
+                "p50_ns": resp.p50_ns,
+                "p99_ns": resp.p99_ns,
+                "baseline_p99_ns": self.baseline_p99_ns,
+                "tls_active": resp.tls_active,
+                "peer_addr": resp.peer_addr,
+                "transport": resp.transport,
+                "peer_country": resp.peer_country,
+                "peer_asn": resp.peer_asn,
+                "signing_key_id": resp.signing_key_id,
+                "labels": resp
+                    .labels
+                    .iter()
+                    .map(|l| (l.key.clone(), l.value.clone().into()))
+                    .collect::<serde_json::Map<_, _>>(),
+            }),
+            Format::Slack => {
+                let mut text = format!(
+                    "hermit benchmark in {}: {} iterations, p50 {}ns, p99 {}ns",
+                    self.region,
+                    resp.latencies_ns.len(),
+                    resp.p50_ns,
+                    resp.p99_ns
+                );
+                if !resp.labels.is_empty() {
+                    let labels: Vec<String> = resp
+                        .labels
+                        .iter()
+                        .map(|l| format!("{}={}", l.key, l.value))
+                        .collect();
+                    text = format!("{} [{}]", text, labels.join(", "));
+                }
+                if let (true, Some(base)) = (regression, self.baseline_p99_ns) {
+                    text = format!(":warning: p99 regression: {} (baseline {}ns)", text, base);
+                }
+                json!({ "text": text })
+            }
+        };
+
+        let http = self.http.clone();
+        let url = self.url.clone();
+        tokio::spawn(async move {
+            let res = http
+                .post(&url)
+                .json(&body)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+            if let Err(e) = res {
+                warn!(event, "benchmark webhook failed: {}", e);
+            }
+        });
+    }
+}
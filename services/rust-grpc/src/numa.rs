@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::sync::OnceLock;
+
+/// A NUMA node that has CPUs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub id: u32,
+    pub cpus: Vec<usize>,
+}
+
+/// Set by the first `bind_thread`.
+static PINNED: OnceLock<u32> = OnceLock::new();
+
+/// The host's NUMA nodes that have CPUs, by id; empty off Linux or when
+/// the kernel has no NUMA support.
+#[cfg(target_os = "linux")]
+pub fn nodes() -> Vec<Node> {
+    let mut nodes: Vec<Node> = std::fs::read_dir("/sys/devices/system/node")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| {
+            let id = e.file_name().to_str()?.strip_prefix("node")?.parse().ok()?;
+            let cpulist = std::fs::read_to_string(e.path().join("cpulist")).ok()?;
+            let cpus = parse_cpulist(&cpulist)?;
+            (!cpus.is_empty()).then_some(Node { id, cpus })
+        })
+        .collect();
+    nodes.sort_by_key(|n| n.id);
+    nodes
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn nodes() -> Vec<Node> {
+    Vec::new()
+}
+
+/// The node with this id, for --numa-node.
+pub fn node(id: u32) -> Result<Node, String> {
+    let nodes = nodes();
+    if nodes.is_empty() {
+        return Err("no NUMA topology found in /sys/devices/system/node".to_string());
+    }
+    let ids: Vec<String> = nodes.iter().map(|n| n.id.to_string()).collect();
+    nodes
+        .into_iter()
+        .find(|n| n.id == id)
+        .ok_or_else(|| format!("no NUMA node {} with CPUs (have {})", id, ids.join(", ")))
+}
+
+/// The node the server's threads were bound to by `bind_thread`, if any.
+pub fn pinned() -> Option<u32> {
+    PINNED.get().copied()
+}
+
+/// Restricts the calling thread to `node`'s CPUs and makes it allocate
+/// from `node`'s memory first, falling back to other nodes only when it
+/// is full. Children inherit both.
+#[cfg(target_os = "linux")]
+pub fn bind_thread(node: &Node) -> Result<(), String> {
+    const MPOL_PREFERRED: libc::c_int = 1;
+    const MASK_BITS: usize = 1024;
+
+    // SAFETY: cpu_set_t is plain data, so all-zero is an empty set.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in &node.cpus {
+        // SAFETY: CPU_SET ignores CPUs beyond the set's capacity.
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    // SAFETY: `set` is a valid cpu_set_t of the size passed.
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) } != 0 {
+        return Err(format!(
+            "pin to NUMA node {}: {}",
+            node.id,
+            std::io::Error::last_os_error()
+        ));
+    }
+    let id = node.id as usize;
+    if id >= MASK_BITS {
+        return Err(format!("NUMA node {} is out of range", node.id));
+    }
+    let mut mask = [0 as libc::c_ulong; MASK_BITS / libc::c_ulong::BITS as usize];
+    mask[id / libc::c_ulong::BITS as usize] |= 1 << (id % libc::c_ulong::BITS as usize);
+    // SAFETY: `mask` holds MASK_BITS bits; the kernel reads maxnode - 1 of
+    // them.
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_set_mempolicy,
+            MPOL_PREFERRED,
+            mask.as_ptr(),
+            MASK_BITS + 1,
+        )
+    };
+    if rc != 0 {
+        return Err(format!(
+            "prefer NUMA node {} memory: {}",
+            node.id,
+            std::io::Error::last_os_error()
+        ));
+    }
+    let _ = PINNED.set(node.id);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_thread(_node: &Node) -> Result<(), String> {
+    Err("NUMA binding requires Linux".to_string())
+}
+
+/// The node of the CPU the calling thread is running on.
+#[cfg(target_os = "linux")]
+pub fn current_node() -> Option<u32> {
+    let (mut cpu, mut node): (libc::c_uint, libc::c_uint) = (0, 0);
+    // SAFETY: getcpu writes one c_uint through each non-null pointer.
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_getcpu,
+            &mut cpu,
+            &mut node,
+            std::ptr::null_mut::<libc::c_void>(),
+        )
+    };
+    (rc == 0).then_some(node)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn current_node() -> Option<u32> {
+    None
+}
+
+/// The node holding the start of `buf`; `None` if it's empty.
+#[cfg(target_os = "linux")]
+pub fn node_of(buf: &[u8]) -> Option<u32> {
+    const MPOL_F_NODE: libc::c_ulong = 1;
+    const MPOL_F_ADDR: libc::c_ulong = 2;
+
+    if buf.is_empty() {
+        return None;
+    }
+    let mut node: libc::c_int = -1;
+    // SAFETY: with MPOL_F_NODE | MPOL_F_ADDR, get_mempolicy only writes
+    // the node to `node`; it reads no nodemask and looks the address up
+    // without dereferencing it.
+    let rc = unsafe {
+        libc::syscall(
+            libc::SYS_get_mempolicy,
+            &mut node,
+            std::ptr::null_mut::<libc::c_ulong>(),
+            0 as libc::c_ulong,
+            buf.as_ptr(),
+            MPOL_F_NODE | MPOL_F_ADDR,
+        )
+    };
+    (rc == 0).then(|| u32::try_from(node).ok()).flatten()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn node_of(_buf: &[u8]) -> Option<u32> {
+    None
+}
+
+/// The kernel's cpulist format, e.g. "0-3,8-11".
+fn parse_cpulist(s: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in s.trim().split(',').filter(|r| !r.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let (first, last): (usize, usize) = (first.parse().ok()?, last.parse().ok()?);
+        cpus.extend(first..=last);
+    }
+    Some(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cpulists() {
+        assert_eq!(parse_cpulist("0-3,8-9\n"), Some(vec![0, 1, 2, 3, 8, 9]));
+        assert_eq!(parse_cpulist("5"), Some(vec![5]));
+        assert_eq!(parse_cpulist("\n"), Some(vec![]));
+        assert_eq!(parse_cpulist("0-x"), None);
+    }
+}
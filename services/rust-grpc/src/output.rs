@@ -0,0 +1,532 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use serde_json::{Map, Value};
+use std::fmt::Write;
+use std::io::IsTerminal;
+
+/// `--output` for client commands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Aligned columns for people, colored on a terminal.
+    #[default]
+    Table,
+    Json,
+    Yaml,
+}
+
+/// Minimum width of a streamed column, since later rows can't widen it.
+const STREAM_WIDTH: usize = 12;
+
+/// Buckets in a distribution's sparkline.
+const SPARK_BUCKETS: usize = 16;
+
+/// Sparkline levels, lowest first.
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// `ns` in the largest of ns, µs, ms and s that keeps it at least 1, to
+/// three significant figures: "850ns", "12.3µs", "1.50ms".
+pub fn duration(ns: i64) -> String {
+    let abs = ns.unsigned_abs() as f64;
+    let (value, unit) = match abs {
+        a if a < 1e3 => return format!("{}ns", ns),
+        a if a < 1e6 => (ns as f64 / 1e3, "µs"),
+        a if a < 1e9 => (ns as f64 / 1e6, "ms"),
+        _ => (ns as f64 / 1e9, "s"),
+    };
+    significant(value, unit)
+}
+
+/// Bytes in binary units: "512B", "64.0KiB", "1.50GiB".
+pub fn bytes(n: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if n < 1024 {
+        return format!("{}B", n);
+    }
+    let mut value = n as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    significant(value, UNITS[unit])
+}
+
+fn significant(value: f64, unit: &str) -> String {
+    let decimals = match value.abs() {
+        v if v < 10.0 => 2,
+        v if v < 100.0 => 1,
+        _ => 0,
+    };
+    format!("{:.*}{}", decimals, value, unit)
+}
+
+/// Samples counted into equal-width buckets between their min and max.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Distribution {
+    pub min_ns: i64,
+    pub max_ns: i64,
+    pub counts: Vec<u64>,
+}
+
+impl Distribution {
+    pub fn from_samples(samples: &[i64], buckets: usize) -> Option<Distribution> {
+        let min = *samples.iter().min()?;
+        let max = *samples.iter().max()?;
+        let buckets = buckets.max(1);
+        let span = (max - min) as f64;
+        let mut counts = vec![0u64; buckets];
+        for &s in samples {
+            let i = if span == 0.0 {
+                0
+            } else {
+                ((s - min) as f64 / span * buckets as f64) as usize
+            };
+            counts[i.min(buckets - 1)] += 1;
+        }
+        Some(Distribution {
+            min_ns: min,
+            max_ns: max,
+            counts,
+        })
+    }
+
+    /// One block per bucket, scaled to the fullest.
+    pub fn sparkline(&self) -> String {
+        let peak = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        self.counts
+            .iter()
+            .map(|&c| {
+                if c == 0 {
+                    ' '
+                } else {
+                    SPARKS[((c * SPARKS.len() as u64 - 1) / peak) as usize]
+                }
+            })
+            .collect()
+    }
+}
+
+/// One value of a result: what scripts get, and how people see it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cell {
+    pub value: Value,
+    pub human: String,
+}
+
+impl Cell {
+    /// A duration in nanoseconds, shown in a readable unit.
+    pub fn nanos(ns: i64) -> Cell {
+        Cell {
+            value: ns.into(),
+            human: duration(ns),
+        }
+    }
+
+    /// A size in bytes, shown in binary units.
+    pub fn bytes(n: u64) -> Cell {
+        Cell {
+            value: n.into(),
+            human: bytes(n),
+        }
+    }
+
+    /// The shape of `samples` (nanoseconds): for scripts the bucket
+    /// counts and range, for people a sparkline between min and max.
+    pub fn distribution(samples: &[i64]) -> Cell {
+        match Distribution::from_samples(samples, SPARK_BUCKETS) {
+            Some(d) => Cell {
+                human: format!(
+                    "{} ▕{}▏ {}",
+                    duration(d.min_ns),
+                    d.sparkline(),
+                    duration(d.max_ns)
+                ),
+                value: serde_json::json!({
+                    "min_ns": d.min_ns,
+                    "max_ns": d.max_ns,
+                    "counts": d.counts,
+                }),
+            },
+            None => Value::Null.into(),
+        }
+    }
+
+    /// `value` for scripts, `human` for people.
+    pub fn with_human(value: impl Into<Value>, human: impl Into<String>) -> Cell {
+        Cell {
+            value: value.into(),
+            human: human.into(),
+        }
+    }
+}
+
+impl<T: Into<Value>> From<T> for Cell {
+    fn from(value: T) -> Cell {
+        let value = value.into();
+        let human = match &value {
+            Value::String(s) => s.clone(),
+            Value::Null => "-".to_string(),
+            other => other.to_string(),
+        };
+        Cell { value, human }
+    }
+}
+
+/// A command's result: named fields and optionally one list of rows,
+/// rendered in the chosen format. The JSON and YAML forms are a stable
+/// schema for scripts: an object with `schema` ("hermit.<kind>.v1"),
+/// each field under its name, and the rows as an array of objects keyed
+/// by column. Fields that are durations are in nanoseconds and named
+/// `*_ns`; the table picks a unit for each. `partial` is always present,
+/// true (with `partial_reason`) when the command stopped early and the
+/// report covers only what it measured until then.
+#[derive(Clone, Debug)]
+pub struct Report {
+    schema: String,
+    fields: Vec<(String, Cell)>,
+    rows: Option<Rows>,
+    partial: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct Rows {
+    name: String,
+    columns: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+    /// Already printed by `stream_row`.
+    streamed: bool,
+}
+
+impl Report {
+    pub fn new(kind: &str) -> Report {
+        Report {
+            schema: format!("hermit.{}.v1", kind),
+            fields: Vec::new(),
+            rows: None,
+            partial: None,
+        }
+    }
+
+    /// Marks the results incomplete, e.g. "interrupted after 3 of 10
+    /// pings".
+    pub fn partial(&mut self, reason: impl Into<String>) -> &mut Report {
+        self.partial = Some(reason.into());
+        self
+    }
+
+    pub fn field(&mut self, key: &str, cell: impl Into<Cell>) -> &mut Report {
+        self.fields.push((key.to_string(), cell.into()));
+        self
+    }
+
+    /// Declares the report's rows, stored under `name`.
+    pub fn rows(&mut self, name: &str, columns: &[&str]) -> &mut Report {
+        self.rows = Some(Rows {
+            name: name.to_string(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows: Vec::new(),
+            streamed: false,
+        });
+        self
+    }
+
+    /// Adds a row, one cell per column.
+    pub fn row(&mut self, cells: Vec<Cell>) -> &mut Report {
+        if let Some(rows) = &mut self.rows {
+            rows.rows.push(cells);
+        }
+        self
+    }
+
+    /// Adds a row and, for a table, prints it at once (after the header,
+    /// before the first), for results that arrive slowly.
+    pub fn stream_row(&mut self, out: &Output, cells: Vec<Cell>) {
+        if let (Format::Table, Some(rows)) = (out.format, &mut self.rows) {
+            let widths: Vec<usize> = rows
+                .columns
+                .iter()
+                .map(|c| c.len().max(STREAM_WIDTH))
+                .collect();
+            if rows.rows.is_empty() {
+                let labels: Vec<&str> = rows.columns.iter().map(|c| label(c)).collect();
+                println!("{}", out.header(&line(&labels, &widths)));
+            }
+            let human: Vec<&str> = cells.iter().map(|c| c.human.as_str()).collect();
+            println!("{}", line(&human, &widths));
+            rows.streamed = true;
+        }
+        self.row(cells);
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut map = Map::new();
+        map.insert("schema".to_string(), self.schema.clone().into());
+        map.insert("partial".to_string(), self.partial.is_some().into());
+        if let Some(reason) = &self.partial {
+            map.insert("partial_reason".to_string(), reason.clone().into());
+        }
+        for (key, cell) in &self.fields {
+            map.insert(key.clone(), cell.value.clone());
+        }
+        if let Some(rows) = &self.rows {
+            let list = rows
+                .rows
+                .iter()
+                .map(|row| {
+                    let obj = rows
+                        .columns
+                        .iter()
+                        .zip(row)
+                        .map(|(c, cell)| (c.clone(), cell.value.clone()));
+                    Value::Object(obj.collect())
+                })
+                .collect();
+            map.insert(rows.name.clone(), Value::Array(list));
+        }
+        Value::Object(map)
+    }
+
+    fn table(&self, out: &Output) -> String {
+        let mut text = String::new();
+        if let Some(rows) = self
+            .rows
+            .as_ref()
+            .filter(|r| !r.streamed && !r.rows.is_empty())
+        {
+            let human: Vec<Vec<&str>> = rows
+                .rows
+                .iter()
+                .map(|r| r.iter().map(|c| c.human.as_str()).collect())
+                .collect();
+            let widths: Vec<usize> = (0..rows.columns.len())
+                .map(|i| {
+                    human
+                        .iter()
+                        .filter_map(|r| r.get(i).map(|s| s.chars().count()))
+                        .chain([rows.columns[i].len()])
+                        .max()
+                        .unwrap_or(0)
+                })
+                .collect();
+            let labels: Vec<&str> = rows.columns.iter().map(|c| label(c)).collect();
+            let _ = writeln!(text, "{}", out.header(&line(&labels, &widths)));
+            for row in &human {
+                let _ = writeln!(text, "{}", line(row, &widths));
+            }
+        }
+        let width = self
+            .fields
+            .iter()
+            .map(|(k, _)| label(k).len())
+            .max()
+            .unwrap_or(0);
+        for (key, cell) in &self.fields {
+            let key = format!("{:width$}", label(key), width = width);
+            let _ = writeln!(text, "{}  {}", out.key(&key), cell.human);
+        }
+        if let Some(reason) = &self.partial {
+            let _ = writeln!(text, "{}", out.warning(&format!("partial: {}", reason)));
+        }
+        text
+    }
+}
+
+/// A column or field name as a table shows it: without the `_ns` unit,
+/// since the table shows durations in friendlier units.
+fn label(key: &str) -> &str {
+    key.strip_suffix("_ns").unwrap_or(key)
+}
+
+fn line<S: AsRef<str>>(cells: &[S], widths: &[usize]) -> String {
+    let padded: Vec<String> = cells
+        .iter()
+        .zip(widths)
+        .map(|(c, w)| format!("{:w$}", c.as_ref(), w = w))
+        .collect();
+    padded.join("  ").trim_end().to_string()
+}
+
+/// Where results go and how they look.
+#[derive(Clone, Copy, Debug)]
+pub struct Output {
+    pub format: Format,
+    /// ANSI colors in tables: on for a terminal unless NO_COLOR is set.
+    pub color: bool,
+}
+
+impl Output {
+    pub fn new(format: Format) -> Output {
+        Output {
+            format,
+            color: format == Format::Table
+                && std::io::stdout().is_terminal()
+                && std::env::var_os("NO_COLOR").is_none(),
+        }
+    }
+
+    /// True for tables, where progress and free-form detail belong.
+    pub fn human(&self) -> bool {
+        self.format == Format::Table
+    }
+
+    pub fn render(&self, report: &Report) -> String {
+        match self.format {
+            Format::Table => report.table(self),
+            Format::Json => format!("{:#}\n", report.to_json()),
+            Format::Yaml => {
+                let mut text = String::new();
+                yaml(&mut text, &report.to_json(), 0);
+                text
+            }
+        }
+    }
+
+    pub fn print(&self, report: &Report) {
+        print!("{}", self.render(report));
+    }
+
+    fn header(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+
+    fn key(&self, text: &str) -> String {
+        self.paint("36", text)
+    }
+
+    fn warning(&self, text: &str) -> String {
+        self.paint("33", text)
+    }
+
+    fn paint(&self, sgr: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", sgr, text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Block-style YAML. Strings are always double-quoted (JSON escapes are
+/// valid YAML), so no value can be misread as another type.
+fn yaml(out: &mut String, value: &Value, indent: usize) {
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, v) in map {
+                match v {
+                    Value::Object(m) if !m.is_empty() => {
+                        let _ = writeln!(out, "{}{}:", pad, key);
+                        yaml(out, v, indent + 2);
+                    }
+                    Value::Array(a) if !a.is_empty() => {
+                        let _ = writeln!(out, "{}{}:", pad, key);
+                        yaml(out, v, indent);
+                    }
+                    _ => {
+                        let _ = writeln!(out, "{}{}: {}", pad, key, scalar(v));
+                    }
+                }
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for item in items {
+                match item {
+                    Value::Object(m) if !m.is_empty() => {
+                        let mut nested = String::new();
+                        yaml(&mut nested, item, indent + 2);
+                        // The first key goes on the dash's line.
+                        let _ = write!(out, "{}- {}", pad, &nested[indent + 2..]);
+                    }
+                    Value::Array(a) if !a.is_empty() => {
+                        let _ = writeln!(out, "{}-", pad);
+                        yaml(out, item, indent + 2);
+                    }
+                    _ => {
+                        let _ = writeln!(out, "{}- {}", pad, scalar(item));
+                    }
+                }
+            }
+        }
+        _ => {
+            let _ = writeln!(out, "{}{}", pad, scalar(value));
+        }
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Object(_) => "{}".to_string(),
+        Value::Array(_) => "[]".to_string(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn humanizes_units_and_distributions() {
+        assert_eq!(duration(850), "850ns");
+        assert_eq!(duration(-1_500), "-1.50µs");
+        assert_eq!(duration(12_345_678), "12.3ms");
+        assert_eq!(duration(250_000_000_000), "250s");
+        assert_eq!(bytes(512), "512B");
+        assert_eq!(bytes(64 << 20), "64.0MiB");
+
+        let d = Distribution::from_samples(&[10, 10, 10, 10, 20, 40], 4).unwrap();
+        assert_eq!(d.counts, [4, 1, 0, 1]);
+        assert_eq!(d.sparkline(), "█▂ ▂");
+        assert_eq!(
+            Cell::distribution(&[1_000, 3_000]).human,
+            format!("1.00µs ▕█{}█▏ 3.00µs", " ".repeat(SPARK_BUCKETS - 2))
+        );
+        assert_eq!(Cell::distribution(&[]).value, Value::Null);
+    }
+
+    #[test]
+    fn renders_tables_json_and_yaml() {
+        let mut report = Report::new("ping");
+        report.rows("pings", &["seq", "rtt_ns"]);
+        report.row(vec![1.into(), Cell::nanos(1500)]);
+        report.row(vec![2.into(), Cell::nanos(12_250)]);
+        report
+            .field("target", "http://localhost:9090")
+            .field("p99_ns", Cell::nanos(12_250))
+            .field(
+                "hops",
+                Cell::with_human(vec!["client", "server"], "client > server"),
+            )
+            .field("empty", Value::Array(vec![]));
+        let plain = |format| Output {
+            format,
+            color: false,
+        };
+
+        assert_eq!(
+            plain(Format::Table).render(&report),
+            "seq  rtt\n1    1.50µs\n2    12.2µs\ntarget  http://localhost:9090\n\
+             p99     12.2µs\nhops    client > server\nempty   []\n"
+        );
+        let json: Value = serde_json::from_str(&plain(Format::Json).render(&report)).unwrap();
+        assert_eq!(json["schema"], "hermit.ping.v1");
+        assert_eq!(json["partial"], false);
+        assert_eq!(json["pings"][1]["rtt_ns"], 12_250);
+        assert_eq!(json["p99_ns"], 12_250);
+        assert_eq!(
+            plain(Format::Yaml).render(&report),
+            "empty: []\nhops:\n- \"client\"\n- \"server\"\np99_ns: 12250\npartial: false\n\
+             pings:\n- rtt_ns: 1500\n  seq: 1\n- rtt_ns: 12250\n  seq: 2\nschema: \"hermit.ping.v1\"\n\
+             target: \"http://localhost:9090\"\n"
+        );
+
+        report.partial("interrupted after 2 of 5 pings");
+        assert!(plain(Format::Table)
+            .render(&report)
+            .ends_with("empty   []\npartial: interrupted after 2 of 5 pings\n"));
+        assert_eq!(
+            report.to_json()["partial_reason"],
+            "interrupted after 2 of 5 pings"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::hops::{self, Hop};
+use crate::metrics::{ProxyDirection, METRICS};
+use crate::throughput::{self, Header};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+/// Largest chunk relayed in one read/write.
+const CHUNK: usize = 64 * 1024;
+
+/// Longest we wait to connect upstream.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest we wait for a throughput test's hop list.
+const HOPS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pause before retrying a failed accept.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Where and how to forward.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// HOST:PORT of the hermit behind the proxy.
+    pub upstream: String,
+    /// This proxy's entry in throughput tests' hop lists.
+    pub name: String,
+}
+
+/// One direction of a proxied connection.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Relayed {
+    pub bytes: u64,
+    pub chunks: u64,
+    /// Total and worst time chunks spent in the proxy.
+    pub delay: Duration,
+    pub max_delay: Duration,
+}
+
+/// Forwards every connection accepted on `listener` to the upstream,
+/// byte for byte, so TLS and gRPC pass through untouched and the client
+/// still talks to the upstream end to end. The proxy's own cost is what
+/// it measures: the time from each read returning to its write being
+/// accepted by the kernel, exported as hermit_proxy_forward_seconds and
+/// logged per connection, alongside the upstream connect time.
+///
+/// The one exception is a throughput test carrying a hop list, to which
+/// the proxy adds its own entry on the way through.
+pub async fn serve(listener: TcpListener, config: Arc<Config>) {
+    if let Ok(addr) = listener.local_addr() {
+        info!(%addr, upstream = %config.upstream, "proxying");
+    }
+    loop {
+        let (client, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("proxy accept failed: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let config = config.clone();
+        tokio::spawn(async move {
+            if let Err(e) = proxy(client, peer, &config).await {
+                debug!(%peer, "proxied connection failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn proxy(mut client: TcpStream, peer: SocketAddr, config: &Config) -> io::Result<()> {
+    let _ = client.set_nodelay(true);
+    let started = Instant::now();
+    let mut upstream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&config.upstream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "upstream connect timed out"))??;
+    let connect = started.elapsed();
+    METRICS.proxy_connected(connect);
+    let _ = upstream.set_nodelay(true);
+    let first = add_hop(&mut client, &config.name).await?;
+    upstream.write_all(&first).await?;
+
+    let (client_rx, client_tx) = client.into_split();
+    let (upstream_rx, upstream_tx) = upstream.into_split();
+    let (up, down) = tokio::try_join!(
+        relay(client_rx, upstream_tx, ProxyDirection::Upstream),
+        relay(upstream_rx, client_tx, ProxyDirection::Downstream),
+    )?;
+    let mean_us = |r: &Relayed| r.delay.as_secs_f64() * 1e6 / r.chunks.max(1) as f64;
+    info!(
+        %peer,
+        connect_us = connect.as_micros() as u64,
+        up_bytes = up.bytes,
+        up_mean_us = mean_us(&up),
+        up_max_us = up.max_delay.as_micros() as u64,
+        down_bytes = down.bytes,
+        down_mean_us = mean_us(&down),
+        down_max_us = down.max_delay.as_micros() as u64,
+        "proxied connection closed"
+    );
+    Ok(())
+}
+
+/// Reads the start of the client's stream, as far as a throughput header
+/// if it begins with one, and returns what to send upstream in its place:
+/// the same bytes, unless the header asks for hops, in which case the
+/// hop list that follows gets this proxy appended.
+async fn add_hop<R: AsyncRead + Unpin>(client: &mut R, name: &str) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; throughput::HEADER_LEN];
+    let mut len = 0;
+    while len < buf.len() {
+        let n = client.read(&mut buf[len..]).await?;
+        len += n;
+        let magic = len.min(throughput::MAGIC.len());
+        if n == 0 || buf[..magic] != throughput::MAGIC[..magic] {
+            break;
+        }
+    }
+    let received = hops::now_ns();
+    buf.truncate(len);
+    let header = buf.as_slice().try_into().ok().map(Header::parse);
+    if !matches!(header, Some(Ok(Header { hops: true, .. }))) {
+        return Ok(buf);
+    }
+    let mut list = tokio::time::timeout(HOPS_TIMEOUT, hops::read(client))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "hop list timed out"))??;
+    // The server needs a slot for itself.
+    if list.len() < hops::MAX_HOPS - 1 {
+        list.push(Hop::departing(name, received));
+    }
+    buf.extend_from_slice(&hops::encode(&list));
+    Ok(buf)
+}
+
+/// Copies `from` to `to` until EOF, then shuts down `to`'s write side so
+/// half-closes propagate.
+async fn relay<R, W>(mut from: R, mut to: W, direction: ProxyDirection) -> io::Result<Relayed>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; CHUNK];
+    let mut relayed = Relayed::default();
+    loop {
+        let n = from.read(&mut buf).await?;
+        if n == 0 {
+            to.shutdown().await?;
+            return Ok(relayed);
+        }
+        let received = Instant::now();
+        to.write_all(&buf[..n]).await?;
+        let delay = received.elapsed();
+        METRICS.proxy_forwarded(direction, delay);
+        relayed.bytes += n as u64;
+        relayed.chunks += 1;
+        relayed.delay += delay;
+        relayed.max_delay = relayed.max_delay.max(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn relays_both_ways_and_half_closes() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Arc::new(Config {
+            upstream: upstream.local_addr().unwrap().to_string(),
+            name: "proxy".to_string(),
+        });
+        tokio::spawn(async move {
+            let (mut conn, _) = upstream.accept().await.unwrap();
+            let mut got = Vec::new();
+            conn.read_to_end(&mut got).await.unwrap();
+            got.reverse();
+            conn.write_all(&got).await.unwrap();
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, config));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"hermit").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, b"timreh");
+    }
+
+    #[tokio::test]
+    async fn joins_the_hop_list() {
+        let header = Header {
+            mode: throughput::Mode::Echo,
+            congestion: None,
+            ack: false,
+            verify: true,
+            hops: true,
+            duration: Duration::ZERO,
+            block: 0,
+        };
+        let mut sent = header.encode().to_vec();
+        sent.extend(hops::encode(&[Hop::departing("client", 0)]));
+        sent.extend_from_slice(b"payload");
+        let mut client = &sent[..];
+        let mut forwarded = add_hop(&mut client, "edge").await.unwrap();
+        forwarded.extend_from_slice(client);
+
+        let mut rest = &forwarded[throughput::HEADER_LEN..];
+        let list = hops::read(&mut rest).await.unwrap();
+        let names: Vec<_> = list.iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(names, ["client", "edge"]);
+        assert_eq!(rest, b"payload");
+
+        let mut other = &b"PRI * HTTP/2.0"[..];
+        assert_eq!(
+            add_hop(&mut other, "edge").await.unwrap(),
+            b"PRI * HTTP/2.0"
+        );
+    }
+}
@@ -0,0 +1,267 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tonic::{Code, Status};
+
+/// How a client call is retried: up to `max_attempts` tries in all,
+/// waiting an exponentially growing, jittered backoff between them, and
+/// only for the status codes in `retry_on`. With `hedge_after` set the
+/// call is hedged instead: another copy starts whenever the ones in
+/// flight have gone that long without an answer (or one fails
+/// retryably), and the first success wins.
+#[derive(Clone, Debug)]
+pub struct Policy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Fraction (0..=1) of each backoff that is randomized away, so
+    /// clients failing together don't retry together.
+    pub jitter: f64,
+    pub retry_on: Vec<Code>,
+    pub hedge_after: Option<Duration>,
+}
+
+impl Default for Policy {
+    /// A single attempt; the other fields are what retries use once
+    /// `max_attempts` is raised.
+    fn default() -> Self {
+        Policy {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.2,
+            retry_on: vec![Code::Unavailable],
+            hedge_after: None,
+        }
+    }
+}
+
+/// What a call under a `Policy` came to.
+#[derive(Debug)]
+pub struct Attempts<T> {
+    pub result: Result<T, Status>,
+    /// Tries started, including any still in flight when another won.
+    pub attempts: u32,
+    /// Which try produced `result`, counting from 1; with hedging, not
+    /// necessarily the last one started.
+    pub answered_by: u32,
+}
+
+impl Policy {
+    /// Whether this policy can make more than one attempt.
+    pub fn retries(&self) -> bool {
+        self.max_attempts > 1
+    }
+
+    pub fn retryable(&self, status: &Status) -> bool {
+        self.retry_on.contains(&status.code())
+    }
+
+    /// The wait before retry `n` (1 for the first retry), with `unit` in
+    /// [0, 1) picking how much of the jitter applies.
+    pub fn backoff_with(&self, n: u32, unit: f64) -> Duration {
+        let exp = self.multiplier.powi(n.saturating_sub(1).min(64) as i32);
+        // Clamped before converting back: late retries would overflow a
+        // Duration.
+        let full = Duration::try_from_secs_f64(self.initial_backoff.as_secs_f64() * exp)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        full.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * unit)
+    }
+
+    pub fn backoff(&self, n: u32) -> Duration {
+        self.backoff_with(n, unit_random())
+    }
+
+    /// Runs `attempt(n)` (n counting from 1) until one succeeds, one fails
+    /// with a status not worth retrying, or the attempts run out, in which
+    /// case the last failure is returned. Attempts run as tasks, so when
+    /// hedging the losers are cancelled once a winner answers.
+    pub async fn call<T, F, Fut>(&self, mut attempt: F) -> Attempts<T>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T, Status>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut running = JoinSet::new();
+        let mut started = 0;
+        let mut next = Some(Instant::now());
+        let mut last = None;
+        loop {
+            let due = next.filter(|_| started < self.max_attempts.max(1));
+            if running.is_empty() && due.is_none() {
+                let status = last.unwrap_or_else(|| Status::unknown("no attempt was made"));
+                return Attempts {
+                    result: Err(status),
+                    attempts: started,
+                    answered_by: started,
+                };
+            }
+            tokio::select! {
+                _ = tokio::time::sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => {
+                    started += 1;
+                    let n = started;
+                    let call = attempt(n);
+                    running.spawn(async move { (n, call.await) });
+                    next = self.hedge_after.map(|d| Instant::now() + d);
+                }
+                Some(joined) = running.join_next() => {
+                    let (n, result) = match joined {
+                        Ok(joined) => joined,
+                        Err(e) => (started, Err(Status::internal(e.to_string()))),
+                    };
+                    match result {
+                        Ok(value) => {
+                            return Attempts {
+                                result: Ok(value),
+                                attempts: started,
+                                answered_by: n,
+                            }
+                        }
+                        Err(status) if self.retryable(&status) => {
+                            next = Some(match self.hedge_after {
+                                Some(_) => Instant::now(),
+                                None => Instant::now() + self.backoff(started),
+                            });
+                            last = Some(status);
+                        }
+                        Err(status) => {
+                            return Attempts {
+                                result: Err(status),
+                                attempts: started,
+                                answered_by: n,
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parse a status code given by name (`unavailable`,
+/// `resource-exhausted`) or number.
+pub fn parse_code(s: &str) -> Result<Code, String> {
+    if let Ok(n) = s.parse::<i32>() {
+        return match Code::from(n) {
+            Code::Unknown if n != 2 => Err(format!("no status code {}", n)),
+            code => Ok(code),
+        };
+    }
+    let want: String = s
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    (0..=16)
+        .map(Code::from)
+        .find(|code| format!("{:?}", code).to_ascii_lowercase() == want)
+        .ok_or_else(|| format!("unknown status code {:?}", s))
+}
+
+/// Uniform in [0, 1). Jitter only needs to differ between clients, so
+/// the standard library's randomly keyed hasher will do.
+fn unit_random() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(crate::hops::now_ns());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn backs_off_exponentially_within_bounds() {
+        let policy = Policy {
+            max_attempts: 5,
+            ..Policy::default()
+        };
+        assert_eq!(policy.backoff_with(1, 0.0), Duration::from_millis(50));
+        assert_eq!(policy.backoff_with(3, 0.0), Duration::from_millis(200));
+        assert_eq!(policy.backoff_with(30, 0.0), Duration::from_secs(1));
+        assert_eq!(policy.backoff_with(2, 0.5), Duration::from_millis(90));
+        let slow = Policy {
+            initial_backoff: Duration::from_secs(3600),
+            max_backoff: Duration::from_secs(86_400),
+            multiplier: 10.0,
+            ..Policy::default()
+        };
+        assert_eq!(slow.backoff_with(64, 0.0), Duration::from_secs(86_400));
+        for n in 1..10 {
+            let d = policy.backoff(n);
+            assert!(d <= policy.backoff_with(n, 0.0) && d >= policy.backoff_with(n, 1.0));
+        }
+        assert_eq!(
+            parse_code("resource-exhausted"),
+            Ok(Code::ResourceExhausted)
+        );
+        assert_eq!(parse_code("14"), Ok(Code::Unavailable));
+        assert!(parse_code("flaky").is_err());
+        assert!(parse_code("99").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_only_retryable_failures() {
+        let policy = Policy {
+            max_attempts: 4,
+            ..Policy::default()
+        };
+        let calls = Arc::new(AtomicU32::new(0));
+        let flaky = |fail_with: Code, failures: u32| {
+            let calls = calls.clone();
+            calls.store(0, Ordering::SeqCst);
+            move |_| {
+                let calls = calls.clone();
+                async move {
+                    if calls.fetch_add(1, Ordering::SeqCst) < failures {
+                        Err(Status::new(fail_with, "nope"))
+                    } else {
+                        Ok("pong")
+                    }
+                }
+            }
+        };
+
+        let done = policy.call(flaky(Code::Unavailable, 2)).await;
+        assert_eq!((done.result.unwrap(), done.attempts), ("pong", 3));
+        let done = policy.call(flaky(Code::Unavailable, 10)).await;
+        assert_eq!(done.result.unwrap_err().code(), Code::Unavailable);
+        assert_eq!(done.attempts, 4);
+        let done = policy.call(flaky(Code::InvalidArgument, 1)).await;
+        assert_eq!(done.result.unwrap_err().code(), Code::InvalidArgument);
+        assert_eq!(done.attempts, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hedges_slow_attempts() {
+        let policy = Policy {
+            max_attempts: 2,
+            hedge_after: Some(Duration::from_millis(10)),
+            ..Policy::default()
+        };
+        let start = Instant::now();
+        let done = policy
+            .call(|n| async move {
+                // The first copy stalls; the hedge answers promptly.
+                let delay = if n == 1 { 1000 } else { 5 };
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                Ok(n)
+            })
+            .await;
+        assert_eq!(
+            (done.result.unwrap(), done.attempts, done.answered_by),
+            (2, 2, 2)
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(15));
+    }
+}
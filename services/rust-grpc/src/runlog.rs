@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::hermit::{BenchmarkRequest, BenchmarkRun};
+use prost::Message;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// Runs kept for replay; the oldest are forgotten first. The file keeps
+/// everything.
+const MAX_RUNS: usize = 10_000;
+
+/// Every benchmark's parameters under a run id, so ReplayBenchmark can
+/// repeat it exactly. With a file, runs are also appended to it as
+/// length-delimited `BenchmarkRun` messages, which survive restarts and
+/// can be read back with any protobuf library.
+pub struct RunLog {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    runs: HashMap<String, BenchmarkRun>,
+    order: VecDeque<String>,
+    file: Option<File>,
+}
+
+impl Default for RunLog {
+    fn default() -> Self {
+        RunLog::new()
+    }
+}
+
+impl RunLog {
+    /// Keeps runs in memory only.
+    pub fn new() -> RunLog {
+        RunLog {
+            inner: Mutex::new(Inner {
+                runs: HashMap::new(),
+                order: VecDeque::new(),
+                file: None,
+            }),
+        }
+    }
+
+    /// Loads the runs already in `path`, creating it if need be, and
+    /// appends new ones to it. A record cut short (the server died while
+    /// writing it) is dropped from the file.
+    pub fn open(path: &Path) -> Result<RunLog, String> {
+        let err = |e: std::io::Error| format!("{}: {}", path.display(), e);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(err)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).map_err(err)?;
+
+        let log = RunLog::new();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let good = data.len() - rest.len();
+            match BenchmarkRun::decode_length_delimited(&mut rest) {
+                Ok(run) => log.inner.lock().unwrap().insert(run),
+                Err(e) => {
+                    warn!(
+                        path = %path.display(),
+                        offset = good,
+                        "dropping unreadable benchmark log tail: {}",
+                        e
+                    );
+                    file.set_len(good as u64).map_err(err)?;
+                    break;
+                }
+            }
+        }
+        let mut inner = log.inner.lock().unwrap();
+        info!(path = %path.display(), runs = inner.runs.len(), "benchmark log opened");
+        inner.file = Some(file);
+        drop(inner);
+        Ok(log)
+    }
+
+    /// Records `request`, which should be exactly what ran, and returns
+    /// the new run's id.
+    pub fn record(&self, request: BenchmarkRequest, replay_of: Option<String>) -> String {
+        let run_id = new_run_id();
+        let run = BenchmarkRun {
+            run_id: run_id.clone(),
+            started_at: Some(SystemTime::now().into()),
+            request: Some(request),
+            replay_of: replay_of.unwrap_or_default(),
+        };
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(file) = &mut inner.file {
+            if let Err(e) = file.write_all(&run.encode_length_delimited_to_vec()) {
+                warn!(run_id, "failed to append to benchmark log: {}", e);
+            }
+        }
+        inner.insert(run);
+        run_id
+    }
+
+    pub fn get(&self, run_id: &str) -> Option<BenchmarkRun> {
+        self.inner.lock().unwrap().runs.get(run_id).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().runs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Inner {
+    fn insert(&mut self, run: BenchmarkRun) {
+        if self.order.len() >= MAX_RUNS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.runs.remove(&oldest);
+            }
+        }
+        self.order.push_back(run.run_id.clone());
+        self.runs.insert(run.run_id.clone(), run);
+    }
+}
+
+/// 16 hex digits. Unique enough for one server's runs; the standard
+/// library's randomly keyed hasher stands in for an RNG.
+fn new_run_id() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(crate::hops::now_ns());
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloads_runs_and_drops_a_torn_record() {
+        let path = std::env::temp_dir().join(format!("hermit-runlog-{}.bin", new_run_id()));
+        let request = |iterations| BenchmarkRequest {
+            iterations,
+            seed: 7,
+            payload_content: "random".to_string(),
+            ..Default::default()
+        };
+
+        let log = RunLog::open(&path).unwrap();
+        let first = log.record(request(10), None);
+        let second = log.record(request(20), Some(first.clone()));
+        assert_ne!(first, second);
+        assert_eq!(log.get(&second).unwrap().replay_of, first);
+        drop(log);
+
+        // A write cut short by a crash.
+        let len = std::fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[40, 10, 3]).unwrap();
+        drop(file);
+
+        let log = RunLog::open(&path).unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        assert_eq!(log.get(&first).unwrap().request, Some(request(10)));
+        let third = log.record(request(30), None);
+        drop(log);
+        let log = RunLog::open(&path).unwrap();
+        assert_eq!(log.get(&third).unwrap().request, Some(request(30)));
+        assert!(log.get("0123456789abcdef").is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use tracing::info;
+
+/// Switch to an unprivileged user and/or group once the listener is bound
+/// and key material has been read, so hermit can serve port 443 without
+/// keeping root. With only `user`, its primary group is used. glibc and
+/// musl apply set*id to every thread, so this is safe with the runtime
+/// already running. Numeric ids need no passwd/group entries, for scratch
+/// containers that have neither.
+#[cfg(unix)]
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> Result<(), String> {
+    use nix::unistd::{setgid, setuid, Gid, Group, Uid, User};
+
+    if user.is_none() && group.is_none() {
+        return Ok(());
+    }
+    let user = user
+        .map(|name| match name.parse() {
+            // The primary group comes from passwd when there is an entry.
+            Ok(id) => Ok((
+                name,
+                Uid::from_raw(id),
+                User::from_uid(Uid::from_raw(id))
+                    .ok()
+                    .flatten()
+                    .map(|u| u.gid),
+            )),
+            Err(_) => User::from_name(name)
+                .map_err(|e| format!("look up user {}: {}", name, e))?
+                .map(|u| (name, u.uid, Some(u.gid)))
+                .ok_or_else(|| format!("no such user: {}", name)),
+        })
+        .transpose()?;
+    let gid = match group {
+        Some(name) => Some(match name.parse() {
+            Ok(id) => Gid::from_raw(id),
+            Err(_) => {
+                Group::from_name(name)
+                    .map_err(|e| format!("look up group {}: {}", name, e))?
+                    .ok_or_else(|| format!("no such group: {}", name))?
+                    .gid
+            }
+        }),
+        None => match user {
+            Some((name, _, None)) => {
+                return Err(format!(
+                    "user {} has no passwd entry to take a group from; pass --group",
+                    name
+                ))
+            }
+            Some((_, _, gid)) => gid,
+            None => None,
+        },
+    };
+
+    // Group first: after setuid we no longer have the right to change it.
+    if let Some(gid) = gid {
+        #[cfg(not(target_vendor = "apple"))]
+        nix::unistd::setgroups(&[gid]).map_err(|e| format!("setgroups: {}", e))?;
+        setgid(gid).map_err(|e| format!("setgid: {}", e))?;
+    }
+    if let Some((_, uid, _)) = user {
+        setuid(uid).map_err(|e| format!("setuid: {}", e))?;
+        if !uid.is_root() && setuid(Uid::from_raw(0)).is_ok() {
+            return Err("root privileges could be regained after setuid".to_string());
+        }
+    }
+    info!(
+        uid = %nix::unistd::getuid(),
+        gid = %nix::unistd::getgid(),
+        "dropped privileges"
+    );
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> Result<(), String> {
+    if user.is_some() || group.is_some() {
+        return Err("--user/--group are only supported on Unix".to_string());
+    }
+    Ok(())
+}
+
+/// Deny all filesystem writes (creating, modifying, removing or renaming
+/// files) for this thread and every thread it later spawns. hermit keeps
+/// its state in memory, so nothing legitimate is lost; reads stay allowed
+/// for DNS config and key material. Must run before the runtime starts,
+/// since Landlock cannot be applied to threads that already exist.
+#[cfg(target_os = "linux")]
+pub fn restrict_filesystem() -> Result<(), String> {
+    use landlock::{AccessFs, Ruleset, RulesetAttr, RulesetStatus, ABI};
+
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_write(ABI::V3))
+        .and_then(|r| r.create())
+        .and_then(|r| r.restrict_self())
+        .map_err(|e| format!("landlock: {}", e))?;
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => info!("landlock: filesystem writes denied"),
+        RulesetStatus::PartiallyEnforced => {
+            info!("landlock: filesystem writes partially denied (older kernel ABI)")
+        }
+        RulesetStatus::NotEnforced => {
+            tracing::warn!("landlock: not supported by this kernel, filesystem unrestricted")
+        }
+    }
+    Ok(())
+}
+
+/// Syscalls a network benchmark server never needs. Anything here is a
+/// strong sign of compromise, so it fails with EPERM rather than running.
+#[cfg(target_os = "linux")]
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_setuid,
+    libc::SYS_setgid,
+    libc::SYS_setreuid,
+    libc::SYS_setregid,
+    libc::SYS_setresuid,
+    libc::SYS_setresgid,
+    libc::SYS_setgroups,
+];
+
+/// Install a seccomp filter on every thread of the process denying
+/// `DENIED_SYSCALLS`. Runs after privileges are dropped, since it also
+/// blocks the set*id calls.
+#[cfg(target_os = "linux")]
+pub fn install_seccomp() -> Result<(), String> {
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+    use std::collections::BTreeMap;
+
+    let arch =
+        TargetArch::try_from(std::env::consts::ARCH).map_err(|e| format!("seccomp: {}", e))?;
+    // c_long is only i64 on 64-bit targets.
+    #[allow(clippy::useless_conversion)]
+    let rules: BTreeMap<i64, Vec<_>> = DENIED_SYSCALLS
+        .iter()
+        .map(|&nr| (i64::from(nr), Vec::new()))
+        .collect();
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        arch,
+    )
+    .map_err(|e| format!("seccomp: {}", e))?;
+    let program: BpfProgram = filter.try_into().map_err(|e| format!("seccomp: {}", e))?;
+    seccompiler::apply_filter_all_threads(&program).map_err(|e| format!("seccomp: {}", e))?;
+    info!(denied = DENIED_SYSCALLS.len(), "seccomp filter installed");
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn restrict_filesystem() -> Result<(), String> {
+    Err("--sandbox requires Linux (Landlock and seccomp)".to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn install_seccomp() -> Result<(), String> {
+    Err("--sandbox requires Linux (Landlock and seccomp)".to_string())
+}
@@ -0,0 +1,41 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use serde::Deserialize;
+
+/// Minimal client for the nexus `secrets` service. Secrets are fetched by
+/// ID from `GET <base>/api/secrets/{id}` and the `value` field is returned
+/// verbatim (PEM for certificates and keys).
+#[derive(Clone)]
+pub struct SecretsClient {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+#[derive(Deserialize)]
+struct SecretBody {
+    value: String,
+}
+
+impl SecretsClient {
+    pub fn new(base_url: &str) -> Self {
+        SecretsClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub async fn fetch(&self, id: &str) -> Result<String, String> {
+        let url = format!("{}/api/secrets/{}", self.base_url, id);
+        let body = self
+            .http
+            .get(&url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("fetch secret {}: {}", id, e))?
+            .json::<SecretBody>()
+            .await
+            .map_err(|e| format!("decode secret {}: {}", id, e))?;
+        Ok(body.value)
+    }
+}
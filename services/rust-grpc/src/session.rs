@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A logged-in client, keyed by the opaque session ID returned from Login.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub username: String,
+    pub roles: Vec<String>,
+    pub created_at_unix: i64,
+    pub expires_at_unix: i64,
+}
+
+impl Session {
+    pub fn new(username: String, roles: Vec<String>, ttl_secs: u64) -> Self {
+        let now = unix_now();
+        Session {
+            id: uuid::Uuid::new_v4().to_string(),
+            username,
+            roles,
+            created_at_unix: now,
+            expires_at_unix: now + ttl_secs as i64,
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.roles.iter().any(|r| r == "admin")
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at_unix <= unix_now()
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// Where sessions live. In-memory sessions die with the process; Redis
+/// lets several hermits behind a load balancer share them and survive
+/// restarts.
+#[tonic::async_trait]
+pub trait SessionStore: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn create(&self, session: &Session) -> Result<(), String>;
+
+    /// Returns the session if it exists and has not expired.
+    async fn get(&self, id: &str) -> Result<Option<Session>, String>;
+
+    /// Returns whether a session was actually removed.
+    async fn revoke(&self, id: &str) -> Result<bool, String>;
+
+    /// Live sessions, optionally restricted to one user.
+    async fn list(&self, username: Option<&str>) -> Result<Vec<Session>, String>;
+}
+
+pub struct MemorySessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+impl Default for MemorySessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        MemorySessionStore {
+            sessions: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl SessionStore for MemorySessionStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn create(&self, session: &Session) -> Result<(), String> {
+        let mut sessions = self.sessions.write().map_err(|e| e.to_string())?;
+        // Opportunistic sweep keeps the map bounded without a reaper task.
+        sessions.retain(|_, s| !s.is_expired());
+        sessions.insert(session.id.clone(), session.clone());
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Session>, String> {
+        let sessions = self.sessions.read().map_err(|e| e.to_string())?;
+        Ok(sessions.get(id).filter(|s| !s.is_expired()).cloned())
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, String> {
+        let mut sessions = self.sessions.write().map_err(|e| e.to_string())?;
+        Ok(sessions.remove(id).is_some())
+    }
+
+    async fn list(&self, username: Option<&str>) -> Result<Vec<Session>, String> {
+        let sessions = self.sessions.read().map_err(|e| e.to_string())?;
+        let mut out: Vec<Session> = sessions
+            .values()
+            .filter(|s| !s.is_expired())
+            .filter(|s| username.is_none_or(|u| s.username == u))
+            .cloned()
+            .collect();
+        out.sort_by_key(|s| s.created_at_unix);
+        Ok(out)
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis_store::RedisSessionStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use super::{Session, SessionStore};
+    use redis::aio::ConnectionManager;
+    use redis::AsyncCommands;
+
+    const KEY_PREFIX: &str = "hermit:session:";
+
+    /// Sessions as JSON strings under `hermit:session:<id>`, expired by
+    /// Redis itself via the key TTL.
+    pub struct RedisSessionStore {
+        conn: ConnectionManager,
+    }
+
+    impl RedisSessionStore {
+        pub async fn connect(url: &str) -> Result<Self, String> {
+            let client = redis::Client::open(url).map_err(|e| e.to_string())?;
+            let conn = ConnectionManager::new(client)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(RedisSessionStore { conn })
+        }
+    }
+
+    #[tonic::async_trait]
+    impl SessionStore for RedisSessionStore {
+        fn name(&self) -> &'static str {
+            "redis"
+        }
+
+        async fn create(&self, session: &Session) -> Result<(), String> {
+            let ttl = (session.expires_at_unix - super::unix_now()).max(1) as u64;
+            let value = serde_json::to_string(session).map_err(|e| e.to_string())?;
+            let mut conn = self.conn.clone();
+            conn.set_ex::<_, _, ()>(format!("{}{}", KEY_PREFIX, session.id), value, ttl)
+                .await
+                .map_err(|e| e.to_string())
+        }
+
+        async fn get(&self, id: &str) -> Result<Option<Session>, String> {
+            let mut conn = self.conn.clone();
+            let value: Option<String> = conn
+                .get(format!("{}{}", KEY_PREFIX, id))
+                .await
+                .map_err(|e| e.to_string())?;
+            value
+                .map(|v| serde_json::from_str(&v).map_err(|e| e.to_string()))
+                .transpose()
+        }
+
+        async fn revoke(&self, id: &str) -> Result<bool, String> {
+            let mut conn = self.conn.clone();
+            let removed: u64 = conn
+                .del(format!("{}{}", KEY_PREFIX, id))
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(removed > 0)
+        }
+
+        async fn list(&self, username: Option<&str>) -> Result<Vec<Session>, String> {
+            let mut conn = self.conn.clone();
+            let keys: Vec<String> = {
+                let mut iter = conn
+                    .scan_match::<_, String>(format!("{}*", KEY_PREFIX))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let mut keys = Vec::new();
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+                keys
+            };
+            if keys.is_empty() {
+                return Ok(Vec::new());
+            }
+            // Keys can expire between SCAN and MGET; those come back as nil.
+            let values: Vec<Option<String>> = conn.mget(&keys).await.map_err(|e| e.to_string())?;
+            let mut out = Vec::new();
+            for value in values.into_iter().flatten() {
+                let session: Session = serde_json::from_str(&value).map_err(|e| e.to_string())?;
+                if username.is_none_or(|u| session.username == u) {
+                    out.push(session);
+                }
+            }
+            out.sort_by_key(|s| s.created_at_unix);
+            Ok(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(username: &str, ttl_secs: u64) -> Session {
+        Session::new(username.to_string(), Vec::new(), ttl_secs)
+    }
+
+    #[tokio::test]
+    async fn memory_store_hides_and_sweeps_expired_sessions() {
+        let store = MemorySessionStore::new();
+        let live = session("alice", 3_600);
+        let expired = session("alice", 0);
+        store.create(&live).await.unwrap();
+        store.create(&expired).await.unwrap();
+        assert_eq!(store.sessions.read().unwrap().len(), 2);
+
+        assert!(store.get(&expired.id).await.unwrap().is_none());
+        assert_eq!(
+            store.get(&live.id).await.unwrap().unwrap().username,
+            "alice"
+        );
+        let listed = store.list(Some("alice")).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, live.id);
+
+        // Creating a session swept the expired one out entirely.
+        store.create(&session("bob", 3_600)).await.unwrap();
+        assert_eq!(store.sessions.read().unwrap().len(), 2);
+        assert!(!store.revoke(&expired.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn memory_store_revokes_sessions() {
+        let store = MemorySessionStore::new();
+        let alice = session("alice", 3_600);
+        let bob = session("bob", 3_600);
+        store.create(&alice).await.unwrap();
+        store.create(&bob).await.unwrap();
+
+        assert!(store.revoke(&alice.id).await.unwrap());
+        assert!(!store.revoke(&alice.id).await.unwrap());
+        assert!(store.get(&alice.id).await.unwrap().is_none());
+        assert!(store.list(Some("alice")).await.unwrap().is_empty());
+        assert_eq!(store.list(None).await.unwrap().len(), 1);
+        assert!(store.get(&bob.id).await.unwrap().is_some());
+    }
+}
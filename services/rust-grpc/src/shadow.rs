@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::hermit::hermit_client::HermitClient;
+use crate::hermit::{BenchmarkRequest, BenchmarkResponse, PingRequest, PingResponse};
+use crate::metrics::{ShadowResult, ShadowRpc, METRICS};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+use tonic::{Code, Request, Status};
+use tracing::{debug, warn};
+
+/// Mirrors beyond this many in flight are dropped rather than queued, so a
+/// slow shadow can't build up memory here.
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Longest a mirrored request may take; a Benchmark at the iteration cap
+/// finishes well within it.
+const MIRROR_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Mirrors Ping and Benchmark RPCs to a shadow hermit instance, e.g. a
+/// canary build, and compares its responses with ours in the background.
+/// The RPC never waits on the shadow. Timings always differ between two
+/// servers, so only what should be deterministic is compared; outcomes go
+/// to the hermit_shadow_requests_total metric and mismatches are logged.
+pub struct Shadow {
+    client: HermitClient<Channel>,
+    addr: String,
+    in_flight: Arc<Semaphore>,
+}
+
+impl Shadow {
+    /// `addr` is http://HOST:PORT for h2c or https://HOST:PORT, trusting
+    /// the PEM `ca_cert`. Connects lazily, so the shadow needn't be up
+    /// yet.
+    pub fn new(addr: &str, ca_cert: Option<&str>) -> Result<Shadow, String> {
+        let mut endpoint = Endpoint::from_shared(addr.to_string())
+            .map_err(|e| format!("shadow address {:?}: {}", addr, e))?
+            .timeout(MIRROR_TIMEOUT);
+        if addr.starts_with("https://") {
+            let path = ca_cert.ok_or("an https shadow requires --shadow-ca-cert")?;
+            let pem = std::fs::read(path).map_err(|e| format!("read {}: {}", path, e))?;
+            let tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(pem));
+            endpoint = endpoint.tls_config(tls).map_err(|e| e.to_string())?;
+        }
+        Ok(Shadow {
+            client: HermitClient::new(endpoint.connect_lazy()),
+            addr: addr.to_string(),
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+        })
+    }
+
+    pub fn ping(&self, req: PingRequest, ours: Result<PingResponse, Code>) {
+        let mut client = self.client.clone();
+        self.mirror(ShadowRpc::Ping, async move {
+            let theirs = client.ping(req).await.map(|r| r.into_inner());
+            compare(ours, theirs, compare_ping)
+        });
+    }
+
+    pub fn benchmark(&self, req: BenchmarkRequest, ours: Result<BenchmarkResponse, Code>) {
+        let mut client = self.client.clone();
+        self.mirror(ShadowRpc::Benchmark, async move {
+            let theirs = client
+                .benchmark(Request::new(req))
+                .await
+                .map(|r| r.into_inner());
+            compare(ours, theirs, compare_benchmark)
+        });
+    }
+
+    /// Runs `comparison` in the background and records its outcome.
+    fn mirror<F>(&self, rpc: ShadowRpc, comparison: F)
+    where
+        F: std::future::Future<Output = Result<Vec<String>, String>> + Send + 'static,
+    {
+        let Ok(permit) = self.in_flight.clone().try_acquire_owned() else {
+            METRICS.shadow_result(rpc, ShadowResult::Dropped);
+            return;
+        };
+        let addr = self.addr.clone();
+        tokio::spawn(async move {
+            let result = match comparison.await {
+                Ok(diffs) if diffs.is_empty() => ShadowResult::Match,
+                Ok(diffs) => {
+                    warn!(shadow = %addr, ?rpc, "shadow response differs: {}", diffs.join("; "));
+                    ShadowResult::Mismatch
+                }
+                Err(e) => {
+                    debug!(shadow = %addr, ?rpc, "shadow request failed: {}", e);
+                    ShadowResult::Error
+                }
+            };
+            METRICS.shadow_result(rpc, result);
+            drop(permit);
+        });
+    }
+}
+
+/// Differences between our outcome and the shadow's, or `Err` when the
+/// shadow itself failed (unreachable, timed out) rather than answering.
+fn compare<T>(
+    ours: Result<T, Code>,
+    theirs: Result<T, Status>,
+    fields: fn(&T, &T) -> Vec<String>,
+) -> Result<Vec<String>, String> {
+    match (ours, theirs) {
+        (Ok(ours), Ok(theirs)) => Ok(fields(&ours, &theirs)),
+        (Err(ours), Err(theirs)) if ours == theirs.code() => Ok(Vec::new()),
+        (_, Err(theirs)) if matches!(theirs.code(), Code::Unavailable | Code::DeadlineExceeded) => {
+            Err(theirs.message().to_string())
+        }
+        (ours, theirs) => Ok(vec![format!(
+            "status {:?} here, {:?} on the shadow",
+            ours.err().unwrap_or(Code::Ok),
+            theirs.err().map_or(Code::Ok, |s| s.code())
+        )]),
+    }
+}
+
+fn compare_ping(ours: &PingResponse, theirs: &PingResponse) -> Vec<String> {
+    let mut diffs = Vec::new();
+    if ours.client_send_ns != theirs.client_send_ns {
+        diffs.push(format!(
+            "client_send_ns echoed as {} here, {} on the shadow",
+            ours.client_send_ns, theirs.client_send_ns
+        ));
+    }
+    if ours.upstream_ns.is_some() != theirs.upstream_ns.is_some() {
+        diffs.push("upstream_ns set on only one side".to_string());
+    }
+    diffs
+}
+
+fn compare_benchmark(ours: &BenchmarkResponse, theirs: &BenchmarkResponse) -> Vec<String> {
+    let mut diffs = Vec::new();
+    let mut check = |field: &str, same: bool| {
+        if !same {
+            diffs.push(format!("{} differs", field));
+        }
+    };
+    check(
+        "iterations",
+        ours.latencies_ns.len() == theirs.latencies_ns.len(),
+    );
+    check(
+        "percentiles",
+        ours.percentiles
+            .iter()
+            .map(|p| p.percentile)
+            .eq(theirs.percentiles.iter().map(|p| p.percentile)),
+    );
+    check("labels", ours.labels == theirs.labels);
+    check(
+        "timer_overhead_subtracted",
+        ours.timer_overhead_subtracted == theirs.timer_overhead_subtracted,
+    );
+    check(
+        "payload_content",
+        ours.payload_content == theirs.payload_content,
+    );
+    check(
+        "interval_log presence",
+        ours.interval_log.is_empty() == theirs.interval_log.is_empty(),
+    );
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hermit::Label;
+
+    #[test]
+    fn compares_outcomes_not_timings() {
+        let ours = BenchmarkResponse {
+            latencies_ns: vec![10, 20],
+            p50_ns: 15,
+            labels: vec![Label {
+                key: "build".to_string(),
+                value: "a".to_string(),
+            }],
+            ..Default::default()
+        };
+        let mut theirs = BenchmarkResponse {
+            latencies_ns: vec![30, 40],
+            p50_ns: 35,
+            ..ours.clone()
+        };
+        let same = compare(Ok(ours.clone()), Ok(theirs.clone()), compare_benchmark);
+        assert_eq!(same.unwrap(), Vec::<String>::new());
+
+        theirs.labels.clear();
+        let diffs = compare(Ok(ours.clone()), Ok(theirs), compare_benchmark).unwrap();
+        assert_eq!(diffs, vec!["labels differs".to_string()]);
+
+        let rejected = Status::invalid_argument("bad percentile");
+        let agreed = compare(Err(Code::InvalidArgument), Err(rejected), compare_benchmark);
+        assert!(agreed.unwrap().is_empty());
+        let disagreed = compare(
+            Ok(ours),
+            Err(Status::invalid_argument("new check")),
+            compare_benchmark,
+        );
+        assert_eq!(disagreed.unwrap().len(), 1);
+        let down = compare(
+            Ok(PingResponse::default()),
+            Err(Status::unavailable("connection refused")),
+            compare_ping,
+        );
+        assert!(down.is_err());
+    }
+}
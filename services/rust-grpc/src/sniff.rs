@@ -0,0 +1,208 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::metrics::{Port, METRICS};
+use crate::throughput;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::info;
+
+/// How long a turned-away client gets to read the refusal and hang up.
+/// Closing with its request still unread would send a reset, which can
+/// overtake the refusal.
+const LINGER: Duration = Duration::from_secs(1);
+
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// What a connection speaks, judged by its first bytes, so a client that
+/// dialled the wrong port can be told so in terms it can show instead of
+/// misreading whatever the server would have answered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// A TLS record, normally a ClientHello.
+    Tls,
+    /// HTTP/2 with prior knowledge, which is how plaintext gRPC starts.
+    H2c,
+    Http1,
+    /// Throughput test frames; see `crate::throughput::Header`.
+    Throughput,
+    Unknown,
+}
+
+impl Protocol {
+    pub const ALL: [Protocol; 5] = [
+        Protocol::Tls,
+        Protocol::H2c,
+        Protocol::Http1,
+        Protocol::Throughput,
+        Protocol::Unknown,
+    ];
+
+    /// `None` until there is a byte to go on. A prefix of a signature
+    /// counts as the whole, so a short first segment is still recognized.
+    pub fn detect(data: &[u8]) -> Option<Protocol> {
+        let prefix_of =
+            |signature: &[u8]| signature.starts_with(&data[..data.len().min(signature.len())]);
+        Some(match data.first()? {
+            0x16 if data.get(1).is_none_or(|&major| major == 3) => Protocol::Tls,
+            _ if prefix_of(throughput::MAGIC) => Protocol::Throughput,
+            _ if prefix_of(H2_PREFACE) => Protocol::H2c,
+            _ if http_method(data) => Protocol::Http1,
+            _ => Protocol::Unknown,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Protocol::Tls => "tls",
+            Protocol::H2c => "h2c",
+            Protocol::Http1 => "http1",
+            Protocol::Throughput => "throughput",
+            Protocol::Unknown => "unknown",
+        }
+    }
+
+    /// As in "client sent {}".
+    pub fn describe(self) -> &'static str {
+        match self {
+            Protocol::Tls => "a TLS ClientHello",
+            Protocol::H2c => "an h2c (plaintext HTTP/2) connection preface",
+            Protocol::Http1 => "a plaintext HTTP/1 request",
+            Protocol::Throughput => "throughput test frames",
+            Protocol::Unknown => "bytes of no protocol hermit knows",
+        }
+    }
+}
+
+/// Whether `data` starts like an HTTP/1 request line (`GET /...`).
+fn http_method(data: &[u8]) -> bool {
+    data.iter()
+        .position(|&b| b == b' ')
+        .is_some_and(|i| (3..=7).contains(&i) && data[..i].iter().all(u8::is_ascii_uppercase))
+}
+
+/// What a plaintext connection speaks, from its first bytes, without
+/// consuming them. `None` if the client closed or sent nothing.
+pub async fn peek(tcp: &TcpStream) -> Option<Protocol> {
+    // Enough for the longest HTTP method and its space.
+    let mut buf = [0u8; 8];
+    let mut n = 0;
+    for _ in 0..3 {
+        n = tcp.peek(&mut buf).await.ok()?;
+        if n == 0 || n == buf.len() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    Protocol::detect(&buf[..n])
+}
+
+/// What to send a client that spoke `sent` to a port expecting
+/// `expected`, before closing: an error in the client's own protocol,
+/// where it has one that can carry a message.
+pub fn refusal(sent: Protocol, expected: Protocol) -> Vec<u8> {
+    let message = match expected {
+        Protocol::Tls => "hermit: this port needs TLS",
+        Protocol::H2c => "hermit: this is the gRPC port, which speaks HTTP/2 only",
+        Protocol::Throughput => "hermit: this is the throughput test port, not gRPC or HTTP",
+        _ => "hermit: wrong protocol for this port",
+    };
+    match sent {
+        Protocol::Http1 => format!(
+            "HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+            message.len() + 1,
+            message
+        )
+        .into_bytes(),
+        Protocol::H2c => {
+            // The server preface (an empty SETTINGS frame), then a GOAWAY
+            // for PROTOCOL_ERROR with the message as its debug data.
+            let mut out = vec![0, 0, 0, 4, 0, 0, 0, 0, 0];
+            out.extend_from_slice(&(8 + message.len() as u32).to_be_bytes()[1..]);
+            out.extend_from_slice(&[7, 0, 0, 0, 0, 0]);
+            out.extend_from_slice(&0u32.to_be_bytes());
+            out.extend_from_slice(&1u32.to_be_bytes());
+            out.extend_from_slice(message.as_bytes());
+            out
+        }
+        // A fatal handshake_failure alert; TLS has no room for more.
+        Protocol::Tls => vec![0x15, 3, 3, 0, 2, 2, 40],
+        // A throughput client waiting on an echo reports the close.
+        Protocol::Throughput | Protocol::Unknown => Vec::new(),
+    }
+}
+
+/// Turns away a client that spoke `sent` to `port`, which expects
+/// `expected`: logs and counts it, sends the `refusal` and closes.
+pub async fn refuse<S>(
+    mut stream: S,
+    peer: SocketAddr,
+    port: Port,
+    sent: Protocol,
+    expected: Protocol,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    info!(
+        %peer,
+        port = port.name(),
+        sent = sent.name(),
+        "client sent {} to the {} port",
+        sent.describe(),
+        port.name()
+    );
+    METRICS.wrong_protocol(port, sent);
+    let linger = async {
+        stream.write_all(&refusal(sent, expected)).await?;
+        stream.shutdown().await?;
+        let mut sink = [0u8; 4096];
+        while stream.read(&mut sink).await? > 0 {}
+        std::io::Result::Ok(())
+    };
+    let _ = tokio::time::timeout(LINGER, linger).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_protocols_and_refuses_in_kind() {
+        for (data, protocol) in [
+            (&b"GET /metrics HTTP/1.1\r\n"[..], Protocol::Http1),
+            (b"OPTIONS * HTTP/1.1", Protocol::Http1),
+            (H2_PREFACE, Protocol::H2c),
+            (b"PRI * H", Protocol::H2c),
+            (b"\x16\x03\x01\x02\x00\x01", Protocol::Tls),
+            (b"HTP1\x02\x00\x02\x00", Protocol::Throughput),
+            (b"HT", Protocol::Throughput),
+            (b"SSH-2.0-OpenSSH_9.6", Protocol::Unknown),
+            (b"get / HTTP/1.1", Protocol::Unknown),
+        ] {
+            assert_eq!(Protocol::detect(data), Some(protocol), "{:?}", data);
+        }
+        assert_eq!(Protocol::detect(b""), None);
+
+        let http = String::from_utf8(refusal(Protocol::Http1, Protocol::Throughput)).unwrap();
+        assert!(http.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(
+            http.ends_with("\r\n\r\nhermit: this is the throughput test port, not gRPC or HTTP\n")
+        );
+
+        let h2 = refusal(Protocol::H2c, Protocol::Throughput);
+        assert_eq!(&h2[..9], [0, 0, 0, 4, 0, 0, 0, 0, 0]);
+        let (goaway, payload) = h2[9..].split_at(9);
+        assert_eq!(goaway[3], 7);
+        assert_eq!(
+            u32::from_be_bytes([0, goaway[0], goaway[1], goaway[2]]) as usize,
+            payload.len()
+        );
+        assert_eq!(payload[..8], [0, 0, 0, 0, 0, 0, 0, 1]);
+        assert!(payload[8..].starts_with(b"hermit: "));
+
+        assert_eq!(refusal(Protocol::Tls, Protocol::H2c)[..3], [0x15, 3, 3]);
+        assert!(refusal(Protocol::Throughput, Protocol::H2c).is_empty());
+    }
+}
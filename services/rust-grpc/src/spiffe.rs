@@ -0,0 +1,318 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+pub mod workload {
+    tonic::include_proto!("spiffe.workload");
+}
+
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::pki_types::{
+    CertificateDer, CertificateRevocationListDer, PrivateKeyDer, PrivatePkcs8KeyDer, UnixTime,
+};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{DigitallySignedStruct, DistinguishedName, RootCertStore, SignatureScheme};
+use std::sync::{Arc, RwLock};
+use tonic::Streaming;
+use workload::{X509svidRequest, X509svidResponse};
+use x509_parser::extensions::GeneralName;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The workload's identity as handed out by the SPIRE agent.
+pub struct Svid {
+    pub spiffe_id: String,
+    pub cert_chain: Vec<CertificateDer<'static>>,
+    pub key: PrivateKeyDer<'static>,
+    pub bundle: Vec<CertificateDer<'static>>,
+}
+
+/// Open the FetchX509SVID stream on the Workload API socket. `socket` may
+/// be given as `unix:///path` (the SPIFFE_ENDPOINT_SOCKET convention) or a
+/// bare path.
+#[cfg(unix)]
+pub async fn watch_svids(socket: &str) -> Result<Streaming<X509svidResponse>, BoxError> {
+    use hyper_util::rt::TokioIo;
+    use tonic::transport::{Endpoint, Uri};
+
+    let path = socket.strip_prefix("unix://").unwrap_or(socket).to_string();
+    // The URI is required by tonic but ignored by the connector.
+    let channel = Endpoint::try_from("http://spiffe.workload")?
+        .connect_with_connector(tower::service_fn(move |_: Uri| {
+            let path = path.clone();
+            async move {
+                let stream = tokio::net::UnixStream::connect(path).await?;
+                Ok::<_, std::io::Error>(TokioIo::new(stream))
+            }
+        }))
+        .await?;
+    fetch_x509svid(channel).await
+}
+
+/// Windows agents serve the Workload API on a named pipe, addressed as
+/// `npipe:name` (meaning `\\.\pipe\name`) or by full pipe path.
+#[cfg(windows)]
+pub async fn watch_svids(socket: &str) -> Result<Streaming<X509svidResponse>, BoxError> {
+    use hyper_util::rt::TokioIo;
+    use std::time::Duration;
+    use tokio::net::windows::named_pipe::ClientOptions;
+    use tonic::transport::{Endpoint, Uri};
+
+    /// All server instances of the pipe are in use; retry shortly.
+    const ERROR_PIPE_BUSY: i32 = 231;
+
+    let name = socket.strip_prefix("npipe:").unwrap_or(socket);
+    let name = if name.starts_with(r"\\") {
+        name.to_string()
+    } else {
+        format!(r"\\.\pipe\{}", name.trim_start_matches('/'))
+    };
+    let channel = Endpoint::try_from("http://spiffe.workload")?
+        .connect_with_connector(tower::service_fn(move |_: Uri| {
+            let name = name.clone();
+            async move {
+                let pipe = loop {
+                    match ClientOptions::new().open(&name) {
+                        Ok(pipe) => break pipe,
+                        Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                };
+                Ok::<_, std::io::Error>(TokioIo::new(pipe))
+            }
+        }))
+        .await?;
+    fetch_x509svid(channel).await
+}
+
+#[cfg(not(any(unix, windows)))]
+pub async fn watch_svids(_socket: &str) -> Result<Streaming<X509svidResponse>, BoxError> {
+    Err("the SPIFFE Workload API needs Unix sockets or Windows named pipes".into())
+}
+
+#[cfg(any(unix, windows))]
+async fn fetch_x509svid(
+    channel: tonic::transport::Channel,
+) -> Result<Streaming<X509svidResponse>, BoxError> {
+    use workload::spiffe_workload_api_client::SpiffeWorkloadApiClient;
+
+    let mut req = tonic::Request::new(X509svidRequest {});
+    // Mandatory security header; agents reject requests without it.
+    req.metadata_mut()
+        .insert("workload.spiffe.io", "true".parse()?);
+    let stream = SpiffeWorkloadApiClient::new(channel)
+        .fetch_x509svid(req)
+        .await?
+        .into_inner();
+    Ok(stream)
+}
+
+/// Take the default (first) SVID out of a Workload API update.
+pub fn default_svid(resp: X509svidResponse) -> Result<Svid, BoxError> {
+    let svid = resp
+        .svids
+        .into_iter()
+        .next()
+        .ok_or("workload API returned no SVIDs")?;
+    let cert_chain = split_der(&svid.x509_svid)?;
+    if cert_chain.is_empty() {
+        return Err("SVID has an empty certificate chain".into());
+    }
+    Ok(Svid {
+        spiffe_id: svid.spiffe_id,
+        cert_chain,
+        key: PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(svid.x509_svid_key)),
+        bundle: split_der(&svid.bundle)?,
+    })
+}
+
+/// The Workload API concatenates DER certificates back to back; split them
+/// on the outer SEQUENCE boundaries.
+fn split_der(mut data: &[u8]) -> Result<Vec<CertificateDer<'static>>, BoxError> {
+    let mut out = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 2 || data[0] != 0x30 {
+            return Err("malformed DER certificate".into());
+        }
+        let (len, header) = match data[1] {
+            n if n < 0x80 => (n as usize, 2),
+            n => {
+                let octets = (n & 0x7f) as usize;
+                if octets == 0 || octets > 4 || data.len() < 2 + octets {
+                    return Err("malformed DER length".into());
+                }
+                let len = data[2..2 + octets]
+                    .iter()
+                    .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+                (len, 2 + octets)
+            }
+        };
+        let end = header + len;
+        if data.len() < end {
+            return Err("truncated DER certificate".into());
+        }
+        out.push(CertificateDer::from(data[..end].to_vec()));
+        data = &data[end..];
+    }
+    Ok(out)
+}
+
+/// mTLS client verifier for SPIFFE: chains must lead to the current trust
+/// bundle, and the leaf's URI SAN must be one of the allowed SPIFFE IDs.
+/// An allowed entry without a path (`spiffe://example.org`) admits the
+/// whole trust domain; an empty list admits anything the bundle signed.
+/// The bundle is swapped in place as it rotates.
+#[derive(Debug)]
+pub struct SpiffeClientVerifier {
+    inner: RwLock<Arc<dyn ClientCertVerifier>>,
+    /// Inputs of `inner`, kept so either can change without the other.
+    trust: RwLock<Trust>,
+    allowed_ids: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+struct Trust {
+    bundle: Vec<CertificateDer<'static>>,
+    crls: Vec<CertificateRevocationListDer<'static>>,
+}
+
+impl SpiffeClientVerifier {
+    /// Client certificates listed in `crls` are rejected. Certificates
+    /// whose issuer has no CRL here are accepted, since SPIFFE deployments
+    /// usually rely on short-lived SVIDs rather than revocation.
+    pub fn new(
+        bundle: &[CertificateDer<'static>],
+        allowed_ids: Vec<String>,
+        crls: Vec<CertificateRevocationListDer<'static>>,
+    ) -> Result<Self, BoxError> {
+        let trust = Trust {
+            bundle: bundle.to_vec(),
+            crls,
+        };
+        Ok(SpiffeClientVerifier {
+            inner: RwLock::new(webpki_verifier(&trust)?),
+            trust: RwLock::new(trust),
+            allowed_ids,
+        })
+    }
+
+    pub fn update_bundle(&self, bundle: &[CertificateDer<'static>]) -> Result<(), BoxError> {
+        self.update(|trust| trust.bundle = bundle.to_vec())
+    }
+
+    pub fn update_crls(
+        &self,
+        crls: Vec<CertificateRevocationListDer<'static>>,
+    ) -> Result<(), BoxError> {
+        self.update(|trust| trust.crls = crls)
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Trust)) -> Result<(), BoxError> {
+        let mut trust = self.trust.write().map_err(|_| "verifier lock poisoned")?;
+        let mut next = trust.clone();
+        change(&mut next);
+        let verifier = webpki_verifier(&next)?;
+        *trust = next;
+        if let Ok(mut inner) = self.inner.write() {
+            *inner = verifier;
+        }
+        Ok(())
+    }
+
+    fn current(&self) -> Result<Arc<dyn ClientCertVerifier>, rustls::Error> {
+        self.inner
+            .read()
+            .map(|v| Arc::clone(&v))
+            .map_err(|_| rustls::Error::General("verifier lock poisoned".into()))
+    }
+
+    fn is_allowed(&self, id: &str) -> bool {
+        self.allowed_ids.is_empty()
+            || self.allowed_ids.iter().any(|allowed| {
+                if id == allowed {
+                    return true;
+                }
+                // A bare trust domain admits every ID under it.
+                let domain_only = allowed
+                    .strip_prefix("spiffe://")
+                    .is_some_and(|rest| !rest.contains('/'));
+                domain_only
+                    && id
+                        .strip_prefix(allowed.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+    }
+}
+
+fn webpki_verifier(trust: &Trust) -> Result<Arc<dyn ClientCertVerifier>, BoxError> {
+    let mut roots = RootCertStore::empty();
+    for cert in &trust.bundle {
+        roots.add(cert.clone())?;
+    }
+    Ok(WebPkiClientVerifier::builder(Arc::new(roots))
+        .with_crls(trust.crls.iter().cloned())
+        .allow_unknown_revocation_status()
+        .build()?)
+}
+
+/// The SPIFFE ID is the single `spiffe://` URI SAN of the leaf.
+fn spiffe_id(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+    let san = parsed.subject_alternative_name().ok()??;
+    san.value.general_names.iter().find_map(|name| match name {
+        GeneralName::URI(uri) if uri.starts_with("spiffe://") => Some(uri.to_string()),
+        _ => None,
+    })
+}
+
+impl ClientCertVerifier for SpiffeClientVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        // SPIFFE clients pick their SVID without CA hints.
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.current()?
+            .verify_client_cert(end_entity, intermediates, now)?;
+        match spiffe_id(end_entity) {
+            Some(id) if self.is_allowed(&id) => Ok(ClientCertVerified::assertion()),
+            Some(id) => Err(rustls::Error::General(format!(
+                "SPIFFE ID {} is not authorized",
+                id
+            ))),
+            None => Err(rustls::Error::General(
+                "client certificate has no SPIFFE ID".into(),
+            )),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.current()?.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.current()?.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.current()
+            .map(|v| v.supported_verify_schemes())
+            .unwrap_or_default()
+    }
+}
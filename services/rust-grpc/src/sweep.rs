@@ -0,0 +1,245 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::bench::Stats;
+use crate::breaker::{self, Breaker};
+use crate::gossip::{Gossip, Member};
+use crate::hermit::hermit_client::HermitClient;
+use crate::hermit::PingRequest;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::time::MissedTickBehavior;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint};
+use tracing::{debug, info, warn};
+
+/// Longest we wait to connect to a peer, and for each ping.
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sweeps in a row a peer must fail before it is skipped, and how many
+/// sweep intervals it is skipped for before being probed again.
+const PEER_FAILURES: u32 = 3;
+const PEER_SKIP_SWEEPS: u32 = 4;
+
+/// Round trips from the sweeping instance to one peer.
+#[derive(Clone, Debug)]
+pub struct PeerLatency {
+    pub region: String,
+    pub grpc_addr: String,
+    /// Round-trip times in nanoseconds, sorted; empty if the peer failed.
+    pub rtts_ns: Vec<i64>,
+    pub error: Option<String>,
+}
+
+impl PeerLatency {
+    pub fn stats(&self) -> Stats {
+        Stats::from_sorted(&self.rtts_ns)
+    }
+}
+
+/// One sweep of the fleet.
+#[derive(Clone, Debug)]
+pub struct Sweep {
+    pub started_at: SystemTime,
+    pub elapsed: Duration,
+    pub peers: Vec<PeerLatency>,
+}
+
+impl Sweep {
+    /// Round trips to every peer together, merged from each peer's
+    /// stats.
+    pub fn stats(&self) -> Stats {
+        let mut all = Stats::default();
+        for peer in &self.peers {
+            all.merge(&peer.stats());
+        }
+        all
+    }
+}
+
+/// Scheduled latency sweeps across the fleet, run by one instance at a
+/// time. Without coordination every instance would probe every other, N^2
+/// probes per sweep; instead the leader, the alive member with the lowest
+/// gossip address, pings each of the others' gRPC services in turn and
+/// keeps the aggregated results. Members agree on the leader once gossip
+/// has converged; until then two may briefly both sweep, which only costs
+/// duplicate probes. A peer that keeps failing trips its circuit
+/// breaker and is skipped for a while rather than costing a timeout every
+/// sweep.
+pub struct Sweeper {
+    gossip: Arc<Gossip>,
+    pings: u32,
+    ca_cert: Option<Vec<u8>>,
+    last: Mutex<Option<Sweep>>,
+    /// By peer gRPC address.
+    breakers: Mutex<HashMap<String, Arc<Breaker>>>,
+}
+
+impl Sweeper {
+    /// Each sweep sends `pings` Pings to every peer; https peers are
+    /// trusted through the PEM `ca_cert`.
+    pub fn new(gossip: Arc<Gossip>, pings: u32, ca_cert: Option<&str>) -> Result<Sweeper, String> {
+        let ca_cert = ca_cert
+            .map(|path| std::fs::read(path).map_err(|e| format!("read {}: {}", path, e)))
+            .transpose()?;
+        Ok(Sweeper {
+            gossip,
+            pings: pings.max(1),
+            ca_cert,
+            last: Mutex::new(None),
+            breakers: Mutex::default(),
+        })
+    }
+
+    /// Gossip address of the member that runs sweeps.
+    pub fn leader(&self) -> SocketAddr {
+        leader(self.gossip.addr(), &self.gossip.alive())
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.leader() == self.gossip.addr()
+    }
+
+    /// The latest sweep this instance ran, if it has led one.
+    pub fn last(&self) -> Option<Sweep> {
+        self.last.lock().unwrap().clone()
+    }
+
+    /// Sweeps every `interval` while this instance is the leader.
+    pub fn start(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut leading = false;
+            loop {
+                ticker.tick().await;
+                if self.is_leader() != leading {
+                    leading = !leading;
+                    info!(leader = %self.leader(), leading, "sweep leadership changed");
+                }
+                if leading {
+                    let sweep = self.sweep(interval).await;
+                    *self.last.lock().unwrap() = Some(sweep);
+                }
+            }
+        });
+    }
+
+    async fn sweep(&self, interval: Duration) -> Sweep {
+        let started_at = SystemTime::now();
+        let start = Instant::now();
+        let members = self.gossip.alive();
+        let mut peers = Vec::new();
+        for member in &members {
+            let breaker = self.breaker(&member.grpc_addr, interval);
+            let result = match breaker.allow() {
+                Ok(permit) => {
+                    let result = self.probe(member).await;
+                    match &result {
+                        Ok(_) => permit.success(),
+                        Err(e) => {
+                            warn!(peer = %member.grpc_addr, "sweep probe failed: {}", e);
+                            permit.failure();
+                        }
+                    }
+                    result
+                }
+                Err(wait) => Err(format!(
+                    "skipped after repeated failures; probing again in {}",
+                    humantime::format_duration(Duration::from_secs(wait.as_secs()))
+                )),
+            };
+            let (mut rtts_ns, error) = match result {
+                Ok(rtts) => (rtts, None),
+                Err(e) => (Vec::new(), Some(e)),
+            };
+            rtts_ns.sort_unstable();
+            peers.push(PeerLatency {
+                region: member.region.clone(),
+                grpc_addr: member.grpc_addr.clone(),
+                rtts_ns,
+                error,
+            });
+        }
+        // Forget peers that left, along with their metrics.
+        self.breakers
+            .lock()
+            .unwrap()
+            .retain(|addr, _| members.iter().any(|m| &m.grpc_addr == addr));
+        debug!(peers = peers.len(), "sweep finished");
+        Sweep {
+            started_at,
+            elapsed: start.elapsed(),
+            peers,
+        }
+    }
+
+    fn breaker(&self, grpc_addr: &str, interval: Duration) -> Arc<Breaker> {
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = breakers.entry(grpc_addr.to_string()).or_insert_with(|| {
+            Arc::new(Breaker::new(
+                format!("sweep:{}", grpc_addr),
+                breaker::Config {
+                    failure_threshold: PEER_FAILURES,
+                    open_for: interval * PEER_SKIP_SWEEPS,
+                    half_open_probes: 1,
+                },
+            ))
+        });
+        breaker.clone()
+    }
+
+    async fn probe(&self, member: &Member) -> Result<Vec<i64>, String> {
+        let addr = &member.grpc_addr;
+        let mut endpoint = Endpoint::from_shared(addr.clone())
+            .map_err(|e| format!("address {:?}: {}", addr, e))?
+            .connect_timeout(PEER_TIMEOUT)
+            .timeout(PEER_TIMEOUT);
+        if addr.starts_with("https://") {
+            let pem = self
+                .ca_cert
+                .as_ref()
+                .ok_or("an https peer requires --sweep-ca-cert")?;
+            let tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(pem));
+            endpoint = endpoint.tls_config(tls).map_err(|e| e.to_string())?;
+        }
+        let channel = endpoint.connect().await.map_err(|e| e.to_string())?;
+        let mut client = HermitClient::new(channel);
+        let mut rtts = Vec::with_capacity(self.pings as usize);
+        for _ in 0..self.pings {
+            let sent = Instant::now();
+            client
+                .ping(PingRequest::default())
+                .await
+                .map_err(|s| s.message().to_string())?;
+            rtts.push(sent.elapsed().as_nanos() as i64);
+        }
+        Ok(rtts)
+    }
+}
+
+/// The lowest address among `me` and the alive `members`.
+fn leader(me: SocketAddr, members: &[Member]) -> SocketAddr {
+    members.iter().map(|m| m.addr).fold(me, SocketAddr::min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gossip::Status;
+
+    #[test]
+    fn lowest_alive_address_leads() {
+        let member = |addr: &str| Member {
+            addr: addr.parse().unwrap(),
+            region: String::new(),
+            grpc_addr: String::new(),
+            status: Status::Alive,
+            incarnation: 1,
+        };
+        let me: SocketAddr = "10.0.0.5:7946".parse().unwrap();
+        assert_eq!(leader(me, &[]), me);
+        let others = [member("10.0.0.9:7946"), member("10.0.0.2:7946")];
+        assert_eq!(leader(me, &others), "10.0.0.2:7946".parse().unwrap());
+    }
+}
@@ -0,0 +1,219 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::auth::totp::{MemoryTotpStore, Totp};
+use crate::auth::AllowAllBackend;
+use crate::clock::SystemClock;
+use crate::db::Database;
+use crate::deadline::Deadlines;
+use crate::grpc::{self, Backends, ServerState};
+use crate::health::{self, Health};
+use crate::hermit::hermit_client::HermitClient;
+use crate::listener::Keepalive;
+use crate::runlog::RunLog;
+use crate::session::{MemorySessionStore, SessionStore};
+use crate::tls::{self, TlsConfig, TlsSource};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+use tracing::warn;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A complete hermit server running inside the caller's tokio runtime on
+/// ephemeral localhost ports: gRPC over h2c, gRPC over TLS with a fresh
+/// self-signed certificate (also serving health probes over ALPN
+/// http/1.1), and the health probe port. Backends are the
+/// dev defaults (allow-all auth, in-memory sessions, no signing key) and
+/// are shared by both gRPC listeners.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// use hermit_server::hermit::PingRequest;
+/// use hermit_server::test_harness::TestServer;
+///
+/// let server = TestServer::start().await?;
+/// let mut client = server.tls_client().await?;
+/// client.ping(PingRequest::default()).await?;
+/// server.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Dropping the server without calling `shutdown` aborts its tasks, so a
+/// panicking test still releases the ports.
+pub struct TestServer {
+    pub grpc_addr: SocketAddr,
+    pub tls_addr: SocketAddr,
+    pub health_addr: SocketAddr,
+    ca_pem: Vec<u8>,
+    stop: watch::Sender<bool>,
+    health_state: Arc<Health>,
+    servers: Vec<JoinHandle<()>>,
+    health: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    pub async fn start() -> Result<Self, BoxError> {
+        // Another test may already have installed it.
+        let _ = rustls::crypto::ring::default_provider().install_default();
+
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        let ca_pem = cert.pem().into_bytes();
+        let tls_cfg = tls::resolve_tls_config(&TlsSource::Pem {
+            cert: ca_pem.clone(),
+            key: key_pair.serialize_pem().into_bytes(),
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let grpc_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let tls_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let health_listener = TcpListener::bind("127.0.0.1:0").await?;
+        let grpc_addr = grpc_listener.local_addr()?;
+        let tls_addr = tls_listener.local_addr()?;
+        let health_addr = health_listener.local_addr()?;
+
+        let health_state = Arc::new(Health::new());
+        health_state.set_ready();
+        let health = tokio::spawn(health::serve(health_listener, health_state.clone()));
+
+        let db = Arc::new(Database::new());
+        let sessions: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+        let totp = Arc::new(Totp::new(Arc::new(MemoryTotpStore::new())));
+        let runs = Arc::new(RunLog::new());
+        let (stop, _) = watch::channel(false);
+        let mut servers = Vec::new();
+        for (listener, tls_cfg) in [(grpc_listener, None), (tls_listener, Some(tls_cfg))] {
+            let state = server_state(listener.local_addr()?.port(), health_state.clone());
+            let backends = Backends {
+                db: db.clone(),
+                auth: Arc::new(AllowAllBackend),
+                sessions: sessions.clone(),
+                totp: totp.clone(),
+                signer: None,
+                webhook: None,
+                geoip: None,
+                shadow: None,
+                gossip: None,
+                registry: None,
+                sweeper: None,
+                runs: runs.clone(),
+            };
+            servers.push(tokio::spawn(run(
+                listener,
+                state,
+                tls_cfg,
+                backends,
+                stop.subscribe(),
+            )));
+        }
+
+        Ok(TestServer {
+            grpc_addr,
+            tls_addr,
+            health_addr,
+            ca_pem,
+            stop,
+            servers,
+            health_state,
+            health: Some(health),
+        })
+    }
+
+    /// Client for the plaintext (h2c) listener.
+    pub async fn grpc_client(&self) -> Result<HermitClient<Channel>, BoxError> {
+        let channel = Endpoint::from_shared(format!("http://{}", self.grpc_addr))?
+            .connect()
+            .await?;
+        Ok(HermitClient::new(channel))
+    }
+
+    /// Client for the TLS listener, trusting only the harness certificate.
+    pub async fn tls_client(&self) -> Result<HermitClient<Channel>, BoxError> {
+        let tls = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(&self.ca_pem))
+            .domain_name("localhost");
+        let channel = Endpoint::from_shared(format!("https://{}", self.tls_addr))?
+            .tls_config(tls)?
+            .connect()
+            .await?;
+        Ok(HermitClient::new(channel))
+    }
+
+    /// PEM of the self-signed certificate served on `tls_addr`.
+    pub fn ca_pem(&self) -> &[u8] {
+        &self.ca_pem
+    }
+
+    /// Stop accepting, let in-flight RPCs finish, and wait for both gRPC
+    /// listeners to exit.
+    pub async fn shutdown(mut self) {
+        // Draining first ends WatchServerInfo streams, as a signal would.
+        self.health_state.start_draining();
+        let _ = self.stop.send(true);
+        for server in std::mem::take(&mut self.servers) {
+            let _ = server.await;
+        }
+        if let Some(health) = self.health.take() {
+            health.abort();
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        for server in &self.servers {
+            server.abort();
+        }
+        if let Some(health) = &self.health {
+            health.abort();
+        }
+    }
+}
+
+fn server_state(port: u16, health: Arc<Health>) -> Arc<ServerState> {
+    Arc::new(ServerState {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        region: "test".to_string(),
+        started_at: SystemTime::now(),
+        start_instant: Instant::now(),
+        grpc_port: port,
+        session_ttl_secs: 3600,
+        deadlines: Deadlines::new(Duration::from_secs(30)),
+        keepalive: Keepalive {
+            tcp: None,
+            http2_interval: None,
+            http2_timeout: Duration::from_secs(10),
+            max_age: None,
+            max_age_grace: Duration::ZERO,
+            max_idle: None,
+        },
+        dscp: None,
+        tcp_maxseg: None,
+        alpn_multiplex: true,
+        trace_frames: 0,
+        clock: Arc::new(SystemClock),
+        health,
+        pair: None,
+    })
+}
+
+async fn run(
+    listener: TcpListener,
+    state: Arc<ServerState>,
+    tls_cfg: Option<TlsConfig>,
+    backends: Backends,
+    mut stop: watch::Receiver<bool>,
+) {
+    let shutdown = async move {
+        let _ = stop.wait_for(|stopped| *stopped).await;
+    };
+    if let Err(e) = grpc::serve(listener, state, tls_cfg, backends, shutdown).await {
+        warn!("test server exited with error: {}", e);
+    }
+}
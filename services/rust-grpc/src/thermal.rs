@@ -0,0 +1,188 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// A drop in average CPU frequency below this fraction of the run's
+/// highest counts as frequency scaling.
+const SCALING_RATIO: f64 = 0.9;
+
+/// One reading of the host's CPUs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Reading {
+    /// Mean current frequency across online CPUs; `None` without cpufreq.
+    pub frequency_khz: Option<u64>,
+    /// Hottest thermal zone; `None` without thermal zones.
+    pub temperature_mc: Option<i64>,
+    /// Sum of the core and package throttle counters across CPUs (x86
+    /// only); `None` without them.
+    pub throttle_count: Option<u64>,
+}
+
+/// CPU frequency and temperature over a benchmark run, and whether either
+/// makes its numbers suspect.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Report {
+    pub samples: u32,
+    pub min_frequency_khz: u64,
+    pub max_frequency_khz: u64,
+    pub max_temperature_mc: Option<i64>,
+    /// Times the CPUs hit their thermal limit during the run.
+    pub throttle_events: u64,
+    pub throttled: bool,
+    /// The average frequency moved by more than 10% during the run.
+    pub frequency_scaled: bool,
+}
+
+impl Report {
+    /// Summarizes readings taken in order across a run; `None` if none of
+    /// them read anything.
+    pub fn from_readings(readings: &[Reading]) -> Option<Report> {
+        let freqs = readings.iter().filter_map(|r| r.frequency_khz);
+        let min_frequency_khz = freqs.clone().min();
+        let max_frequency_khz = freqs.max();
+        let max_temperature_mc = readings.iter().filter_map(|r| r.temperature_mc).max();
+        let mut counts = readings.iter().filter_map(|r| r.throttle_count);
+        let first = counts.next();
+        let throttle_events = first
+            .zip(counts.next_back())
+            .map_or(0, |(first, last)| last.saturating_sub(first));
+        if min_frequency_khz.is_none() && max_temperature_mc.is_none() && first.is_none() {
+            return None;
+        }
+        let (min, max) = (
+            min_frequency_khz.unwrap_or_default(),
+            max_frequency_khz.unwrap_or_default(),
+        );
+        Some(Report {
+            samples: readings.len() as u32,
+            min_frequency_khz: min,
+            max_frequency_khz: max,
+            max_temperature_mc,
+            throttle_events,
+            throttled: throttle_events > 0,
+            frequency_scaled: (min as f64) < max as f64 * SCALING_RATIO,
+        })
+    }
+}
+
+/// Reads the CPUs every `interval` on its own thread while a benchmark
+/// runs, so the loop being measured does no extra work. Readings are
+/// also taken at start and stop, so short runs still get two.
+pub struct Monitor {
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<Vec<Reading>>,
+}
+
+impl Monitor {
+    /// `None` off Linux, or when the host exposes neither cpufreq nor
+    /// thermal zones (most VMs and containers without /sys).
+    pub fn start(interval: Duration) -> Option<Monitor> {
+        let first = read();
+        if first == Reading::default() {
+            return None;
+        }
+        let (stop, stopped) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("thermal-monitor".to_string())
+            .spawn(move || {
+                let mut readings = vec![first];
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    readings.push(read());
+                }
+                readings.push(read());
+                readings
+            })
+            .ok()?;
+        Some(Monitor { stop, thread })
+    }
+
+    pub fn stop(self) -> Option<Report> {
+        let _ = self.stop.send(());
+        let readings = self.thread.join().ok()?;
+        Report::from_readings(&readings)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read() -> Reading {
+    let value = |path: std::path::PathBuf| {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|s| s.trim().parse::<i64>().ok())
+    };
+    let cpus: Vec<_> = std::fs::read_dir("/sys/devices/system/cpu")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| {
+            let name = e.file_name();
+            let name = name.to_string_lossy();
+            name.strip_prefix("cpu")
+                .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .map(|e| e.path())
+        .collect();
+    let freqs: Vec<i64> = cpus
+        .iter()
+        .filter_map(|cpu| value(cpu.join("cpufreq/scaling_cur_freq")))
+        .collect();
+    let throttles: Vec<i64> = cpus
+        .iter()
+        .flat_map(|cpu| {
+            ["core_throttle_count", "package_throttle_count"]
+                .map(|f| value(cpu.join("thermal_throttle").join(f)))
+        })
+        .flatten()
+        .collect();
+    let temperature_mc = std::fs::read_dir("/sys/class/thermal")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().starts_with("thermal_zone"))
+        .filter_map(|e| value(e.path().join("temp")))
+        .max();
+    Reading {
+        frequency_khz: (!freqs.is_empty())
+            .then(|| (freqs.iter().sum::<i64>() / freqs.len() as i64) as u64),
+        temperature_mc,
+        throttle_count: (!throttles.is_empty()).then(|| throttles.iter().sum::<i64>() as u64),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read() -> Reading {
+    Reading::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_throttling_and_scaling() {
+        assert_eq!(Report::from_readings(&[Reading::default()]), None);
+
+        let reading = |khz, mc, throttles| Reading {
+            frequency_khz: Some(khz),
+            temperature_mc: Some(mc),
+            throttle_count: Some(throttles),
+        };
+        let steady = [reading(3_000_000, 60_000, 4), reading(2_900_000, 65_000, 4)];
+        let report = Report::from_readings(&steady).unwrap();
+        assert_eq!(report.samples, 2);
+        assert_eq!(report.max_temperature_mc, Some(65_000));
+        assert!(!report.throttled && !report.frequency_scaled);
+
+        let hot = [
+            reading(3_000_000, 60_000, 4),
+            reading(2_000_000, 99_000, 6),
+            reading(2_100_000, 97_000, 7),
+        ];
+        let report = Report::from_readings(&hot).unwrap();
+        assert_eq!(report.throttle_events, 3);
+        assert_eq!(report.min_frequency_khz, 2_000_000);
+        assert!(report.throttled && report.frequency_scaled);
+    }
+}
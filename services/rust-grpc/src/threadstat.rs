@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+/// Per-thread scheduler and memory counters, read around each benchmark
+/// sample so an outlier can be attributed to whatever the kernel did to
+/// the thread while it was being timed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// Time spent runnable but waiting for a CPU (schedstat field 2).
+    pub run_delay_ns: u64,
+    /// Times the thread blocked (a syscall or fault that had to wait).
+    pub voluntary_switches: u64,
+    pub involuntary_switches: u64,
+    pub minor_faults: u64,
+    pub major_faults: u64,
+    pub user_cpu_ns: u64,
+    /// CPU time in the kernel: syscalls and fault handling.
+    pub system_cpu_ns: u64,
+}
+
+impl Counters {
+    pub fn since(&self, earlier: &Counters) -> Counters {
+        Counters {
+            run_delay_ns: self.run_delay_ns.saturating_sub(earlier.run_delay_ns),
+            voluntary_switches: self
+                .voluntary_switches
+                .saturating_sub(earlier.voluntary_switches),
+            involuntary_switches: self
+                .involuntary_switches
+                .saturating_sub(earlier.involuntary_switches),
+            minor_faults: self.minor_faults.saturating_sub(earlier.minor_faults),
+            major_faults: self.major_faults.saturating_sub(earlier.major_faults),
+            user_cpu_ns: self.user_cpu_ns.saturating_sub(earlier.user_cpu_ns),
+            system_cpu_ns: self.system_cpu_ns.saturating_sub(earlier.system_cpu_ns),
+        }
+    }
+
+    /// Most likely reason for a slow sample given its counter deltas, or
+    /// `None` if nothing was recorded.
+    pub fn probable_cause(&self) -> Option<&'static str> {
+        if self.run_delay_ns > 0 || self.involuntary_switches > 0 {
+            Some("preemption")
+        } else if self.major_faults > 0 {
+            Some("major page fault")
+        } else if self.minor_faults > 0 {
+            Some("minor page fault")
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads `Counters` for the calling thread. Must be used from one thread
+/// only; the benchmark loop never yields, so that holds there.
+#[cfg(target_os = "linux")]
+pub struct Sampler {
+    schedstat: std::fs::File,
+}
+
+#[cfg(target_os = "linux")]
+impl Sampler {
+    /// `None` when schedstat is unavailable (kernel built without
+    /// CONFIG_SCHEDSTATS, or /proc not mounted).
+    pub fn open() -> Option<Sampler> {
+        let schedstat = std::fs::File::open("/proc/thread-self/schedstat").ok()?;
+        let mut sampler = Sampler { schedstat };
+        sampler.sample()?;
+        Some(sampler)
+    }
+
+    /// One pread and one getrusage; a few microseconds.
+    pub fn sample(&mut self) -> Option<Counters> {
+        use nix::sys::resource::{getrusage, UsageWho};
+        use std::os::unix::fs::FileExt;
+
+        let mut buf = [0u8; 96];
+        let n = self.schedstat.read_at(&mut buf, 0).ok()?;
+        let line = std::str::from_utf8(&buf[..n]).ok()?;
+        let run_delay_ns = line.split_whitespace().nth(1)?.parse().ok()?;
+        let usage = getrusage(UsageWho::RUSAGE_THREAD).ok()?;
+        let ns = |t: nix::sys::time::TimeVal| {
+            (t.tv_sec() as u64 * 1_000_000 + t.tv_usec() as u64) * 1_000
+        };
+        Some(Counters {
+            run_delay_ns,
+            voluntary_switches: usage.voluntary_context_switches() as u64,
+            involuntary_switches: usage.involuntary_context_switches() as u64,
+            minor_faults: usage.minor_page_faults() as u64,
+            major_faults: usage.major_page_faults() as u64,
+            user_cpu_ns: ns(usage.user_time()),
+            system_cpu_ns: ns(usage.system_time()),
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub struct Sampler;
+
+#[cfg(not(target_os = "linux"))]
+impl Sampler {
+    pub fn open() -> Option<Sampler> {
+        None
+    }
+
+    pub fn sample(&mut self) -> Option<Counters> {
+        None
+    }
+}
@@ -0,0 +1,957 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::alloc_audit::{self, HotPath};
+use crate::hops::{self, Hop};
+use crate::hugepage::{self, Backing};
+use crate::metrics::Port;
+use crate::sniff::{self, Protocol};
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+/// Without the tls feature there is never an acceptor.
+#[cfg(not(feature = "tls"))]
+#[derive(Clone)]
+enum TlsAcceptor {}
+
+pub(crate) const MAGIC: &[u8; 4] = b"HTP1";
+pub(crate) const HEADER_LEN: usize = 16;
+
+const ACK_MAGIC: &[u8; 4] = b"HTA1";
+const ACK_LEN: usize = 16;
+
+/// Header flag: reply with an acknowledgement before the test starts.
+const FLAG_ACK: u8 = 1;
+/// Header flag: end an echo test with a checksum trailer.
+const FLAG_VERIFY: u8 = 2;
+/// Header flag: a hop list follows the header.
+const FLAG_HOPS: u8 = 4;
+const TRAILER_LEN: usize = 16;
+
+/// Clients get this long to finish the TLS handshake and send a header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after a failed accept (e.g. out of file descriptors) so the
+/// loop doesn't spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+const MAX_DURATION: Duration = Duration::from_secs(60);
+const DEFAULT_BLOCK: usize = 128 * 1024;
+const MAX_BLOCK: usize = 1024 * 1024;
+/// Echo tests with blocks at least this large on plaintext connections
+/// are spliced through a pipe instead of copied through userspace.
+const SPLICE_MIN_BLOCK: usize = 256 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Upload,
+    Download,
+    Echo,
+}
+
+/// Sent by the client to start a test; 16 bytes, integers big-endian:
+///
+/// ```text
+/// 0..4   magic "HTP1"
+/// 4      mode: 0 = upload (client sends), 1 = download (server sends),
+///        2 = echo (server sends back what the client sends)
+/// 5      congestion control: 0 = the listener's, 1 = reno, 2 = cubic,
+///        3 = bbr
+/// 6      flags: bit 0 = acknowledge, bit 1 = verify (echo only), bit 2 =
+///        hops (see below for each)
+/// 7      reserved, zero
+/// 8..12  download duration in milliseconds (capped at 60s)
+/// 12..16 server read/write size in bytes (0 = 128 KiB, capped at 1 MiB)
+/// ```
+///
+/// With the acknowledge flag the server answers the header with 16 bytes
+/// before the test starts: "HTA1", a status byte (0 = ok, 1 = the
+/// requested congestion control is unavailable, after which the server
+/// closes), 3 reserved bytes and the active congestion control's name,
+/// NUL-padded to 8 bytes.
+///
+/// With the verify flag an echo test ends, after the echoed data, with a
+/// 16-byte trailer: the number of bytes the server received (u64) and
+/// their CRC-32 (u32; the zlib/IEEE polynomial), then 4 zero bytes.
+///
+/// With the hops flag the header is followed by a hop list (see
+/// `hops::encode`): one entry per tier the test crossed, normally just
+/// the client's, to which each hermit-proxy on the way appends its own.
+/// The server appends itself and sends the list back straight away,
+/// before any acknowledgement, as a per-hop latency waterfall of the
+/// request path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub mode: Mode,
+    pub congestion: Option<CongestionControl>,
+    pub ack: bool,
+    pub verify: bool,
+    pub hops: bool,
+    pub duration: Duration,
+    pub block: usize,
+}
+
+/// TCP congestion control algorithms a test can ask for. Which ones work
+/// depends on the kernel; see /proc/sys/net/ipv4/tcp_available_congestion_control.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CongestionControl {
+    Reno,
+    Cubic,
+    Bbr,
+}
+
+impl CongestionControl {
+    pub fn name(self) -> &'static str {
+        match self {
+            CongestionControl::Reno => "reno",
+            CongestionControl::Cubic => "cubic",
+            CongestionControl::Bbr => "bbr",
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            CongestionControl::Reno => 1,
+            CongestionControl::Cubic => 2,
+            CongestionControl::Bbr => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Option<CongestionControl>, String> {
+        Ok(Some(match id {
+            0 => return Ok(None),
+            1 => CongestionControl::Reno,
+            2 => CongestionControl::Cubic,
+            3 => CongestionControl::Bbr,
+            c => return Err(format!("unknown congestion control {}", c)),
+        }))
+    }
+}
+
+impl std::str::FromStr for CongestionControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "reno" => Ok(CongestionControl::Reno),
+            "cubic" => Ok(CongestionControl::Cubic),
+            "bbr" => Ok(CongestionControl::Bbr),
+            _ => Err(format!(
+                "unknown congestion control {:?} (reno, cubic, bbr)",
+                s
+            )),
+        }
+    }
+}
+
+/// The socket under a test's stream, kept so congestion control can be
+/// changed after the stream (possibly wrapped in TLS) is handed over. Only
+/// valid while that stream is open.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Socket {
+    #[cfg(target_os = "linux")]
+    fd: Option<std::os::fd::RawFd>,
+}
+
+impl Socket {
+    pub fn of(tcp: &TcpStream) -> Socket {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::fd::AsRawFd;
+            Socket {
+                fd: Some(tcp.as_raw_fd()),
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = tcp;
+            Socket {}
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn congestion(&self) -> Option<String> {
+        get_congestion(self.fd?).ok()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn congestion(&self) -> Option<String> {
+        None
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_congestion(&self, cc: CongestionControl) -> io::Result<()> {
+        match self.fd {
+            Some(fd) => set_congestion(fd, cc),
+            None => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn set_congestion(&self, _cc: CongestionControl) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Set the congestion control that connections accepted on `listener`
+/// start with; they inherit it from the listening socket.
+#[cfg(target_os = "linux")]
+pub fn set_listener_congestion(
+    listener: &std::net::TcpListener,
+    cc: CongestionControl,
+) -> Result<(), String> {
+    use std::os::fd::AsRawFd;
+    set_congestion(listener.as_raw_fd(), cc)
+        .map_err(|e| format!("congestion control {}: {}", cc.name(), e))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_listener_congestion(
+    _listener: &std::net::TcpListener,
+    _cc: CongestionControl,
+) -> Result<(), String> {
+    Err("choosing congestion control is only supported on Linux".to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn set_congestion(fd: std::os::fd::RawFd, cc: CongestionControl) -> io::Result<()> {
+    let name = cc.name();
+    // SAFETY: name outlives the call and its length is passed with it.
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_CONGESTION,
+            name.as_ptr().cast(),
+            name.len() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn get_congestion(fd: std::os::fd::RawFd) -> io::Result<String> {
+    // TCP_CA_NAME_MAX is 16.
+    let mut buf = [0u8; 16];
+    let mut len = buf.len() as libc::socklen_t;
+    // SAFETY: buf is writable for len bytes.
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_CONGESTION,
+            buf.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let name = &buf[..len as usize];
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    Ok(String::from_utf8_lossy(&name[..end]).into_owned())
+}
+
+/// How a test went.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub mode: Mode,
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Congestion control the connection ran with; empty when unknown.
+    pub congestion: String,
+    /// What backed the server's block buffer; only userspace copies use
+    /// it, so splice()d echo tests report `Regular`.
+    pub buffer: Backing,
+}
+
+impl Header {
+    /// The client's side of `parse`.
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        buf[..4].copy_from_slice(MAGIC);
+        buf[4] = match self.mode {
+            Mode::Upload => 0,
+            Mode::Download => 1,
+            Mode::Echo => 2,
+        };
+        buf[5] = self.congestion.map_or(0, CongestionControl::id);
+        buf[6] = if self.ack { FLAG_ACK } else { 0 }
+            | if self.verify { FLAG_VERIFY } else { 0 }
+            | if self.hops { FLAG_HOPS } else { 0 };
+        let millis = self.duration.as_millis().min(u128::from(u32::MAX)) as u32;
+        buf[8..12].copy_from_slice(&millis.to_be_bytes());
+        buf[12..].copy_from_slice(&(self.block.min(MAX_BLOCK) as u32).to_be_bytes());
+        buf
+    }
+
+    pub fn parse(buf: &[u8; HEADER_LEN]) -> Result<Header, String> {
+        if &buf[..4] != MAGIC {
+            return Err("bad magic".to_string());
+        }
+        let mode = match buf[4] {
+            0 => Mode::Upload,
+            1 => Mode::Download,
+            2 => Mode::Echo,
+            m => return Err(format!("unknown mode {}", m)),
+        };
+        let congestion = CongestionControl::from_id(buf[5])?;
+        let millis = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+        let block = u32::from_be_bytes([buf[12], buf[13], buf[14], buf[15]]) as usize;
+        Ok(Header {
+            mode,
+            congestion,
+            ack: buf[6] & FLAG_ACK != 0,
+            verify: buf[6] & FLAG_VERIFY != 0,
+            hops: buf[6] & FLAG_HOPS != 0,
+            duration: Duration::from_millis(u64::from(millis)).min(MAX_DURATION),
+            block: if block == 0 {
+                DEFAULT_BLOCK
+            } else {
+                block.min(MAX_BLOCK)
+            },
+        })
+    }
+}
+
+/// Serve bulk throughput tests, the bandwidth counterpart to Benchmark's
+/// latency numbers. With an acceptor, connections complete a TLS
+/// handshake first so the numbers include encryption cost.
+///
+/// Upload: the server discards everything up to the client's half-close,
+/// replies with the bytes received and the nanoseconds from first byte to
+/// EOF (two u64s), and closes. Download: the server writes zeros for the
+/// requested duration and closes; the client times what it reads. Echo:
+/// the server writes back everything it reads until the client's
+/// half-close, then closes; on Linux, plaintext echo tests with blocks
+/// of 256 KiB or more are spliced so the payload never enters userspace.
+/// Plaintext clients that open with something else (an HTTP request, a
+/// TLS ClientHello, gRPC) are refused in their own protocol; see
+/// `crate::sniff`. An upload can be driven with standard tools, e.g.
+/// `(printf 'HTP1\0\0\0\0\0\0\0\0\0\0\0\0'; head -c 1G /dev/zero) | nc -N host port | xxd`.
+pub async fn serve(listener: TcpListener, #[cfg(feature = "tls")] tls: Option<TlsAcceptor>) {
+    #[cfg(not(feature = "tls"))]
+    let tls: Option<TlsAcceptor> = None;
+    if let Ok(addr) = listener.local_addr() {
+        #[cfg(target_os = "linux")]
+        let congestion = {
+            use std::os::fd::AsRawFd;
+            get_congestion(listener.as_raw_fd()).unwrap_or_default()
+        };
+        #[cfg(not(target_os = "linux"))]
+        let congestion = "";
+        info!(%addr, tls = tls.is_some(), %congestion, "throughput tests listening");
+    }
+    loop {
+        let (tcp, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("throughput accept failed: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let _ = tcp.set_nodelay(true);
+        let socket = Socket::of(&tcp);
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            match tls {
+                #[cfg(feature = "tls")]
+                Some(acceptor) => {
+                    match tokio::time::timeout(HEADER_TIMEOUT, acceptor.accept(tcp)).await {
+                        Ok(Ok(stream)) => serve_connection(stream, socket, peer).await,
+                        Ok(Err(e)) => debug!(%peer, "throughput TLS handshake failed: {}", e),
+                        Err(_) => debug!(%peer, "throughput TLS handshake timed out"),
+                    }
+                }
+                #[cfg(not(feature = "tls"))]
+                Some(never) => match never {},
+                None => match tokio::time::timeout(HEADER_TIMEOUT, sniff::peek(&tcp)).await {
+                    Ok(Some(Protocol::Throughput)) => serve_connection(tcp, socket, peer).await,
+                    Ok(Some(sent)) => {
+                        let port = Port::Throughput;
+                        sniff::refuse(tcp, peer, port, sent, Protocol::Throughput).await
+                    }
+                    Ok(None) => debug!(%peer, "throughput client closed before sending a header"),
+                    Err(_) => debug!(%peer, "throughput header timed out"),
+                },
+            }
+        });
+    }
+}
+
+/// Run and log one test on an already-accepted connection, e.g. one
+/// handed over from the gRPC TLS port by ALPN. `socket` is the TCP socket
+/// under `stream`.
+pub async fn serve_connection<S>(stream: S, socket: Socket, peer: SocketAddr)
+where
+    S: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    match run(stream, socket).await {
+        Ok(out) => info!(
+            %peer,
+            mode = ?out.mode,
+            bytes = out.bytes,
+            congestion = %out.congestion,
+            buffer = out.buffer.name(),
+            gbit_per_sec = out.bytes as f64 * 8.0 / out.elapsed.as_secs_f64().max(1e-9) / 1e9,
+            "throughput test finished"
+        ),
+        Err(e) => debug!(%peer, "throughput test failed: {}", e),
+    }
+}
+
+/// Run one test on `stream`, whose TCP socket is `socket`.
+pub async fn run<S>(mut stream: S, socket: Socket) -> io::Result<Outcome>
+where
+    S: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    let mut buf = [0u8; HEADER_LEN];
+    tokio::time::timeout(HEADER_TIMEOUT, stream.read_exact(&mut buf))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let received = hops::now_ns();
+    let header = Header::parse(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if header.hops {
+        let mut hops = tokio::time::timeout(HEADER_TIMEOUT, hops::read(&mut stream))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        if hops.len() == hops::MAX_HOPS {
+            hops.pop();
+        }
+        hops.push(Hop::departing("server", received));
+        stream.write_all(&hops::encode(&hops)).await?;
+    }
+    let chosen = header.congestion.map(|cc| socket.set_congestion(cc));
+    let congestion = socket.congestion().unwrap_or_default();
+    if header.ack {
+        let mut ack = [0u8; ACK_LEN];
+        ack[..4].copy_from_slice(ACK_MAGIC);
+        ack[4] = matches!(chosen, Some(Err(_))) as u8;
+        let name = &congestion.as_bytes()[..congestion.len().min(8)];
+        ack[8..8 + name.len()].copy_from_slice(name);
+        stream.write_all(&ack).await?;
+    }
+    if let Some(Err(e)) = chosen {
+        stream.shutdown().await?;
+        let cc = header.congestion.map_or("", CongestionControl::name);
+        return Err(io::Error::new(
+            e.kind(),
+            format!("congestion control {}: {}", cc, e),
+        ));
+    }
+    let mut block = hugepage::Buffer::new(header.block);
+
+    let (bytes, elapsed) = match header.mode {
+        Mode::Upload => {
+            let mut bytes = 0u64;
+            let mut start = None;
+            loop {
+                let n = stream.read(&mut block).await?;
+                if n == 0 {
+                    break;
+                }
+                start.get_or_insert_with(Instant::now);
+                bytes += n as u64;
+            }
+            let elapsed = start.map_or(Duration::ZERO, |s| s.elapsed());
+            let mut result = [0u8; 16];
+            result[..8].copy_from_slice(&bytes.to_be_bytes());
+            result[8..].copy_from_slice(&(elapsed.as_nanos() as u64).to_be_bytes());
+            stream.write_all(&result).await?;
+            (bytes, elapsed)
+        }
+        Mode::Download => {
+            let mut bytes = 0u64;
+            let start = Instant::now();
+            while start.elapsed() < header.duration {
+                stream.write_all(&block).await?;
+                bytes += block.len() as u64;
+            }
+            (bytes, start.elapsed())
+        }
+        Mode::Echo => {
+            let mut bytes = 0u64;
+            let mut start = None;
+            // Verifying needs the bytes in userspace.
+            let splice = if header.verify {
+                None
+            } else {
+                splice_target(&stream, header.block)
+            };
+            if let Some(tcp) = splice {
+                bytes = splice_echo(tcp, header.block, &mut start).await?;
+            } else {
+                let mut crc = crc32fast::Hasher::new();
+                loop {
+                    let round_trip = async {
+                        let n = stream.read(&mut block).await?;
+                        if n > 0 {
+                            start.get_or_insert_with(Instant::now);
+                            if header.verify {
+                                crc.update(&block[..n]);
+                            }
+                            stream.write_all(&block[..n]).await?;
+                        }
+                        io::Result::Ok(n)
+                    };
+                    let n = alloc_audit::track(HotPath::Echo, round_trip).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    bytes += n as u64;
+                }
+                if header.verify {
+                    let mut trailer = [0u8; TRAILER_LEN];
+                    trailer[..8].copy_from_slice(&bytes.to_be_bytes());
+                    trailer[8..12].copy_from_slice(&crc.finalize().to_be_bytes());
+                    stream.write_all(&trailer).await?;
+                }
+            }
+            (bytes, start.map_or(Duration::ZERO, |s| s.elapsed()))
+        }
+    };
+    stream.shutdown().await?;
+    Ok(Outcome {
+        mode: header.mode,
+        bytes,
+        elapsed,
+        congestion,
+        buffer: block.backing(),
+    })
+}
+
+/// What a refused client sees: the server hung up without a byte of echo,
+/// which `crate::sniff` does to throughput frames sent to the gRPC port.
+const NOT_ECHOED: &str =
+    "the server closed without echoing anything; is this hermit's throughput port?";
+
+/// A verified echo test's result.
+#[derive(Clone, Debug)]
+pub struct Echoed {
+    /// From the first write to the last echoed byte.
+    pub elapsed: Duration,
+    /// The request path, when hops were asked for: the client, each
+    /// proxy, then the server.
+    pub hops: Vec<Hop>,
+}
+
+/// Client side of a verified echo test: sends `payload` on `stream` in
+/// `block`-sized writes, checks every echoed byte against it and then the
+/// server's count and checksum of what it received. With `trace_hops`,
+/// also collects the hop list.
+pub async fn verify_echo<S>(
+    stream: S,
+    payload: &[u8],
+    block: usize,
+    trace_hops: bool,
+) -> Result<Echoed, String>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let header = Header {
+        mode: Mode::Echo,
+        congestion: None,
+        ack: false,
+        verify: true,
+        hops: trace_hops,
+        duration: Duration::ZERO,
+        block,
+    };
+    let (mut rx, mut tx) = tokio::io::split(stream);
+    let start = Instant::now();
+    let send = async {
+        tx.write_all(&header.encode()).await?;
+        if trace_hops {
+            tx.write_all(&hops::encode(&[Hop::departing("client", 0)]))
+                .await?;
+        }
+        for chunk in payload.chunks(block.max(1)) {
+            tx.write_all(chunk).await?;
+        }
+        tx.shutdown().await
+    };
+    let receive = async {
+        let hops = if trace_hops {
+            hops::read(&mut rx).await.map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => io::Error::new(e.kind(), NOT_ECHOED),
+                _ => e,
+            })?
+        } else {
+            Vec::new()
+        };
+        let mut buf = vec![0u8; block.clamp(1, MAX_BLOCK)];
+        let mut offset = 0;
+        while offset < payload.len() {
+            let want = buf.len().min(payload.len() - offset);
+            let n = rx.read(&mut buf[..want]).await?;
+            if n == 0 && offset == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, NOT_ECHOED));
+            }
+            if n == 0 {
+                let e = format!("echo ended after {} of {} bytes", offset, payload.len());
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, e));
+            }
+            let sent = &payload[offset..offset + n];
+            if let Some(i) = (0..n).find(|&i| buf[i] != sent[i]) {
+                let e = format!(
+                    "echoed byte {} differs: sent {:#04x}, got {:#04x}",
+                    offset + i,
+                    sent[i],
+                    buf[i]
+                );
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            offset += n;
+        }
+        let elapsed = start.elapsed();
+        let mut trailer = [0u8; TRAILER_LEN];
+        rx.read_exact(&mut trailer).await?;
+        Ok((elapsed, trailer, hops))
+    };
+    let ((), (elapsed, trailer, hops)) =
+        tokio::try_join!(send, receive).map_err(|e| e.to_string())?;
+
+    let received = u64::from_be_bytes(trailer[..8].try_into().unwrap());
+    if received != payload.len() as u64 {
+        return Err(format!(
+            "server received {} bytes, {} were sent",
+            received,
+            payload.len()
+        ));
+    }
+    let crc = u32::from_be_bytes(trailer[8..12].try_into().unwrap());
+    let expected = crc32fast::hash(payload);
+    if crc != expected {
+        return Err(format!(
+            "server checksum {:08x} differs from the payload's {:08x}",
+            crc, expected
+        ));
+    }
+    Ok(Echoed { elapsed, hops })
+}
+
+/// The stream itself when an echo test with `block`-sized reads should
+/// be spliced: plain TCP (the socket is the stream, so nothing is
+/// buffered above it) on Linux.
+fn splice_target<S: 'static>(stream: &S, block: usize) -> Option<&TcpStream> {
+    if !cfg!(target_os = "linux") || block < SPLICE_MIN_BLOCK {
+        return None;
+    }
+    (stream as &dyn std::any::Any).downcast_ref()
+}
+
+/// Echo by moving data socket -> pipe -> socket inside the kernel, so
+/// large payloads aren't bottlenecked on copying through userspace.
+/// Returns the bytes echoed; `start` is set when the first arrive.
+#[cfg(target_os = "linux")]
+async fn splice_echo(
+    tcp: &TcpStream,
+    block: usize,
+    start: &mut Option<Instant>,
+) -> io::Result<u64> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use tokio::io::Interest;
+
+    let mut fds = [0; 2];
+    // SAFETY: pipe2 writes two descriptors into `fds`, which has room for
+    // exactly two.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 succeeded, so both descriptors are open and nothing
+    // else owns them.
+    let (rx, tx) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    // Grow the pipe to a whole block if allowed (fs.pipe-max-size, 1 MiB
+    // by default); otherwise move whatever fits per round.
+    // SAFETY: F_SETPIPE_SZ takes an int and touches no memory of ours;
+    // `tx` is open.
+    unsafe { libc::fcntl(tx.as_raw_fd(), libc::F_SETPIPE_SZ, block as libc::c_int) };
+    // SAFETY: as above; F_GETPIPE_SZ takes no argument.
+    let capacity = match unsafe { libc::fcntl(tx.as_raw_fd(), libc::F_GETPIPE_SZ) } {
+        n if n > 0 => n as usize,
+        _ => return Err(io::Error::last_os_error()),
+    };
+
+    let sock = tcp.as_raw_fd();
+    let mut bytes = 0u64;
+    loop {
+        let round_trip = async {
+            // The pipe is empty here, so WouldBlock can only mean the
+            // socket has nothing to read, and vice versa below.
+            let n = tcp
+                .async_io(Interest::READABLE, || {
+                    splice(sock, tx.as_raw_fd(), capacity)
+                })
+                .await?;
+            if n > 0 {
+                start.get_or_insert_with(Instant::now);
+            }
+            let mut left = n;
+            while left > 0 {
+                left -= tcp
+                    .async_io(Interest::WRITABLE, || splice(rx.as_raw_fd(), sock, left))
+                    .await?;
+            }
+            io::Result::Ok(n)
+        };
+        let n = alloc_audit::track(HotPath::Echo, round_trip).await?;
+        if n == 0 {
+            return Ok(bytes);
+        }
+        bytes += n as u64;
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn splice_echo(
+    _tcp: &TcpStream,
+    _block: usize,
+    _start: &mut Option<Instant>,
+) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(target_os = "linux")]
+fn splice(from: std::os::fd::RawFd, to: std::os::fd::RawFd, len: usize) -> io::Result<usize> {
+    let flags = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+    // SAFETY: with null offsets splice reads and writes no memory of
+    // ours; invalid descriptors only make it fail.
+    let n = unsafe {
+        libc::splice(
+            from,
+            std::ptr::null_mut(),
+            to,
+            std::ptr::null_mut(),
+            len,
+            flags,
+        )
+    };
+    if n < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(mode: u8, millis: u32, block: u32) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        buf[..4].copy_from_slice(MAGIC);
+        buf[4] = mode;
+        buf[8..12].copy_from_slice(&millis.to_be_bytes());
+        buf[12..].copy_from_slice(&block.to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn parses_and_clamps_header() {
+        let h = Header::parse(&header(1, 120_000, 0)).unwrap();
+        assert_eq!(h.mode, Mode::Download);
+        assert_eq!(h.duration, MAX_DURATION);
+        assert_eq!(h.block, DEFAULT_BLOCK);
+        assert_eq!(
+            Header::parse(&header(0, 0, 1 << 30)).unwrap().block,
+            MAX_BLOCK
+        );
+
+        assert_eq!(Header::parse(&header(2, 0, 0)).unwrap().mode, Mode::Echo);
+        assert!(Header::parse(&header(3, 0, 0)).is_err());
+        let mut bad = header(0, 0, 0);
+        bad[0] = b'X';
+        assert!(Header::parse(&bad).is_err());
+
+        let mut flagged = header(0, 0, 0);
+        flagged[5] = 3;
+        flagged[6] = FLAG_ACK;
+        let h = Header::parse(&flagged).unwrap();
+        assert_eq!((h.congestion, h.ack), (Some(CongestionControl::Bbr), true));
+        flagged[5] = 4;
+        assert!(Header::parse(&flagged).is_err());
+    }
+
+    #[tokio::test]
+    async fn upload_reports_bytes_received() {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let test = tokio::spawn(run(server, Socket::default()));
+
+        client.write_all(&header(0, 0, 4096)).await.unwrap();
+        client.write_all(&vec![7u8; 300_000]).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut result = Vec::new();
+        client.read_to_end(&mut result).await.unwrap();
+
+        assert_eq!(result.len(), 16);
+        assert_eq!(u64::from_be_bytes(result[..8].try_into().unwrap()), 300_000);
+        let out = test.await.unwrap().unwrap();
+        assert_eq!((out.mode, out.bytes), (Mode::Upload, 300_000));
+    }
+
+    #[tokio::test]
+    async fn download_streams_until_duration_elapses() {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let test = tokio::spawn(run(server, Socket::default()));
+
+        client.write_all(&header(1, 20, 1024)).await.unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+
+        let out = test.await.unwrap().unwrap();
+        assert_eq!(out.mode, Mode::Download);
+        assert_eq!(out.bytes, received.len() as u64);
+        assert!(out.bytes > 0 && out.bytes % 1024 == 0);
+        assert!(out.elapsed >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn echo_returns_what_was_sent() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let test = tokio::spawn(run(server, Socket::default()));
+
+        let (mut rx, mut tx) = tokio::io::split(client);
+        let sent: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let writer = {
+            let sent = sent.clone();
+            tokio::spawn(async move {
+                tx.write_all(&header(2, 0, 4096)).await.unwrap();
+                tx.write_all(&sent).await.unwrap();
+                tx.shutdown().await.unwrap();
+            })
+        };
+        let mut received = Vec::new();
+        rx.read_to_end(&mut received).await.unwrap();
+        writer.await.unwrap();
+
+        assert_eq!(received, sent);
+        let out = test.await.unwrap().unwrap();
+        assert_eq!((out.mode, out.bytes), (Mode::Echo, 200_000));
+    }
+
+    #[tokio::test]
+    async fn verified_echo_checks_data_and_trailer() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let test = tokio::spawn(run(server, Socket::default()));
+        let payload = crate::bench::payload(crate::bench::PayloadContent::Incompressible, 300_000);
+        let echoed = verify_echo(client, &payload, 8192, true).await.unwrap();
+        let names: Vec<_> = echoed.hops.iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(names, ["client", "server"]);
+        let out = test.await.unwrap().unwrap();
+        assert_eq!((out.mode, out.bytes), (Mode::Echo, 300_000));
+
+        // A server that flips a bit is caught at the byte it changed.
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        let corrupt = tokio::spawn(async move {
+            let mut buf = [0u8; HEADER_LEN];
+            server.read_exact(&mut buf).await.unwrap();
+            assert!(Header::parse(&buf).unwrap().verify);
+            let mut data = vec![0u8; 1000];
+            server.read_exact(&mut data).await.unwrap();
+            data[700] ^= 1;
+            server.write_all(&data).await.unwrap();
+        });
+        let err = verify_echo(client, &[5u8; 1000], 1000, false)
+            .await
+            .unwrap_err();
+        assert!(err.starts_with("echoed byte 700 differs"), "{}", err);
+        corrupt.await.unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn splices_large_block_echo_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        assert!(splice_target(&server, SPLICE_MIN_BLOCK).is_some());
+        let socket = Socket::of(&server);
+        let test = tokio::spawn(run(server, socket));
+
+        let (mut rx, mut tx) = client.into_split();
+        let sent: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+        let writer = {
+            let sent = sent.clone();
+            tokio::spawn(async move {
+                tx.write_all(&header(2, 0, MAX_BLOCK as u32)).await.unwrap();
+                tx.write_all(&sent).await.unwrap();
+                tx.shutdown().await.unwrap();
+            })
+        };
+        let mut received = Vec::new();
+        rx.read_to_end(&mut received).await.unwrap();
+        writer.await.unwrap();
+
+        assert!(received == sent);
+        let out = test.await.unwrap().unwrap();
+        assert_eq!((out.mode, out.bytes), (Mode::Echo, 3_000_000));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn acknowledges_requested_congestion_control() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let socket = Socket::of(&server);
+        let test = tokio::spawn(run(server, socket));
+
+        // reno is always built in.
+        let mut req = header(0, 0, 0);
+        req[5] = 1;
+        req[6] = FLAG_ACK;
+        client.write_all(&req).await.unwrap();
+        let mut ack = [0u8; ACK_LEN];
+        client.read_exact(&mut ack).await.unwrap();
+        assert_eq!(&ack[..5], b"HTA1\0");
+        assert_eq!(&ack[8..], b"reno\0\0\0\0");
+
+        client.shutdown().await.unwrap();
+        let out = test.await.unwrap().unwrap();
+        assert_eq!(out.congestion, "reno");
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn refuses_other_protocols_on_the_test_port() {
+        use crate::metrics::METRICS;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, None));
+        let before = METRICS.wrong_protocols(Port::Throughput, Protocol::Http1);
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: hermit\r\n\r\n")
+            .await
+            .unwrap();
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).await.unwrap();
+        assert!(reply.starts_with("HTTP/1.1 400 "), "{}", reply);
+        assert!(reply.ends_with("throughput test port, not gRPC or HTTP\n"));
+        assert_eq!(
+            METRICS.wrong_protocols(Port::Throughput, Protocol::Http1),
+            before + 1
+        );
+
+        // The test port still serves tests.
+        let client = TcpStream::connect(addr).await.unwrap();
+        let payload = vec![7u8; 1000];
+        verify_echo(client, &payload, 512, false).await.unwrap();
+    }
+}
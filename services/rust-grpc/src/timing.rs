@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::clock::ClockSource;
+use crate::listener::ConnInfo;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::codegen::http;
+use tower::{Layer, Service};
+
+/// `ClockSource::now_ns()` at the moment a request entered the tower stack, i.e.
+/// after HTTP/2 framing and header decode but before routing, interceptors
+/// and protobuf decode. Handlers subtract it from their own entry time to
+/// get the gRPC stack's share of server-side latency.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestArrival(pub i64);
+
+#[derive(Clone)]
+pub struct TimingLayer {
+    clock: Arc<dyn ClockSource>,
+}
+
+impl TimingLayer {
+    /// `clock` must be the one handlers read, or the difference is noise.
+    pub fn new(clock: Arc<dyn ClockSource>) -> Self {
+        TimingLayer { clock }
+    }
+}
+
+impl<S> Layer<S> for TimingLayer {
+    type Service = TimingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TimingService {
+            inner,
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TimingService<S> {
+    inner: S,
+    clock: Arc<dyn ClockSource>,
+}
+
+impl<S, B> Service<http::Request<B>> for TimingService<S>
+where
+    S: Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        if let Some(conn) = req.extensions().get::<ConnInfo>() {
+            conn.request_arrived();
+        }
+        req.extensions_mut()
+            .insert(RequestArrival(self.clock.now_ns()));
+        self.inner.call(req)
+    }
+}
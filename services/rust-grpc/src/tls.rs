@@ -1,67 +1,662 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use rcgen::{generate_simple_self_signed, CertifiedKey};
-use rustls::ServerConfig;
-use rustls_pemfile::{certs, pkcs8_private_keys};
-use std::io::BufReader;
-use std::sync::Arc;
-use tracing::info;
+use crate::secrets::SecretsClient;
+use crate::spiffe::{self, SpiffeClientVerifier};
+use rcgen::{generate_simple_self_signed, CertifiedKey as RcgenKey};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::CertificateRevocationListDer;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{KeyLog, ServerConfig};
+use rustls_pemfile::{certs, crls, pkcs8_private_keys};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+use x509_parser::extensions::GeneralName;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 #[derive(Clone)]
 pub struct TlsConfig {
-    pub cert_pem: Vec<u8>,
-    pub key_pem: Vec<u8>,
     pub server_config: Arc<ServerConfig>,
+    pub certs: Arc<ReloadableCert>,
+    /// Present when clients are authenticated by SPIFFE ID (mTLS).
+    pub spiffe_verifier: Option<Arc<SpiffeClientVerifier>>,
+}
+
+/// Where the server certificate and key come from.
+#[derive(Clone)]
+pub enum TlsSource {
+    /// Generate a self-signed certificate for development.
+    SelfSigned,
+    /// PEM files on disk.
+    Files { cert: String, key: String },
+    /// PEM already in memory (used by the test harness).
+    Pem { cert: Vec<u8>, key: Vec<u8> },
+    /// PEM values held by the nexus secrets service.
+    Secrets {
+        client: SecretsClient,
+        cert_id: String,
+        key_id: String,
+    },
+    /// X.509 SVIDs from a SPIFFE Workload API socket. Clients must present
+    /// an SVID from the same trust bundle whose ID is in `allowed_ids`.
+    Spiffe {
+        socket: String,
+        allowed_ids: Vec<String>,
+        /// CRL files (PEM or DER) checked against client certificates.
+        crl_paths: Vec<String>,
+    },
+}
+
+/// The rustls provider to install as the process default. ring unless
+/// `post_quantum`, which switches to aws-lc-rs and prefers the
+/// X25519MLKEM768 hybrid, falling back to classical groups for clients
+/// that don't offer it.
+#[cfg(feature = "pq")]
+pub fn crypto_provider(post_quantum: bool) -> Result<CryptoProvider, String> {
+    use rustls::crypto::aws_lc_rs::{self, kx_group};
+
+    if !post_quantum {
+        return Ok(rustls::crypto::ring::default_provider());
+    }
+    Ok(CryptoProvider {
+        kx_groups: vec![
+            kx_group::X25519MLKEM768,
+            kx_group::X25519,
+            kx_group::SECP256R1,
+            kx_group::SECP384R1,
+        ],
+        ..aws_lc_rs::default_provider()
+    })
+}
+
+#[cfg(not(feature = "pq"))]
+pub fn crypto_provider(post_quantum: bool) -> Result<CryptoProvider, String> {
+    if post_quantum {
+        return Err("--post-quantum requires building with --features pq".to_string());
+    }
+    Ok(rustls::crypto::ring::default_provider())
+}
+
+/// An extra certificate served to clients that ask for `name` via SNI.
+#[derive(Clone, Debug)]
+pub struct SniCert {
+    pub name: String,
+    pub cert: String,
+    pub key: String,
+}
+
+/// Parse `NAME=CERT,KEY`, e.g. `hermit.internal=/etc/hermit/int.pem,/etc/hermit/int.key`.
+pub fn parse_sni_cert(s: &str) -> Result<SniCert, String> {
+    let (name, paths) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=CERT,KEY, got {:?}", s))?;
+    let (cert, key) = paths
+        .split_once(',')
+        .ok_or_else(|| format!("expected NAME=CERT,KEY, got {:?}", s))?;
+    if name.is_empty() || cert.is_empty() || key.is_empty() {
+        return Err(format!("expected NAME=CERT,KEY, got {:?}", s));
+    }
+    Ok(SniCert {
+        name: name.to_ascii_lowercase(),
+        cert: cert.to_string(),
+        key: key.to_string(),
+    })
 }
 
-/// Load TLS from files or generate self-signed cert for development.
-pub fn resolve_tls_config(
-    cert_path: Option<&str>,
-    key_path: Option<&str>,
+/// Certificate resolver whose key can be swapped while the listener is
+/// running, so rotated material takes effect for new handshakes without
+/// a restart. Hostnames with their own certificate (`add_sni`) get that
+/// one instead; everything else, including clients without SNI, gets the
+/// rotating default.
+#[derive(Debug)]
+pub struct ReloadableCert {
+    current: RwLock<Arc<CertifiedKey>>,
+    by_name: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+    /// Summary of `current`, recomputed on every swap.
+    summary: watch::Sender<CertSummary>,
+}
+
+/// What clients need to know about the serving certificate.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CertSummary {
+    /// Hex SHA-256 of the leaf certificate's DER.
+    pub sha256: String,
+    pub not_after_unix: i64,
+}
+
+impl CertSummary {
+    fn of(key: &CertifiedKey) -> Self {
+        let Some(leaf) = key.cert.first() else {
+            return CertSummary::default();
+        };
+        let sha256 = hex::encode(ring::digest::digest(&ring::digest::SHA256, leaf));
+        let not_after_unix = x509_parser::parse_x509_certificate(leaf)
+            .map(|(_, cert)| cert.validity().not_after.timestamp())
+            .unwrap_or_default();
+        CertSummary {
+            sha256,
+            not_after_unix,
+        }
+    }
+}
+
+/// The leaf certificate and what a client needs to pin it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CertDetails {
+    pub der: Vec<u8>,
+    /// Hex SHA-256 of the leaf DER.
+    pub sha256: String,
+    /// Hex SHA-256 of the leaf's SubjectPublicKeyInfo.
+    pub spki_sha256: String,
+    /// Subject alternative names as `DNS:…`, `IP:…`, `URI:…` or `email:…`.
+    pub sans: Vec<String>,
+    pub not_before_unix: i64,
+    pub not_after_unix: i64,
+}
+
+impl CertDetails {
+    fn of(key: &CertifiedKey) -> Result<Self, String> {
+        let leaf = key.cert.first().ok_or("certificate chain is empty")?;
+        let (_, cert) =
+            x509_parser::parse_x509_certificate(leaf).map_err(|e| format!("parse leaf: {}", e))?;
+        let sha256 = |data: &[u8]| hex::encode(ring::digest::digest(&ring::digest::SHA256, data));
+        let sans = cert
+            .subject_alternative_name()
+            .map_err(|e| format!("parse subjectAltName: {}", e))?
+            .map(|ext| ext.value.general_names.iter().filter_map(san).collect())
+            .unwrap_or_default();
+        Ok(CertDetails {
+            der: leaf.to_vec(),
+            sha256: sha256(leaf),
+            spki_sha256: sha256(cert.public_key().raw),
+            sans,
+            not_before_unix: cert.validity().not_before.timestamp(),
+            not_after_unix: cert.validity().not_after.timestamp(),
+        })
+    }
+}
+
+fn san(name: &GeneralName) -> Option<String> {
+    match name {
+        GeneralName::DNSName(dns) => Some(format!("DNS:{}", dns)),
+        GeneralName::URI(uri) => Some(format!("URI:{}", uri)),
+        GeneralName::RFC822Name(email) => Some(format!("email:{}", email)),
+        GeneralName::IPAddress(ip) => {
+            let ip = match ip.len() {
+                4 => IpAddr::from(<[u8; 4]>::try_from(*ip).ok()?),
+                16 => IpAddr::from(<[u8; 16]>::try_from(*ip).ok()?),
+                _ => return None,
+            };
+            Some(format!("IP:{}", ip))
+        }
+        _ => None,
+    }
+}
+
+impl ReloadableCert {
+    fn new(key: CertifiedKey) -> Self {
+        ReloadableCert {
+            summary: watch::channel(CertSummary::of(&key)).0,
+            current: RwLock::new(Arc::new(key)),
+            by_name: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Serve `key` to clients whose SNI is `name` (exact match, ignoring
+    /// case).
+    pub fn add_sni(&self, name: &str, key: CertifiedKey) {
+        if let Ok(mut by_name) = self.by_name.write() {
+            by_name.insert(name.to_ascii_lowercase(), Arc::new(key));
+        }
+    }
+
+    fn lookup(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        if let Some(name) = server_name {
+            let by_name = self.by_name.read().ok()?;
+            if let Some(key) = by_name.get(&name.to_ascii_lowercase()) {
+                return Some(key.clone());
+            }
+        }
+        self.current.read().ok().map(|k| Arc::clone(&k))
+    }
+
+    /// Swap in a new default certificate. Any OCSP staple belonged to the
+    /// old one and is dropped until `set_ocsp` supplies a fresh one.
+    fn replace(&self, mut key: CertifiedKey) {
+        let summary = CertSummary::of(&key);
+        key.ocsp = None;
+        if let Ok(mut current) = self.current.write() {
+            *current = Arc::new(key);
+        }
+        self.summary.send_replace(summary);
+    }
+
+    /// Staple this DER OCSP response to the default certificate.
+    pub fn set_ocsp(&self, response: Option<Vec<u8>>) {
+        if let Ok(mut current) = self.current.write() {
+            let mut key = CertifiedKey::clone(&current);
+            key.ocsp = response;
+            *current = Arc::new(key);
+        }
+    }
+
+    pub fn summary(&self) -> CertSummary {
+        self.summary.borrow().clone()
+    }
+
+    /// Notified whenever the certificate is swapped.
+    pub fn subscribe(&self) -> watch::Receiver<CertSummary> {
+        self.summary.subscribe()
+    }
+
+    /// Details of the certificate a client sending `server_name` via SNI
+    /// would get right now.
+    pub fn details(&self, server_name: Option<&str>) -> Result<CertDetails, String> {
+        let key = self
+            .lookup(server_name)
+            .ok_or("certificate store unavailable")?;
+        CertDetails::of(&key)
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.lookup(hello.server_name())
+    }
+}
+
+/// Load TLS from the configured source, generating a self-signed cert for
+/// development when nothing is configured.
+pub async fn resolve_tls_config(
+    source: &TlsSource,
 ) -> Result<TlsConfig, Box<dyn std::error::Error>> {
-    let (cert_pem, key_pem) = match (cert_path, key_path) {
-        (Some(c), Some(k)) => {
-            info!("loading TLS cert from {}, key from {}", c, k);
-            (std::fs::read(c)?, std::fs::read(k)?)
+    let (key, spiffe_verifier) = match source {
+        TlsSource::Spiffe {
+            socket,
+            allowed_ids,
+            crl_paths,
+        } => {
+            let svid = first_svid(socket).await.map_err(unsync)?;
+            info!(spiffe_id = %svid.spiffe_id, "obtained X.509 SVID from workload API");
+            let crls = load_crls(crl_paths).map_err(unsync)?;
+            let verifier = SpiffeClientVerifier::new(&svid.bundle, allowed_ids.clone(), crls)
+                .map_err(unsync)?;
+            let key = svid_key(svid).map_err(unsync)?;
+            (key, Some(Arc::new(verifier)))
         }
         _ => {
+            let (cert_pem, key_pem) = load_pem(source).await.map_err(unsync)?;
+            (certified_key(&cert_pem, &key_pem).map_err(unsync)?, None)
+        }
+    };
+    let certs = Arc::new(ReloadableCert::new(key));
+
+    let builder = ServerConfig::builder();
+    let builder = match &spiffe_verifier {
+        Some(v) => builder.with_client_cert_verifier(v.clone()),
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder.with_cert_resolver(certs.clone());
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+
+    Ok(TlsConfig {
+        server_config: Arc::new(server_config),
+        certs,
+        spiffe_verifier,
+    })
+}
+
+/// Open (or append to) the key log file for `log_keys`. Called before
+/// the sandbox is applied, since Landlock forbids opening files for
+/// writing afterwards. Created owner-only on Unix.
+pub fn open_key_log(path: &str) -> Result<File, String> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .map_err(|e| format!("open TLS key log {}: {}", path, e))
+}
+
+/// Record every session's secrets to `file` in the NSS key log format
+/// (as written for SSLKEYLOGFILE), so Wireshark can decrypt captures.
+/// Anyone who can read the file can decrypt all of hermit's traffic; this
+/// is for debugging only.
+pub fn log_keys(cfg: &mut TlsConfig, file: File) {
+    warn!("TLS KEY LOGGING ENABLED: session secrets are written to disk, do not use in production");
+    Arc::make_mut(&mut cfg.server_config).key_log = Arc::new(KeyLogWriter(Mutex::new(file)));
+}
+
+#[derive(Debug)]
+struct KeyLogWriter(Mutex<File>);
+
+impl KeyLog for KeyLogWriter {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let line = format!(
+            "{} {} {}\n",
+            label,
+            hex::encode(client_random),
+            hex::encode(secret)
+        );
+        let Ok(mut file) = self.0.lock() else {
+            return;
+        };
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!("write TLS key log: {}", e);
+        }
+    }
+}
+
+/// Load the per-hostname certificates. They come from files and are not
+/// rotated.
+pub fn load_sni_certs(cfg: &TlsConfig, sni: &[SniCert]) -> Result<(), Box<dyn std::error::Error>> {
+    for entry in sni {
+        let cert = std::fs::read(&entry.cert)?;
+        let key = std::fs::read(&entry.key)?;
+        let key = certified_key(&cert, &key)
+            .map_err(|e| format!("certificate for {}: {}", entry.name, e))?;
+        cfg.certs.add_sni(&entry.name, key);
+        info!(name = %entry.name, cert = %entry.cert, "SNI certificate loaded");
+    }
+    Ok(())
+}
+
+/// Staple the DER OCSP response at `path` (e.g. from `openssl ocsp
+/// -respout`) and re-read it every `every`. The response must be for the
+/// default certificate: after a rotation the staple is dropped until the
+/// file's contents change.
+pub fn staple_ocsp(
+    cfg: &TlsConfig,
+    path: String,
+    every: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = std::fs::read(&path)?;
+    cfg.certs.set_ocsp(Some(response.clone()));
+    info!(%path, "OCSP response stapled");
+    tokio::spawn(poll_ocsp(path, cfg.certs.clone(), response, every));
+    Ok(())
+}
+
+async fn poll_ocsp(path: String, certs: Arc<ReloadableCert>, mut last: Vec<u8>, every: Duration) {
+    let mut ticker = tokio::time::interval(every);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        match std::fs::read(&path) {
+            Ok(response) if response != last => {
+                certs.set_ocsp(Some(response.clone()));
+                info!(%path, "OCSP staple refreshed");
+                last = response;
+            }
+            Ok(_) => {}
+            Err(e) => warn!(%path, "OCSP staple reload failed: {}", e),
+        }
+    }
+}
+
+/// Keep TLS material current: poll the secrets service, or follow the
+/// SPIFFE Workload API stream. File and self-signed sources are static.
+pub fn spawn_rotation(source: TlsSource, cfg: &TlsConfig, every: Duration) {
+    match source {
+        TlsSource::Secrets { .. } => {
+            tokio::spawn(poll_secrets(source, cfg.certs.clone(), every));
+        }
+        TlsSource::Spiffe {
+            socket, crl_paths, ..
+        } => {
+            if let Some(verifier) = cfg.spiffe_verifier.clone() {
+                if !crl_paths.is_empty() {
+                    tokio::spawn(poll_crls(crl_paths, verifier.clone(), every));
+                }
+                tokio::spawn(follow_svids(socket, cfg.certs.clone(), verifier));
+            }
+        }
+        TlsSource::Files { .. } | TlsSource::Pem { .. } | TlsSource::SelfSigned => {}
+    }
+}
+
+async fn poll_secrets(source: TlsSource, certs: Arc<ReloadableCert>, every: Duration) {
+    let mut last = load_pem(&source).await.ok();
+    let mut ticker = tokio::time::interval(every);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let pem = match load_pem(&source).await {
+            Ok(pem) => pem,
+            Err(e) => {
+                warn!("TLS rotation check failed: {}", e);
+                continue;
+            }
+        };
+        if last.as_ref() == Some(&pem) {
+            continue;
+        }
+        match certified_key(&pem.0, &pem.1) {
+            Ok(key) => {
+                certs.replace(key);
+                info!("TLS certificate rotated from secrets service");
+                last = Some(pem);
+            }
+            Err(e) => warn!("rotated TLS material rejected, keeping current: {}", e),
+        }
+    }
+}
+
+/// CAs reissue CRLs on a schedule; pick up new ones without a restart.
+async fn poll_crls(paths: Vec<String>, verifier: Arc<SpiffeClientVerifier>, every: Duration) {
+    let mut ticker = tokio::time::interval(every);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        match load_crls(&paths).and_then(|crls| verifier.update_crls(crls)) {
+            Ok(()) => debug!("client CRLs reloaded"),
+            Err(e) => warn!("client CRL reload failed, keeping current: {}", e),
+        }
+    }
+}
+
+fn load_crls(paths: &[String]) -> Result<Vec<CertificateRevocationListDer<'static>>, BoxError> {
+    let mut out = Vec::new();
+    for path in paths {
+        let data = std::fs::read(path)?;
+        if data.starts_with(b"-----BEGIN") {
+            for crl in crls(&mut BufReader::new(data.as_slice())) {
+                out.push(crl?);
+            }
+        } else {
+            out.push(CertificateRevocationListDer::from(data));
+        }
+    }
+    Ok(out)
+}
+
+/// The agent pushes a new message whenever the SVID or bundle rotates;
+/// reconnect with a fixed backoff if the stream drops.
+async fn follow_svids(
+    socket: String,
+    certs: Arc<ReloadableCert>,
+    verifier: Arc<SpiffeClientVerifier>,
+) {
+    const RECONNECT: Duration = Duration::from_secs(5);
+    loop {
+        let mut stream = match spiffe::watch_svids(&socket).await {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("workload API connect failed: {}", e);
+                tokio::time::sleep(RECONNECT).await;
+                continue;
+            }
+        };
+        loop {
+            let update = match stream.message().await {
+                Ok(Some(update)) => update,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("workload API stream error: {}", e);
+                    break;
+                }
+            };
+            let applied = spiffe::default_svid(update).and_then(|svid| {
+                verifier.update_bundle(&svid.bundle)?;
+                let id = svid.spiffe_id.clone();
+                certs.replace(svid_key(svid)?);
+                Ok(id)
+            });
+            match applied {
+                Ok(id) => info!(spiffe_id = %id, "X.509 SVID rotated"),
+                Err(e) => warn!("SVID update rejected, keeping current: {}", e),
+            }
+        }
+        tokio::time::sleep(RECONNECT).await;
+    }
+}
+
+async fn first_svid(socket: &str) -> Result<spiffe::Svid, BoxError> {
+    let mut stream = spiffe::watch_svids(socket).await?;
+    let update = stream
+        .message()
+        .await?
+        .ok_or("workload API closed the stream before sending an SVID")?;
+    spiffe::default_svid(update)
+}
+
+fn svid_key(svid: spiffe::Svid) -> Result<CertifiedKey, BoxError> {
+    let provider = CryptoProvider::get_default().ok_or("no rustls crypto provider installed")?;
+    Ok(CertifiedKey::from_der(svid.cert_chain, svid.key, provider)?)
+}
+
+fn unsync(e: BoxError) -> Box<dyn std::error::Error> {
+    e
+}
+
+async fn load_pem(source: &TlsSource) -> Result<(Vec<u8>, Vec<u8>), BoxError> {
+    match source {
+        TlsSource::Files { cert, key } => {
+            info!("loading TLS cert from {}, key from {}", cert, key);
+            Ok((std::fs::read(cert)?, std::fs::read(key)?))
+        }
+        TlsSource::Secrets {
+            client,
+            cert_id,
+            key_id,
+        } => {
+            info!(
+                cert_id = %cert_id,
+                key_id = %key_id,
+                "loading TLS material from secrets service"
+            );
+            let cert = client.fetch(cert_id).await?;
+            let key = client.fetch(key_id).await?;
+            Ok((cert.into_bytes(), key.into_bytes()))
+        }
+        TlsSource::Pem { cert, key } => Ok((cert.clone(), key.clone())),
+        TlsSource::Spiffe { .. } => Err("SPIFFE SVIDs are not loaded as PEM".into()),
+        TlsSource::SelfSigned => {
             info!("generating self-signed TLS certificate");
-            let CertifiedKey { cert, key_pair } = generate_simple_self_signed(vec![
+            let RcgenKey { cert, key_pair } = generate_simple_self_signed(vec![
                 "localhost".to_string(),
                 "hermit.local".to_string(),
                 "127.0.0.1".to_string(),
             ])?;
-            (
+            Ok((
                 cert.pem().as_bytes().to_vec(),
                 key_pair.serialize_pem().as_bytes().to_vec(),
-            )
+            ))
         }
-    };
-
-    let server_config = build_rustls_config(&cert_pem, &key_pem)?;
-
-    Ok(TlsConfig {
-        cert_pem,
-        key_pem,
-        server_config: Arc::new(server_config),
-    })
+    }
 }
 
-fn build_rustls_config(
-    cert_pem: &[u8],
-    key_pem: &[u8],
-) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+fn certified_key(cert_pem: &[u8], key_pem: &[u8]) -> Result<CertifiedKey, BoxError> {
     let cert_chain = certs(&mut BufReader::new(cert_pem)).collect::<Result<Vec<_>, _>>()?;
     let mut keys =
         pkcs8_private_keys(&mut BufReader::new(key_pem)).collect::<Result<Vec<_>, _>>()?;
 
+    if cert_chain.is_empty() {
+        return Err("no certificates found in PEM".into());
+    }
     if keys.is_empty() {
         return Err("no private keys found in PEM".into());
     }
 
-    let config = ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(cert_chain, keys.remove(0).into())?;
+    let provider = CryptoProvider::get_default().ok_or("no rustls crypto provider installed")?;
+    Ok(CertifiedKey::from_der(
+        cert_chain,
+        keys.remove(0).into(),
+        provider,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_for(name: &str) -> CertifiedKey {
+        let RcgenKey { cert, key_pair } =
+            generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let provider = rustls::crypto::ring::default_provider();
+        CertifiedKey::from_der(
+            vec![cert.der().clone()],
+            rustls::pki_types::PrivateKeyDer::Pkcs8(key_pair.serialize_der().into()),
+            &provider,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn sni_selects_named_certificate_or_default() {
+        let certs = ReloadableCert::new(key_for("default.example"));
+        certs.add_sni("Hermit.Internal", key_for("hermit.internal"));
+        let default = certs.summary().sha256;
+        let sha = |name: Option<&str>| CertSummary::of(&certs.lookup(name).unwrap()).sha256;
+
+        assert_ne!(sha(Some("hermit.internal")), default);
+        assert_eq!(sha(Some("HERMIT.internal")), sha(Some("hermit.internal")));
+        assert_eq!(sha(Some("other.example")), default);
+        assert_eq!(sha(None), default);
+    }
 
-    Ok(config)
+    #[test]
+    fn cert_details_report_pins_and_sans() {
+        let certs = ReloadableCert::new(key_for("default.example"));
+        certs.add_sni("hermit.internal", key_for("hermit.internal"));
+
+        let details = certs.details(Some("hermit.internal")).unwrap();
+        assert_eq!(details.sans, vec!["DNS:hermit.internal".to_string()]);
+        assert_eq!(details.spki_sha256.len(), 64);
+        assert_ne!(details.spki_sha256, details.sha256);
+        assert!(details.not_after_unix > details.not_before_unix);
+
+        let default = certs.details(None).unwrap();
+        assert_eq!(default.sha256, certs.summary().sha256);
+        assert_eq!(default.sans, vec!["DNS:default.example".to_string()]);
+    }
+
+    #[test]
+    fn parses_sni_cert_flag() {
+        let sni = parse_sni_cert("Hermit.Internal=/c.pem,/k.pem").unwrap();
+        assert_eq!(
+            (sni.name.as_str(), sni.cert.as_str(), sni.key.as_str()),
+            ("hermit.internal", "/c.pem", "/k.pem")
+        );
+        for bad in ["hermit.internal", "=/c.pem,/k.pem", "h=/c.pem", "h=,/k.pem"] {
+            assert!(parse_sni_cert(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn ocsp_staple_is_dropped_on_rotation() {
+        let certs = ReloadableCert::new(key_for("a.example"));
+        certs.set_ocsp(Some(vec![1, 2, 3]));
+        assert_eq!(certs.lookup(None).unwrap().ocsp, Some(vec![1, 2, 3]));
+        certs.replace(key_for("a.example"));
+        assert_eq!(certs.lookup(None).unwrap().ocsp, None);
+    }
 }
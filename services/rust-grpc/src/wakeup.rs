@@ -0,0 +1,29 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::metrics::{Wakeup, METRICS};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Measures how late the runtime wakes tasks, into the
+/// `hermit_runtime_wakeup_latency_seconds` histogram. When client-side
+/// jitter rises along with this, the server's scheduling is to blame
+/// rather than the network.
+///
+/// Each round sleeps for `interval` and records how long after the
+/// deadline the timer fired, then spawns a task and records how long it
+/// took to be polled. Tokio's timer has millisecond resolution, so timer
+/// lateness includes up to 1ms of rounding; the task latency doesn't.
+pub async fn probe(interval: Duration) {
+    let mut deadline = Instant::now() + interval;
+    loop {
+        tokio::time::sleep_until(deadline).await;
+        let woke = Instant::now();
+        METRICS.wakeup_latency(Wakeup::Timer, woke.saturating_duration_since(deadline));
+
+        let spawned = Instant::now();
+        if let Ok(polled) = tokio::spawn(async { Instant::now() }).await {
+            METRICS.wakeup_latency(Wakeup::Task, polled.saturating_duration_since(spawned));
+        }
+        deadline = woke + interval;
+    }
+}
@@ -0,0 +1,144 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use hermit_server::hermit::{BenchmarkRequest, CertInfoRequest, PingRequest, ServerInfoRequest};
+use hermit_server::test_harness::TestServer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::test]
+async fn serves_grpc_over_h2c_and_tls() {
+    let server = TestServer::start().await.expect("start test server");
+
+    let mut plain = server.grpc_client().await.expect("h2c client");
+    let pong = plain
+        .ping(PingRequest {
+            client_send_ns: 42,
+            client_send_realtime_ns: 0,
+        })
+        .await
+        .expect("ping over h2c")
+        .into_inner();
+    assert_eq!(pong.client_send_ns, 42);
+    assert!(pong.server_send_ns >= pong.server_recv_ns);
+
+    let mut tls = server.tls_client().await.expect("TLS client");
+    let info = tls
+        .server_info(ServerInfoRequest {})
+        .await
+        .expect("server info over TLS")
+        .into_inner();
+    assert!(info.tls_enabled);
+    assert_eq!(info.grpc_port, server.tls_addr.port() as u32);
+    assert!(info.tls_handshake_ns > 0);
+    assert!(info.first_request_ns >= info.tls_handshake_ns, "{:?}", info);
+    assert_eq!(info.tls_kx_group, "X25519");
+    assert_eq!(info.proto_schema_version.len(), 16);
+    assert!(info.rustc_version.starts_with("rustc "), "{:?}", info);
+
+    let cert = tls
+        .get_cert_info(CertInfoRequest::default())
+        .await
+        .expect("cert info over TLS")
+        .into_inner();
+    assert_eq!(cert.sha256, info.tls_cert_sha256);
+    assert!(
+        cert.sans.iter().any(|san| san == "DNS:localhost"),
+        "{:?}",
+        cert.sans
+    );
+    assert_eq!(cert.not_after, info.tls_cert_not_after);
+    if cfg!(target_os = "linux") {
+        assert!(info.send_mss > 0 && info.path_mtu > 0, "{:?}", info);
+    }
+
+    let bench = tls
+        .benchmark(BenchmarkRequest {
+            iterations: 10,
+            ..Default::default()
+        })
+        .await
+        .expect("benchmark over TLS")
+        .into_inner();
+    assert!(
+        bench.peer_addr.starts_with("127.0.0.1:"),
+        "{:?}",
+        bench.peer_addr
+    );
+    assert_eq!(bench.transport, "tls");
+    assert_eq!(bench.tls_version, "TLS 1.3");
+    assert_eq!(bench.alpn, "h2");
+    let h2 = bench.http2_settings.expect("http2 settings");
+    assert_eq!(h2.max_frame_size, 16 * 1024);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn health_probe_reports_ready() {
+    let server = TestServer::start().await.expect("start test server");
+
+    let mut stream = tokio::net::TcpStream::connect(server.health_addr)
+        .await
+        .expect("connect health port");
+    stream
+        .write_all(b"GET /readyz HTTP/1.1\r\nHost: test\r\n\r\n")
+        .await
+        .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK"), "{}", resp);
+    assert!(resp.ends_with("{\"status\":\"ready\"}\n"), "{}", resp);
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn watch_server_info_ends_when_draining() {
+    let server = TestServer::start().await.expect("start test server");
+
+    let mut tls = server.tls_client().await.expect("TLS client");
+    let mut updates = tls
+        .watch_server_info(ServerInfoRequest {})
+        .await
+        .expect("watch server info")
+        .into_inner();
+    let first = updates.message().await.unwrap().expect("initial snapshot");
+    assert!(first.ready && !first.draining);
+    assert_eq!(first.tls_cert_sha256.len(), 64);
+    assert!(first.tls_cert_not_after.is_some());
+
+    let shutdown = tokio::spawn(server.shutdown());
+    let last = updates.message().await.unwrap().expect("drain update");
+    assert!(last.draining);
+    assert!(updates.message().await.unwrap().is_none());
+    shutdown.await.unwrap();
+}
+
+#[tokio::test]
+async fn tls_port_serves_health_over_alpn_http() {
+    let server = TestServer::start().await.expect("start test server");
+
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut server.ca_pem()) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let mut config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    let tcp = tokio::net::TcpStream::connect(server.tls_addr)
+        .await
+        .expect("connect TLS port");
+    let mut stream = tokio_rustls::TlsConnector::from(std::sync::Arc::new(config))
+        .connect("localhost".try_into().unwrap(), tcp)
+        .await
+        .expect("TLS handshake");
+    stream
+        .write_all(b"GET /readyz HTTP/1.1\r\nHost: test\r\n\r\n")
+        .await
+        .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK"), "{}", resp);
+
+    server.shutdown().await;
+}